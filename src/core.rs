@@ -1,79 +1,188 @@
-use std::net::SocketAddr;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
 use rand::distributions::{Alphanumeric, DistString};
 use rand::{thread_rng, Rng};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver, UnboundedReceiver};
+use tokio::sync::oneshot;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{timeout, Duration};
 
-use crate::message::NetworkMessage;
+use crate::message::{InboundMessage, NetworkMessage};
+use crate::network::{Inflight, NetworkEvent, PeerEvent, Readiness, Transport};
 
-pub struct Core {
-    id: usize,                    // id of the node.
-    name: SocketAddr,             // Note: a public key would make more sense as name.
-    nodes: Vec<SocketAddr>,       // ip addresses of all nodes.
-    tx: Sender<NetworkMessage>,   // Channel to send messages to the network.
-    rx: Receiver<NetworkMessage>, // Channel to receive network messages.
-    rx_tick: Receiver<bool>,      // Channel to receive ticks.
+pub struct Core<T> {
+    id: usize,                               // id of the node.
+    name: SocketAddr,                        // Note: a public key would make more sense as name.
+    nodes: Vec<SocketAddr>,                  // ip addresses of all nodes.
+    transport: T,                            // Sends messages to the network and receives them.
+    events: UnboundedReceiver<NetworkEvent>, // Connects, disconnects and failed deliveries.
+    rx_tick: Receiver<bool>,                 // Channel to receive ticks.
+    hooks: ShutdownHooks,                    // Run once when the core is stopped.
+    inflight: Inflight,                      // Messages buffered anywhere in the node.
+    links: HashMap<SocketAddr, usize>,       // Open connections to and from each node.
 }
 
-impl Core {
+/// Runs when Core stops, after it handled the messages that were already delivered, e.g. to
+/// persist its state or hand it off.
+pub type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+type ShutdownHooks = Arc<Mutex<Vec<ShutdownHook>>>;
+
+/// A running Core.
+pub struct CoreHandle {
+    task: JoinHandle<()>,
+    stop: oneshot::Sender<()>,
+    hooks: ShutdownHooks,
+}
+
+impl CoreHandle {
+    /// Add a hook that runs when the core is stopped, after the ones added before.
+    pub fn on_shutdown(&self, hook: impl FnOnce() + Send + 'static) {
+        self.hooks.lock().unwrap().push(Box::new(hook));
+    }
+
+    /// Stop the core and wait until its shutdown hooks ran. If that takes longer than the
+    /// deadline the core is aborted. Returns an error if the core panicked.
+    pub async fn shutdown(self, deadline: Duration) -> Result<(), JoinError> {
+        let _ = self.stop.send(());
+        let mut task = self.task;
+        match timeout(deadline, &mut task).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!("core didn't shut down in time, aborting it");
+                task.abort();
+                match task.await {
+                    Err(e) if !e.is_cancelled() => Err(e),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Transport> Core<T> {
     pub fn spawn(
         id: usize,
         name: SocketAddr,
         nodes: Vec<SocketAddr>,
-        tx: Sender<NetworkMessage>,
-        rx: Receiver<NetworkMessage>,
-    ) {
+        transport: T,
+        rx_events: UnboundedReceiver<NetworkEvent>,
+        ready: Readiness,
+        inflight: Inflight,
+    ) -> CoreHandle {
         let (tx_tick, rx_tick) = channel(10);
+        let (stop, rx_stop) = oneshot::channel();
+        let hooks = ShutdownHooks::default();
 
         // Spawn a ticker that sends a value to rx_tick at a random value between 20ms and 500ms.
         tokio::spawn(async move {
             loop {
                 let duration = thread_rng().gen_range(20..500);
                 tokio::time::sleep(Duration::from_millis(duration)).await;
-                tx_tick.send(true).await.unwrap();
+                // Stop once the core is gone.
+                if tx_tick.send(true).await.is_err() {
+                    return;
+                }
             }
         });
 
-        tokio::spawn(async move {
-            Self {
+        let shared = hooks.clone();
+        let task = tokio::spawn(async move {
+            // From now on messages are read from the transport.
+            ready.set_ready();
+            let mut core = Self {
                 id,
                 name,
                 nodes,
-                tx,
-                rx,
+                transport,
+                events: rx_events,
                 rx_tick,
+                hooks: shared,
+                inflight,
+                links: HashMap::new(),
+            };
+            tokio::select! {
+                _ = core.run() => (),
+                _ = rx_stop => core.on_shutdown(),
             }
-            .run()
-            .await;
         });
+        CoreHandle { task, stop, hooks }
+    }
+
+    // Handle the messages that were delivered but not read yet, then run the shutdown hooks.
+    fn on_shutdown(&mut self) {
+        while let Some(InboundMessage { message, peer }) = self.transport.try_recv() {
+            self.inflight.remove(1);
+            tracing::info!(id = self.id, sender = %message.sender, %peer, message = %message.message, "got message");
+        }
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        for hook in hooks {
+            hook();
+        }
+        tracing::info!(id = self.id, "shut down");
+    }
+
+    // Follow which nodes are reachable: a node is as long as a connection to or from it is open.
+    fn on_peer_event(&mut self, event: PeerEvent) {
+        match event {
+            PeerEvent::Connected(peer) => {
+                let links = self.links.entry(peer).or_default();
+                *links += 1;
+                if *links == 1 {
+                    tracing::info!(id = self.id, %peer, reachable = self.links.len(), "peer reachable");
+                }
+            }
+            PeerEvent::Disconnected(peer) => {
+                let Some(links) = self.links.get_mut(&peer) else {
+                    return;
+                };
+                *links -= 1;
+                if *links == 0 {
+                    self.links.remove(&peer);
+                    tracing::info!(id = self.id, %peer, reachable = self.links.len(), "peer unreachable");
+                }
+            }
+        }
     }
 
-    /// Broadcast a given message to every node in the network.
+    /// Broadcast a given message to every other node in the network.
     async fn broadcast(&mut self, m: String) {
-        let message = NetworkMessage {
-            sender: self.name.clone(),
-            addresses: self.nodes.clone(),
-            message: m.clone(),
-        };
-        match self.tx.send(message).await {
+        let message = NetworkMessage::broadcast(self.name, &self.nodes, m);
+        // Count the message for every recipient before the sender can settle it.
+        let recipients = message.addresses.len();
+        self.inflight.add(recipients);
+        match self.transport.send(message).await {
             Ok(_) => (),
-            Err(e) => println!("{}", e),
+            Err(e) => {
+                self.inflight.remove(recipients);
+                tracing::error!(error = %e, "failed to broadcast");
+            }
         }
     }
 
     pub async fn run(&mut self) {
-        // Listen to incoming messages and process them. Note: self.rx is the channel where we can
+        // Listen to incoming messages and process them. Note: self.transport is where we can
         // retreive data from the message receiver.
         loop {
             tokio::select! {
-                Some(message) = self.rx.recv() => {
-                    println!("{} got message from {}: {}", self.id, message.sender, message.message);
+                Some(InboundMessage { message, peer }) = self.transport.recv() => {
+                    self.inflight.remove(1);
+                    tracing::info!(id = self.id, sender = %message.sender, %peer, message = %message.message, "got message");
                 }
+                Some(event) = self.events.recv() => match event {
+                    NetworkEvent::Peer(event) => self.on_peer_event(event),
+                    NetworkEvent::DeliveryFailed(failed) => {
+                        tracing::warn!(id = self.id, peer = %failed.peer, message = %failed.message.message, "failed to deliver message");
+                    }
+                },
                 Some(_) = self.rx_tick.recv() => {
                     // Create random string.
                     let content = Alphanumeric.sample_string(&mut thread_rng(), 32);
-                    println!("{} broadcasting {}", self.id, content);
+                    tracing::debug!(id = self.id, %content, "broadcasting");
                     self.broadcast(content).await;
                 }
             };
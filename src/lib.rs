@@ -0,0 +1,12 @@
+pub mod core;
+pub mod message;
+pub mod network;
+pub mod node;
+
+/// Print the tracing events of info level and above to stdout. Call it once at the start of a
+/// binary, later calls leave the first subscriber in place.
+pub fn init_tracing() {
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .try_init();
+}
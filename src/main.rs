@@ -1,28 +1,49 @@
-use std::net::SocketAddr;
 use tokio::time::{sleep, Duration};
 
-mod core;
-mod message;
-mod network;
-mod node;
+use tcp_test::node;
+
+const USAGE: &str = "usage: tcp-test [nodes] [base port]";
 
 #[tokio::main]
 async fn main() {
-    let n = 4;
+    tcp_test::init_tracing();
+
+    // The number of nodes and the port of the first one can be given on the command line.
+    let mut args = std::env::args().skip(1);
+    let n = match args.next().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        None => 4,
+        Some(Err(_)) => exit(USAGE),
+    };
+    let base_port = match args.next().map(|arg| arg.parse::<u16>()) {
+        Some(Ok(port)) => port,
+        None => 8000,
+        Some(Err(_)) => exit(USAGE),
+    };
     let runtime = 15;
 
-    // Create n local ip addresses with different ports.
-    let addresses = (0..n)
-        .map(|x| format!("127.0.0.1:{}", 8000 + x).parse::<SocketAddr>().unwrap())
-        .collect::<Vec<_>>();
+    // Create n local ip addresses with consecutive ports.
+    let addresses = match node::local_addresses(n, base_port) {
+        Ok(addresses) => addresses,
+        Err(e) => exit(&e.to_string()),
+    };
 
     // Spawn n nodes.
+    let mut nodes = Vec::new();
     for i in 0..n {
-        let addresses = addresses.clone();
-        tokio::spawn(async move {
-            node::Node::new(i, addresses).await;
-        });
+        nodes.push(node::Node::new(i, addresses.clone()).await);
     }
 
     sleep(Duration::from_secs(runtime)).await;
+
+    for node in nodes {
+        if let Err(e) = node.shutdown().await {
+            tracing::error!(error = %e, "node failed");
+        }
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
 }
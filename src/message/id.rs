@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[cfg(test)]
+#[path = "tests/id_tests.rs"]
+pub mod id_tests;
+
+/// Header that carries the id of a message.
+pub const MESSAGE_ID: &str = "x-net-id";
+
+/// Source of message ids.
+pub trait IdGenerator: std::fmt::Debug + Send + Sync {
+    fn next_id(&self) -> u64;
+}
+
+/// Ids that are unique across the nodes of a network: the upper 16 bits hold the id of the node,
+/// the lower 48 bits count up.
+#[derive(Debug)]
+pub struct AtomicIds {
+    prefix: u64,
+    counter: AtomicU64,
+}
+
+impl AtomicIds {
+    pub fn new(node: u16) -> Self {
+        Self {
+            prefix: (node as u64) << 48,
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl IdGenerator for AtomicIds {
+    fn next_id(&self) -> u64 {
+        self.prefix | (self.counter.fetch_add(1, Ordering::Relaxed) & ((1 << 48) - 1))
+    }
+}
+
+/// Hands out the given ids in order and then counts up from the last one, for tests that assert
+/// exact ids.
+#[derive(Debug)]
+pub struct SequenceIds(Mutex<std::vec::IntoIter<u64>>, AtomicU64);
+
+impl SequenceIds {
+    pub fn new(ids: Vec<u64>) -> Self {
+        let next = ids.last().map_or(0, |last| last + 1);
+        Self(Mutex::new(ids.into_iter()), AtomicU64::new(next))
+    }
+}
+
+impl IdGenerator for SequenceIds {
+    fn next_id(&self) -> u64 {
+        match self.0.lock().unwrap().next() {
+            Some(id) => id,
+            None => self.1.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
@@ -1,10 +1,341 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, fmt, net::SocketAddr, time::Duration};
 
 use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+use crate::message::{Payload, MESSAGE_ID};
+
+#[cfg(test)]
+#[path = "tests/message_tests.rs"]
+pub mod message_tests;
+
+/// Start of the keys of the headers the network sets itself, like the ones below. Other headers
+/// can't use it, see NetworkMessage::set_header.
+pub const RESERVED_PREFIX: &str = "x-net-";
+
+/// Header that carries the epoch, or view, of the protocol a message belongs to.
+pub const EPOCH: &str = "x-net-epoch";
+
+/// Header with the affinity key of a message. Messages with the same key take the same of the
+/// parallel connections to a peer, which keeps them in order.
+pub const AFFINITY: &str = "x-net-affinity";
+
+/// Header with the sequence number of a message on the stream to its recipient. Every peer gets
+/// its own sequence, counting the messages written to it from 1.
+pub const SEQUENCE: &str = "x-net-seq";
+
+/// Header with the comma separated sequence numbers that were written to the recipient before
+/// but are never going to arrive under them, e.g. because their messages weren't acknowledged
+/// and were retransmitted with a new number. The receiver doesn't wait for them.
+pub const SKIPPED: &str = "x-net-skipped";
+
+/// Header with the topic of a message. The receiver can order the messages of every topic on
+/// their own, see ReceiverConfig::topics.
+pub const TOPIC: &str = "x-net-topic";
+
+/// Header with the number of a message among the messages of its topic to the same recipient.
+/// The NetworkSender counts them from 0 and retransmissions keep their number.
+pub const TOPIC_SEQUENCE: &str = "x-net-topic-seq";
+
+/// Header with the time to live of a message in milliseconds, counted from the moment the
+/// NetworkSender picked it up. An expired message is dropped instead of sent or retransmitted.
+pub const TTL: &str = "x-net-ttl";
+
+/// Header of a request with its id, unique among the requests of its sender.
+pub const REQUEST: &str = "x-net-request";
+
+/// Header of a reply with the id of the request it answers.
+pub const REPLY_TO: &str = "x-net-reply-to";
+
+/// Header with the kind of a message the network exchanges on its own. Messages without it are
+/// data for Core.
+pub const KIND: &str = "x-net-kind";
+
+// Value of the kind header of a heartbeat.
+const HEARTBEAT: &str = "heartbeat";
+
+/// Whether a message carries data for Core or is exchanged by the network on its own, see KIND.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Data,
+    // Keeps an idle connection alive and shows that the peer is still there. Never delivered.
+    Heartbeat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetworkMessage {
     pub sender: SocketAddr,
     pub addresses: Vec<SocketAddr>, // Vector containing all recipients.
-    pub message: String,
+    pub message: Payload,
+    // Small key-value pairs that travel with the message, e.g. a request id or a tenant. An empty
+    // map only costs its length prefix. Missing in json from peers that don't know headers yet.
+    // Keys starting with RESERVED_PREFIX belong to the network, set others with set_header.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // Time the message was submitted to the NetworkSender, see submit. Stays local, it isn't
+    // serialized and doesn't count towards equality.
+    #[serde(skip)]
+    pub submitted: Option<Instant>,
+}
+
+impl PartialEq for NetworkMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.sender == other.sender
+            && self.addresses == other.addresses
+            && self.message == other.message
+            && self.headers == other.headers
+    }
+}
+
+impl NetworkMessage {
+    /// Message from the sender to the given addresses, without headers.
+    pub fn new(
+        sender: SocketAddr,
+        addresses: Vec<SocketAddr>,
+        message: impl Into<Payload>,
+    ) -> Self {
+        Self {
+            sender,
+            addresses,
+            message: message.into(),
+            headers: HashMap::new(),
+            submitted: None,
+        }
+    }
+
+    /// Message from the sender to every one of the peers except the sender itself.
+    pub fn broadcast(
+        sender: SocketAddr,
+        peers: &[SocketAddr],
+        message: impl Into<Payload>,
+    ) -> Self {
+        let addresses = peers
+            .iter()
+            .copied()
+            .filter(|peer| *peer != sender)
+            .collect();
+        Self::new(sender, addresses, message)
+    }
+
+    /// Message from the sender to a single peer.
+    pub fn unicast(sender: SocketAddr, address: SocketAddr, message: impl Into<Payload>) -> Self {
+        Self::new(sender, vec![address], message)
+    }
+
+    /// Heartbeat from the sender to the peer at the address.
+    pub fn heartbeat(sender: SocketAddr, address: SocketAddr) -> Self {
+        let mut message = Self::unicast(sender, address, "");
+        message
+            .headers
+            .insert(KIND.to_string(), HEARTBEAT.to_string());
+        message
+    }
+
+    /// Stamp the time the message is submitted to the NetworkSender, which starts the clock of
+    /// its time to first byte. A message that is submitted again keeps its first stamp.
+    pub fn submit(mut self) -> Self {
+        self.submitted.get_or_insert_with(Instant::now);
+        self
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        match self.headers.get(KIND).map(String::as_str) {
+            Some(HEARTBEAT) => MessageKind::Heartbeat,
+            _ => MessageKind::Data,
+        }
+    }
+
+    /// Id of the message, if it has one.
+    pub fn id(&self) -> Option<u64> {
+        self.headers.get(MESSAGE_ID)?.parse().ok()
+    }
+
+    /// Epoch of the message, if it has one.
+    pub fn epoch(&self) -> Option<u64> {
+        self.headers.get(EPOCH)?.parse().ok()
+    }
+
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.headers.insert(EPOCH.to_string(), epoch.to_string());
+    }
+
+    /// Set a header of the application. Keys with the RESERVED_PREFIX are rejected, they would
+    /// be taken for one of the headers of the network.
+    pub fn set_header(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), ReservedHeader> {
+        let key = key.into();
+        if key.starts_with(RESERVED_PREFIX) {
+            return Err(ReservedHeader(key));
+        }
+        self.headers.insert(key, value.into());
+        Ok(())
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(String::as_str)
+    }
+
+    pub fn affinity(&self) -> Option<&str> {
+        self.headers.get(AFFINITY).map(String::as_str)
+    }
+
+    pub fn set_affinity(&mut self, key: &str) {
+        self.headers.insert(AFFINITY.to_string(), key.to_string());
+    }
+
+    /// How long the message is worth sending, if it expires.
+    pub fn ttl(&self) -> Option<Duration> {
+        let millis = self.headers.get(TTL)?.parse().ok()?;
+        Some(Duration::from_millis(millis))
+    }
+
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.headers
+            .insert(TTL.to_string(), ttl.as_millis().to_string());
+    }
+
+    /// Id of the request, if the message is one.
+    pub fn request_id(&self) -> Option<u64> {
+        self.headers.get(REQUEST)?.parse().ok()
+    }
+
+    pub fn set_request_id(&mut self, id: u64) {
+        self.headers.insert(REQUEST.to_string(), id.to_string());
+    }
+
+    /// Id of the request the message answers, if it is a reply.
+    pub fn reply_to(&self) -> Option<u64> {
+        self.headers.get(REPLY_TO)?.parse().ok()
+    }
+
+    /// Reply of the sender to the request, addressed to the node that sent the request. None if
+    /// the message isn't a request.
+    pub fn reply(&self, sender: SocketAddr, message: impl Into<Payload>) -> Option<Self> {
+        let id = self.request_id()?;
+        let mut reply = Self::unicast(sender, self.sender, message);
+        reply.headers.insert(REPLY_TO.to_string(), id.to_string());
+        Some(reply)
+    }
+
+    /// Sequence number of the message on the stream to its recipient, if it has one.
+    pub fn sequence(&self) -> Option<u64> {
+        self.headers.get(SEQUENCE)?.parse().ok()
+    }
+
+    pub fn set_sequence(&mut self, seq: u64) {
+        self.headers.insert(SEQUENCE.to_string(), seq.to_string());
+    }
+
+    /// Sequence numbers the recipient shouldn't wait for, see SKIPPED.
+    pub fn skipped(&self) -> Vec<u64> {
+        let skipped = self.header(SKIPPED).unwrap_or_default().split(',');
+        skipped.filter_map(|seq| seq.parse().ok()).collect()
+    }
+
+    pub fn set_skipped(&mut self, skipped: &[u64]) {
+        if skipped.is_empty() {
+            self.headers.remove(SKIPPED);
+            return;
+        }
+        let skipped = skipped.iter().map(u64::to_string).collect::<Vec<_>>();
+        self.headers.insert(SKIPPED.to_string(), skipped.join(","));
+    }
+
+    pub fn topic(&self) -> Option<&str> {
+        self.header(TOPIC)
+    }
+
+    pub fn set_topic(&mut self, topic: impl Into<String>) {
+        self.headers.insert(TOPIC.to_string(), topic.into());
+    }
+
+    /// Number of the message within its topic, if it has one.
+    pub fn topic_sequence(&self) -> Option<u64> {
+        self.headers.get(TOPIC_SEQUENCE)?.parse().ok()
+    }
+
+    pub fn set_topic_sequence(&mut self, seq: u64) {
+        self.headers
+            .insert(TOPIC_SEQUENCE.to_string(), seq.to_string());
+    }
+}
+
+/// A header key of the application that starts with RESERVED_PREFIX.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReservedHeader(pub String);
+
+impl fmt::Display for ReservedHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header {:?} is reserved for the network", self.0)
+    }
+}
+
+impl std::error::Error for ReservedHeader {}
+
+// Part of a large message that is streamed in several frames.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub message_id: u64,
+    pub index: u32,
+    pub total: u32, // Number of chunks the message was split into.
+    pub data: Vec<u8>,
+}
+
+// Sent back by the receiver for every chunk it got.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ChunkAck {
+    pub message_id: u64,
+    pub index: u32,
+}
+
+// Put into the deliver channel by the NetworkReceiver for every received message. The sender of
+// the message is whatever the remote node claims, peer is the remote address of the connection
+// it arrived on. Messages handed over within the process have the sender as their peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InboundMessage {
+    pub message: NetworkMessage,
+    pub peer: SocketAddr,
+}
+
+// Reported to Core when a message couldn't be delivered to one of its recipients and the
+// retransmitter gave up on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryFailed {
+    pub message: NetworkMessage,
+    pub peer: SocketAddr,
+}
+
+// Reported when the NetworkSender gave up on a peer because connecting to it failed too many
+// times in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerUnreachable {
+    pub peer: SocketAddr,
+    pub failures: usize,
+}
+
+// What happened to a message on its way to one of its recipients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    // Written to the connection to the peer.
+    Sent,
+    // Couldn't be written to the peer and there is no retransmitter left to try again.
+    Failed,
+    // Given up on by the retransmitter once it reached its max_attempts, or discarded without
+    // trying, e.g. because the peer is unreachable or its quota is exhausted.
+    Dropped,
+    // Refused because the send queue of the peer was full and its overflow policy rejects.
+    Rejected,
+    // Dropped because its time to live passed before it could be sent, see TTL.
+    Expired,
+}
+
+// Reported for every message and recipient once its fate is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryReceipt {
+    pub message_id: Option<u64>,
+    pub peer: SocketAddr,
+    pub outcome: DeliveryOutcome,
 }
@@ -1,3 +1,8 @@
+mod id;
+#[allow(clippy::module_inception)]
 mod message;
+mod payload;
 
-pub use crate::message::message::*;
\ No newline at end of file
+pub use crate::message::id::*;
+pub use crate::message::message::*;
+pub use crate::message::payload::*;
@@ -0,0 +1,17 @@
+use super::*;
+
+#[test]
+fn atomic() {
+    // Ids of different nodes never collide.
+    let (a, b) = (AtomicIds::new(1), AtomicIds::new(2));
+    assert_eq!(a.next_id(), 1 << 48);
+    assert_eq!(a.next_id(), (1 << 48) + 1);
+    assert_eq!(b.next_id(), 2 << 48);
+}
+
+#[test]
+fn sequence() {
+    let ids = SequenceIds::new(vec![5, 3]);
+    let ids = (0..4).map(|_| ids.next_id()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![5, 3, 4, 5]);
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+fn nodes() -> Vec<SocketAddr> {
+    (0..3)
+        .map(|i| format!("127.0.0.1:{}", 8000 + i).parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn broadcast() {
+    let nodes = nodes();
+    let message = NetworkMessage::broadcast(nodes[1], &nodes, "hello");
+    assert_eq!(message.sender, nodes[1]);
+    assert_eq!(message.addresses, vec![nodes[0], nodes[2]]);
+    assert_eq!(message.message, "hello");
+    assert!(message.headers.is_empty());
+
+    // Without peers, or with only ourselves, the message goes nowhere.
+    assert!(NetworkMessage::broadcast(nodes[0], &[], "hello")
+        .addresses
+        .is_empty());
+    assert!(NetworkMessage::broadcast(nodes[0], &nodes[..1], "hello")
+        .addresses
+        .is_empty());
+}
+
+#[test]
+fn unicast() {
+    let nodes = nodes();
+    let message = NetworkMessage::unicast(nodes[0], nodes[2], "hello");
+    assert_eq!(message.sender, nodes[0]);
+    assert_eq!(message.addresses, vec![nodes[2]]);
+    assert_eq!(message.message, "hello");
+
+    // Sending to ourselves is up to the caller.
+    let message = NetworkMessage::unicast(nodes[0], nodes[0], "hello");
+    assert_eq!(message.addresses, vec![nodes[0]]);
+}
+
+#[test]
+fn headers() {
+    let nodes = nodes();
+    let mut message = NetworkMessage::unicast(nodes[0], nodes[1], "hello");
+    message.set_header("tenant", "a").unwrap();
+    assert_eq!(message.header("tenant"), Some("a"));
+
+    // Keys of the network can't be set or overwritten by the application.
+    message.set_epoch(3);
+    assert_eq!(
+        message.set_header(EPOCH, "4"),
+        Err(ReservedHeader(EPOCH.to_string()))
+    );
+    assert!(message.set_header("x-net-other", "b").is_err());
+    assert_eq!(message.epoch(), Some(3));
+
+    // Headers of the application don't collide with those of the network anymore.
+    message.set_header("seq", "x").unwrap();
+    message.set_header("kind", "heartbeat").unwrap();
+    assert_eq!(message.sequence(), None);
+    assert_eq!(message.kind(), MessageKind::Data);
+}
+
+#[test]
+fn heartbeat() {
+    let nodes = nodes();
+    let message = NetworkMessage::heartbeat(nodes[0], nodes[1]);
+    assert_eq!(message.addresses, vec![nodes[1]]);
+    assert_eq!(message.kind(), MessageKind::Heartbeat);
+
+    // Messages of Core are data, whatever their headers are.
+    let mut message = NetworkMessage::unicast(nodes[0], nodes[1], "hello");
+    assert_eq!(message.kind(), MessageKind::Data);
+    message.set_sequence(1);
+    assert_eq!(message.kind(), MessageKind::Data);
+}
+
+#[test]
+fn reply() {
+    let nodes = nodes();
+    let mut request = NetworkMessage::unicast(nodes[0], nodes[1], "ping");
+    assert!(request.reply(nodes[1], "pong").is_none());
+
+    // The reply goes back to the node that asked and refers to its request.
+    request.set_request_id(7);
+    let reply = request.reply(nodes[1], "pong").unwrap();
+    assert_eq!(reply.sender, nodes[1]);
+    assert_eq!(reply.addresses, vec![nodes[0]]);
+    assert_eq!(reply.message, "pong");
+    assert_eq!(reply.reply_to(), Some(7));
+    assert_eq!(reply.request_id(), None);
+}
+
+#[test]
+fn ttl() {
+    let nodes = nodes();
+    let mut message = NetworkMessage::unicast(nodes[0], nodes[1], "vote");
+    assert_eq!(message.ttl(), None);
+    message.set_ttl(Duration::from_millis(250));
+    assert_eq!(message.ttl(), Some(Duration::from_millis(250)));
+    assert_eq!(message.headers[TTL], "250");
+}
@@ -0,0 +1,59 @@
+use super::*;
+use crate::message::NetworkMessage;
+
+#[test]
+fn shared_buffer() {
+    let payload = Payload::from("x".repeat(1024));
+    let copy = payload.clone();
+    assert_eq!(copy.bytes().as_ptr(), payload.bytes().as_ptr());
+    assert_eq!(copy, payload);
+    assert_eq!(&*copy, "x".repeat(1024));
+}
+
+#[test]
+fn serialized_like_a_string() {
+    // Peers that still have a String payload read and write the same bytes.
+    #[derive(Serialize, Deserialize)]
+    struct Old {
+        message: String,
+    }
+    #[derive(Serialize, Deserialize)]
+    struct New {
+        message: Payload,
+    }
+    let old = Old {
+        message: "Hello, World!".into(),
+    };
+    let new = New {
+        message: Payload::from("Hello, World!"),
+    };
+    assert_eq!(
+        bincode::serialize(&old).unwrap(),
+        bincode::serialize(&new).unwrap()
+    );
+    assert_eq!(
+        serde_json::to_string(&old).unwrap(),
+        serde_json::to_string(&new).unwrap()
+    );
+
+    let json = serde_json::to_vec(&old).unwrap();
+    let decoded: New = serde_json::from_slice(&json).unwrap();
+    assert_eq!(decoded.message, "Hello, World!");
+    let message = NetworkMessage::unicast(
+        "127.0.0.1:1234".parse().unwrap(),
+        "127.0.0.1:1235".parse().unwrap(),
+        "Hello",
+    );
+    let bytes = bincode::serialize(&message).unwrap();
+    let decoded: NetworkMessage = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(decoded, message);
+}
+
+#[test]
+fn invalid_utf8() {
+    // Bytes that aren't UTF-8 are rejected when decoded, a payload never holds them.
+    let mut bytes = bincode::serialize("ab").unwrap();
+    *bytes.last_mut().unwrap() = 0xff;
+    assert!(bincode::deserialize::<Payload>(&bytes).is_err());
+    assert!(serde_json::from_slice::<Payload>(b"\"\\udc00\"").is_err());
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{Duration, Instant};
+
+use crate::network::Delivery;
+
+/// Messages that were written to peers which acknowledge them, see PeerConfig::ack_timeout, but
+/// weren't acknowledged yet. Keyed by peer and the sequence number the message was written with,
+/// shared between the workers of a NetworkSender.
+#[derive(Debug, Clone, Default)]
+pub struct Unacked(Arc<Mutex<HashMap<SocketAddr, Waiting>>>);
+
+// Messages waiting for their acknowledgement and when they were written, by sequence number.
+type Waiting = BTreeMap<u64, (Delivery, Instant)>;
+
+impl Unacked {
+    pub(crate) fn sent(&self, seq: u64, delivery: Delivery) {
+        let mut peers = self.0.lock().unwrap();
+        let peer = peers.entry(delivery.address).or_default();
+        peer.insert(seq, (delivery, Instant::now()));
+    }
+
+    // Forget the message the peer acknowledged and return it with the time it was written. None
+    // if it wasn't waiting for an acknowledgement, e.g. because it already timed out.
+    pub(crate) fn ack(&self, peer: SocketAddr, seq: u64) -> Option<(Delivery, Instant)> {
+        let mut peers = self.0.lock().unwrap();
+        peers.get_mut(&peer)?.remove(&seq)
+    }
+
+    // Time at which the oldest message to the peer times out.
+    pub(crate) fn deadline(&self, peer: SocketAddr, timeout: Duration) -> Option<Instant> {
+        let peers = self.0.lock().unwrap();
+        let (_, (_, sent)) = peers.get(&peer)?.first_key_value()?;
+        Some(*sent + timeout)
+    }
+
+    // Remove the messages to the peer that were sent at least timeout ago.
+    pub(crate) fn expired(&self, peer: SocketAddr, timeout: Duration) -> Vec<Delivery> {
+        let mut peers = self.0.lock().unwrap();
+        let waiting = match peers.get_mut(&peer) {
+            Some(waiting) => waiting,
+            None => return Vec::new(),
+        };
+        let expired = waiting
+            .iter()
+            .filter(|(_, (_, sent))| sent.elapsed() >= timeout)
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|seq| waiting.remove(&seq))
+            .map(|(delivery, _)| delivery)
+            .collect()
+    }
+
+    /// Number of messages to the peer that weren't acknowledged yet.
+    pub fn get(&self, peer: &SocketAddr) -> usize {
+        self.0.lock().unwrap().get(peer).map_or(0, Waiting::len)
+    }
+
+    /// Number of unacknowledged messages of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, usize> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, waiting)| (*peer, waiting.len()))
+            .collect()
+    }
+}
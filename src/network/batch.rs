@@ -0,0 +1,59 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::network::CodecError;
+
+#[cfg(test)]
+#[path = "tests/batch_tests.rs"]
+pub mod batch_tests;
+
+/// First byte of a frame that carries several messages. No format tag of a message uses it.
+pub const BATCH: u8 = 0x4d;
+
+// Bytes of the length in front of every frame of a batch.
+const LENGTH: usize = 4;
+
+/// Encode the frames of several messages into a single batch frame: the batch tag followed by
+/// every frame with its length in front.
+pub fn encode_batch(frames: &[Bytes]) -> Bytes {
+    let len = frames
+        .iter()
+        .map(|frame| LENGTH + frame.len())
+        .sum::<usize>();
+    let mut batch = BytesMut::with_capacity(1 + len);
+    batch.put_u8(BATCH);
+    for frame in frames {
+        batch.put_u32(frame.len() as u32);
+        batch.put_slice(frame);
+    }
+    batch.freeze()
+}
+
+/// Length of the batch frame encode_batch makes of frames with the given total length.
+pub fn batch_len(frames: usize, len: usize) -> usize {
+    1 + frames * LENGTH + len
+}
+
+/// The frames of the messages a frame carries: those of a batch, or the frame itself if it isn't
+/// one. Fails if the lengths in the batch don't add up.
+pub fn unbatch(mut frame: BytesMut) -> Result<Vec<BytesMut>, CodecError> {
+    if frame.first() != Some(&BATCH) {
+        return Ok(vec![frame]);
+    }
+    frame.advance(1);
+    let mut frames = Vec::new();
+    while !frame.is_empty() {
+        if frame.len() < LENGTH {
+            return Err(CodecError::MalformedBatch);
+        }
+        let len = frame.get_u32() as usize;
+        if frame.len() < len {
+            return Err(CodecError::MalformedBatch);
+        }
+        frames.push(frame.split_to(len));
+    }
+    // The sender never batches nothing.
+    if frames.is_empty() {
+        return Err(CodecError::MalformedBatch);
+    }
+    Ok(frames)
+}
@@ -0,0 +1,112 @@
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc};
+
+use tokio::sync::mpsc::{channel, error::SendError, Receiver, Sender};
+
+use crate::message::{InboundMessage, NetworkMessage};
+
+#[cfg(test)]
+#[path = "tests/channel_tests.rs"]
+pub mod channel_tests;
+
+/// How Core exchanges messages with the other nodes.
+pub trait Transport: Send + 'static {
+    /// Hand a message to the network, which delivers it to each of its addresses. Fails if the
+    /// network stopped taking messages.
+    fn send(
+        &mut self,
+        message: NetworkMessage,
+    ) -> impl Future<Output = Result<(), SendError<NetworkMessage>>> + Send;
+
+    /// Wait for the next received message. Returns None once no more messages can arrive.
+    fn recv(&mut self) -> impl Future<Output = Option<InboundMessage>> + Send;
+
+    /// A received message that is already waiting, without waiting for one.
+    fn try_recv(&mut self) -> Option<InboundMessage>;
+}
+
+/// The channels to a NetworkSender and from a NetworkReceiver, which exchange the messages over
+/// TCP.
+#[derive(Debug)]
+pub struct NetworkTransport {
+    pub transmit: Sender<NetworkMessage>,
+    pub deliver: Receiver<InboundMessage>,
+}
+
+impl Transport for NetworkTransport {
+    async fn send(&mut self, message: NetworkMessage) -> Result<(), SendError<NetworkMessage>> {
+        self.transmit.send(message.submit()).await
+    }
+
+    async fn recv(&mut self) -> Option<InboundMessage> {
+        self.deliver.recv().await
+    }
+
+    fn try_recv(&mut self) -> Option<InboundMessage> {
+        self.deliver.try_recv().ok()
+    }
+}
+
+/// Transport of a node in a network that only exists in memory, so tests can run several nodes
+/// without binding ports. A message is delivered at once to every address of the network it is
+/// sent to, addresses outside of the network are ignored.
+#[derive(Debug)]
+pub struct ChannelTransport {
+    address: SocketAddr,
+    nodes: Arc<HashMap<SocketAddr, Sender<InboundMessage>>>,
+    deliver: Receiver<InboundMessage>,
+}
+
+// Messages a node of a ChannelTransport network buffers before sending to it waits.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+impl ChannelTransport {
+    /// Connect the given addresses with each other. Returns the transports in the same order.
+    pub fn network(addresses: &[SocketAddr]) -> Vec<Self> {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            addresses.iter().map(|_| channel(CHANNEL_CAPACITY)).unzip();
+        let nodes = Arc::new(
+            addresses
+                .iter()
+                .copied()
+                .zip(senders)
+                .collect::<HashMap<_, _>>(),
+        );
+        addresses
+            .iter()
+            .zip(receivers)
+            .map(|(address, deliver)| Self {
+                address: *address,
+                nodes: nodes.clone(),
+                deliver,
+            })
+            .collect()
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Transport for ChannelTransport {
+    async fn send(&mut self, message: NetworkMessage) -> Result<(), SendError<NetworkMessage>> {
+        for address in &message.addresses {
+            if let Some(node) = self.nodes.get(address) {
+                let inbound = InboundMessage {
+                    message: message.clone(),
+                    peer: self.address,
+                };
+                // A node that stopped reading is gone, like a peer that can't be reached.
+                let _ = node.send(inbound).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<InboundMessage> {
+        self.deliver.recv().await
+    }
+
+    fn try_recv(&mut self) -> Option<InboundMessage> {
+        self.deliver.try_recv().ok()
+    }
+}
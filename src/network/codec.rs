@@ -0,0 +1,282 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bincode::Options;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::message::NetworkMessage;
+
+#[cfg(test)]
+#[path = "tests/codec_tests.rs"]
+pub mod codec_tests;
+
+/// Serialization format of messages on the wire. Every frame starts with the tag of the codec
+/// that encoded it, so a receiver can decode frames of different formats.
+pub trait Codec: fmt::Debug + Send + Sync {
+    // Tag byte identifying the format.
+    fn tag(&self) -> u8;
+
+    fn encode(&self, message: &NetworkMessage) -> Result<Vec<u8>, CodecError>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<NetworkMessage, CodecError>;
+
+    // Copy of the codec that decodes messages of at most max_length bytes, for codecs that bound
+    // what they decode. The others return None and are used as they are.
+    fn limited(&self, _max_length: usize) -> Option<Arc<dyn Codec>> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    // The frame was empty, so there is no tag.
+    MissingTag,
+    // The frame has a tag but no message. No codec encodes a message to nothing, so this is
+    // never a valid frame.
+    EmptyPayload,
+    // No codec is known for the tag of the frame.
+    UnknownFormat(u8),
+    // The compressed payload of the frame is invalid or compression isn't supported.
+    Compression(String),
+    // The lengths of the frames in a batch don't add up to the batch.
+    MalformedBatch,
+    // A chunk of a streamed message is truncated, out of range or too large.
+    MalformedChunk,
+    // The authentication tag of the frame is missing or doesn't match.
+    Unauthenticated,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Bincode(e) => write!(f, "bincode error: {}", e),
+            CodecError::Json(e) => write!(f, "json error: {}", e),
+            CodecError::MissingTag => write!(f, "frame has no format tag"),
+            CodecError::EmptyPayload => write!(f, "frame has no payload"),
+            CodecError::UnknownFormat(tag) => write!(f, "unknown format tag {}", tag),
+            CodecError::Compression(e) => write!(f, "compression error: {}", e),
+            CodecError::MalformedBatch => write!(f, "malformed batch frame"),
+            CodecError::MalformedChunk => write!(f, "malformed chunk frame"),
+            CodecError::Unauthenticated => write!(f, "frame failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Default maximum length of a frame, the default of LengthDelimitedCodec.
+pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec {
+    // Maximum number of bytes a decoded message may take up. Bincode trusts the length prefixes
+    // of strings and collections, so without a limit a small frame could claim to contain
+    // gigabytes. None limits a message to the frame it was sent in: the max_frame_length of the
+    // receiver, or MAX_FRAME_LENGTH.
+    pub limit: Option<u64>,
+}
+
+impl BincodeCodec {
+    pub fn with_limit(limit: u64) -> Self {
+        Self { limit: Some(limit) }
+    }
+}
+
+impl Codec for BincodeCodec {
+    fn tag(&self) -> u8 {
+        0
+    }
+
+    fn encode(&self, message: &NetworkMessage) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(message).map_err(CodecError::Bincode)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<NetworkMessage, CodecError> {
+        // Same options as bincode::deserialize, plus the limit. Bincode only enforces limits when
+        // reading from a reader, not from a slice.
+        bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(self.limit.unwrap_or(MAX_FRAME_LENGTH as u64))
+            .deserialize_from(bytes)
+            .map_err(CodecError::Bincode)
+    }
+
+    fn limited(&self, max_length: usize) -> Option<Arc<dyn Codec>> {
+        let max_length = max_length as u64;
+        Some(Arc::new(Self::with_limit(
+            self.limit.map_or(max_length, |limit| limit.min(max_length)),
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn tag(&self) -> u8 {
+        1
+    }
+
+    fn encode(&self, message: &NetworkMessage) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(message).map_err(CodecError::Json)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<NetworkMessage, CodecError> {
+        serde_json::from_slice(bytes).map_err(CodecError::Json)
+    }
+}
+
+/// Codecs a receiver is able to decode, looked up by the tag of a frame.
+#[derive(Debug, Clone)]
+pub struct Codecs {
+    codecs: Vec<Arc<dyn Codec>>,
+
+    // Maximum length of a frame after decompression.
+    max_length: usize,
+}
+
+impl Codecs {
+    pub fn new(codecs: Vec<Arc<dyn Codec>>) -> Self {
+        Self {
+            codecs,
+            max_length: MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// The codecs bounded to frames of at most max_length bytes, both compressed and
+    /// decompressed. The NetworkReceiver bounds its codecs to its max_frame_length.
+    pub fn limited(&self, max_length: usize) -> Self {
+        Self {
+            codecs: self
+                .codecs
+                .iter()
+                .map(|codec| codec.limited(max_length).unwrap_or_else(|| codec.clone()))
+                .collect(),
+            max_length,
+        }
+    }
+
+    /// Decode a frame with the codec its tag refers to, decompressing it first if necessary.
+    /// Frames without a tag or without a payload are errors.
+    pub fn decode(&self, frame: &[u8]) -> Result<NetworkMessage, CodecError> {
+        let (tag, bytes) = frame.split_first().ok_or(CodecError::MissingTag)?;
+        if bytes.is_empty() {
+            return Err(CodecError::EmptyPayload);
+        }
+        let codec = self
+            .codecs
+            .iter()
+            .find(|codec| codec.tag() == tag & !COMPRESSED)
+            .ok_or(CodecError::UnknownFormat(*tag))?;
+        if tag & COMPRESSED == 0 {
+            return codec.decode(bytes);
+        }
+        codec.decode(&decompress(bytes, self.max_length)?)
+    }
+
+    /// Decode a frame like decode, but hand frames with an unknown tag back as Unknown instead of
+    /// failing. They are usually of a format a newer node introduced, so they can be skipped.
+    pub fn decode_tolerant(&self, frame: &[u8]) -> Result<Decoded, CodecError> {
+        match self.decode(frame) {
+            Ok(message) => Ok(Decoded::Message(message)),
+            Err(CodecError::UnknownFormat(tag)) => Ok(Decoded::Unknown {
+                tag,
+                raw_bytes: Bytes::copy_from_slice(&frame[1..]),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A frame decoded by Codecs::decode_tolerant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoded {
+    Message(NetworkMessage),
+    // No codec is known for the tag, raw_bytes is the payload after it.
+    Unknown { tag: u8, raw_bytes: Bytes },
+}
+
+impl Default for Codecs {
+    fn default() -> Self {
+        Self::new(vec![Arc::new(BincodeCodec::default()), Arc::new(JsonCodec)])
+    }
+}
+
+// Bit that is set in the tag of frames with an LZ4 compressed payload.
+pub const COMPRESSED: u8 = 0x80;
+
+/// Encode a message into a frame: the tag of the codec followed by the encoded message. Fails if
+/// the codec encodes the message to nothing, receivers would reject the frame.
+pub fn encode_frame(codec: &dyn Codec, message: &NetworkMessage) -> Result<Bytes, CodecError> {
+    encode_frame_compressed(codec, message, None)
+}
+
+/// Like encode_frame, but the encoded message is compressed with LZ4 if it has at least
+/// `threshold` bytes. Compressing small messages costs more than it saves. Without the
+/// compression feature nothing is compressed.
+pub fn encode_frame_compressed(
+    codec: &dyn Codec,
+    message: &NetworkMessage,
+    threshold: Option<usize>,
+) -> Result<Bytes, CodecError> {
+    let bytes = codec.encode(message)?;
+    if bytes.is_empty() {
+        return Err(CodecError::EmptyPayload);
+    }
+    let (tag, bytes) = match threshold {
+        Some(threshold) if cfg!(feature = "compression") && bytes.len() >= threshold => {
+            (codec.tag() | COMPRESSED, compress(&bytes))
+        }
+        _ => (codec.tag(), bytes),
+    };
+    let mut frame = BytesMut::with_capacity(bytes.len() + 1);
+    frame.put_u8(tag);
+    frame.put_slice(&bytes);
+    Ok(frame.freeze())
+}
+
+#[cfg(feature = "compression")]
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(bytes)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    bytes.to_vec()
+}
+
+#[cfg(feature = "compression")]
+fn decompress(bytes: &[u8], max_length: usize) -> Result<Vec<u8>, CodecError> {
+    // The uncompressed size is prepended. Check it before allocating, a small frame could claim
+    // to decompress to gigabytes.
+    let size = bytes
+        .get(..4)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .ok_or_else(|| CodecError::Compression("missing size".to_string()))?;
+    if size > max_length {
+        return Err(CodecError::Compression(format!("size {} too large", size)));
+    }
+    lz4_flex::decompress_size_prepended(bytes).map_err(|e| CodecError::Compression(e.to_string()))
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_: &[u8], _: usize) -> Result<Vec<u8>, CodecError> {
+    Err(CodecError::Compression("not supported".to_string()))
+}
+
+/// Hex dump of the first `max` bytes of a frame, for debugging wire issues like codec mismatches.
+pub fn hex_dump(frame: &[u8], max: usize) -> String {
+    let mut dump = frame
+        .iter()
+        .take(max)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if frame.len() > max {
+        dump.push_str(" ..");
+    }
+    dump
+}
@@ -0,0 +1,580 @@
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::message::IdGenerator;
+#[cfg(feature = "hmac")]
+use crate::network::FrameAuth;
+use crate::network::{
+    BincodeCodec, ClientTls, Codec, Codecs, Dedup, EpochFilter, Hello, Inflight, LocalRegistry,
+    Loopback, NoopSink, ObserverSink, OverflowPolicy, Quota, RateLimit, RetransmitOrder, ServerTls,
+    Shutdown, Timeout, Topics, MAX_FRAME_LENGTH,
+};
+
+/// Settings that only apply to a single peer.
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    // Peers with a higher priority get connect permits first when permits are scarce, e.g. after
+    // a partition heals and every worker tries to reconnect at once.
+    pub priority: u8,
+
+    // Format used for messages sent to the peer, e.g. for peers that run an older version.
+    pub codec: Arc<dyn Codec>,
+
+    // Address to connect to if the peer can't be reached at its own address.
+    pub fallback: Option<SocketAddr>,
+
+    // Compress the whole connection to the peer with zstd instead of single messages, which pays
+    // off for many similar small messages. Receivers detect it on their own.
+    pub stream_compression: bool,
+
+    // Only send as many messages as the peer granted credits, waiting for further credits once
+    // they are used up. The receiver of the peer must have flow control enabled.
+    pub flow_control: bool,
+
+    // Number of messages queued for the worker of the peer before the overflow policy applies.
+    pub queue_capacity: usize,
+
+    // What happens to messages for the peer while its queue is full.
+    pub overflow: OverflowPolicy,
+
+    // Deliver the messages to the peer in the order they were sent, also when some of them are
+    // retransmitted: later messages wait until the retransmitted ones were sent or given up on.
+    pub fifo: bool,
+
+    // Wait for the peer to acknowledge every message and retransmit the ones that aren't
+    // acknowledged within this time. A message only counts as sent once it was acknowledged.
+    // Needs SenderConfig::sequence_numbers, without them messages count as sent once written.
+    // Ignored with a pool_size above 1, NodeConfig::validate rejects that. Timeout::Rtt adapts the time to the round trip times the acknowledgements show, see
+    // NetworkSender::rtts. None doesn't wait for acknowledgements.
+    pub ack_timeout: Option<Timeout>,
+
+    // Send a heartbeat to the peer whenever nothing else was sent to it for this long, so an idle
+    // connection isn't closed by the idle timeout of the peer or a NAT. None doesn't send
+    // heartbeats.
+    pub heartbeat_interval: Option<Duration>,
+
+    // Heartbeats in a row the peer may leave unanswered before its connection counts as broken,
+    // which reconnects without waiting for a message to fail.
+    pub missed_heartbeats: u32,
+
+    // Coalesce the messages queued for the peer into batch frames, which saves the framing and a
+    // write per message under load. The receiver of the peer unpacks them. None writes every
+    // message in a frame of its own.
+    pub batching: Option<Batching>,
+
+    // Connections to the peer, each with a worker of its own. Messages are spread over them by
+    // weighted round-robin, faster connections get more, so they aren't ordered: they get no
+    // sequence numbers and aren't acknowledged, FIFO only orders retransmissions. So above 1
+    // ack_timeout and SenderConfig::sequence_numbers don't apply to the peer, the sender warns
+    // about it and NodeConfig::validate rejects an ack_timeout. Messages with the same affinity
+    // key take the same connection and stay in order among themselves. The receiver of the peer
+    // must keep duplicate connections open, see DuplicatePolicy::AllowBoth, NodeConfig::validate
+    // rejects anything else.
+    pub pool_size: usize,
+
+    // Caps the rate of messages or bytes sent to the peer. The worker waits for the limit, so a
+    // slow peer fills its own queue and not those of the others. None sends as fast as the
+    // connection allows.
+    pub rate_limit: Option<RateLimit>,
+
+    // Connect to the peer over the Unix socket at this path instead of its address, for peers on
+    // the same host. The address still identifies the peer, and the fallback isn't tried.
+    pub unix_socket: Option<PathBuf>,
+
+    // Stream large frames to the peer in chunks that it acknowledges one by one, so a chunk lost
+    // on the way is sent again on its own. None writes every frame at once.
+    pub streaming: Option<Streaming>,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            codec: Arc::new(BincodeCodec::default()),
+            fallback: None,
+            stream_compression: false,
+            flow_control: false,
+            queue_capacity: 10_000,
+            overflow: OverflowPolicy::Block,
+            fifo: false,
+            ack_timeout: None,
+            heartbeat_interval: None,
+            missed_heartbeats: 3,
+            batching: None,
+            pool_size: 1,
+            rate_limit: None,
+            unix_socket: None,
+            streaming: None,
+        }
+    }
+}
+
+/// Limits of a batch frame. A worker sends a batch once it is full or max_delay after it took
+/// its first message, whichever comes first, so batching adds at most max_delay of latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    pub max_messages: usize,
+    // Encoded messages of a batch, never more than the maximum frame length of the sender.
+    pub max_bytes: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_messages: 64,
+            max_bytes: 64 * 1024,
+            max_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+/// How frames of a peer are streamed, see PeerConfig::streaming. Frames of at least threshold bytes
+/// are split into chunks of chunk_size bytes. At most window chunks are unacknowledged at a time,
+/// and a chunk that isn't acknowledged within timeout is sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streaming {
+    pub threshold: usize,
+    pub chunk_size: usize,
+    pub window: usize,
+    pub timeout: Duration,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            threshold: 1024 * 1024,
+            chunk_size: 64 * 1024,
+            window: 16,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Exponential backoff: the first delay is base_delay, every further one is multiplier times as
+/// long as the one before, but never longer than max_delay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(30),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given attempt, counting from 1.
+    pub fn delay(&self, attempts: usize) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = self.base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::from_secs_f64(delay.max(0.0).min(self.max_delay.as_secs_f64()))
+    }
+}
+
+/// When the NetworkSender connects to its peers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Dialing {
+    // Connect to a peer once the first message for it is sent, so a node with a huge list of
+    // peers only opens the connections it uses.
+    #[default]
+    Lazy,
+    // Connect to the given peers at startup, so their first messages don't wait for the
+    // connection. Peers that can't be reached are tried again once a message for them is sent.
+    Eager(Vec<SocketAddr>),
+    // Connect at startup to the given peers, by their node id, whose id is higher than the id of
+    // this node. The peers with a lower id are expected to connect to this node instead, the
+    // ones whose handshake didn't arrive within accept_timeout, according to the node ids of
+    // NetworkSender::accepting, are connected to after all. Messages only travel on connections
+    // of their sender though, so a peer that connected to this node is still connected to once
+    // a message for it is sent. A pair of nodes that exchanges messages ends up with two
+    // connections either way, only their startup opens one.
+    Paired {
+        id: u64,
+        peers: Vec<(SocketAddr, u64)>,
+        accept_timeout: Duration,
+    },
+}
+
+/// Settings for the NetworkSender.
+#[derive(Debug, Clone)]
+pub struct SenderConfig {
+    // Maximum number of outgoing connection attempts that can be in progress at the same time.
+    pub connect_permits: usize,
+
+    // Give up on a connection attempt after this long, so a peer whose address swallows the
+    // handshake doesn't hold a worker for the timeout of the OS.
+    pub connect_timeout: Duration,
+
+    // A worker whose connection breaks reconnects up to this many times, backing off before
+    // every attempt, before it gives up and hands the failed message to the retransmitter.
+    // Messages keep queueing up for the worker in the meantime.
+    pub reconnects: usize,
+    pub reconnect_backoff: Backoff,
+
+    // Per peer settings. Peers without an entry use default_peer.
+    pub peers: HashMap<SocketAddr, PeerConfig>,
+    pub default_peer: PeerConfig,
+
+    // Time after the first connection attempt to a peer during which failing to connect doesn't
+    // count as a failed attempt, as long as the peer was never connected. This keeps peers that
+    // simply didn't start yet from being given up on.
+    pub startup_grace: Duration,
+
+    // Messages that are encoded to at least this many bytes are compressed with LZ4, each on
+    // its own. None disables compression. PeerConfig::stream_compression compresses the whole
+    // connection with zstd instead.
+    pub compression_threshold: Option<usize>,
+
+    // Limits the bytes sent to each peer per window. None sends without limit.
+    pub quota: Option<Quota>,
+
+    // Mark a peer as unreachable after this many failed connection attempts in a row. Messages
+    // to an unreachable peer are dropped instead of spawning ever new workers for it. None keeps
+    // trying forever.
+    pub max_connect_failures: Option<usize>,
+
+    // Maximum number of workers for new peers spawned per second. Dials to further new peers
+    // wait, unlike connect_permits this also bounds connections that are quickly established.
+    // None spawns without limit.
+    pub spawn_rate: Option<f64>,
+
+    // Log the length and a hex dump of up to this many bytes of every sent frame at trace level.
+    // None disables the dump, which is the default because of the formatting cost.
+    pub frame_dump: Option<usize>,
+
+    // Receives the events of the sender and its workers.
+    pub observer: Arc<dyn ObserverSink>,
+
+    // Maximum number of distinct peers the sender tracks. Messages to further peers are dropped,
+    // so a bogus membership can't make the node open ever more connections. None tracks every
+    // peer.
+    pub max_peers: Option<usize>,
+
+    // Gives every new message without an id header one. None leaves messages as they are.
+    pub ids: Option<Arc<dyn IdGenerator>>,
+
+    // Offer peers to upgrade connections to TLS. Disabled by default.
+    pub tls: ClientTls,
+
+    // Append an authentication tag to every frame written to the peers, which must share the
+    // secret. Credits, acknowledgements and answers to heartbeats that come back aren't
+    // authenticated. None writes the frames as they are.
+    #[cfg(feature = "hmac")]
+    pub auth: Option<FrameAuth>,
+
+    // Connects and disconnects of a peer during this window count towards its flap rate.
+    pub flap_window: Duration,
+
+    // Messages to nodes in the registry are handed to them directly, without a connection. They
+    // skip the quota, the codecs and everything else of the way through the network. None
+    // connects to every peer.
+    pub local: Option<LocalRegistry>,
+
+    // TCP_USER_TIMEOUT of outgoing connections: close a connection whose sent data stays
+    // unacknowledged this long. Only applies on Linux. None keeps the system default.
+    pub user_timeout: Option<Duration>,
+
+    // Set TCP_NODELAY on outgoing connections, so small frames aren't held back by Nagle's
+    // algorithm until the previous one is acknowledged.
+    pub nodelay: bool,
+
+    // Stops counting messages once they are sent or dropped. Whoever enqueues messages to the
+    // transmit channel counts them.
+    pub inflight: Inflight,
+
+    // Order of the messages to FIFO peers, shared with the NetworkRetransmitter.
+    pub order: RetransmitOrder,
+
+    // Whether peers are connected to at startup or on their first message.
+    pub dialing: Dialing,
+
+    // Messages that are encoded to a longer frame are dropped, receivers would close the
+    // connection on them.
+    pub max_frame_length: usize,
+
+    // Connect and send failures of a peer are logged once per window, with a summary of how
+    // many more there were. None logs every failure.
+    pub log_window: Option<Duration>,
+
+    // Once triggered the sender stops taking messages, like when its transmit channel is closed.
+    pub shutdown: Shutdown,
+
+    // Number the messages written to each peer in a sequence header, so both ends can compare
+    // their high-water marks. Disabled by default, the header makes every frame a bit larger.
+    // Peers with a pool_size above 1 get no numbers.
+    pub sequence_numbers: bool,
+
+    // Our own node. Messages to its address are put straight into its deliver channel, without a
+    // connection or serialization. None sends them over TCP like to any other peer.
+    pub loopback: Option<Loopback>,
+
+    // Start every connection with a handshake frame announcing the node, for receivers that
+    // require one. None starts right with the messages.
+    pub hello: Option<Hello>,
+}
+
+impl SenderConfig {
+    /// Returns the settings for the given peer.
+    pub fn peer(&self, address: &SocketAddr) -> PeerConfig {
+        let peer = self.peers.get(address);
+        peer.unwrap_or(&self.default_peer).clone()
+    }
+}
+
+impl Default for SenderConfig {
+    fn default() -> Self {
+        Self {
+            connect_permits: 64,
+            connect_timeout: Duration::from_secs(5),
+            reconnects: 3,
+            reconnect_backoff: Backoff {
+                base_delay: Duration::from_millis(50),
+                max_delay: Duration::from_secs(1),
+                multiplier: 2.0,
+            },
+            peers: HashMap::new(),
+            default_peer: PeerConfig::default(),
+            startup_grace: Duration::ZERO,
+            compression_threshold: None,
+            quota: None,
+            max_connect_failures: None,
+            spawn_rate: None,
+            frame_dump: None,
+            observer: Arc::new(NoopSink),
+            max_peers: None,
+            ids: None,
+            tls: ClientTls::default(),
+            #[cfg(feature = "hmac")]
+            auth: None,
+            flap_window: Duration::from_secs(60),
+            local: None,
+            user_timeout: None,
+            nodelay: true,
+            inflight: Inflight::default(),
+            order: RetransmitOrder::default(),
+            dialing: Dialing::Lazy,
+            sequence_numbers: false,
+            loopback: None,
+            hello: None,
+            log_window: Some(Duration::from_secs(10)),
+            max_frame_length: MAX_FRAME_LENGTH,
+            shutdown: Shutdown::default(),
+        }
+    }
+}
+
+/// What the NetworkReceiver does when a second connection from the same node arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    // Close the older connection, a new connection usually means the old one is stale.
+    #[default]
+    KeepNewest,
+    // Reject the new connection.
+    KeepOldest,
+    // Keep both connections open.
+    AllowBoth,
+}
+
+/// What the NetworkReceiver does with messages that arrive before Core is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EarlyPolicy {
+    // Deliver up to this many messages, they wait in the deliver channel until Core reads them.
+    // Further messages are dropped until Core is ready.
+    Buffer(usize),
+    // Don't accept connections until Core is ready. Peers see their connection attempts pile up
+    // and retry later.
+    Hold,
+}
+
+impl Default for EarlyPolicy {
+    fn default() -> Self {
+        EarlyPolicy::Buffer(10_000)
+    }
+}
+
+/// What the NetworkReceiver does with frames whose format tag none of its codecs knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownPolicy {
+    // Log and skip the frame, the connection stays open. A newer peer may use formats an older
+    // node doesn't know yet.
+    #[default]
+    Skip,
+    // Close the connection like for any other protocol error.
+    Disconnect,
+}
+
+/// What the NetworkReceiver does with connections beyond ReceiverConfig::max_connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionLimit {
+    // Reset the connection right after accepting it, so the peer sees it refused at once.
+    #[default]
+    Reject,
+    // Close the connection cleanly right after accepting it, the peer sees it end before it
+    // could send anything.
+    Close,
+}
+
+/// Settings for the NetworkReceiver.
+#[derive(Debug, Clone)]
+pub struct ReceiverConfig {
+    pub duplicate_policy: DuplicatePolicy,
+
+    // Formats that can be decoded, the format tag of a frame selects one of them.
+    pub codecs: Codecs,
+
+    // Debugging aid: treat every line received on a connection as the content of a message
+    // instead of expecting frames, so a node can be used with netcat. Off by default.
+    pub text_mode: bool,
+
+    // Maximum number of open inbound connections, including text mode ones. None doesn't limit
+    // them.
+    pub max_connections: Option<usize>,
+    pub connection_limit: ConnectionLimit,
+
+    // Maximum number of frames a single connection can have read but not delivered yet. Once it
+    // is reached the connection isn't read anymore, so TCP backpressure slows the peer down.
+    pub max_outstanding: usize,
+
+    // Log the length and a hex dump of up to this many bytes of every received frame at trace
+    // level. None disables the dump.
+    pub frame_dump: Option<usize>,
+
+    // Only applies if the receiver waits for the readiness of Core.
+    pub early_policy: EarlyPolicy,
+
+    // Receives the events of the receiver and its workers.
+    pub observer: Arc<dyn ObserverSink>,
+
+    // Accept or demand upgrades of incoming connections to TLS. Doesn't apply in text mode.
+    pub tls: ServerTls,
+
+    // Drop every frame whose authentication tag doesn't match the secret, see SenderConfig::auth.
+    // Doesn't apply in text mode. None takes the frames as they are.
+    #[cfg(feature = "hmac")]
+    pub auth: Option<FrameAuth>,
+
+    // Credits every connection of a sender with flow control starts with. Such senders don't send
+    // more messages than they were granted credits, Core grants further ones through
+    // NetworkReceiver::credits. None disables flow control.
+    pub credits: Option<u32>,
+
+    // Drops messages from epochs that are too old, Core keeps a clone to advance the epoch. None
+    // delivers messages of every epoch.
+    pub epochs: Option<EpochFilter>,
+
+    // Registers the receiver once it runs, so senders in the same process deliver to it directly.
+    // Such messages don't go through the interceptors, the early policy or the epoch filter.
+    pub local: Option<LocalRegistry>,
+
+    // Drop messages whose id was already delivered, over all connections. Messages without an id
+    // are always delivered. None delivers duplicates.
+    pub dedup: Option<Dedup>,
+
+    // Deliver the messages of every remote node in the order of their sequence numbers, see
+    // SenderConfig::sequence_numbers, holding back at most this many messages per node that
+    // arrived early. A gap that isn't filled by then is skipped. Messages without a number are
+    // delivered as they arrive. None delivers every message as it arrives.
+    pub reorder: Option<usize>,
+
+    // Deliver the messages of every topic, see NetworkMessage::set_topic, with the ordering
+    // guarantee of the topic. Each remote node has a sequence per strict topic, so a missing
+    // message only holds back its own topic. Messages with a topic bypass reorder. None delivers
+    // them like messages without a topic.
+    pub topics: Option<Topics>,
+
+    // TCP_USER_TIMEOUT of incoming connections, see SenderConfig::user_timeout.
+    pub user_timeout: Option<Duration>,
+
+    // Close an incoming connection that didn't deliver a frame for this long, so peers that hold
+    // on to their sockets without sending don't keep workers around forever. Doesn't apply in
+    // text mode. None keeps idle connections open.
+    pub idle_timeout: Option<Duration>,
+
+    // Set TCP_NODELAY on incoming connections, see SenderConfig::nodelay. The receiver only
+    // writes credits and acknowledgements to them.
+    pub nodelay: bool,
+
+    // Set SO_REUSEADDR on the listener, so a restarted node can bind its address while the
+    // connections of the previous instance are still in TIME_WAIT.
+    pub reuse_address: bool,
+
+    // A listener that fails to bind, e.g. because the address is briefly held by another
+    // process, is bound again up to this many times, backing off before every attempt.
+    pub bind_retries: usize,
+    pub bind_backoff: Backoff,
+
+    // Counts the messages put into the deliver channel, whoever reads them stops counting them.
+    pub inflight: Inflight,
+
+    // Frames of an unknown format are skipped by default.
+    pub unknown: UnknownPolicy,
+
+    // Protocol errors of a connection are logged once per window, see SenderConfig::log_window.
+    pub log_window: Option<Duration>,
+
+    // A connection whose peer announces a longer frame is closed before the frame is buffered.
+    // Also bounds how large a frame may decompress and a message may decode to.
+    pub max_frame_length: usize,
+
+    // Frames that fail to decode are skipped, but a connection is closed once more than this
+    // many in a row failed.
+    pub max_decode_failures: usize,
+
+    // Expect a handshake frame at the start of every connection and close connections that
+    // start without one. Without it the sender of the first message identifies the remote node.
+    pub handshake: bool,
+
+    // Once triggered the receiver closes its listener and stops accepting connections. Open
+    // connections are still read until their peers close them.
+    pub shutdown: Shutdown,
+
+    // Listen on a Unix socket at this path instead of binding the address, which still
+    // identifies the node. A stale socket left at the path is replaced.
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            codecs: Codecs::default(),
+            text_mode: false,
+            max_connections: None,
+            connection_limit: ConnectionLimit::Reject,
+            max_outstanding: 64,
+            frame_dump: None,
+            early_policy: EarlyPolicy::default(),
+            observer: Arc::new(NoopSink),
+            tls: ServerTls::default(),
+            #[cfg(feature = "hmac")]
+            auth: None,
+            credits: None,
+            epochs: None,
+            local: None,
+            dedup: None,
+            reorder: None,
+            topics: None,
+            user_timeout: None,
+            idle_timeout: None,
+            nodelay: true,
+            reuse_address: true,
+            bind_retries: 5,
+            bind_backoff: Backoff::default(),
+            inflight: Inflight::default(),
+            unknown: UnknownPolicy::Skip,
+            log_window: Some(Duration::from_secs(10)),
+            max_frame_length: MAX_FRAME_LENGTH,
+            max_decode_failures: 10,
+            handshake: false,
+            shutdown: Shutdown::default(),
+            unix_socket: None,
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::message::ChunkAck;
+
+/// Byte a sender with flow control starts the connection with, after a TLS upgrade. It tells
+/// the receiver to grant credits and comes before the marker of stream compression.
+pub const FLOW_CONTROL: u8 = 0x46;
+
+/// Byte a sender that waits for acknowledgements starts the connection with, before the byte of
+/// flow control, which is the last one before credits are granted. The receiver acknowledges the sequence number of every message it read over the
+/// other direction of the connection.
+pub const ACKNOWLEDGE: u8 = 0x41;
+
+/// Byte a sender that sends heartbeats starts the connection with, before the byte of
+/// acknowledgements. The receiver answers every heartbeat over the other direction of the
+/// connection.
+pub const HEARTBEAT: u8 = 0x42;
+
+/// Byte a sender that streams large messages starts the connection with, before the byte of
+/// heartbeats. The receiver acknowledges every chunk of a streamed message over the other
+/// direction of the connection.
+pub const STREAMING: u8 = 0x53;
+
+/// Credits of the nodes connected to a NetworkReceiver, shared with its workers. With flow
+/// control a sender only sends as many messages as it was granted credits, so Core can pace its
+/// peers by how fast it processes their messages.
+#[derive(Debug, Clone, Default)]
+pub struct Credits(Arc<Mutex<HashMap<SocketAddr, UnboundedSender<u32>>>>);
+
+impl Credits {
+    pub(crate) fn register(&self, node: SocketAddr, grants: UnboundedSender<u32>) {
+        self.0.lock().unwrap().insert(node, grants);
+    }
+
+    // Only forget the node if a newer connection didn't register in the meantime.
+    pub(crate) fn unregister(&self, node: &SocketAddr, grants: &UnboundedSender<u32>) {
+        let mut nodes = self.0.lock().unwrap();
+        if nodes
+            .get(node)
+            .is_some_and(|known| known.same_channel(grants))
+        {
+            nodes.remove(node);
+        }
+    }
+
+    /// Allow the node to send the given number of further messages. Returns false if the node
+    /// isn't connected or its connection doesn't use flow control.
+    pub fn grant(&self, node: &SocketAddr, credits: u32) -> bool {
+        match self.0.lock().unwrap().get(node) {
+            Some(grants) => grants.send(credits).is_ok(),
+            None => false,
+        }
+    }
+}
+
+// Credits, acknowledgements and answers to heartbeats travel as frames in the otherwise unused
+// direction from the receiver to the sender. A frame of four bytes holds the number of new
+// credits, one of eight bytes an acknowledged sequence number, one of twelve bytes the stream id
+// and index of an acknowledged chunk and an empty one answers a heartbeat.
+fn encode(credits: u32) -> Bytes {
+    Bytes::copy_from_slice(&credits.to_be_bytes())
+}
+
+fn encode_ack(seq: u64) -> Bytes {
+    Bytes::copy_from_slice(&seq.to_be_bytes())
+}
+
+fn encode_chunk_ack(ack: ChunkAck) -> Bytes {
+    let mut frame = Vec::with_capacity(12);
+    frame.extend_from_slice(&ack.message_id.to_be_bytes());
+    frame.extend_from_slice(&ack.index.to_be_bytes());
+    Bytes::from(frame)
+}
+
+enum Feedback {
+    Credits(u32),
+    Ack(u64),
+    ChunkAck(ChunkAck),
+    Heartbeat,
+}
+
+fn decode(frame: &[u8]) -> Option<Feedback> {
+    match frame.len() {
+        0 => Some(Feedback::Heartbeat),
+        4 => Some(Feedback::Credits(u32::from_be_bytes(
+            frame.try_into().ok()?,
+        ))),
+        8 => Some(Feedback::Ack(u64::from_be_bytes(frame.try_into().ok()?))),
+        12 => Some(Feedback::ChunkAck(ChunkAck {
+            message_id: u64::from_be_bytes(frame[..8].try_into().ok()?),
+            index: u32::from_be_bytes(frame[8..].try_into().ok()?),
+        })),
+        _ => None,
+    }
+}
+
+/// Ask the receiver for credits.
+pub(crate) async fn request_credits<W>(writer: &mut W) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&[FLOW_CONTROL]).await
+}
+
+/// Ask the receiver to acknowledge the chunks of streamed messages.
+pub(crate) async fn request_chunk_acks<W>(writer: &mut W) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&[STREAMING]).await
+}
+
+/// Ask the receiver to answer heartbeats.
+pub(crate) async fn request_heartbeats<W>(writer: &mut W) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&[HEARTBEAT]).await
+}
+
+/// Ask the receiver to acknowledge the messages.
+pub(crate) async fn request_acks<W>(writer: &mut W) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(&[ACKNOWLEDGE]).await
+}
+
+/// Returns whether the sender asked for credits, consuming its request.
+pub(crate) async fn credits_requested<R>(reader: &mut BufReader<R>) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    requested(reader, FLOW_CONTROL).await
+}
+
+/// Returns whether the sender asked for acknowledgements, consuming its request.
+pub(crate) async fn acks_requested<R>(reader: &mut BufReader<R>) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    requested(reader, ACKNOWLEDGE).await
+}
+
+/// Returns whether the sender asked for acknowledgements of chunks, consuming its request.
+pub(crate) async fn chunk_acks_requested<R>(reader: &mut BufReader<R>) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    requested(reader, STREAMING).await
+}
+
+/// Returns whether the sender asked for answers to its heartbeats, consuming its request.
+pub(crate) async fn heartbeats_requested<R>(reader: &mut BufReader<R>) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    requested(reader, HEARTBEAT).await
+}
+
+async fn requested<R>(reader: &mut BufReader<R>, request: u8) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    let requested = reader.fill_buf().await?.first() == Some(&request);
+    if requested {
+        reader.consume(1);
+    }
+    Ok(requested)
+}
+
+/// Write the initial credits and every further grant, the sequence numbers of acknowledged
+/// messages and chunks and the answers to heartbeats to the connection until the connection or
+/// every sender of them is gone. Without initial credits the connection doesn't use flow control
+/// and there are no grants, without acks or chunk_acks nothing of the kind is acknowledged and
+/// without heartbeats nothing is answered.
+pub(crate) fn spawn_credit_writer<W>(
+    writer: W,
+    initial: Option<u32>,
+    acks: bool,
+    chunk_acks: bool,
+    heartbeats: bool,
+) -> Replies
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let (grants, mut rx_grants) = match initial {
+        Some(initial) => {
+            let (tx, rx) = unbounded_channel();
+            let _ = tx.send(initial);
+            (Some(tx), Some(rx))
+        }
+        None => (None, None),
+    };
+    let (acks, mut rx_acks) = match acks {
+        true => {
+            let (tx, rx) = unbounded_channel();
+            (Some(tx), Some(rx))
+        }
+        false => (None, None),
+    };
+    let (chunk_acks, mut rx_chunk_acks) = match chunk_acks {
+        true => {
+            let (tx, rx) = unbounded_channel();
+            (Some(tx), Some(rx))
+        }
+        false => (None, None),
+    };
+    let (heartbeats, mut rx_heartbeats) = match heartbeats {
+        true => {
+            let (tx, rx) = unbounded_channel();
+            (Some(tx), Some(rx))
+        }
+        false => (None, None),
+    };
+    tokio::spawn(async move {
+        let mut writer = FramedWrite::new(writer, LengthDelimitedCodec::new());
+        loop {
+            let frame = tokio::select! {
+                Some(credits) = next(&mut rx_grants) => encode(credits),
+                Some(seq) = next(&mut rx_acks) => encode_ack(seq),
+                Some(ack) = next(&mut rx_chunk_acks) => encode_chunk_ack(ack),
+                Some(()) = next(&mut rx_heartbeats) => Bytes::new(),
+                else => break,
+            };
+            if writer.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+    Replies {
+        grants,
+        acks,
+        chunk_acks,
+        heartbeats,
+    }
+}
+
+/// Channels to the task of spawn_credit_writer, None for what the sender didn't ask for.
+#[derive(Default)]
+pub(crate) struct Replies {
+    pub grants: Option<UnboundedSender<u32>>,
+    pub acks: Option<UnboundedSender<u64>>,
+    pub chunk_acks: Option<UnboundedSender<ChunkAck>>,
+    pub heartbeats: Option<UnboundedSender<()>>,
+}
+
+// Next item of the channel, None if there is no channel or it is closed.
+async fn next<T>(rx: &mut Option<UnboundedReceiver<T>>) -> Option<T> {
+    rx.as_mut()?.recv().await
+}
+
+/// Read the grants of the receiver into the returned semaphore, one permit per credit, and the
+/// acknowledged sequence numbers, acknowledged chunks and answered heartbeats into the returned
+/// channels. The semaphore is closed once the connection is, so a sender waiting for credits
+/// gives up.
+pub(crate) fn spawn_credit_reader<R>(reader: R) -> Answers
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let credits = Arc::new(Semaphore::new(0));
+    let semaphore = credits.clone();
+    let (tx_acks, acks) = unbounded_channel();
+    let (tx_chunk_acks, chunk_acks) = unbounded_channel();
+    let (tx_heartbeats, heartbeats) = unbounded_channel();
+    let handle = tokio::spawn(async move {
+        let mut reader = FramedRead::new(reader, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = reader.next().await {
+            match decode(&frame) {
+                Some(Feedback::Credits(granted)) => semaphore.add_permits(granted as usize),
+                Some(Feedback::Ack(seq)) => {
+                    let _ = tx_acks.send(seq);
+                }
+                Some(Feedback::ChunkAck(ack)) => {
+                    let _ = tx_chunk_acks.send(ack);
+                }
+                Some(Feedback::Heartbeat) => {
+                    let _ = tx_heartbeats.send(());
+                }
+                None => {
+                    tracing::warn!(bytes = frame.len(), "invalid credit frame");
+                    break;
+                }
+            }
+        }
+        semaphore.close();
+    });
+    Answers {
+        credits,
+        acks,
+        chunk_acks,
+        heartbeats,
+        reader: handle,
+    }
+}
+
+/// What the task of spawn_credit_reader reads, and the task itself.
+pub(crate) struct Answers {
+    pub credits: Arc<Semaphore>,
+    pub acks: UnboundedReceiver<u64>,
+    pub chunk_acks: UnboundedReceiver<ChunkAck>,
+    pub heartbeats: UnboundedReceiver<()>,
+    pub reader: JoinHandle<()>,
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+};
+
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/dedup_tests.rs"]
+pub mod dedup_tests;
+
+/// Bounds of the cache of message ids the NetworkReceiver already delivered. Ids are only unique
+/// per sender, so the cache remembers them together with the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dedup {
+    // Maximum number of remembered ids, the oldest one is forgotten first.
+    pub capacity: usize,
+
+    // Ids are forgotten this long after they were first seen, so an id that is reused much later
+    // is delivered again.
+    pub ttl: Duration,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self {
+            capacity: 100_000,
+            ttl: Duration::from_secs(600),
+        }
+    }
+}
+
+// A message id together with the sender that picked it.
+type Key = (SocketAddr, u64);
+
+/// Ids of recently seen messages, bounded in number and age.
+#[derive(Debug)]
+pub struct DedupCache {
+    bounds: Dedup,
+
+    // Time each id was first seen.
+    seen: HashMap<Key, Instant>,
+
+    // The ids in the order they were first seen.
+    order: VecDeque<(Key, Instant)>,
+}
+
+impl DedupCache {
+    pub fn new(bounds: Dedup) -> Self {
+        Self {
+            bounds,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember the id of a message from the sender. Returns false if the sender already used it
+    /// within the TTL, i.e. the message is a duplicate.
+    pub fn insert(&mut self, sender: SocketAddr, id: u64, now: Instant) -> bool {
+        self.expire(now);
+        let key = (sender, id);
+        if self.seen.contains_key(&key) {
+            return false;
+        }
+        self.seen.insert(key, now);
+        self.order.push_back((key, now));
+        if self.order.len() > self.bounds.capacity.max(1) {
+            self.evict();
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, first)) = self.order.front() {
+            if now.duration_since(*first) < self.bounds.ttl {
+                break;
+            }
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Some((key, _)) = self.order.pop_front() {
+            self.seen.remove(&key);
+        }
+    }
+}
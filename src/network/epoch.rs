@@ -0,0 +1,54 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::message::NetworkMessage;
+
+/// The current epoch of Core, shared between Core and the workers of a NetworkReceiver. Messages
+/// from epochs that are more than the tolerance behind it are stale and dropped before they reach
+/// Core.
+#[derive(Debug, Clone)]
+pub struct EpochFilter {
+    current: Arc<AtomicU64>,
+    tolerance: u64,
+
+    // Messages dropped for being stale.
+    dropped: Arc<AtomicU64>,
+}
+
+impl EpochFilter {
+    pub fn new(current: u64, tolerance: u64) -> Self {
+        Self {
+            current: Arc::new(AtomicU64::new(current)),
+            tolerance,
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Move to the given epoch. The epoch never goes back, older epochs are ignored.
+    pub fn advance(&self, epoch: u64) {
+        self.current.fetch_max(epoch, Ordering::SeqCst);
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Number of messages dropped because their epoch was too old.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    // Returns false and counts the message if it has to be dropped. Messages without an epoch
+    // always pass.
+    pub(crate) fn admit(&self, message: &NetworkMessage) -> bool {
+        let stale = message
+            .epoch()
+            .is_some_and(|epoch| epoch.saturating_add(self.tolerance) < self.current());
+        if stale {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+        !stale
+    }
+}
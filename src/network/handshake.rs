@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+#[path = "tests/handshake_tests.rs"]
+pub mod handshake_tests;
+
+/// First byte of a handshake frame. No format tag of a message uses it.
+pub const HANDSHAKE: u8 = 0x48;
+
+/// Identity a sender announces in the first frame of a connection: its logical node id and the
+/// address it listens on. The remote address of a connection is an ephemeral port and tells the
+/// receiver neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub node_id: u64,
+    pub address: SocketAddr,
+}
+
+impl Hello {
+    pub fn new(node_id: u64, address: SocketAddr) -> Self {
+        Self { node_id, address }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut frame = BytesMut::new();
+        frame.put_u8(HANDSHAKE);
+        frame.extend_from_slice(&bincode::serialize(self).expect("a hello always serializes"));
+        frame.freeze()
+    }
+
+    /// Returns None if the frame isn't a well-formed handshake.
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        match frame.split_first() {
+            Some((&HANDSHAKE, body)) => bincode::deserialize(body).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Node ids of the nodes connected to a NetworkReceiver, by the address they listen on. Only
+/// nodes that sent a handshake are known.
+#[derive(Debug, Clone, Default)]
+pub struct NodeIds(Arc<Mutex<HashMap<SocketAddr, u64>>>);
+
+impl NodeIds {
+    pub(crate) fn record(&self, hello: Hello) {
+        self.0.lock().unwrap().insert(hello.address, hello.node_id);
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<u64> {
+        self.0.lock().unwrap().get(address).copied()
+    }
+
+    pub fn snapshot(&self) -> HashMap<SocketAddr, u64> {
+        self.0.lock().unwrap().clone()
+    }
+}
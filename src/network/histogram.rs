@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/histogram_tests.rs"]
+pub mod histogram_tests;
+
+// Every power of two is split into this many buckets, so a recorded value is off by less than
+// 1/16 of it. Values below twice the number are recorded exactly.
+const SUB_BUCKETS: u64 = 16;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+const BUCKETS: usize = ((65 - SUB_BITS) as u64 * SUB_BUCKETS) as usize;
+
+/// Histogram of u64 values with log-linear buckets, a lightweight take on HdrHistogram.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.counts[Self::bucket(value)] += 1;
+        self.count += 1;
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Value below or at which the given share of the recorded values lie, e.g. 0.99 for the
+    /// 99th percentile. It is the highest value of the bucket, but never above the maximum. Returns
+    /// 0 if nothing was recorded yet.
+    pub fn value_at_quantile(&self, quantile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::highest(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn percentiles(&self) -> Percentiles {
+        Percentiles {
+            count: self.count,
+            p50: self.value_at_quantile(0.5),
+            p90: self.value_at_quantile(0.9),
+            p99: self.value_at_quantile(0.99),
+            max: self.max,
+        }
+    }
+
+    fn bucket(value: u64) -> usize {
+        if value < 2 * SUB_BUCKETS {
+            return value as usize;
+        }
+        // Shift the value so its top bits fall between SUB_BUCKETS and twice that.
+        let shift = 64 - value.leading_zeros() - SUB_BITS - 1;
+        ((shift as u64 + 1) * SUB_BUCKETS + (value >> shift) - SUB_BUCKETS) as usize
+    }
+
+    // Highest value that is recorded in the bucket.
+    fn highest(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < 2 * SUB_BUCKETS {
+            return bucket;
+        }
+        let shift = bucket / SUB_BUCKETS - 1;
+        let lowest = (bucket % SUB_BUCKETS + SUB_BUCKETS) << shift;
+        lowest + ((1 << shift) - 1)
+    }
+}
+
+/// Summary of a histogram.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+// Histograms of the messages sent to a peer.
+#[derive(Debug, Clone, Default)]
+struct Flow {
+    // Size of the frames in bytes.
+    sizes: Histogram,
+
+    // Time between two messages for the peer being picked up by the NetworkSender, in
+    // microseconds.
+    inter_arrival: Histogram,
+
+    // Time it took to write a frame to the connection, in microseconds.
+    send_times: Histogram,
+
+    // Time from the submission of a message to the start of writing its frame, in microseconds.
+    time_to_first_byte: Histogram,
+
+    // Time the last message was picked up.
+    last: Option<Instant>,
+}
+
+/// Percentiles of the histograms of a peer: frame sizes in bytes, the time between two messages
+/// being picked up by the NetworkSender, the time it took to write a frame and the time to first
+/// byte, all in microseconds. The time to first byte runs from the submission of a message, see
+/// NetworkMessage::submit, to the start of writing its frame, so it covers the local queueing
+/// but not the network. A message that wasn't stamped counts from its pickup instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowSnapshot {
+    pub sizes: Percentiles,
+    pub inter_arrival: Percentiles,
+    pub send_times: Percentiles,
+    pub time_to_first_byte: Percentiles,
+}
+
+impl Flow {
+    fn snapshot(&self) -> FlowSnapshot {
+        FlowSnapshot {
+            sizes: self.sizes.percentiles(),
+            inter_arrival: self.inter_arrival.percentiles(),
+            send_times: self.send_times.percentiles(),
+            time_to_first_byte: self.time_to_first_byte.percentiles(),
+        }
+    }
+}
+
+/// Flow histograms per peer, shared between the workers of a NetworkSender.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFlows(Arc<Mutex<HashMap<SocketAddr, Flow>>>);
+
+impl PeerFlows {
+    // Record a message that was picked up at enqueued and took the given time to send.
+    pub(crate) fn record(&self, peer: SocketAddr, bytes: usize, enqueued: Instant, took: Duration) {
+        let mut flows = self.0.lock().unwrap();
+        let flow = flows.entry(peer).or_default();
+        flow.sizes.record(bytes as u64);
+        flow.send_times.record(took.as_micros() as u64);
+        // Retransmitted messages were picked up before the last one, they don't arrive anew.
+        match flow.last {
+            Some(last) if enqueued < last => (),
+            Some(last) => {
+                let gap = enqueued - last;
+                flow.inter_arrival.record(gap.as_micros() as u64);
+                flow.last = Some(enqueued);
+            }
+            None => flow.last = Some(enqueued),
+        }
+    }
+
+    // Record that the frame of a message submitted at the given time is about to be written.
+    pub(crate) fn first_byte(&self, peer: SocketAddr, submitted: Instant) {
+        let mut flows = self.0.lock().unwrap();
+        let delay = submitted.elapsed().as_micros() as u64;
+        flows
+            .entry(peer)
+            .or_default()
+            .time_to_first_byte
+            .record(delay);
+    }
+
+    /// Histograms of a single peer.
+    pub fn get(&self, peer: &SocketAddr) -> Option<FlowSnapshot> {
+        self.0.lock().unwrap().get(peer).map(Flow::snapshot)
+    }
+
+    /// Histograms of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, FlowSnapshot> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, flow)| (*peer, flow.snapshot()))
+            .collect()
+    }
+}
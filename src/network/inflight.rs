@@ -0,0 +1,36 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Number of messages buffered anywhere in a node: in the transmit channel, the queues of the
+/// workers, the retransmit backlog and the deliver channel. A message to several peers counts
+/// once per peer until it is sent, given up on or dropped. Received messages count until Core
+/// read them. Cloned handles share the same count.
+#[derive(Debug, Clone, Default)]
+pub struct Inflight(Arc<AtomicUsize>);
+
+impl Inflight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Count messages that were just enqueued.
+    pub fn add(&self, messages: usize) {
+        self.0.fetch_add(messages, Ordering::SeqCst);
+    }
+
+    /// Stop counting messages that reached their final disposition. The count never drops below
+    /// zero, so messages that were enqueued without being counted don't wrap it around.
+    pub fn remove(&self, messages: usize) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                Some(count.saturating_sub(messages))
+            });
+    }
+}
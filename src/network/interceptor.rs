@@ -0,0 +1,24 @@
+use std::sync::{Arc, Mutex};
+
+use crate::message::NetworkMessage;
+
+/// Hook that inspects or modifies a message, e.g. to add metadata or redact content.
+pub type Interceptor = Box<dyn FnMut(&mut NetworkMessage) + Send>;
+
+/// Ordered chain of interceptors, shared between a component and its workers.
+#[derive(Clone, Default)]
+pub struct Interceptors(Arc<Mutex<Vec<Interceptor>>>);
+
+impl Interceptors {
+    /// Append an interceptor, it runs after the ones added before.
+    pub fn add(&self, interceptor: Interceptor) {
+        self.0.lock().unwrap().push(interceptor);
+    }
+
+    /// Run every interceptor on the message, in the order they were added.
+    pub fn apply(&self, message: &mut NetworkMessage) {
+        for interceptor in self.0.lock().unwrap().iter_mut() {
+            interceptor(message);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc::Sender;
+
+use crate::message::{InboundMessage, NetworkMessage};
+use crate::network::Inflight;
+
+/// Deliver channels of the receivers running in this process, by address. A NetworkSender that
+/// shares the registry with them hands messages for these addresses straight to the receiving
+/// node instead of opening a connection, e.g. when a test runs the whole network in one process.
+#[derive(Debug, Clone, Default)]
+pub struct LocalRegistry(Arc<Mutex<HashMap<SocketAddr, Loopback>>>);
+
+impl LocalRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver messages for the given address to the given channel from now on, counting them
+    /// as in flight on the receiving node.
+    pub fn register(
+        &self,
+        address: SocketAddr,
+        deliver: Sender<InboundMessage>,
+        inflight: Inflight,
+    ) {
+        let node = Loopback::new(address, deliver, inflight);
+        self.0.lock().unwrap().insert(address, node);
+    }
+
+    pub fn unregister(&self, address: &SocketAddr) {
+        self.0.lock().unwrap().remove(address);
+    }
+
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        self.0.lock().unwrap().contains_key(address)
+    }
+
+    // Hand the message to the node at the address if it runs in this process. Returns false if
+    // it doesn't, or if it stopped reading its messages, in which case it is forgotten.
+    pub(crate) async fn deliver(&self, address: SocketAddr, message: NetworkMessage) -> bool {
+        let node = match self.0.lock().unwrap().get(&address) {
+            Some(node) => node.clone(),
+            None => return false,
+        };
+        if !node.deliver(message).await {
+            self.unregister(&address);
+            return false;
+        }
+        true
+    }
+}
+
+/// A node that gets messages handed over within the process: its address, the deliver channel
+/// of its receiver and the count of its messages in flight.
+#[derive(Debug, Clone)]
+pub struct Loopback {
+    pub address: SocketAddr,
+    pub deliver: Sender<InboundMessage>,
+    pub inflight: Inflight,
+}
+
+impl Loopback {
+    pub fn new(address: SocketAddr, deliver: Sender<InboundMessage>, inflight: Inflight) -> Self {
+        Self {
+            address,
+            deliver,
+            inflight,
+        }
+    }
+
+    // Put the message into the deliver channel, as if it arrived from its sender. Returns false
+    // if the node stopped reading its messages.
+    pub(crate) async fn deliver(&self, message: NetworkMessage) -> bool {
+        self.inflight.add(1);
+        let message = InboundMessage {
+            peer: message.sender,
+            message,
+        };
+        if self.deliver.send(message).await.is_err() {
+            self.inflight.remove(1);
+            return false;
+        }
+        true
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/logging_tests.rs"]
+pub mod logging_tests;
+
+/// Coalesces warnings that can come in floods, e.g. connect failures while a peer is down. The
+/// first occurrence of an event for a peer is logged, further ones during the window are only
+/// counted and summed up by the first occurrence after the window. Cloned handles share the
+/// counts.
+#[derive(Debug, Clone, Default)]
+pub struct LogLimiter {
+    // None logs every occurrence.
+    window: Option<Duration>,
+    events: Arc<Mutex<HashMap<(&'static str, SocketAddr), Occurrences>>>,
+}
+
+// Occurrences of an event for a peer since the last one that was logged.
+#[derive(Debug)]
+struct Occurrences {
+    since: Instant,
+    suppressed: u64,
+}
+
+impl LogLimiter {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            events: Arc::default(),
+        }
+    }
+
+    /// Log the message at warn level, unless the event already occurred for the peer during the
+    /// window. Event names the occurrences in the summary, e.g. "connect failures".
+    pub fn warn(&self, event: &'static str, peer: SocketAddr, message: impl fmt::Display) {
+        let window = match self.window {
+            Some(window) => window,
+            None => {
+                tracing::warn!(%peer, "{}", message);
+                return;
+            }
+        };
+        let now = Instant::now();
+        let mut events = self.events.lock().unwrap();
+        match events.get_mut(&(event, peer)) {
+            Some(occurrences) if now.duration_since(occurrences.since) < window => {
+                occurrences.suppressed += 1;
+                return;
+            }
+            Some(occurrences) => {
+                if occurrences.suppressed > 0 {
+                    tracing::warn!(
+                        %peer,
+                        "{} {} for {} in the last {:?}",
+                        occurrences.suppressed,
+                        event,
+                        peer,
+                        now.duration_since(occurrences.since)
+                    );
+                }
+                occurrences.since = now;
+                occurrences.suppressed = 0;
+            }
+            None => {
+                let occurrences = Occurrences {
+                    since: now,
+                    suppressed: 0,
+                };
+                events.insert((event, peer), occurrences);
+            }
+        }
+        tracing::warn!(%peer, "{}", message);
+    }
+}
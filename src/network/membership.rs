@@ -0,0 +1,47 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Changes the peers a NetworkSender sends to while it runs. Every peer is a member until it is
+/// removed. Messages to a removed peer are dropped from the moment remove_peer returns, and its
+/// worker is shut down, which still writes what it had queued before. Cloned handles change the
+/// same sender.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    removed: Arc<Mutex<HashSet<SocketAddr>>>,
+
+    // Tells the NetworkSender which peer changed, so it can shut down or forget its worker.
+    changes: UnboundedSender<SocketAddr>,
+}
+
+impl Membership {
+    pub(crate) fn new() -> (Self, UnboundedReceiver<SocketAddr>) {
+        let (changes, rx) = unbounded_channel();
+        let membership = Self {
+            removed: Arc::default(),
+            changes,
+        };
+        (membership, rx)
+    }
+
+    /// Let messages through to the peer again, with its connect failures forgotten.
+    pub fn add_peer(&self, address: SocketAddr) {
+        if self.removed.lock().unwrap().remove(&address) {
+            let _ = self.changes.send(address);
+        }
+    }
+
+    pub fn remove_peer(&self, address: SocketAddr) {
+        if self.removed.lock().unwrap().insert(address) {
+            let _ = self.changes.send(address);
+        }
+    }
+
+    pub fn is_member(&self, address: &SocketAddr) -> bool {
+        !self.removed.lock().unwrap().contains(address)
+    }
+}
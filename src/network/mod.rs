@@ -1,3 +1,66 @@
+mod ack;
+#[cfg(feature = "hmac")]
+mod auth;
+mod batch;
+mod channel;
+mod codec;
+mod config;
+mod credit;
+mod dedup;
+mod epoch;
+mod handshake;
+#[cfg(feature = "histograms")]
+mod histogram;
+mod inflight;
+mod interceptor;
+mod local;
+mod logging;
+mod membership;
+#[allow(clippy::module_inception)]
 mod network;
+mod observer;
+mod ordering;
+mod queue;
+mod quota;
+mod ready;
+mod request;
+mod rtt;
+mod scheduler;
+mod stats;
+mod stream;
+mod tls;
+mod transport;
+mod workers;
 
-pub use crate::network::network::*;
\ No newline at end of file
+pub use crate::network::ack::*;
+#[cfg(feature = "hmac")]
+pub use crate::network::auth::*;
+pub use crate::network::batch::*;
+pub use crate::network::channel::*;
+pub use crate::network::codec::*;
+pub use crate::network::config::*;
+pub use crate::network::credit::*;
+pub use crate::network::dedup::*;
+pub use crate::network::epoch::*;
+pub use crate::network::handshake::*;
+#[cfg(feature = "histograms")]
+pub use crate::network::histogram::*;
+pub use crate::network::inflight::*;
+pub use crate::network::interceptor::*;
+pub use crate::network::local::*;
+pub use crate::network::logging::*;
+pub use crate::network::membership::*;
+pub use crate::network::network::*;
+pub use crate::network::observer::*;
+pub use crate::network::ordering::*;
+pub use crate::network::queue::*;
+pub use crate::network::quota::*;
+pub use crate::network::ready::*;
+pub use crate::network::request::*;
+pub use crate::network::rtt::*;
+pub use crate::network::scheduler::*;
+pub use crate::network::stats::*;
+pub use crate::network::stream::*;
+pub use crate::network::tls::*;
+pub use crate::network::transport::*;
+pub use crate::network::workers::*;
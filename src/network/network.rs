@@ -1,45 +1,414 @@
-use crate::message::NetworkMessage;
-use bytes::Bytes;
+use crate::message::{
+    ChunkAck, DeliveryFailed, DeliveryOutcome, DeliveryReceipt, InboundMessage, MessageKind,
+    NetworkMessage, Payload, PeerUnreachable, MESSAGE_ID,
+};
+#[cfg(feature = "histograms")]
+use crate::network::PeerFlows;
+use crate::network::{
+    acks_requested, batch_len, bounded, chunk_acks_requested, credits_requested, encode_batch,
+    encode_chunk, encode_frame, encode_frame_compressed, frame_reader_with_limit,
+    frame_writer_with_limit, heartbeats_requested, hex_dump, request_acks, request_chunk_acks,
+    request_credits, request_heartbeats, set_user_timeout, spawn_credit_reader,
+    spawn_credit_writer, unbatch, Admission, Backoff, Bandwidth, Batching, ClientTls, CodecError,
+    Codecs, ConnectScheduler, ConnectionLimit, Credits, Decoded, DedupCache, Dialing,
+    DuplicatePolicy, EarlyPolicy, EpochFilter, FrameWriter, Hello, HighWaterMarks, Inflight,
+    Interceptors, LogLimiter, Membership, NetworkEvent, NetworkStats, NodeIds, NoopSink,
+    ObserverSink, OutstandingFrames, OverflowPolicy, Pacer, PeerConfig, PeerDelays, PeerEvent,
+    PeerLinks, PeerReorder, PeerRtts, PeerTopics, Push, QueueReceiver, QueueSender, RateLimits,
+    Readiness, ReceiverConfig, Replies, RetransmitOrder, SenderConfig, ServerTls, Shutdown,
+    SkippedSequences, Socket, Stream, StreamSender, Streams, TopicSequences, Unacked,
+    UnknownPolicy, WeightedRoundRobin, Workers, CHUNK, MAX_FRAME_LENGTH,
+};
+#[cfg(feature = "hmac")]
+use crate::network::{FrameAuth, TAG_LENGTH};
+use bytes::{Bytes, BytesMut};
 use futures::{stream::futures_unordered::FuturesUnordered, SinkExt, StreamExt};
-use std::{collections::HashMap, net::SocketAddr};
-use tokio::sync::oneshot;
-use tokio::time::{sleep, Duration};
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+use tokio::io::{split, AsyncRead, BufReader};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot, Notify, OwnedSemaphorePermit, Semaphore,
+};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{error::Elapsed, sleep, sleep_until, timeout, timeout_at, Duration, Instant};
 use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::mpsc::{channel, Receiver, Sender},
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::mpsc::{
+        error::{SendError, TryRecvError},
+        Receiver, Sender,
+    },
 };
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::{Framed, LinesCodec};
+use tracing::Instrument;
 
 #[cfg(test)]
 #[path = "tests/network_tests.rs"]
+#[allow(clippy::assertions_on_constants, clippy::clone_on_copy)]
 pub mod network_tests;
 
+/// A message on its way to a single peer, together with the number of failed attempts so far.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub message: NetworkMessage,
+    pub address: SocketAddr,
+    pub attempts: usize,
+
+    // Time the NetworkSender picked up the message.
+    pub enqueued: Instant,
+
+    // Position among the messages to a FIFO peer, None for other peers.
+    pub seq: Option<u64>,
+
+    // Reports the outcome of a tracked message, shared by its deliveries to every recipient.
+    pub(crate) completion: Option<Arc<Completion>>,
+}
+
+impl Delivery {
+    pub fn new(message: NetworkMessage, address: SocketAddr) -> Self {
+        Self {
+            message,
+            address,
+            attempts: 0,
+            enqueued: Instant::now(),
+            seq: None,
+            completion: None,
+        }
+    }
+
+    pub fn receipt(&self, outcome: DeliveryOutcome) -> DeliveryReceipt {
+        DeliveryReceipt {
+            message_id: self.message.id(),
+            peer: self.address,
+            outcome,
+        }
+    }
+
+    /// Whether the time to live of the message passed since the NetworkSender picked it up.
+    pub fn expired(&self) -> bool {
+        let ttl = self.message.ttl();
+        ttl.is_some_and(|ttl| self.enqueued.elapsed() >= ttl)
+    }
+
+    // Count the delivery towards the outcome of its message, if it is tracked.
+    pub(crate) fn complete(&self, outcome: DeliveryOutcome) {
+        if let Some(completion) = &self.completion {
+            completion.settled(outcome);
+        }
+    }
+}
+
+/// A message together with the notifier of its outcome, see SenderHandle::send_tracked. A
+/// message to several peers has a single outcome: Sent once it was sent to all of them,
+/// otherwise the first outcome that wasn't Sent. The notifier is dropped without an outcome if
+/// the message is lost, e.g. because the sender shut down or the retransmitter saved it to its
+/// backlog.
+#[derive(Debug)]
+pub struct Tracked {
+    pub message: NetworkMessage,
+    pub outcome: oneshot::Sender<DeliveryOutcome>,
+}
+
+// The deliveries of a tracked message that didn't settle yet, the outcome so far and the notifier.
+#[derive(Debug)]
+pub(crate) struct Completion(
+    Mutex<(
+        usize,
+        DeliveryOutcome,
+        Option<oneshot::Sender<DeliveryOutcome>>,
+    )>,
+);
+
+impl Completion {
+    fn new(deliveries: usize, notify: oneshot::Sender<DeliveryOutcome>) -> Arc<Self> {
+        let completion = Arc::new(Self(Mutex::new((
+            deliveries,
+            DeliveryOutcome::Sent,
+            Some(notify),
+        ))));
+        // A message without recipients has nothing left to do.
+        if deliveries == 0 {
+            completion.report();
+        }
+        completion
+    }
+
+    fn settled(&self, outcome: DeliveryOutcome) {
+        let done = {
+            let mut state = self.0.lock().unwrap();
+            state.0 = state.0.saturating_sub(1);
+            if state.1 == DeliveryOutcome::Sent {
+                state.1 = outcome;
+            }
+            state.0 == 0
+        };
+        if done {
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(notify) = state.2.take() {
+            let _ = notify.send(state.1);
+        }
+    }
+}
+
+/// Settings for the NetworkRetransmitter.
+#[derive(Debug, Clone)]
+pub struct RetransmitPolicy {
+    // Give up on a message after this many failed attempts. None retries forever.
+    pub max_attempts: Option<usize>,
+
+    // Delays between the retransmissions of a message.
+    pub backoff: Backoff,
+
+    // Share of the backoff delay by which a retransmission is randomly moved in either
+    // direction, so the nodes that lost the same peer don't all retry at the same instant.
+    pub jitter: f64,
+
+    // Messages that may wait for their retransmission at the same time. Once there are more, the
+    // one that has waited longest is given up on and reported like one that ran out of attempts,
+    // with a Dropped receipt. None keeps every message, which a long outage of a busy peer can
+    // turn into a lot of memory.
+    pub max_pending: Option<usize>,
+
+    // File the messages that are still waiting to be retransmitted are saved to when the
+    // retransmitter shuts down. They are loaded from it again on startup, so a restart doesn't
+    // lose them. None keeps them in memory only.
+    pub backlog: Option<PathBuf>,
+
+    // Receives the retransmit and failure events.
+    pub observer: Arc<dyn ObserverSink>,
+
+    // Gets a receipt for every message that is given up on, usually the receipts channel of the
+    // NetworkSender.
+    pub receipts: Option<Sender<DeliveryReceipt>>,
+
+    // Releases the messages to FIFO peers that waited for a message that is given up on. Has to
+    // be shared with the NetworkSender.
+    pub order: RetransmitOrder,
+
+    // Stops counting the messages that are given up on, usually shared with the NetworkSender.
+    pub inflight: Inflight,
+
+    // Once triggered the retransmitter stops like when its channel is closed, saving the
+    // backlog.
+    pub shutdown: Shutdown,
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            backoff: Backoff::default(),
+            jitter: 0.3,
+            max_pending: Some(10_000),
+            backlog: None,
+            observer: Arc::new(NoopSink),
+            receipts: None,
+            inflight: Inflight::default(),
+            order: RetransmitOrder::default(),
+            shutdown: Shutdown::default(),
+        }
+    }
+}
+
+impl RetransmitPolicy {
+    /// Delay before the retransmission of a message that failed the given number of times: the
+    /// backoff delay, moved by up to the jitter share of it.
+    pub fn delay(&self, attempts: usize) -> Duration {
+        let delay = self.backoff.delay(attempts);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter <= 0.0 {
+            return delay;
+        }
+        delay.mul_f64(thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+    }
+}
+
 pub struct NetworkRetransmitter;
 
 impl NetworkRetransmitter {
-    pub fn run(mut rx: Receiver<(NetworkMessage, SocketAddr)>, tx: Sender<NetworkMessage>) {
+    pub fn run(rx: Receiver<Delivery>, tx: Sender<Delivery>) -> JoinHandle<()> {
+        Self::run_with_policy(rx, tx, RetransmitPolicy::default(), None)
+    }
+
+    // Messages that are given up on are reported to the optional events channel as
+    // NetworkEvent::DeliveryFailed. The retransmitter shuts down once every sender of the rx
+    // channel is dropped, or if the tx channel is closed.
+    pub fn run_with_policy(
+        mut rx: Receiver<Delivery>,
+        tx: Sender<Delivery>,
+        policy: RetransmitPolicy,
+        events: Option<UnboundedSender<NetworkEvent>>,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
+            // Messages waiting for their delay to pass, by id, so the oldest comes first.
+            let mut backlog = BTreeMap::new();
             let mut pending = FuturesUnordered::new();
+            let mut next_id = 0u64;
+
+            // Resume the retransmissions of the last run.
+            if let Some(path) = &policy.backlog {
+                for delivery in Self::load(path) {
+                    pending.push(Self::delay(next_id, policy.delay(delivery.attempts)));
+                    backlog.insert(next_id, delivery);
+                    next_id += 1;
+                }
+            }
+
             loop {
                 tokio::select! {
-                    Some((mes, addr)) = rx.recv() => {
-                        println!("Incoming message, addr: {}", addr.clone());
-                        let new_message = NetworkMessage {
-                            sender: mes.sender,
-                            addresses: vec![addr],
-                            message: mes.message.clone(),
+                    delivery = rx.recv() => {
+                        let mut delivery = match delivery {
+                            Some(delivery) => delivery,
+                            None => break,
+                        };
+                        if delivery.expired() {
+                            tracing::warn!(peer = %delivery.address, "dropping expired message");
+                            Self::give_up(delivery, DeliveryOutcome::Expired, &policy, &tx, &events)
+                                .await;
+                            continue;
+                        }
+                        tracing::debug!(peer = %delivery.address, "retransmitting message");
+                        delivery.attempts += 1;
+                        if policy.max_attempts.is_some_and(|max| delivery.attempts >= max) {
+                            tracing::warn!(
+                                peer = %delivery.address,
+                                attempts = delivery.attempts,
+                                "giving up on message"
+                            );
+                            Self::give_up(delivery, DeliveryOutcome::Dropped, &policy, &tx, &events)
+                                .await;
+                            continue;
+                        }
+                        policy.observer.retransmit(delivery.address, delivery.attempts);
+                        delivery.message.addresses = vec![delivery.address];
+                        pending.push(Self::delay(next_id, policy.delay(delivery.attempts)));
+                        backlog.insert(next_id, delivery);
+                        next_id += 1;
+
+                        if policy.max_pending.is_some_and(|max| backlog.len() > max) {
+                            let (_, oldest) = backlog.pop_first().unwrap();
+                            tracing::warn!(
+                                peer = %oldest.address,
+                                "too many pending retransmits, dropping oldest message"
+                            );
+                            Self::give_up(oldest, DeliveryOutcome::Dropped, &policy, &tx, &events)
+                                .await;
+                        }
+                    }
+                    _ = policy.shutdown.wait() => break,
+                    Some(id) = pending.next() => {
+                        // The message was dropped while it waited.
+                        let delivery = match backlog.remove(&id) {
+                            Some(delivery) => delivery,
+                            None => continue,
                         };
-                        pending.push(Self::delay(new_message));
+                        // It may have expired while it waited.
+                        if delivery.expired() {
+                            tracing::warn!(peer = %delivery.address, "dropping expired message");
+                            Self::give_up(delivery, DeliveryOutcome::Expired, &policy, &tx, &events)
+                                .await;
+                            continue;
+                        }
+                        if let Err(SendError(delivery)) = tx.send(delivery).await {
+                            backlog.insert(id, delivery);
+                            break;
+                        }
                     }
-                    Some(mes) = pending.next() => tx.send(mes).await.unwrap(),
                 }
             }
-        });
+
+            if let Some(path) = &policy.backlog {
+                Self::save(path, backlog.into_values());
+            }
+        })
+    }
+
+    // Report a message that won't be retransmitted anymore and release the messages to its peer
+    // that waited for it.
+    async fn give_up(
+        delivery: Delivery,
+        outcome: DeliveryOutcome,
+        policy: &RetransmitPolicy,
+        tx: &Sender<Delivery>,
+        events: &Option<UnboundedSender<NetworkEvent>>,
+    ) {
+        policy.observer.failed(delivery.address);
+        policy.inflight.remove(1);
+        delivery.complete(outcome);
+        for released in policy.order.give_up(&delivery) {
+            let _ = tx.send(released).await;
+        }
+        if let Some(receipts) = &policy.receipts {
+            let _ = receipts.send(delivery.receipt(outcome)).await;
+        }
+        if let Some(events) = events {
+            let _ = events.send(NetworkEvent::DeliveryFailed(DeliveryFailed {
+                message: delivery.message,
+                peer: delivery.address,
+            }));
+        }
+    }
+
+    async fn delay(id: u64, delay: Duration) -> u64 {
+        sleep(delay).await;
+        id
     }
 
-    async fn delay(message: NetworkMessage) -> NetworkMessage {
-        sleep(Duration::from_millis(30)).await;
-        message
+    // Read the backlog saved by a previous run and remove the file, so the messages aren't
+    // retransmitted twice.
+    fn load(path: &Path) -> Vec<Delivery> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                tracing::error!(error = %e, "failed to read retransmit backlog");
+                return Vec::new();
+            }
+        };
+        let _ = std::fs::remove_file(path);
+        match bincode::deserialize::<Vec<(NetworkMessage, SocketAddr, usize)>>(&bytes) {
+            Ok(saved) => saved
+                .into_iter()
+                .map(|(message, address, attempts)| Delivery {
+                    attempts,
+                    ..Delivery::new(message, address)
+                })
+                .collect(),
+            Err(e) => {
+                tracing::error!(error = %e, "failed to decode retransmit backlog");
+                Vec::new()
+            }
+        }
+    }
+
+    fn save(path: &Path, deliveries: impl Iterator<Item = Delivery>) {
+        let saved = deliveries
+            .map(|delivery| (delivery.message, delivery.address, delivery.attempts))
+            .collect::<Vec<_>>();
+        let bytes = match bincode::serialize(&saved) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to encode retransmit backlog");
+                return;
+            }
+        };
+        if let Err(e) = std::fs::write(path, bytes) {
+            tracing::error!(error = %e, "failed to write retransmit backlog");
+        }
     }
 }
 
@@ -47,196 +416,2783 @@ pub struct NetworkSender {
     // Channel for communication between NetworkSender and other threads.
     transmit: Receiver<NetworkMessage>,
 
+    // Channel where the NetworkRetransmitter hands back messages that should be sent again.
+    retries: Receiver<Delivery>,
+
+    // Messages whose outcome is reported to their submitter, see submit_tracked.
+    tracked: Option<Receiver<Tracked>>,
+
+    config: SenderConfig,
+
+    // Applied to every new message before it is serialized.
+    interceptors: Interceptors,
+
+    // Notified when a peer is marked as unreachable.
+    unreachable: Option<Sender<PeerUnreachable>>,
+
+    // State shared with the workers.
+    shared: Shared,
+
+    // Queues of the running workers by peer.
+    senders: HashMap<SocketAddr, Pool>,
+
+    // Tasks of the running workers by peer.
+    workers: Workers,
+
+    // The peers that were removed, and the addresses of the peers that were added or removed
+    // since the last look.
+    membership: Membership,
+    changes: UnboundedReceiver<SocketAddr>,
+
+    // Set once the peers of Dialing::Eager or Dialing::Paired were dialed.
+    warmed_up: Readiness,
+
+    // Nodes that connected to the receiver of this node, see Dialing::Paired.
+    accepted: NodeIds,
+}
+
+// State shared between the NetworkSender and its workers.
+#[derive(Clone)]
+struct Shared {
     // Channel for communication between NetworkSender and NetworkRetransmitter
-    retransmit: Sender<(NetworkMessage, SocketAddr)>,
+    retransmit: Sender<Delivery>,
+
+    // Limits concurrent connection attempts of all workers.
+    scheduler: ConnectScheduler,
+
+    // Time messages spent waiting before a worker sent them, per peer, counted from their pickup
+    // and from their submission.
+    queue_delays: PeerDelays,
+    first_bytes: PeerDelays,
+
+    // Address that currently works for a peer, either its own or its fallback address.
+    routes: Routes,
+
+    // Messages of at least this size are compressed.
+    compression_threshold: Option<usize>,
+
+    // Bytes sent per peer, limited by the quota.
+    bandwidth: Bandwidth,
+
+    // Token buckets of the peers with a rate limit.
+    rates: RateLimits,
+
+    // Number of bytes of every frame to log, None disables logging frames.
+    frame_dump: Option<usize>,
+
+    // Receives the events of the workers.
+    observer: Arc<dyn ObserverSink>,
+
+    // How connections are upgraded to TLS.
+    tls: ClientTls,
+
+    // Signs every written frame.
+    #[cfg(feature = "hmac")]
+    auth: Option<FrameAuth>,
+
+    // Uptime and flap rate of the connection to each peer.
+    links: PeerLinks,
+
+    // Gets a receipt for every sent or dropped message.
+    receipts: Option<Sender<DeliveryReceipt>>,
+
+    // Gets the connects and disconnects of the workers.
+    events: Option<UnboundedSender<NetworkEvent>>,
+
+    // TCP_USER_TIMEOUT of the connections.
+    user_timeout: Option<Duration>,
+
+    // Whether TCP_NODELAY is set on the connections.
+    nodelay: bool,
+
+    // Time after which a connection attempt fails.
+    connect_timeout: Duration,
+
+    // Reconnects of a worker whose connection broke before it gives up, and the delays between
+    // them.
+    reconnects: usize,
+    reconnect_backoff: Backoff,
+
+    // Coalesces the warnings about failed connects and sends.
+    log: LogLimiter,
+
+    // Longer frames aren't sent.
+    max_frame_length: usize,
+
+    // Messages that are neither sent nor dropped yet.
+    inflight: Inflight,
+
+    // Histograms of the messages sent to each peer.
+    #[cfg(feature = "histograms")]
+    flows: PeerFlows,
+
+    // Keeps retransmits to FIFO peers in order.
+    order: RetransmitOrder,
+
+    // Highest sequence number written to each peer, None doesn't number the messages.
+    sent: Option<HighWaterMarks>,
+
+    // Sent as the first frame of every connection.
+    hello: Option<Hello>,
+
+    // Messages, failures and connections, in total and per peer.
+    stats: Arc<NetworkStats>,
+
+    // Numbers the messages of every topic per peer, once they passed the checks of encode.
+    topics: TopicSequences,
+
+    // Messages that wait for the acknowledgement of their peer, and the sequence numbers of
+    // those that were retransmitted because they weren't acknowledged in time.
+    unacked: Unacked,
+    skipped: SkippedSequences,
+
+    // Round trip times estimated from the acknowledgements, per peer.
+    rtts: PeerRtts,
 }
 
-impl NetworkSender {
-    pub fn new(
-        transmit: Receiver<NetworkMessage>,
-        retransmit: Sender<(NetworkMessage, SocketAddr)>,
-    ) -> Self {
-        Self {
-            transmit,
-            retransmit,
+impl Shared {
+    // Hand the delivery to the retransmitter. Later messages to a FIFO peer wait for it.
+    async fn retry(&self, delivery: Delivery) -> Result<(), SendError<Delivery>> {
+        self.order.retransmitting(&delivery);
+        self.retransmit.send(delivery).await
+    }
+
+    // Like retry, but once the retransmitter is gone, e.g. during shutdown, the delivery is
+    // dropped instead.
+    async fn retry_or_drop(&self, delivery: Delivery) {
+        if let Err(SendError(delivery)) = self.retry(delivery).await {
+            tracing::warn!(peer = %delivery.address, "retransmitter is gone, dropping message");
+            self.settle(&delivery, DeliveryOutcome::Failed).await;
         }
     }
 
-    // Kepp one TCP connection per peer, handled by a seperate thread. Communication is done via
-    // dedicated channels for every worker.
-    pub async fn run(&mut self) {
-        // Keep track of workers. Maps socket address to sender channel for worker.
-        let mut senders = HashMap::<SocketAddr, Sender<NetworkMessage>>::new();
-
-        // Receive messages from channel.
-        while let Some(m) = self.transmit.recv().await {
-            for address in &m.addresses {
-                // Look up socket address of receiver in hash map.
-                let spawn = match senders.get(&address) {
-                    // If entry in hash map exists use the channel to send the message to the worker. If
-                    // there is an error with the channel spawn a new worker for the receiver socket
-                    // address.
-                    Some(tx) => tx.send(m.clone()).await.is_err(),
-                    // If there is no entry spawn a new worker for the receiver socket address.
-                    None => true,
-                };
+    fn peer_event(&self, event: PeerEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(NetworkEvent::Peer(event));
+        }
+    }
 
-                if spawn {
-                    // Spawn a new worker for the receiver socket address.
-                    let (tx_ok, rx_ok) = oneshot::channel();
-                    let tx = Self::spawn_worker(*address, self.retransmit.clone(), tx_ok).await;
+    // Time at which the oldest unacknowledged message to the peer times out, together with the
+    // timeout of the peer, resolved with its current round trip time.
+    fn ack_deadline(&self, address: SocketAddr, peer: &PeerConfig) -> Option<(Instant, Duration)> {
+        let timeout = self.rtts.resolve(&address, peer.ack_timeout.as_ref()?);
+        Some((self.unacked.deadline(address, timeout)?, timeout))
+    }
 
-                    let mut retransmit = false;
+    // Append the authentication tag to a frame that is about to be written.
+    fn sign(&self, frame: Bytes) -> Bytes {
+        #[cfg(feature = "hmac")]
+        if let Some(auth) = &self.auth {
+            return auth.sign(&frame);
+        }
+        frame
+    }
 
-                    match rx_ok.await {
-                        Ok(res) => {
-                            match res {
-                                true => {
-                                    // Send the new worker the message via a channel.
-                                    if let Ok(()) = tx.send(m.clone()).await {
-                                        // If sending was successful put the channel into the hash map.
-                                        senders.insert(*address, tx);
-                                    }
-                                }
-                                false => {
-                                    println!("Worker failed to connect");
-                                    retransmit = true;
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            println!("Failed to spawn worker");
-                            retransmit = true;
-                        }
-                    }
+    // Longest encoded message or batch that still fits into a frame with its tag.
+    fn max_payload_length(&self) -> usize {
+        #[cfg(feature = "hmac")]
+        if self.auth.is_some() {
+            return self.max_frame_length.saturating_sub(TAG_LENGTH);
+        }
+        self.max_frame_length
+    }
 
-                    if retransmit {
-                        self.retransmit
-                            .send((m.clone(), address.clone()))
-                            .await
-                            .unwrap();
-                    }
-                }
-            }
+    // The delivery is done: stop counting it and report how it ended.
+    async fn settle(&self, delivery: &Delivery, outcome: DeliveryOutcome) {
+        self.inflight.remove(1);
+        delivery.complete(outcome);
+        if let Some(receipts) = &self.receipts {
+            let _ = receipts.send(delivery.receipt(outcome)).await;
         }
     }
+}
 
-    async fn spawn_worker(
-        address: SocketAddr,
-        retransmit: Sender<(NetworkMessage, SocketAddr)>,
-        ok: oneshot::Sender<bool>,
-    ) -> Sender<NetworkMessage> {
-        // Create channel for communication with NetworkSender.
-        let (tx, mut rx): (Sender<NetworkMessage>, Receiver<NetworkMessage>) = channel(10_000);
+// Maps the address of a peer to the address it was last reached at.
+type Routes = Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>;
 
-        tokio::spawn(async move {
-            // Connect to provided socket address.
-            let stream = match TcpStream::connect(address).await {
-                Ok(stream) => {
-                    println!("Outgoing connection established with {}", address);
-                    let _ = ok.send(true);
-                    stream
-                }
-                // If the connection fails return. This means this worker thread is killed. Therefore
-                // using the above created channel will fail. Because of this a new worker will be
-                // spawned by the NetworkSender.
-                Err(e) => {
-                    println!("Failed to connect to {}: {}", address, e);
-                    let _ = ok.send(false);
-                    return;
-                }
-            };
+// The other direction of a connection with flow control, acknowledgements, streaming or
+// heartbeats: the credits granted by the peer, the sequence numbers and chunks it acknowledged,
+// its answers to heartbeats and the task reading them.
+struct Feedback {
+    credits: Option<Arc<Semaphore>>,
+    acks: Option<UnboundedReceiver<u64>>,
+    chunk_acks: Option<UnboundedReceiver<ChunkAck>>,
+    heartbeats: Option<UnboundedReceiver<()>>,
+    reader: JoinHandle<()>,
+}
 
-            // Frame the TCP stream.
-            let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+// The reader holds on to its half of the connection, it is stopped with the worker, also when
+// the worker is aborted.
+impl Drop for Feedback {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
 
-            // Continuously listen to messages passed to the above created channel.
-            while let Some(message) = rx.recv().await {
-                // Serialize message
-                let bytes = Bytes::from(bincode::serialize(&message).expect("Failed to serialize"));
+// What the worker of a peer does next.
+enum Next {
+    Deliver(Box<Delivery>),
+    Heartbeat,
+    // The NetworkSender closed the queue of the worker.
+    Close,
+}
 
-                // Send the message to the nework
-                match transport.send(bytes).await {
-                    Ok(_) => println!("Successfully sent message to {}", address),
-                    Err(e) => {
-                        println!("Failed to send message to {}: {}", address, e);
-                        retransmit
-                            .send((message.clone(), address.clone()))
-                            .await
-                            .unwrap();
-                        return;
-                    }
-                }
-            }
-        });
-        tx
-    }
+// Queues of the workers of a peer, one per connection, see PeerConfig::pool_size. Messages with
+// an affinity key stick to one connection, the others are spread by how fast the connections
+// send. The queue of a worker that died is removed as soon as it turns out to be closed.
+#[derive(Debug)]
+struct Pool {
+    queues: Vec<Option<QueueSender<Delivery>>>,
+    scheduler: Arc<Mutex<WeightedRoundRobin>>,
 }
 
-pub struct NetworkReceiver {
-    // Our own network address.
-    address: SocketAddr,
+impl Pool {
+    fn new(size: usize) -> Self {
+        Self {
+            queues: (0..size.max(1)).map(|_| None).collect(),
+            scheduler: Arc::new(Mutex::new(WeightedRoundRobin::new(size))),
+        }
+    }
 
-    // Channel where received messages are put in.
-    deliver: Sender<NetworkMessage>,
+    // The connection that gets the message.
+    fn next_slot(&self, message: &NetworkMessage) -> usize {
+        if self.queues.len() == 1 {
+            return 0;
+        }
+        let mut scheduler = self.scheduler.lock().unwrap();
+        scheduler.pick_for(message.affinity())
+    }
+
+    // The handle the worker of the connection records its send times with.
+    fn slot(&self, index: usize) -> Slot {
+        Slot {
+            scheduler: self.scheduler.clone(),
+            index,
+        }
+    }
+
+    // Whether a worker of the peer runs.
+    fn is_open(&self) -> bool {
+        self.queues.iter().any(Option::is_some)
+    }
 }
 
-impl NetworkReceiver {
-    pub fn new(address: SocketAddr, deliver: Sender<NetworkMessage>) -> Self {
-        Self { address, deliver }
+// The connection of a worker in the pool of its peer. How long its sends take weights the
+// connection against the others of the pool.
+#[derive(Debug, Clone)]
+struct Slot {
+    scheduler: Arc<Mutex<WeightedRoundRobin>>,
+    index: usize,
+}
+
+impl Slot {
+    fn record(&self, elapsed: Duration) {
+        self.scheduler.lock().unwrap().record(self.index, elapsed);
     }
+}
 
-    // Spawn a new worker for each incoming request. This worker is responsible for
-    // receiving messages from exactly one connection and forwards those messages to
-    // the deliver channel.
-    pub async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
-            .await
-            .expect("Failed to bind TCP port");
+// A message of the worker of a peer, numbered and serialized for it.
+struct Encoded {
+    delivery: Delivery,
+    seq: Option<u64>,
+    bytes: Bytes,
+}
 
-        println!("Listening on {}", self.address);
+impl NetworkSender {
+    pub fn new(
+        transmit: Receiver<NetworkMessage>,
+        retransmit: Sender<Delivery>,
+        retries: Receiver<Delivery>,
+    ) -> Self {
+        Self::with_config(transmit, retransmit, retries, SenderConfig::default())
+    }
 
-        // Continuously accept new incoming connections.
-        loop {
-            let (socket, peer) = match listener.accept().await {
-                Ok(value) => value,
-                // If there is an error with the connection just continue with the loop.
-                Err(e) => {
-                    println!("{}", e);
-                    continue;
-                }
-            };
-            println!("incoming connection established with {}", peer);
-            // Spawn a new worker that handles the just established connection.
-            Self::spawn_worker(socket, peer, self.deliver.clone()).await;
+    pub fn with_config(
+        transmit: Receiver<NetworkMessage>,
+        retransmit: Sender<Delivery>,
+        retries: Receiver<Delivery>,
+        config: SenderConfig,
+    ) -> Self {
+        // The connections of a pool would hand out the same numbers and acknowledgements can't be
+        // told apart, so messages to pools go without both.
+        let pools = config
+            .peers
+            .iter()
+            .map(|(address, peer)| (address.to_string(), peer))
+            .chain([("default".to_string(), &config.default_peer)])
+            .filter(|(_, peer)| peer.pool_size > 1);
+        for (peer, settings) in pools {
+            if config.sequence_numbers || settings.ack_timeout.is_some() {
+                tracing::warn!(
+                    %peer,
+                    "pool_size above 1, sequence numbers and acknowledgements are turned off"
+                );
+            }
+        }
+
+        let shared = Shared {
+            retransmit,
+            scheduler: ConnectScheduler::new(config.connect_permits),
+            queue_delays: PeerDelays::default(),
+            first_bytes: PeerDelays::default(),
+            routes: Routes::default(),
+            compression_threshold: config.compression_threshold,
+            bandwidth: Bandwidth::new(config.quota),
+            rates: RateLimits::default(),
+            frame_dump: config.frame_dump,
+            observer: config.observer.clone(),
+            tls: config.tls.clone(),
+            #[cfg(feature = "hmac")]
+            auth: config.auth.clone(),
+            links: PeerLinks::new(config.flap_window),
+            receipts: None,
+            events: None,
+            user_timeout: config.user_timeout,
+            nodelay: config.nodelay,
+            connect_timeout: config.connect_timeout,
+            reconnects: config.reconnects,
+            reconnect_backoff: config.reconnect_backoff,
+            log: LogLimiter::new(config.log_window),
+            max_frame_length: config.max_frame_length,
+            inflight: config.inflight.clone(),
+            order: config.order.clone(),
+            sent: config.sequence_numbers.then(HighWaterMarks::default),
+            hello: config.hello,
+            stats: Arc::default(),
+            topics: TopicSequences::default(),
+            unacked: Unacked::default(),
+            skipped: SkippedSequences::default(),
+            rtts: PeerRtts::default(),
+            #[cfg(feature = "histograms")]
+            flows: PeerFlows::default(),
+        };
+        let (membership, changes) = Membership::new();
+        Self {
+            transmit,
+            retries,
+            tracked: None,
+            config,
+            interceptors: Interceptors::default(),
+            unreachable: None,
+            shared,
+            senders: HashMap::new(),
+            workers: Workers::default(),
+            membership,
+            changes,
+            warmed_up: Readiness::new(),
+            accepted: NodeIds::default(),
         }
     }
 
-    async fn spawn_worker(socket: TcpStream, peer: SocketAddr, deliver: Sender<NetworkMessage>) {
-        tokio::spawn(async move {
-            // Frame the TCP stream.
-            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    /// Add an interceptor that runs on every outgoing message before it is serialized. Retried
+    /// messages were already intercepted and don't run through the chain again.
+    pub fn add_interceptor(
+        &mut self,
+        interceptor: impl FnMut(&mut NetworkMessage) + Send + 'static,
+    ) {
+        self.interceptors.add(Box::new(interceptor));
+    }
 
-            // Continuously receive incoming data from the framed TCP stream.
-            while let Some(frame) = transport.next().await {
-                match frame {
-                    Ok(m) => {
-                        // Deserialize received message.
-                        let message = bincode::deserialize(&m.freeze()).unwrap();
-                        match deliver.send(message).await {
-                            Ok(_) => (),
-                            Err(e) => println!("{}", e),
-                        }
-                    }
-                    // If there is some error with the framed TCP stream return. This will
-                    // kill the worker thread.
-                    Err(e) => {
-                        println!("{}", e);
-                        return;
-                    }
-                }
-            }
-            println!("Connection closed by peer {}", peer);
-        });
+    /// Time between the sender picking up a message and a worker writing it to the connection,
+    /// including the time spent waiting for a connection or a retransmission.
+    pub fn queue_delays(&self) -> PeerDelays {
+        self.shared.queue_delays.clone()
+    }
+
+    /// Time between the submission of a message, through a SenderHandle or a NetworkTransport,
+    /// and a worker writing it to the connection. Messages that weren't submitted through either
+    /// count from their pickup like the queue delays.
+    pub fn time_to_first_byte(&self) -> PeerDelays {
+        self.shared.first_bytes.clone()
+    }
+
+    /// Uptime and flap rate of the connection to each peer.
+    pub fn links(&self) -> PeerLinks {
+        self.shared.links.clone()
+    }
+
+    /// Highest sequence number written to each peer. As every message to a peer gets the next
+    /// number, it is also the number of messages sent to it. None unless
+    /// SenderConfig::sequence_numbers is set.
+    pub fn sent_sequences(&self) -> Option<HighWaterMarks> {
+        self.shared.sent.clone()
+    }
+
+    /// Histograms of the sizes, inter-arrival and send times of the messages to each peer.
+    #[cfg(feature = "histograms")]
+    pub fn flows(&self) -> PeerFlows {
+        self.shared.flows.clone()
+    }
+
+    /// Report peers that are given up on because of SenderConfig::max_connect_failures to the
+    /// given channel.
+    pub fn report_unreachable(&mut self, tx: Sender<PeerUnreachable>) {
+        self.unreachable = Some(tx);
+    }
+
+    /// Send a receipt to the given channel for every message and recipient once the message was
+    /// sent or dropped. Pass the channel to the retransmitter as well to get receipts for the
+    /// messages it gives up on.
+    pub fn report_receipts(&mut self, tx: Sender<DeliveryReceipt>) {
+        self.shared.receipts = Some(tx);
+    }
+
+    /// Send an event to the given channel whenever a worker connected to its peer, and when its
+    /// connection was closed or broke.
+    pub fn peer_events(&mut self, tx: UnboundedSender<NetworkEvent>) {
+        self.shared.events = Some(tx);
+    }
+
+    /// Learn from the given node ids, usually those of the receiver of this node, which peers
+    /// connected to this node. With Dialing::Paired they aren't dialed at startup.
+    pub fn accepting(&mut self, ids: NodeIds) {
+        self.accepted = ids;
+    }
+
+    /// Also take messages from the given channel, each with a notifier that gets the outcome of
+    /// the message once it was sent or given up on. Pass the channel to SenderHandle::with_tracking
+    /// to submit them. Messages the retransmitter gives up on get their outcome from it.
+    pub fn submit_tracked(&mut self, rx: Receiver<Tracked>) {
+        self.tracked = Some(rx);
+    }
+
+    /// Bytes sent to each peer, counted as the size of the frames.
+    pub fn bandwidth(&self) -> Bandwidth {
+        self.shared.bandwidth.clone()
+    }
+
+    /// Messages to peers with PeerConfig::ack_timeout that weren't acknowledged yet.
+    pub fn unacked(&self) -> Unacked {
+        self.shared.unacked.clone()
+    }
+
+    /// Smoothed round trip times and their variation per peer, estimated from the time between
+    /// writing a message and getting its acknowledgement. Only peers with PeerConfig::ack_timeout
+    /// have them, and retransmitted messages don't count because their acknowledgement may be
+    /// for an earlier attempt.
+    pub fn rtts(&self) -> PeerRtts {
+        self.shared.rtts.clone()
+    }
+
+    /// Counters of the sent messages, failed sends, retransmits and connections.
+    pub fn stats(&self) -> Arc<NetworkStats> {
+        self.shared.stats.clone()
+    }
+
+    /// Handle to add and remove peers while the sender runs.
+    pub fn membership(&self) -> Membership {
+        self.membership.clone()
+    }
+
+    /// Tasks of the workers that send to the peers. A worker that died or was aborted is noticed
+    /// by the sender, which spawns a new one for the next message to the peer.
+    pub fn workers(&self) -> Workers {
+        self.workers.clone()
+    }
+
+    /// Set once the sender runs and tried to connect to every peer of Dialing::Eager, or to the
+    /// peers with a higher id of Dialing::Paired, whether or not they could be reached. Peers
+    /// that couldn't are connected to lazily like the others.
+    pub fn warmed_up(&self) -> Readiness {
+        self.warmed_up.clone()
+    }
+
+    // Next message for the worker of the peer, or a heartbeat once the connection was idle since
+    // the given time for the heartbeat interval. While it waits, messages that the peer
+    // acknowledged are settled and those whose acknowledgement didn't arrive in time are
+    // retransmitted.
+    async fn next_delivery(
+        rx: &mut QueueReceiver<Delivery>,
+        feedback: &mut Option<Feedback>,
+        idle_since: Instant,
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> Next {
+        let heartbeat = peer
+            .heartbeat_interval
+            .map(|interval| idle_since + interval);
+        loop {
+            let waiting = shared.ack_deadline(address, peer);
+            tokio::select! {
+                delivery = rx.recv() => {
+                    return match delivery {
+                        Some(delivery) => Next::Deliver(Box::new(delivery)),
+                        None => Next::Close,
+                    };
+                }
+                (ack, timeout) = async {
+                    match waiting {
+                        Some((deadline, timeout)) => {
+                            (Self::next_ack(feedback, deadline).await, timeout)
+                        }
+                        None => std::future::pending().await,
+                    }
+                } => Self::handle_ack(ack, address, timeout, shared).await,
+                _ = async {
+                    match heartbeat {
+                        Some(heartbeat) => sleep_until(heartbeat).await,
+                        None => std::future::pending().await,
+                    }
+                } => return Next::Heartbeat,
+            }
+        }
+    }
+
+    // Send a heartbeat, unless the peer already left missed_heartbeats of them in a row
+    // unanswered or closed the connection. Returns false if the connection counts as broken.
+    async fn heartbeat(
+        transport: &mut FrameWriter,
+        feedback: &mut Option<Feedback>,
+        unanswered: &mut u32,
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> bool {
+        if let Some(answers) = feedback.as_mut().and_then(|f| f.heartbeats.as_mut()) {
+            loop {
+                match answers.try_recv() {
+                    Ok(()) => *unanswered = 0,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        tracing::warn!(peer = %address, "connection closed by peer");
+                        return false;
+                    }
+                }
+            }
+        }
+        if *unanswered >= peer.missed_heartbeats {
+            tracing::warn!(
+                peer = %address,
+                unanswered = *unanswered,
+                "peer doesn't answer heartbeats"
+            );
+            return false;
+        }
+
+        // Without a handshake the receiver doesn't need to know who sends the heartbeat.
+        let sender = shared
+            .hello
+            .map_or(SocketAddr::from(([0, 0, 0, 0], 0)), |hello| hello.address);
+        let message = NetworkMessage::heartbeat(sender, address);
+        let bytes = encode_frame(&*peer.codec, &message).expect("a heartbeat always encodes");
+        if let Err(e) = transport.send(shared.sign(bytes)).await {
+            shared.log.warn(
+                "send failures",
+                address,
+                format_args!("Failed to send heartbeat to {}: {}", address, e),
+            );
+            return false;
+        }
+        tracing::trace!(peer = %address, "sent heartbeat");
+        *unanswered += 1;
+        true
+    }
+
+    // Write a frame to the peer, or stream it in chunks if it is large enough, see
+    // PeerConfig::streaming. A streamed frame is written once every chunk was acknowledged, chunks
+    // that aren't acknowledged in time are written again.
+    async fn write_frame(
+        transport: &mut FrameWriter,
+        feedback: &mut Option<Feedback>,
+        bytes: Bytes,
+        streams: &mut u64,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> std::io::Result<()> {
+        let streaming = peer
+            .streaming
+            .filter(|streaming| bytes.len() >= streaming.threshold);
+        let acks = feedback.as_mut().and_then(|f| f.chunk_acks.as_mut());
+        let (Some(streaming), Some(acks)) = (streaming, acks) else {
+            return transport.send(shared.sign(bytes)).await;
+        };
+        *streams += 1;
+        let mut stream = StreamSender::new(
+            *streams,
+            &bytes,
+            streaming.chunk_size,
+            streaming.window,
+            streaming.timeout,
+        );
+        loop {
+            for chunk in stream.poll(Instant::now()) {
+                transport.send(shared.sign(encode_chunk(&chunk))).await?;
+            }
+            // Without chunks in flight every chunk was acknowledged.
+            let Some(deadline) = stream.deadline() else {
+                return Ok(());
+            };
+            match timeout_at(deadline, acks.recv()).await {
+                Ok(Some(ack)) => stream.ack(ack),
+                Ok(None) => return Err(std::io::ErrorKind::ConnectionAborted.into()),
+                // The span of the worker has the peer.
+                Err(_) => tracing::debug!("chunk wasn't acknowledged, sending it again"),
+            }
+        }
+    }
+
+    // Wait for the next acknowledgement or until the deadline passed, which returns None.
+    async fn next_ack(feedback: &mut Option<Feedback>, deadline: Instant) -> Option<u64> {
+        let acks = feedback.as_mut().and_then(|f| f.acks.as_mut());
+        tokio::select! {
+            Some(seq) = async {
+                match acks {
+                    Some(acks) => acks.recv().await,
+                    None => None,
+                }
+            } => Some(seq),
+            _ = sleep_until(deadline) => None,
+        }
+    }
+
+    // Settle the acknowledged message, or retransmit the messages that timed out.
+    async fn handle_ack(ack: Option<u64>, address: SocketAddr, timeout: Duration, shared: &Shared) {
+        match ack {
+            Some(seq) => {
+                if let Some((delivery, sent)) = shared.unacked.ack(address, seq) {
+                    if delivery.attempts == 0 {
+                        shared.rtts.sample(address, sent.elapsed());
+                    }
+                    shared.settle(&delivery, DeliveryOutcome::Sent).await;
+                }
+            }
+            None => {
+                for delivery in shared.unacked.expired(address, timeout) {
+                    tracing::debug!(peer = %address, "message wasn't acknowledged, retransmitting");
+                    shared.skipped.add(&delivery);
+                    shared.retry_or_drop(delivery).await;
+                }
+            }
+        }
+    }
+
+    /// Run the sender on a task of its own, see run. The handle tells once it stopped and
+    /// whether it panicked.
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            self.run().await;
+        })
+    }
+
+    // Kepp one TCP connection per peer, handled by a seperate thread. Communication is done via
+    // dedicated channels for every worker. Returns once the transmit channel is closed and the
+    // workers sent the messages they already had.
+    pub async fn run(&mut self) {
+        let mut workers = FuturesUnordered::new();
+
+        // Peers that were never connected, mapped to the time of the first connection attempt.
+        // Their connect failures don't count as failed attempts during the startup grace period.
+        let mut starting = HashMap::<SocketAddr, Instant>::new();
+
+        // Failed connection attempts in a row per peer, and the peers that were given up on.
+        let mut failures = HashMap::<SocketAddr, usize>::new();
+        let mut unreachable = HashSet::<SocketAddr>::new();
+
+        // Every peer a message was handed to a worker for, limited by max_peers.
+        let mut peers = HashSet::<SocketAddr>::new();
+
+        // Paces the workers spawned for peers that don't have one yet.
+        let mut pacer = self.config.spawn_rate.map(Pacer::new);
+
+        // Connect to the peers that are dialed eagerly, all at once. Of the paired peers only
+        // those with a higher id are dialed at first, the ones with a lower id get until the
+        // deadline to connect to this node.
+        let (eager, mut waiting, deadline) = match &self.config.dialing {
+            Dialing::Lazy => (Vec::new(), Vec::new(), Instant::now()),
+            Dialing::Eager(eager) => (eager.clone(), Vec::new(), Instant::now()),
+            Dialing::Paired {
+                id,
+                peers,
+                accept_timeout,
+            } => {
+                let (higher, lower): (Vec<_>, Vec<_>) =
+                    peers.iter().partition(|(_, peer)| peer > id);
+                let eager = higher.into_iter().map(|(address, _)| address).collect();
+                (eager, lower, Instant::now() + *accept_timeout)
+            }
+        };
+        let (dialed, reached) = self.dial(&eager).await;
+        workers.extend(
+            dialed
+                .into_iter()
+                .map(|(address, worker)| Self::supervise(address, worker)),
+        );
+        peers.extend(reached);
+        self.warmed_up.set_ready();
+
+        // Receive new messages and messages that should be sent again.
+        loop {
+            let deliveries = tokio::select! {
+                m = self.transmit.recv() => match m {
+                    Some(m) => self.deliveries(m, None),
+                    // Nobody can submit messages anymore, shut down.
+                    None => break,
+                },
+                Some(Tracked { message, outcome }) = async {
+                    match &mut self.tracked {
+                        Some(tracked) => tracked.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => self.deliveries(message, Some(outcome)),
+                Some(delivery) = self.retries.recv() => {
+                    self.shared.stats.retransmit(delivery.address);
+                    vec![delivery]
+                }
+                Some(address) = self.changes.recv() => {
+                    if self.membership.is_member(&address) {
+                        // A peer that is added again starts over.
+                        unreachable.remove(&address);
+                        failures.remove(&address);
+                        starting.remove(&address);
+                    } else {
+                        tracing::info!(peer = %address, "removing peer");
+                        self.senders.remove(&address);
+                        peers.remove(&address);
+                    }
+                    Vec::new()
+                }
+                // The queue of the worker is closed with it, the next message to the peer spawns
+                // a new worker.
+                Some((address, Err(e))) = workers.next() => {
+                    tracing::warn!(peer = %address, error = %e, "worker died");
+                    Vec::new()
+                }
+                // Paired peers with a lower id that didn't connect to this node in time are dialed
+                // after all, unless a message for them did so already.
+                _ = sleep_until(deadline), if !waiting.is_empty() => {
+                    let missing = waiting
+                        .drain(..)
+                        .filter(|(address, id)| self.accepted.get(address) != Some(*id))
+                        .map(|(address, _)| address)
+                        .filter(|address| !self.senders.get(address).is_some_and(Pool::is_open))
+                        .collect::<Vec<_>>();
+                    if !missing.is_empty() {
+                        tracing::debug!(peers = ?missing, "paired peers didn't connect, dialing them");
+                    }
+                    let (dialed, reached) = self.dial(&missing).await;
+                    workers.extend(
+                        dialed.into_iter().map(|(address, worker)| Self::supervise(address, worker)),
+                    );
+                    peers.extend(reached);
+                    Vec::new()
+                }
+                _ = self.config.shutdown.wait() => break,
+            };
+
+            // Messages to FIFO peers wait for earlier ones that are being retransmitted.
+            let deliveries = deliveries
+                .into_iter()
+                .flat_map(|delivery| self.shared.order.admit(delivery))
+                .collect::<Vec<_>>();
+
+            for delivery in deliveries {
+                let address = delivery.address;
+
+                // Our own node and nodes in the same process get the message without a
+                // connection.
+                let own = self.config.loopback.as_ref();
+                if let Some(loopback) = own.filter(|loopback| loopback.address == address) {
+                    if loopback.deliver(delivery.message.clone()).await {
+                        self.shared.inflight.remove(1);
+                        delivery.complete(DeliveryOutcome::Sent);
+                        continue;
+                    }
+                }
+                if let Some(local) = &self.config.local {
+                    if local.deliver(address, delivery.message.clone()).await {
+                        self.shared.inflight.remove(1);
+                        delivery.complete(DeliveryOutcome::Sent);
+                        continue;
+                    }
+                }
+
+                if !self.membership.is_member(&address) {
+                    tracing::debug!(peer = %address, "dropping message to removed peer");
+                    self.shared
+                        .settle(&delivery, DeliveryOutcome::Dropped)
+                        .await;
+                    continue;
+                }
+                if unreachable.contains(&address) {
+                    tracing::warn!(peer = %address, "dropping message to unreachable peer");
+                    self.shared
+                        .settle(&delivery, DeliveryOutcome::Dropped)
+                        .await;
+                    continue;
+                }
+                if !peers.contains(&address) {
+                    if self.config.max_peers.is_some_and(|max| peers.len() >= max) {
+                        tracing::warn!(peer = %address, "too many peers, dropping message");
+                        self.config.observer.peer_rejected(address);
+                        self.shared
+                            .settle(&delivery, DeliveryOutcome::Dropped)
+                            .await;
+                        continue;
+                    }
+                    peers.insert(address);
+                }
+
+                // Look up the queue of the worker whose turn it is in the pool of the peer.
+                let known = self.senders.get(&address).is_some_and(Pool::is_open);
+                let slot = self.pool(address).next_slot(&delivery.message);
+                let spawn = match &self.senders[&address].queues[slot] {
+                    // If the worker exists queue the message for it. If the worker is gone remove
+                    // its queue and spawn a new worker in its place.
+                    Some(tx) => {
+                        let policy = self.config.peer(&address).overflow;
+                        let gone = self.overflow(tx.push(delivery.clone(), policy).await).await;
+                        if gone {
+                            self.pool(address).queues[slot] = None;
+                        }
+                        gone
+                    }
+                    // If there is no worker spawn a new one.
+                    None => true,
+                };
+
+                if spawn {
+                    if !known {
+                        starting.entry(address).or_insert_with(Instant::now);
+                        if let Some(pacer) = &mut pacer {
+                            pacer.wait().await;
+                        }
+                    }
+
+                    // Spawn a new worker for the receiver socket address.
+                    let (tx_ok, rx_ok) = oneshot::channel();
+                    let (tx, worker) = Self::spawn_worker(
+                        address,
+                        self.config.peer(&address),
+                        self.shared.clone(),
+                        self.pool(address).slot(slot),
+                        tx_ok,
+                    )
+                    .await;
+                    self.workers.register(address, &worker);
+                    workers.push(Self::supervise(address, worker));
+
+                    let mut retransmit = false;
+
+                    match rx_ok.await {
+                        Ok(res) => {
+                            match res {
+                                true => {
+                                    starting.remove(&address);
+                                    failures.remove(&address);
+
+                                    // Queue the message for the new worker and put its queue
+                                    // into the hash map. The queue is empty, so the message can't
+                                    // overflow it. A worker that died right away is retried like
+                                    // one that failed to connect.
+                                    match tx.push(delivery.clone(), OverflowPolicy::Block).await {
+                                        Push::Queued => {
+                                            self.pool(address).queues[slot] = Some(tx);
+                                        }
+                                        _ => retransmit = true,
+                                    }
+                                }
+                                false => {
+                                    tracing::warn!(peer = %address, "worker failed to connect");
+                                    retransmit = true;
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            tracing::error!(peer = %address, "failed to spawn worker");
+                            retransmit = true;
+                        }
+                    }
+
+                    if retransmit {
+                        // A peer without a running worker is paced like a new one again.
+                        if !self.senders.get(&address).is_some_and(Pool::is_open) {
+                            self.senders.remove(&address);
+                        }
+                        let mut delivery = delivery;
+                        // A peer that was never connected might just not be up yet.
+                        let grace = starting
+                            .get(&address)
+                            .is_some_and(|start| start.elapsed() < self.config.startup_grace);
+                        if grace {
+                            delivery.attempts = 0;
+                        } else {
+                            let failures = failures.entry(address).or_insert(0);
+                            *failures += 1;
+                            if self
+                                .config
+                                .max_connect_failures
+                                .is_some_and(|max| *failures >= max)
+                            {
+                                tracing::warn!(
+                                    peer = %address,
+                                    failures = *failures,
+                                    "peer unreachable after failed connection attempts"
+                                );
+                                unreachable.insert(address);
+                                if let Some(tx) = &self.unreachable {
+                                    let event = PeerUnreachable {
+                                        peer: address,
+                                        failures: *failures,
+                                    };
+                                    let _ = tx.send(event).await;
+                                }
+                                self.shared
+                                    .settle(&delivery, DeliveryOutcome::Dropped)
+                                    .await;
+                                continue;
+                            }
+                        }
+                        self.shared.retry_or_drop(delivery).await;
+                    }
+                }
+            }
+        }
+
+        // Let the workers send what they already have. Closing their channels makes them finish
+        // once their queue is empty.
+        self.senders.clear();
+        while workers.next().await.is_some() {}
+    }
+
+    // Prepare a new message and split it into a delivery per recipient.
+    fn deliveries(
+        &self,
+        mut m: NetworkMessage,
+        outcome: Option<oneshot::Sender<DeliveryOutcome>>,
+    ) -> Vec<Delivery> {
+        if let Some(ids) = &self.config.ids {
+            m.headers
+                .entry(MESSAGE_ID.to_string())
+                .or_insert_with(|| ids.next_id().to_string());
+        }
+        self.interceptors.apply(&mut m);
+        let completion = outcome.map(|outcome| Completion::new(m.addresses.len(), outcome));
+        m.addresses
+            .iter()
+            .map(|address| {
+                let mut delivery = Delivery::new(m.clone(), *address);
+                delivery.completion = completion.clone();
+                if self.config.peer(address).fifo {
+                    self.shared.order.stamp(&mut delivery);
+                }
+                delivery
+            })
+            .collect::<Vec<_>>()
+    }
+
+    // The pool of workers of the peer, empty until a worker is spawned for it.
+    fn pool(&mut self, address: SocketAddr) -> &mut Pool {
+        let size = self.config.peer(&address).pool_size;
+        self.senders
+            .entry(address)
+            .or_insert_with(|| Pool::new(size))
+    }
+
+    // Wait for the worker of the peer to end. An error tells that it panicked or was aborted.
+    // Connect all workers of the pools of the given peers at once. Returns the workers, for run
+    // to supervise, and the peers that could be reached.
+    async fn dial(
+        &mut self,
+        addresses: &[SocketAddr],
+    ) -> (Vec<(SocketAddr, JoinHandle<()>)>, Vec<SocketAddr>) {
+        let mut workers = Vec::new();
+        let mut dialed = Vec::new();
+        for address in addresses {
+            let peer = self.config.peer(address);
+            // Not through pool, which would borrow all of self.
+            let pool = self
+                .senders
+                .entry(*address)
+                .or_insert_with(|| Pool::new(peer.pool_size));
+            for slot in 0..peer.pool_size.max(1) {
+                let (tx_ok, rx_ok) = oneshot::channel();
+                let (tx, worker) = Self::spawn_worker(
+                    *address,
+                    peer.clone(),
+                    self.shared.clone(),
+                    pool.slot(slot),
+                    tx_ok,
+                )
+                .await;
+                self.workers.register(*address, &worker);
+                workers.push((*address, worker));
+                dialed.push((*address, slot, tx, rx_ok));
+            }
+        }
+        let mut reached = Vec::new();
+        for (address, slot, tx, rx_ok) in dialed {
+            if let Ok(true) = rx_ok.await {
+                reached.push(address);
+                self.pool(address).queues[slot] = Some(tx);
+            }
+        }
+        (workers, reached)
+    }
+
+    async fn supervise(
+        address: SocketAddr,
+        worker: JoinHandle<()>,
+    ) -> (SocketAddr, Result<(), JoinError>) {
+        (address, worker.await)
+    }
+
+    // Handle the result of queueing a message for an existing worker. Returns whether the worker
+    // is gone, so a new one has to be spawned.
+    async fn overflow(&self, push: Push<Delivery>) -> bool {
+        match push {
+            Push::Queued => false,
+            Push::Dropped(delivery) => {
+                tracing::warn!(peer = %delivery.address, "queue is full, dropping message");
+                self.shared
+                    .settle(&delivery, DeliveryOutcome::Dropped)
+                    .await;
+                false
+            }
+            Push::Rejected(delivery) => {
+                tracing::warn!(peer = %delivery.address, "queue is full, rejecting message");
+                self.shared
+                    .settle(&delivery, DeliveryOutcome::Rejected)
+                    .await;
+                false
+            }
+            Push::Closed(_) => true,
+        }
+    }
+
+    // Try the address of the peer and its fallback address, starting with the one that worked the
+    // last time. Every attempt needs a connect permit, which is given back as soon as the attempt
+    // is finished.
+    async fn connect(address: SocketAddr, peer: &PeerConfig, shared: &Shared) -> Option<TcpStream> {
+        let mut candidates = vec![address];
+        candidates.extend(peer.fallback);
+        if let Some(working) = shared.routes.lock().unwrap().get(&address) {
+            candidates.sort_by_key(|candidate| candidate != working);
+        }
+
+        for candidate in candidates {
+            let permit = shared.scheduler.acquire(peer.priority).await;
+            let connect = TcpStream::connect(candidate);
+            let result = match timeout(shared.connect_timeout, connect).await {
+                Ok(result) => result,
+                Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+            };
+            drop(permit);
+
+            match result {
+                Ok(stream) => {
+                    tracing::info!(peer = %candidate, "outgoing connection established");
+                    configure_socket(&stream, candidate, shared.nodelay, shared.user_timeout);
+                    shared.routes.lock().unwrap().insert(address, candidate);
+                    return Some(stream);
+                }
+                Err(e) => shared.log.warn(
+                    "connect failures",
+                    candidate,
+                    format_args!("Failed to connect to {}: {}", candidate, e),
+                ),
+            }
+        }
+        None
+    }
+
+    // Connect to the peer over its Unix socket, with a connect permit like a TCP connect.
+    async fn connect_unix(
+        address: SocketAddr,
+        path: &Path,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> Option<Stream> {
+        let permit = shared.scheduler.acquire(peer.priority).await;
+        #[cfg(unix)]
+        let connect = async {
+            tokio::net::UnixStream::connect(path)
+                .await
+                .map(Stream::Unix)
+        };
+        #[cfg(not(unix))]
+        let connect = async { Err::<Stream, _>(std::io::ErrorKind::Unsupported.into()) };
+        let result = match timeout(shared.connect_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+        };
+        drop(permit);
+
+        match result {
+            Ok(stream) => {
+                tracing::info!(peer = %address, path = %path.display(), "outgoing connection established");
+                Some(stream)
+            }
+            Err(e) => {
+                shared.log.warn(
+                    "connect failures",
+                    address,
+                    format_args!(
+                        "Failed to connect to {} at {}: {}",
+                        address,
+                        path.display(),
+                        e
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    // Connect to the peer and warm the connection up, so the first message doesn't wait for it:
+    // negotiate TLS, ask the peer for credits if flow control is used, for acknowledgements if
+    // they are waited for, for acknowledgements of chunks if frames are streamed and for answers
+    // to heartbeats if they are sent, and frame the stream. Credits, acknowledgements and answers
+    // arrive over the other direction of the connection.
+    async fn open(
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> Option<(FrameWriter, Option<Feedback>)> {
+        let stream = match &peer.unix_socket {
+            Some(path) => Self::connect_unix(address, path, peer, shared).await?,
+            None => Stream::Tcp(Self::connect(address, peer, shared).await?),
+        };
+        shared.observer.connected(address);
+        shared.stats.opened(address);
+
+        let setup = async {
+            let mut stream = stream.client_upgrade(&shared.tls).await?;
+            let acks = peer.ack_timeout.is_some() && shared.sent.is_some() && peer.pool_size <= 1;
+            let heartbeats = peer.heartbeat_interval.is_some();
+            let streaming = peer.streaming.is_some();
+            let (mut transport, feedback) = if peer.flow_control || acks || streaming || heartbeats
+            {
+                if streaming {
+                    request_chunk_acks(&mut stream).await?;
+                }
+                if heartbeats {
+                    request_heartbeats(&mut stream).await?;
+                }
+                if acks {
+                    request_acks(&mut stream).await?;
+                }
+                if peer.flow_control {
+                    request_credits(&mut stream).await?;
+                }
+                let (read, write) = split(stream);
+                let transport = frame_writer_with_limit(
+                    write,
+                    peer.stream_compression,
+                    shared.max_frame_length,
+                )
+                .await?;
+                let answers = spawn_credit_reader(read);
+                let feedback = Feedback {
+                    credits: peer.flow_control.then_some(answers.credits),
+                    acks: acks.then_some(answers.acks),
+                    chunk_acks: streaming.then_some(answers.chunk_acks),
+                    heartbeats: heartbeats.then_some(answers.heartbeats),
+                    reader: answers.reader,
+                };
+                (transport, Some(feedback))
+            } else {
+                let transport = frame_writer_with_limit(
+                    stream,
+                    peer.stream_compression,
+                    shared.max_frame_length,
+                )
+                .await?;
+                (transport, None)
+            };
+            if let Some(hello) = &shared.hello {
+                transport.send(shared.sign(hello.encode())).await?;
+            }
+            Ok::<_, std::io::Error>((transport, feedback))
+        };
+        // A connection that doesn't get the TLS the policy asks for counts as failed.
+        match setup.await {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                tracing::warn!(peer = %address, error = %e, "failed to set up connection");
+                shared.observer.disconnected(address);
+                shared.stats.dropped(address);
+                None
+            }
+        }
+    }
+
+    async fn spawn_worker(
+        address: SocketAddr,
+        peer: PeerConfig,
+        shared: Shared,
+        slot: Slot,
+        ok: oneshot::Sender<bool>,
+    ) -> (QueueSender<Delivery>, JoinHandle<()>) {
+        // Create queue for communication with NetworkSender.
+        let (tx, mut rx) = bounded::<Delivery>(peer.queue_capacity);
+
+        let worker = async move {
+            // If the connection fails return. This means this worker thread is killed. Therefore
+            // using the above created channel will fail. Because of this a new worker will be
+            // spawned by the NetworkSender.
+            let (mut transport, mut feedback) = match Self::open(address, &peer, &shared).await {
+                Some(connection) => connection,
+                None => {
+                    let _ = ok.send(false);
+                    return;
+                }
+            };
+
+            // Only now the connection is ready for messages.
+            let _ = ok.send(true);
+            shared.observer.ready(address);
+            shared.links.connected(address);
+            shared.peer_event(PeerEvent::Connected(address));
+
+            // Messages whose connection broke, they are sent again once the worker reconnected.
+            let mut failed: Vec<Delivery> = Vec::new();
+
+            // Since when nothing was written to the connection, and the heartbeats written since
+            // the peer last answered one.
+            let mut idle_since = Instant::now();
+            let mut unanswered = 0;
+
+            // Id of the last frame that was streamed.
+            let mut streams = 0;
+
+            // Continuously listen to messages passed to the above created channel.
+            loop {
+                let mut batch = match failed.is_empty() {
+                    false => std::mem::take(&mut failed),
+                    true => match Self::next_delivery(
+                        &mut rx,
+                        &mut feedback,
+                        idle_since,
+                        address,
+                        &peer,
+                        &shared,
+                    )
+                    .await
+                    {
+                        Next::Deliver(delivery) => vec![*delivery],
+                        Next::Heartbeat => {
+                            let alive = Self::heartbeat(
+                                &mut transport,
+                                &mut feedback,
+                                &mut unanswered,
+                                address,
+                                &peer,
+                                &shared,
+                            )
+                            .await;
+                            if !alive {
+                                // Don't wait for a message to fail on the dead connection.
+                                match Self::reconnect(address, &peer, &shared, feedback.take()).await
+                                {
+                                    Some(connection) => {
+                                        (transport, feedback) = connection;
+                                        unanswered = 0;
+                                    }
+                                    None => {
+                                        Self::give_up(rx, address, &peer, &shared).await;
+                                        return;
+                                    }
+                                }
+                            }
+                            idle_since = Instant::now();
+                            continue;
+                        }
+                        Next::Close => break,
+                    },
+                };
+                if let Some(batching) = &peer.batching {
+                    Self::fill_batch(&mut rx, &mut batch, batching).await;
+                }
+                let mut pending = Self::encode(batch, address, &peer, &shared).await;
+
+                // Write the messages in as few frames as the limits of a batch and the credits
+                // of the peer allow.
+                let mut broken = false;
+                while !pending.is_empty() {
+                    let frame = match Self::next_frame(&mut pending, &feedback, &peer, &shared).await
+                    {
+                        Some(frame) => frame,
+                        None => {
+                            tracing::warn!(peer = %address, "connection closed while waiting for credits");
+                            broken = true;
+                            break;
+                        }
+                    };
+                    let bytes = match &frame[..] {
+                        [encoded] => encoded.bytes.clone(),
+                        frame => {
+                            let frames = frame.iter().map(|encoded| encoded.bytes.clone());
+                            encode_batch(&frames.collect::<Vec<_>>())
+                        }
+                    };
+
+                    if let Some(max) = shared.frame_dump {
+                        let dump = hex_dump(&bytes, max);
+                        tracing::trace!(peer = %address, len = bytes.len(), %dump, "sent frame");
+                    }
+
+                    // Send the messages to the nework
+                    for encoded in &frame {
+                        let delivery = &encoded.delivery;
+                        let submitted = delivery.message.submitted.unwrap_or(delivery.enqueued);
+                        shared
+                            .queue_delays
+                            .record(address, delivery.enqueued.elapsed());
+                        shared.first_bytes.record(address, submitted.elapsed());
+                        #[cfg(feature = "histograms")]
+                        shared.flows.first_byte(address, submitted);
+                    }
+                    let started = Instant::now();
+                    let written = Self::write_frame(
+                        &mut transport,
+                        &mut feedback,
+                        bytes,
+                        &mut streams,
+                        &peer,
+                        &shared,
+                    );
+                    match written.await {
+                        Ok(_) => {
+                            slot.record(started.elapsed());
+                            shared.stats.frame_sent(address);
+                            idle_since = Instant::now();
+                            for Encoded {
+                                delivery,
+                                seq,
+                                bytes,
+                            } in frame
+                            {
+                                let len = bytes.len();
+                                tracing::debug!(peer = %address, len, "sent message");
+                                #[cfg(feature = "histograms")]
+                                shared
+                                    .flows
+                                    .record(address, len, delivery.enqueued, started.elapsed());
+                                shared.observer.message_sent(address, len);
+                                shared.stats.sent(address, len);
+                                if let (Some(sent), Some(seq)) = (&shared.sent, seq) {
+                                    sent.record(address, seq);
+                                    let skipped = delivery.message.skipped();
+                                    shared.skipped.announced(address, &skipped);
+                                }
+                                // A message to a peer that acknowledges it is sent once it is.
+                                let acked = feedback.as_ref().is_some_and(|f| f.acks.is_some());
+                                match seq.filter(|_| acked) {
+                                    Some(seq) => shared.unacked.sent(seq, delivery),
+                                    None => shared.settle(&delivery, DeliveryOutcome::Sent).await,
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            shared.stats.send_failed(address);
+                            shared.log.warn(
+                                "send failures",
+                                address,
+                                format_args!("Failed to send message to {}: {}", address, e),
+                            );
+                            failed.extend(frame.into_iter().map(|encoded| encoded.delivery));
+                            broken = true;
+                            break;
+                        }
+                    }
+                }
+                if !broken {
+                    continue;
+                }
+                failed.extend(pending.into_iter().map(|encoded| encoded.delivery));
+
+                // The connection broke. Once the worker runs out of attempts to reconnect the
+                // messages go to the retransmitter and the worker exits.
+                match Self::reconnect(address, &peer, &shared, feedback.take()).await {
+                    Some(connection) => {
+                        (transport, feedback) = connection;
+                        unanswered = 0;
+                    }
+                    None => {
+                        for delivery in failed {
+                            shared.retry_or_drop(delivery).await;
+                        }
+                        Self::give_up(rx, address, &peer, &shared).await;
+                        return;
+                    }
+                }
+            }
+            // Wait for the acknowledgements of the messages that were already sent.
+            while let Some((deadline, timeout)) = shared.ack_deadline(address, &peer) {
+                let ack = Self::next_ack(&mut feedback, deadline).await;
+                Self::handle_ack(ack, address, timeout, &shared).await;
+            }
+            drop(feedback);
+            shared.observer.disconnected(address);
+            shared.stats.dropped(address);
+            shared.links.disconnected(address);
+            shared.peer_event(PeerEvent::Disconnected(address));
+        }
+        .instrument(tracing::info_span!("sender", peer = %address));
+        let worker = tokio::spawn(worker);
+        (tx, worker)
+    }
+
+    // Add the messages that are queued or arrive within the delay of the batch to it, until it
+    // is full.
+    async fn fill_batch(
+        rx: &mut QueueReceiver<Delivery>,
+        batch: &mut Vec<Delivery>,
+        batching: &Batching,
+    ) {
+        let deadline = Instant::now() + batching.max_delay;
+        while batch.len() < batching.max_messages {
+            let delivery = match rx.try_recv() {
+                Some(delivery) => delivery,
+                None => match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(delivery)) => delivery,
+                    _ => break,
+                },
+            };
+            batch.push(delivery);
+        }
+    }
+
+    // Number the messages of the batch, on the stream to the peer and within their topics, and
+    // serialize them in the format of the peer. Messages the peer would reject and those over
+    // its quota are dropped, the rate limit of the peer holds them back.
+    async fn encode(
+        batch: Vec<Delivery>,
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> VecDeque<Encoded> {
+        // The connections of a pool would hand out the same numbers.
+        let sent = shared.sent.as_ref().filter(|_| peer.pool_size <= 1);
+        let last = sent.map(|sent| sent.get(&address).unwrap_or(0));
+        let mut encoded = VecDeque::with_capacity(batch.len());
+        for mut delivery in batch {
+            // Messages that waited in the queue or for a connection may be of no use anymore.
+            if delivery.expired() {
+                tracing::warn!(peer = %address, "dropping expired message");
+                shared.settle(&delivery, DeliveryOutcome::Expired).await;
+                continue;
+            }
+
+            // Number the message on the stream to the peer. A message that isn't written
+            // leaves its number to the next one, retransmissions get a new number. The numbers
+            // of retransmitted messages travel along, so the peer doesn't wait for them.
+            let seq = last.map(|last| {
+                let seq = last + encoded.len() as u64 + 1;
+                let skipped = shared.skipped.get(address, &delivery.message);
+                delivery.message.set_sequence(seq);
+                delivery.message.set_skipped(&skipped);
+                seq
+            });
+
+            // Number the message within its topic. A message that is dropped below hands its
+            // number back, retransmissions keep theirs.
+            let stamped = shared.topics.stamp(&mut delivery);
+            let give_back = |delivery: &Delivery| {
+                if stamped {
+                    shared.topics.unstamp(delivery);
+                }
+            };
+
+            let bytes = match encode_frame_compressed(
+                &*peer.codec,
+                &delivery.message,
+                shared.compression_threshold,
+            ) {
+                Ok(bytes) => bytes,
+                // The peer would reject the frame, so don't send it.
+                Err(CodecError::EmptyPayload) => {
+                    tracing::warn!(peer = %address, "dropping message with empty payload");
+                    give_back(&delivery);
+                    shared.settle(&delivery, DeliveryOutcome::Dropped).await;
+                    continue;
+                }
+                // Only this message is lost, the connection and the messages behind it stay.
+                Err(e) => {
+                    tracing::warn!(peer = %address, error = %e, "dropping message that failed to serialize");
+                    give_back(&delivery);
+                    shared.settle(&delivery, DeliveryOutcome::Dropped).await;
+                    continue;
+                }
+            };
+
+            // The peer would close the connection on a frame above the limit.
+            if bytes.len() > shared.max_payload_length() {
+                tracing::warn!(
+                    peer = %address,
+                    len = bytes.len(),
+                    "dropping message, its frame is too long"
+                );
+                give_back(&delivery);
+                shared.settle(&delivery, DeliveryOutcome::Dropped).await;
+                continue;
+            }
+
+            // Wait for or drop the message if the quota of the peer is exhausted.
+            if !Self::admit(address, bytes.len(), shared).await {
+                tracing::warn!(peer = %address, "quota exhausted, dropping message");
+                give_back(&delivery);
+                shared.settle(&delivery, DeliveryOutcome::Dropped).await;
+                continue;
+            }
+            if let Some(limit) = peer.rate_limit {
+                shared.rates.acquire(address, limit, bytes.len()).await;
+            }
+            encoded.push_back(Encoded {
+                delivery,
+                seq,
+                bytes,
+            });
+        }
+        encoded
+    }
+
+    // Take the messages of the next frame off the encoded ones: the first one, and as many after
+    // it as fit into a batch. With flow control every message takes a credit, the worker only
+    // waits for the one of the first message. None if the credits were closed together with the
+    // connection.
+    async fn next_frame(
+        encoded: &mut VecDeque<Encoded>,
+        feedback: &Option<Feedback>,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) -> Option<Vec<Encoded>> {
+        let credits = feedback.as_ref().and_then(|f| f.credits.as_ref());
+        if let Some(credits) = credits {
+            credits.acquire().await.ok()?.forget();
+        }
+        let mut frame = Vec::from_iter(encoded.pop_front());
+        if let Some(batching) = &peer.batching {
+            let max_bytes = batching.max_bytes.min(shared.max_payload_length());
+            let mut len = frame.iter().map(|first| first.bytes.len()).sum::<usize>();
+            while let Some(next) = encoded.front() {
+                if batch_len(frame.len() + 1, len + next.bytes.len()) > max_bytes {
+                    break;
+                }
+                if let Some(credits) = credits {
+                    match credits.try_acquire() {
+                        Ok(credit) => credit.forget(),
+                        Err(_) => break,
+                    }
+                }
+                len += next.bytes.len();
+                frame.extend(encoded.pop_front());
+            }
+        }
+        Some(frame)
+    }
+
+    // Reopen the broken connection to the peer. Messages keep queueing up while the worker
+    // reconnects, backing off before every attempt. None once it ran out of attempts.
+    async fn reconnect(
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+        feedback: Option<Feedback>,
+    ) -> Option<(FrameWriter, Option<Feedback>)> {
+        drop(feedback);
+        shared.observer.disconnected(address);
+        shared.stats.dropped(address);
+        shared.links.disconnected(address);
+        shared.peer_event(PeerEvent::Disconnected(address));
+        for attempt in 1..=shared.reconnects {
+            sleep(shared.reconnect_backoff.delay(attempt)).await;
+            tracing::info!(peer = %address, attempt, "reconnecting");
+            if let Some(connection) = Self::open(address, peer, shared).await {
+                shared.observer.ready(address);
+                shared.links.connected(address);
+                shared.peer_event(PeerEvent::Connected(address));
+                return Some(connection);
+            }
+        }
+        None
+    }
+
+    // Hand what the worker of the peer still has to the retransmitter before it exits, because
+    // it couldn't reconnect.
+    async fn give_up(
+        rx: QueueReceiver<Delivery>,
+        address: SocketAddr,
+        peer: &PeerConfig,
+        shared: &Shared,
+    ) {
+        // Messages that weren't acknowledged are retransmitted, the peer may not have read them.
+        if peer.ack_timeout.is_some() {
+            for delivery in shared.unacked.expired(address, Duration::ZERO) {
+                shared.skipped.add(&delivery);
+                shared.retry_or_drop(delivery).await;
+            }
+        }
+        // Messages still queued for the closed connection are lost, unless the peer gets its
+        // messages in order. Then they are retransmitted behind the failed one.
+        for delivery in rx.close() {
+            match delivery.seq {
+                Some(_) => {
+                    shared.retry_or_drop(delivery).await;
+                }
+                None => shared.settle(&delivery, DeliveryOutcome::Dropped).await,
+            }
+        }
+    }
+
+    // Returns false if the message has to be dropped.
+    async fn admit(address: SocketAddr, bytes: usize, shared: &Shared) -> bool {
+        loop {
+            match shared.bandwidth.admit(address, bytes as u64) {
+                Admission::Send => return true,
+                Admission::Wait(delay) => sleep(delay).await,
+                Admission::Reject => return false,
+            }
+        }
+    }
+}
+
+/// Cloneable handle to submit messages to a NetworkSender.
+#[derive(Clone)]
+pub struct SenderHandle {
+    // Address of our own node, used as sender of the messages.
+    name: SocketAddr,
+
+    // Transmit channel of the NetworkSender.
+    tx: Sender<NetworkMessage>,
+
+    // Channel of the NetworkSender for tracked messages, see NetworkSender::submit_tracked.
+    tracked: Option<Sender<Tracked>>,
+}
+
+impl SenderHandle {
+    pub fn new(name: SocketAddr, tx: Sender<NetworkMessage>) -> Self {
+        Self {
+            name,
+            tx,
+            tracked: None,
+        }
+    }
+
+    /// Handle that can also submit tracked messages over the given channel.
+    pub fn with_tracking(mut self, tracked: Sender<Tracked>) -> Self {
+        self.tracked = Some(tracked);
+        self
+    }
+
+    /// Submit the message and report its outcome through the notifier once it was sent or given
+    /// up on, see Tracked. Fails if the handle has no channel for tracked messages or the
+    /// NetworkSender stopped taking them.
+    pub async fn send_tracked(
+        &self,
+        message: NetworkMessage,
+        outcome: oneshot::Sender<DeliveryOutcome>,
+    ) -> Result<(), SendError<NetworkMessage>> {
+        let tracked = match &self.tracked {
+            Some(tracked) => tracked,
+            None => return Err(SendError(message)),
+        };
+        let message = message.submit();
+        tracked
+            .send(Tracked { message, outcome })
+            .await
+            .map_err(|SendError(tracked)| SendError(tracked.message))
+    }
+
+    /// Send a different payload to each of the given peers. The messages are enqueued as a unit:
+    /// capacity for all of them is reserved first, so either every message is enqueued or none
+    /// is. A batch larger than the capacity of the channel could never be reserved, it fails as
+    /// a whole right away. Returns the result for each peer, in the order of the batch.
+    pub async fn send_many(
+        &self,
+        messages: Vec<(SocketAddr, impl Into<Payload>)>,
+    ) -> Vec<(SocketAddr, Result<(), SendError<NetworkMessage>>)> {
+        let messages = messages
+            .into_iter()
+            .map(|(peer, payload)| (peer, NetworkMessage::unicast(self.name, peer, payload)))
+            .collect::<Vec<_>>();
+        let permits = match messages.len() <= self.tx.max_capacity() {
+            true => self.tx.reserve_many(messages.len()).await.ok(),
+            false => None,
+        };
+        match permits {
+            Some(permits) => permits
+                .zip(messages)
+                .map(|(permit, (peer, message))| {
+                    permit.send(message.submit());
+                    (peer, Ok(()))
+                })
+                .collect(),
+            None => messages
+                .into_iter()
+                .map(|(peer, message)| (peer, Err(SendError(message))))
+                .collect(),
+        }
+    }
+
+    /// Send the payload to every one of the peers except our own node.
+    pub async fn broadcast(
+        &self,
+        peers: &[SocketAddr],
+        payload: impl Into<Payload>,
+    ) -> Result<(), SendError<NetworkMessage>> {
+        let message = NetworkMessage::broadcast(self.name, peers, payload).submit();
+        self.tx.send(message).await
+    }
+}
+
+pub struct NetworkReceiver {
+    // Our own network address.
+    address: SocketAddr,
+
+    // Channel where received messages are put in.
+    deliver: Sender<InboundMessage>,
+
+    config: ReceiverConfig,
+
+    // Applied to every received message after it is deserialized.
+    interceptors: Interceptors,
+
+    // Bytes received from each remote node.
+    bandwidth: Bandwidth,
+
+    // Frames read but not delivered yet, per connection.
+    outstanding: OutstandingFrames,
+
+    // Highest sequence number read from each remote node.
+    received: HighWaterMarks,
+
+    // Node ids the remote nodes announced in their handshake.
+    node_ids: NodeIds,
+
+    // Grants credits to the connected nodes if flow control is enabled.
+    credits: Credits,
+
+    // Applies the early policy until Core is ready. None delivers right away.
+    gate: Option<Gate>,
+
+    // Tasks of the workers of the open connections.
+    workers: Workers,
+
+    // Gets the connects and disconnects of the remote nodes.
+    events: Option<UnboundedSender<NetworkEvent>>,
+
+    // Bound listener, e.g. inherited from a parent process. If there is none the address gets
+    // bound when running, and the listener is kept until the receiver is dropped.
+    listener: OnceLock<std::net::TcpListener>,
+}
+
+impl NetworkReceiver {
+    pub fn new(address: SocketAddr, deliver: Sender<InboundMessage>) -> Self {
+        Self::with_config(address, deliver, ReceiverConfig::default())
+    }
+
+    pub fn with_config(
+        address: SocketAddr,
+        deliver: Sender<InboundMessage>,
+        config: ReceiverConfig,
+    ) -> Self {
+        Self {
+            address,
+            deliver,
+            config,
+            interceptors: Interceptors::default(),
+            bandwidth: Bandwidth::default(),
+            outstanding: OutstandingFrames::default(),
+            received: HighWaterMarks::default(),
+            node_ids: NodeIds::default(),
+            credits: Credits::default(),
+            gate: None,
+            workers: Workers::default(),
+            events: None,
+            listener: OnceLock::new(),
+        }
+    }
+
+    /// Bytes received from each remote node, counted as the size of the frames. Lines received in
+    /// text mode aren't counted.
+    pub fn bandwidth(&self) -> Bandwidth {
+        self.bandwidth.clone()
+    }
+
+    /// Frames each inbound connection has read but not delivered yet, by remote address of the
+    /// connection.
+    pub fn outstanding(&self) -> OutstandingFrames {
+        self.outstanding.clone()
+    }
+
+    /// Highest sequence number read from each remote node, counting duplicates and messages that
+    /// were dropped after they arrived.
+    pub fn received_sequences(&self) -> HighWaterMarks {
+        self.received.clone()
+    }
+
+    /// Tasks of the workers of the open connections, by remote address of the connection. They
+    /// keep reading after the receiver stopped, until their peers close the connections or they
+    /// are aborted.
+    pub fn workers(&self) -> Workers {
+        self.workers.clone()
+    }
+
+    /// Node ids of the remote nodes by their listening address, learned from the handshake when
+    /// ReceiverConfig::handshake is set.
+    pub fn node_ids(&self) -> NodeIds {
+        self.node_ids.clone()
+    }
+
+    /// Credits of the connected nodes, through which Core grants them further messages when
+    /// flow control is enabled.
+    pub fn credits(&self) -> Credits {
+        self.credits.clone()
+    }
+
+    /// Apply the early policy of the config to messages arriving before the given readiness is
+    /// set, typically by Core once it started reading the deliver channel.
+    pub fn wait_for(&mut self, readiness: Readiness) {
+        self.gate = Some(Gate {
+            readiness,
+            policy: self.config.early_policy,
+            early: Arc::default(),
+        });
+    }
+
+    /// Send an event to the given channel whenever a connection identified its remote node, by
+    /// the handshake or its first message, and once such a connection is closed. A connection
+    /// that is replaced by a newer one of the same node isn't reported as closed, neither is one
+    /// whose worker was aborted.
+    pub fn peer_events(&mut self, tx: UnboundedSender<NetworkEvent>) {
+        self.events = Some(tx);
+    }
+
+    /// Add an interceptor that runs on every received message before it is delivered.
+    pub fn add_interceptor(
+        &mut self,
+        interceptor: impl FnMut(&mut NetworkMessage) + Send + 'static,
+    ) {
+        self.interceptors.add(Box::new(interceptor));
+    }
+
+    /// Create a receiver that accepts connections on an already bound listener instead of binding
+    /// its own. Connections that queued up on the listener before are accepted right away.
+    pub fn with_listener(
+        listener: std::net::TcpListener,
+        deliver: Sender<InboundMessage>,
+        config: ReceiverConfig,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            address: listener.local_addr()?,
+            deliver,
+            config,
+            interceptors: Interceptors::default(),
+            bandwidth: Bandwidth::default(),
+            outstanding: OutstandingFrames::default(),
+            received: HighWaterMarks::default(),
+            node_ids: NodeIds::default(),
+            credits: Credits::default(),
+            gate: None,
+            workers: Workers::default(),
+            events: None,
+            listener: OnceLock::from(listener),
+        })
+    }
+
+    /// Create a receiver from a listening socket inherited from the parent process, which allows
+    /// restarting a node without peers seeing the listener go away.
+    ///
+    /// # Safety
+    ///
+    /// The file descriptor must be an open, bound and listening TCP socket that isn't owned by
+    /// anything else.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(
+        fd: std::os::unix::io::RawFd,
+        deliver: Sender<InboundMessage>,
+        config: ReceiverConfig,
+    ) -> std::io::Result<Self> {
+        use std::os::unix::io::FromRawFd;
+        Self::with_listener(std::net::TcpListener::from_raw_fd(fd), deliver, config)
+    }
+
+    /// File descriptor of the listener, so it can be handed on to a successor process. This is
+    /// the inherited listener, or the one the receiver bound once it runs. The receiver keeps
+    /// the listener open until it is dropped, also after it was shut down.
+    #[cfg(unix)]
+    pub fn listener_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        self.listener.get().map(|listener| listener.as_raw_fd())
+    }
+
+    // Listen on the Unix socket if there is one, otherwise use the bound listener if there is
+    // one or bind the address and keep the listener.
+    async fn listen(&self) -> std::io::Result<Listener> {
+        if let Some(path) = &self.config.unix_socket {
+            return Listener::bind_unix(path);
+        }
+        let listener = match self.listener.get() {
+            Some(listener) => listener,
+            None => {
+                let bound = bind(self.address, self.config.reuse_address)?.into_std()?;
+                self.listener.get_or_init(|| bound)
+            }
+        };
+        let listener = listener.try_clone()?;
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener).map(Listener::Tcp)
+    }
+
+    // Bind the listener, backing off after every failed attempt. Gives up early once the
+    // receiver is shut down.
+    async fn bind_retrying(&self) -> std::io::Result<Listener> {
+        let mut attempt = 0;
+        loop {
+            let e = match self.listen().await {
+                Ok(listener) => return Ok(listener),
+                Err(e) => e,
+            };
+            if attempt == self.config.bind_retries {
+                tracing::error!(address = %self.address, error = %e, "failed to bind");
+                return Err(e);
+            }
+            attempt += 1;
+            let delay = self.config.bind_backoff.delay(attempt);
+            tracing::warn!(address = %self.address, error = %e, attempt, ?delay, "failed to bind, retrying");
+            tokio::select! {
+                _ = sleep(delay) => (),
+                _ = self.config.shutdown.wait() => return Err(e),
+            }
+        }
+    }
+
+    /// Run the receiver on a task of its own, see run.
+    pub fn spawn(self) -> JoinHandle<std::io::Result<()>> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    /// Accept connections until the receiver is shut down. Fails if the listener can't be bound,
+    /// after the retries of the config.
+    pub async fn run(&self) -> std::io::Result<()> {
+        let mut listener = self.bind_retrying().await?;
+
+        tracing::info!(address = %self.address, "listening");
+        if let Some(local) = &self.config.local {
+            local.register(
+                self.address,
+                self.deliver.clone(),
+                self.config.inflight.clone(),
+            );
+        }
+
+        // Keep track of the open connections per remote node.
+        let connections = Connections::default();
+        let dedup = self
+            .config
+            .dedup
+            .map(|bounds| Arc::new(Mutex::new(DedupCache::new(bounds))));
+        let reorder = self.config.reorder.map(PeerReorder::new);
+        let topics = self.config.topics.clone().map(PeerTopics::new);
+        let log = LogLimiter::new(self.config.log_window);
+        let codecs = self.config.codecs.limited(self.config.max_frame_length);
+        let mut next_id = 0;
+
+        // Every open connection holds a permit.
+        let limit = self
+            .config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+
+        // Spawn a new worker for each incoming request. This worker is responsible for
+        // receiving messages from exactly one connection and forwards those messages to
+        // the deliver channel.
+        // Continuously accept new incoming connections.
+        loop {
+            if let Some(gate) = &self.gate {
+                if gate.policy == EarlyPolicy::Hold {
+                    tokio::select! {
+                        _ = gate.readiness.wait() => (),
+                        _ = self.config.shutdown.wait() => break,
+                    }
+                }
+            }
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = self.config.shutdown.wait() => break,
+            };
+            let (socket, peer) = match accepted {
+                Ok(value) => value,
+                // If there is an error with the connection just continue with the loop.
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to accept connection");
+                    continue;
+                }
+            };
+            let permit = match &limit {
+                Some(limit) => match limit.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        tracing::warn!(%peer, "too many connections, rejecting connection");
+                        if let (Stream::Tcp(socket), ConnectionLimit::Reject) =
+                            (&socket, self.config.connection_limit)
+                        {
+                            let _ = socket.set_zero_linger();
+                        }
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            tracing::info!(%peer, "incoming connection established");
+            if let Stream::Tcp(socket) = &socket {
+                configure_socket(socket, peer, self.config.nodelay, self.config.user_timeout);
+            }
+            if self.config.text_mode {
+                let worker = Self::spawn_text_worker(
+                    socket.plain(),
+                    peer,
+                    self.address,
+                    self.deliver.clone(),
+                    self.interceptors.clone(),
+                    self.config.inflight.clone(),
+                    permit,
+                );
+                self.workers.register(peer, &worker);
+                continue;
+            }
+            // Spawn a new worker that handles the just established connection.
+            next_id += 1;
+            let inbound = Inbound {
+                deliver: self.deliver.clone(),
+                codecs: codecs.clone(),
+                interceptors: self.interceptors.clone(),
+                bandwidth: self.bandwidth.clone(),
+                outstanding: self.outstanding.clone(),
+                received: self.received.clone(),
+                max_outstanding: self.config.max_outstanding,
+                unknown: self.config.unknown,
+                log: log.clone(),
+                max_frame_length: self.config.max_frame_length,
+                max_decode_failures: self.config.max_decode_failures,
+                handshake: self.config.handshake,
+                node_ids: self.node_ids.clone(),
+                frame_dump: self.config.frame_dump,
+                idle_timeout: self.config.idle_timeout,
+                gate: self.gate.clone(),
+                observer: self.config.observer.clone(),
+                tls: self.config.tls.clone(),
+                #[cfg(feature = "hmac")]
+                auth: self.config.auth.clone(),
+                credits: self.config.credits,
+                grants: self.credits.clone(),
+                epochs: self.config.epochs.clone(),
+                dedup: dedup.clone(),
+                reorder: reorder.clone(),
+                topics: topics.clone(),
+                inflight: self.config.inflight.clone(),
+                events: self.events.clone(),
+            };
+            let worker = Self::spawn_worker(
+                socket,
+                peer,
+                inbound,
+                Connection {
+                    id: next_id,
+                    connections: connections.clone(),
+                    policy: self.config.duplicate_policy,
+                    _permit: permit,
+                },
+            );
+            self.workers.register(peer, &worker);
+        }
+        Ok(())
+    }
+
+    fn spawn_worker(
+        socket: Stream,
+        peer: SocketAddr,
+        inbound: Inbound,
+        connection: Connection,
+    ) -> JoinHandle<()> {
+        // The span of the connection gets the remote node once it identified itself.
+        let span = tracing::info_span!(
+            "receiver",
+            %peer,
+            sender = tracing::field::Empty,
+            node_id = tracing::field::Empty
+        );
+        let worker = async move {
+            inbound.observer.connected(peer);
+
+            // Upgrade to TLS if the peer asks for it, then frame the stream.
+            let socket = match unless_idle(inbound.idle_timeout, socket.server_upgrade(&inbound.tls)).await {
+                Ok(Ok(socket)) => socket,
+                Err(_) => {
+                    tracing::info!(%peer, "closing idle connection");
+                    inbound.observer.disconnected(peer);
+                    return;
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!(%peer, error = %e, "failed to negotiate TLS");
+                    inbound.observer.disconnected(peer);
+                    return;
+                }
+            };
+
+            // Senders with flow control ask for credits, senders that wait for
+            // acknowledgements or stream frames for those and senders of heartbeats for answers
+            // to them, all of them are written to the other direction of the connection.
+            let mut socket = BufReader::new(socket);
+            let requested = async {
+                let chunk_acks = chunk_acks_requested(&mut socket).await?;
+                let heartbeats = heartbeats_requested(&mut socket).await?;
+                let acks = acks_requested(&mut socket).await?;
+                let credits = credits_requested(&mut socket).await?;
+                Ok::<_, std::io::Error>((credits, acks, chunk_acks, heartbeats))
+            };
+            let (requested, acks, chunk_acks, heartbeats) = match unless_idle(inbound.idle_timeout, requested).await {
+                Ok(Ok(requested)) => requested,
+                Err(_) => {
+                    tracing::info!(%peer, "closing idle connection");
+                    inbound.observer.disconnected(peer);
+                    return;
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!(%peer, error = %e, "failed to set up connection");
+                    inbound.observer.disconnected(peer);
+                    return;
+                }
+            };
+            if requested && inbound.credits.is_none() {
+                tracing::warn!(%peer, "peer asks for credits, but flow control is disabled");
+            }
+            let initial = inbound.credits.filter(|_| requested);
+            let (socket, replies): (Box<dyn AsyncRead + Send + Unpin>, _) =
+                if initial.is_some() || acks || chunk_acks || heartbeats {
+                    let (read, write) = split(socket);
+                    let replies = spawn_credit_writer(write, initial, acks, chunk_acks, heartbeats);
+                    (Box::new(read), replies)
+                } else {
+                    (Box::new(socket), Replies::default())
+                };
+            let Replies {
+                grants,
+                acks,
+                chunk_acks,
+                heartbeats,
+            } = replies;
+            let mut transport =
+                match frame_reader_with_limit(socket, inbound.max_frame_length).await {
+                    Ok(transport) => transport,
+                    Err(e) => {
+                        tracing::warn!(%peer, error = %e, "failed to set up connection");
+                        inbound.observer.disconnected(peer);
+                        return;
+                    }
+                };
+
+            // Used by a newer connection from the same node to close this one.
+            let close = Arc::new(Notify::new());
+
+            // The remote node, known after the handshake or the first message was received.
+            let mut identity = None;
+
+            // The handshake identifies the remote node before any message is read.
+            if inbound.handshake {
+                let hello = match unless_idle(inbound.idle_timeout, transport.next()).await {
+                    Ok(Some(Ok(frame))) => inbound
+                        .verify(frame)
+                        .ok()
+                        .and_then(|frame| Hello::decode(&frame)),
+                    _ => None,
+                };
+                let hello = match hello {
+                    Some(hello) => hello,
+                    None => {
+                        tracing::warn!(%peer, "closing connection, missing or malformed handshake");
+                        inbound.observer.disconnected(peer);
+                        return;
+                    }
+                };
+                let span = tracing::Span::current();
+                span.record("sender", tracing::field::display(hello.address));
+                span.record("node_id", hello.node_id);
+                if !connection.register(hello.address, close.clone()) {
+                    tracing::info!(%peer, sender = %hello.address, "rejecting duplicate connection");
+                    inbound.observer.disconnected(peer);
+                    return;
+                }
+                if let Some(grants) = &grants {
+                    inbound.grants.register(hello.address, grants.clone());
+                }
+                inbound.node_ids.record(hello);
+                inbound.peer_event(PeerEvent::Connected(hello.address));
+                identity = Some(hello.address);
+            }
+
+            // Frames in a row that couldn't be decoded.
+            let mut decode_failures = 0;
+
+            // Chunks of the frames that are streamed over the connection.
+            let mut streams = Streams::new(inbound.max_frame_length);
+
+            // Every frame holds a permit until it is delivered. Without a permit the connection
+            // isn't read, which pushes back on the peer.
+            let semaphore = inbound.outstanding.register(peer, inbound.max_outstanding);
+
+            // Deliver the messages in a separate task, so a full deliver channel only stops
+            // reading once the limit of outstanding frames is reached.
+            let (tx_forward, mut rx_forward) = unbounded_channel();
+            let deliver = inbound.deliver.clone();
+            let inflight = inbound.inflight.clone();
+            let forwarder = tokio::spawn(async move {
+                while let Some((message, permit)) = rx_forward.recv().await {
+                    if let Err(e) = deliver.send(message).await {
+                        tracing::warn!(sender = %e.0.message.sender, "deliver channel is closed, dropping message");
+                        inflight.remove(1);
+                    }
+                    drop(permit);
+                }
+            });
+
+            // Continuously receive incoming data from the framed TCP stream.
+            'frames: loop {
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                let frame = tokio::select! {
+                    frame = unless_idle(inbound.idle_timeout, transport.next()) => frame,
+                    _ = close.notified() => {
+                        tracing::info!(%peer, "closing stale connection");
+                        break;
+                    }
+                };
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(_) => {
+                        tracing::info!(%peer, "closing idle connection");
+                        break;
+                    }
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => {
+                        tracing::info!(%peer, "connection closed by peer");
+                        break;
+                    }
+                };
+                match frame {
+                    Ok(frame) => {
+                        if let Some(max) = inbound.frame_dump {
+                            let dump = hex_dump(&frame, max);
+                            tracing::trace!(%peer, len = frame.len(), %dump, "received frame");
+                        }
+
+                        // A forged or changed frame is dropped, the ones after it may be fine.
+                        let frame = match inbound.verify(frame) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                inbound.log.warn(
+                                    "authentication failures",
+                                    peer,
+                                    format_args!("Dropping frame from {}: {}", peer, e),
+                                );
+                                continue;
+                            }
+                        };
+
+                        // Every chunk of a streamed frame is acknowledged, the frame is handled
+                        // once its last chunk arrived.
+                        let frame = match frame.first() {
+                            Some(&CHUNK) => match streams.receive(&frame) {
+                                Ok((ack, assembled)) => {
+                                    if let Some(chunk_acks) = &chunk_acks {
+                                        let _ = chunk_acks.send(ack);
+                                    }
+                                    match assembled {
+                                        Some(frame) => frame,
+                                        None => continue,
+                                    }
+                                }
+                                Err(e) => {
+                                    inbound.log.warn(
+                                        "protocol errors",
+                                        peer,
+                                        format_args!("Protocol error from {}: {}", peer, e),
+                                    );
+                                    break;
+                                }
+                            },
+                            _ => frame,
+                        };
+
+                        // A batch carries several messages, which are handled one after the
+                        // other. The first one that is delivered takes the permit of the frame.
+                        let frames = match unbatch(frame) {
+                            Ok(frames) => frames,
+                            Err(e) => {
+                                inbound.log.warn(
+                                    "protocol errors",
+                                    peer,
+                                    format_args!("Protocol error from {}: {}", peer, e),
+                                );
+                                break;
+                            }
+                        };
+                        let mut permit = Some(permit);
+                        for m in frames {
+                            // Deserialize received message with the codec given by its format tag.
+                            let message = match inbound.codecs.decode_tolerant(&m) {
+                                Ok(Decoded::Message(message)) => message,
+                                Ok(Decoded::Unknown { tag, raw_bytes }) => match inbound.unknown {
+                                    UnknownPolicy::Skip => {
+                                        tracing::warn!(
+                                            %peer,
+                                            tag,
+                                            len = raw_bytes.len(),
+                                            "skipping frame with unknown format tag"
+                                        );
+                                        continue;
+                                    }
+                                    UnknownPolicy::Disconnect => {
+                                        tracing::warn!(%peer, tag, "closing connection, unknown format tag");
+                                        break 'frames;
+                                    }
+                                },
+                                // Every frame has a tag and a payload, the peer doesn't speak our
+                                // protocol.
+                                Err(e @ (CodecError::MissingTag | CodecError::EmptyPayload)) => {
+                                    inbound.log.warn(
+                                        "protocol errors",
+                                        peer,
+                                        format_args!("Protocol error from {}: {}", peer, e),
+                                    );
+                                    break 'frames;
+                                }
+                                // A corrupt frame or one of an incompatible version, the next one
+                                // may be fine. Too many in a row and the peer is likely broken.
+                                Err(e) => {
+                                    decode_failures += 1;
+                                    inbound.log.warn(
+                                        "decode errors",
+                                        peer,
+                                        format_args!("Failed to decode frame from {}: {}", peer, e),
+                                    );
+                                    if decode_failures > inbound.max_decode_failures {
+                                        tracing::warn!(
+                                            %peer,
+                                            decode_failures,
+                                            "closing connection after decode errors"
+                                        );
+                                        break 'frames;
+                                    }
+                                    continue;
+                                }
+                            };
+                            decode_failures = 0;
+
+                            // Heartbeats only keep the connection alive, they are answered instead of
+                            // delivered and don't identify the remote node.
+                            if message.kind() == MessageKind::Heartbeat {
+                                tracing::trace!(%peer, "received heartbeat");
+                                if let Some(heartbeats) = &heartbeats {
+                                    let _ = heartbeats.send(());
+                                }
+                                continue;
+                            }
+
+                            // The sender of the first message identifies the remote node.
+                            if identity.is_none() {
+                                identity = Some(message.sender);
+                                tracing::Span::current()
+                                    .record("sender", tracing::field::display(message.sender));
+                                if !connection.register(message.sender, close.clone()) {
+                                    tracing::info!(%peer, sender = %message.sender, "rejecting duplicate connection");
+                                    break 'frames;
+                                }
+                                if let Some(grants) = &grants {
+                                    inbound.grants.register(message.sender, grants.clone());
+                                }
+                                inbound.peer_event(PeerEvent::Connected(message.sender));
+                            }
+
+                            inbound.bandwidth.record(message.sender, m.len() as u64);
+                            inbound.observer.message_received(message.sender, m.len());
+                            tracing::debug!(sender = %message.sender, len = m.len(), "received message");
+                            if let Some(seq) = message.sequence() {
+                                inbound.received.record(message.sender, seq);
+                                // Whatever happens to the message from here on, it was read.
+                                if let Some(acks) = &acks {
+                                    let _ = acks.send(seq);
+                                }
+                            }
+
+                            // Messages that arrived early wait for their predecessors, which may
+                            // arrive over another connection. They don't hold on to their permits.
+                            // Those of a topic only wait for the predecessors in their topic.
+                            let sender = message.sender;
+                            let ready = match (&inbound.topics, &inbound.reorder) {
+                                (Some(topics), _) if message.topic().is_some() => {
+                                    topics.push(InboundMessage { message, peer })
+                                }
+                                (_, Some(reorder)) => {
+                                    let (ready, skipped) = reorder.push(InboundMessage { message, peer });
+                                    if skipped > 0 {
+                                        tracing::warn!(%sender, skipped, "skipping missing messages");
+                                    }
+                                    ready
+                                }
+                                _ => vec![InboundMessage { message, peer }],
+                            };
+                            for InboundMessage { mut message, peer } in ready {
+                                let epochs = inbound.epochs.as_ref();
+                                if epochs.is_some_and(|epochs| !epochs.admit(&message)) {
+                                    tracing::debug!(%peer, sender = %message.sender, "dropping stale message");
+                                    continue;
+                                }
+                                if let (Some(dedup), Some(id)) = (&inbound.dedup, message.id()) {
+                                    if !dedup.lock().unwrap().insert(message.sender, id, Instant::now()) {
+                                        tracing::debug!(%peer, sender = %message.sender, id, "dropping duplicate message");
+                                        continue;
+                                    }
+                                }
+                                inbound.interceptors.apply(&mut message);
+                                if inbound.gate.as_ref().is_some_and(|gate| !gate.admit()) {
+                                    tracing::warn!(%peer, sender = %message.sender, "dropping message, Core isn't ready");
+                                    continue;
+                                }
+                                inbound.inflight.add(1);
+                                let _ = tx_forward.send((InboundMessage { message, peer }, permit.take()));
+                            }
+                        }
+                    }
+                    // If there is some error with the framed TCP stream return. This will
+                    // kill the worker thread. A partial frame is never decoded, neither is one
+                    // longer than max_frame_length.
+                    Err(e) => {
+                        if transport.decoder().mid_frame() {
+                            tracing::warn!(%peer, error = %e, "connection reset mid-frame");
+                            inbound.observer.reset_mid_frame(peer);
+                        } else {
+                            tracing::warn!(%peer, error = %e, "closing connection");
+                        }
+                        break;
+                    }
+                }
+            }
+            if let Some(identity) = identity {
+                if connection.unregister(identity) {
+                    inbound.peer_event(PeerEvent::Disconnected(identity));
+                }
+                if let Some(grants) = &grants {
+                    inbound.grants.unregister(&identity, grants);
+                }
+            }
+
+            // Messages that were already read are still delivered.
+            drop(tx_forward);
+            let _ = forwarder.await;
+            inbound.outstanding.unregister(&peer);
+            inbound.observer.disconnected(peer);
+        }
+        .instrument(span);
+        tokio::spawn(worker)
+    }
+
+    // Debugging aid: every line received on the connection is delivered as the content of a
+    // message, so a node can be poked with netcat.
+    fn spawn_text_worker(
+        socket: Box<dyn Socket>,
+        peer: SocketAddr,
+        address: SocketAddr,
+        deliver: Sender<InboundMessage>,
+        interceptors: Interceptors,
+        inflight: Inflight,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let _permit = permit;
+            let codec = LinesCodec::new_with_max_length(MAX_FRAME_LENGTH);
+            let mut transport = Framed::new(socket, codec);
+            while let Some(line) = transport.next().await {
+                match line {
+                    Ok(line) => {
+                        let mut message = NetworkMessage::new(peer, vec![address], line);
+                        interceptors.apply(&mut message);
+                        inflight.add(1);
+                        if deliver
+                            .send(InboundMessage { message, peer })
+                            .await
+                            .is_err()
+                        {
+                            tracing::warn!(%peer, "deliver channel is closed, dropping message");
+                            inflight.remove(1);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(%peer, error = %e, "closing connection");
+                        return;
+                    }
+                }
+            }
+            tracing::info!(%peer, "connection closed by peer");
+        })
+    }
+}
+
+// State shared between the NetworkReceiver and its workers.
+#[derive(Clone)]
+struct Inbound {
+    // Channel where received messages are put in.
+    deliver: Sender<InboundMessage>,
+
+    // Formats that can be decoded.
+    codecs: Codecs,
+
+    // Applied to every received message after it is deserialized.
+    interceptors: Interceptors,
+
+    // Bytes received from each remote node.
+    bandwidth: Bandwidth,
+
+    // Frames read but not delivered yet, per connection.
+    outstanding: OutstandingFrames,
+    max_outstanding: usize,
+
+    // What happens to frames of an unknown format.
+    unknown: UnknownPolicy,
+
+    // Coalesces the warnings about protocol errors.
+    log: LogLimiter,
+
+    // Connections announcing longer frames are closed.
+    max_frame_length: usize,
+
+    // Frames in a row that may fail to decode before the connection is closed.
+    max_decode_failures: usize,
+
+    // Whether connections have to start with a handshake, and the node ids learned from them.
+    handshake: bool,
+    node_ids: NodeIds,
+
+    // Highest sequence number read from each remote node.
+    received: HighWaterMarks,
+
+    // Number of bytes of every frame to log, None disables logging frames.
+    frame_dump: Option<usize>,
+
+    // How long a connection may go without a frame before it is closed.
+    idle_timeout: Option<Duration>,
+
+    // Applies the early policy until Core is ready.
+    gate: Option<Gate>,
+
+    // Receives the events of the workers.
+    observer: Arc<dyn ObserverSink>,
+
+    // How connections are upgraded to TLS.
+    tls: ServerTls,
+
+    // Verifies every read frame.
+    #[cfg(feature = "hmac")]
+    auth: Option<FrameAuth>,
+
+    // Initial credits of a connection with flow control, None disables flow control.
+    credits: Option<u32>,
+
+    // Grants further credits to the connected nodes.
+    grants: Credits,
+
+    // Drops stale messages.
+    epochs: Option<EpochFilter>,
+
+    // Ids of the messages delivered so far, None delivers duplicates.
+    dedup: Option<Arc<Mutex<DedupCache>>>,
+
+    // Puts the messages of each remote node back into sequence, None delivers them as they
+    // arrive.
+    reorder: Option<PeerReorder>,
+
+    // Applies the ordering of every topic per remote node, None leaves messages with a topic to
+    // reorder.
+    topics: Option<PeerTopics>,
+
+    // Messages put into the deliver channel but not read yet.
+    inflight: Inflight,
+
+    // Gets the connects and disconnects of the remote nodes.
+    events: Option<UnboundedSender<NetworkEvent>>,
+}
+
+impl Inbound {
+    fn peer_event(&self, event: PeerEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(NetworkEvent::Peer(event));
+        }
+    }
+
+    // Check the authentication tag of a read frame and strip it.
+    fn verify(&self, frame: BytesMut) -> Result<BytesMut, CodecError> {
+        #[cfg(feature = "hmac")]
+        if let Some(auth) = &self.auth {
+            return auth.verify(frame);
+        }
+        Ok(frame)
+    }
+}
+
+// Holds back messages that arrive before Core is ready.
+#[derive(Clone)]
+struct Gate {
+    readiness: Readiness,
+    policy: EarlyPolicy,
+
+    // Messages delivered before Core was ready, over all connections.
+    early: Arc<AtomicUsize>,
+}
+
+impl Gate {
+    // Returns false if the message has to be dropped.
+    fn admit(&self) -> bool {
+        if self.readiness.is_ready() {
+            return true;
+        }
+        match self.policy {
+            EarlyPolicy::Buffer(capacity) => self.early.fetch_add(1, Ordering::SeqCst) < capacity,
+            EarlyPolicy::Hold => true,
+        }
+    }
+}
+
+// Open inbound connections, mapped from the remote node to the connection id and the handle to
+// close the connection.
+type Connections = Arc<Mutex<HashMap<SocketAddr, (u64, Arc<Notify>)>>>;
+
+// A single inbound connection.
+struct Connection {
+    id: u64,
+    connections: Connections,
+    policy: DuplicatePolicy,
+
+    // Counts the connection towards ReceiverConfig::max_connections until it is closed.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Connection {
+    // Register the connection as belonging to the given node. Returns false if the connection
+    // has to be rejected.
+    fn register(&self, identity: SocketAddr, close: Arc<Notify>) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match self.policy {
+            DuplicatePolicy::KeepNewest => {
+                if let Some((_, old)) = connections.insert(identity, (self.id, close)) {
+                    old.notify_one();
+                }
+                true
+            }
+            DuplicatePolicy::KeepOldest => {
+                if connections.contains_key(&identity) {
+                    return false;
+                }
+                connections.insert(identity, (self.id, close));
+                true
+            }
+            DuplicatePolicy::AllowBoth => true,
+        }
+    }
+
+    // Remove the connection, unless it was already replaced by a newer one. Returns false if
+    // it was replaced, or rejected as a duplicate.
+    fn unregister(&self, identity: SocketAddr) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match connections.get(&identity) {
+            Some((id, _)) if *id == self.id => {
+                connections.remove(&identity);
+                true
+            }
+            Some(_) => false,
+            None => self.policy == DuplicatePolicy::AllowBoth,
+        }
+    }
+}
+
+// Wait for the next data of a connection. Fails once the connection was idle for the idle
+// timeout, without one it waits as long as it has to.
+async fn unless_idle<F: Future>(
+    idle_timeout: Option<Duration>,
+    next: F,
+) -> Result<F::Output, Elapsed> {
+    match idle_timeout {
+        Some(idle_timeout) => timeout(idle_timeout, next).await,
+        None => Ok(next.await),
+    }
+}
+
+// Where a NetworkReceiver accepts connections.
+enum Listener {
+    Tcp(TcpListener),
+    // Counts the accepted connections.
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, u16),
+}
+
+impl Listener {
+    fn bind_unix(path: &Path) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            let stale = std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket());
+            if stale {
+                std::fs::remove_file(path)?;
+            }
+            tokio::net::UnixListener::bind(path).map(|listener| Self::Unix(listener, 0))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    // Connections over a Unix socket have no remote address. Each gets an unspecified address
+    // of its own, which keys it like the ephemeral port of a TCP connection until the remote node
+    // identifies itself.
+    async fn accept(&mut self) -> std::io::Result<(Stream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), peer))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, accepted) => {
+                let (stream, _) = listener.accept().await?;
+                *accepted = accepted.wrapping_add(1).max(1);
+                let peer = SocketAddr::from(([0, 0, 0, 0], *accepted));
+                Ok((Stream::Unix(stream), peer))
+            }
+        }
+    }
+}
+
+// Bind a listener to the address, the way TcpListener::bind does but with SO_REUSEADDR up to
+// the caller.
+fn bind(address: SocketAddr, reuse_address: bool) -> std::io::Result<TcpListener> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(reuse_address)?;
+    socket.bind(address)?;
+    socket.listen(1024)
+}
+
+// Apply the socket options of a new connection. A connection whose options can't be set still
+// works, just with the system defaults, so failures are only logged.
+fn configure_socket(
+    stream: &TcpStream,
+    peer: SocketAddr,
+    nodelay: bool,
+    user_timeout: Option<Duration>,
+) {
+    if nodelay {
+        if let Err(e) = stream.set_nodelay(true) {
+            tracing::warn!(%peer, error = %e, "failed to set TCP_NODELAY");
+        }
+    }
+    if let Some(timeout) = user_timeout {
+        if let Err(e) = set_user_timeout(stream, timeout) {
+            tracing::warn!(%peer, error = %e, "failed to set user timeout");
+        }
     }
 }
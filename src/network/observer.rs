@@ -0,0 +1,248 @@
+use std::{fmt, net::SocketAddr};
+
+use crate::message::DeliveryFailed;
+
+#[cfg(test)]
+#[path = "tests/observer_tests.rs"]
+pub mod observer_tests;
+
+/// Receives the key events of the network components, so they can be exported to any metrics or
+/// logging system. Every method does nothing by default. Connections are identified by the
+/// address of the other end, messages by the peer they were sent to or received from.
+pub trait ObserverSink: fmt::Debug + Send + Sync {
+    fn message_sent(&self, _peer: SocketAddr, _bytes: usize) {}
+
+    fn message_received(&self, _peer: SocketAddr, _bytes: usize) {}
+
+    fn connected(&self, _peer: SocketAddr) {}
+
+    // An outgoing connection finished its handshakes and takes messages.
+    fn ready(&self, _peer: SocketAddr) {}
+
+    fn disconnected(&self, _peer: SocketAddr) {}
+
+    // A message is handed to the retransmitter after the given number of failed attempts.
+    fn retransmit(&self, _peer: SocketAddr, _attempts: usize) {}
+
+    // The retransmitter gave up on a message.
+    fn failed(&self, _peer: SocketAddr) {}
+
+    // A message to a new peer was dropped because the sender already tracks as many peers as it
+    // may.
+    fn peer_rejected(&self, _peer: SocketAddr) {}
+
+    // An inbound connection ended, by a reset or a close, in the middle of a frame. The partial
+    // frame was discarded.
+    fn reset_mid_frame(&self, _peer: SocketAddr) {}
+}
+
+/// Change of a connection to or from a peer, for the application to follow which peers are
+/// reachable. Every Connected is followed by a Disconnected once that connection is gone, a peer
+/// with several connections is reported for each of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    Connected(SocketAddr),
+    Disconnected(SocketAddr),
+}
+
+/// What the network reports to the application besides the messages it delivers, all in one
+/// channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkEvent {
+    Peer(PeerEvent),
+    // The retransmitter gave up on a message to one of its recipients.
+    DeliveryFailed(DeliveryFailed),
+}
+
+/// Ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl ObserverSink for NoopSink {}
+
+/// Logs every event at debug level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl ObserverSink for LogSink {
+    fn message_sent(&self, peer: SocketAddr, bytes: usize) {
+        tracing::debug!(%peer, bytes, "message sent");
+    }
+
+    fn message_received(&self, peer: SocketAddr, bytes: usize) {
+        tracing::debug!(%peer, bytes, "message received");
+    }
+
+    fn connected(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "connected");
+    }
+
+    fn ready(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "ready");
+    }
+
+    fn disconnected(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "disconnected");
+    }
+
+    fn retransmit(&self, peer: SocketAddr, attempts: usize) {
+        tracing::debug!(%peer, attempts, "retransmit");
+    }
+
+    fn failed(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "delivery failed");
+    }
+
+    fn peer_rejected(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "peer rejected");
+    }
+
+    fn reset_mid_frame(&self, peer: SocketAddr) {
+        tracing::debug!(%peer, "connection reset mid-frame");
+    }
+}
+
+/// Counts the events in Prometheus counters labeled by peer.
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone)]
+pub struct PrometheusSink {
+    events: prometheus::IntCounterVec,
+    bytes: prometheus::IntCounterVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusSink {
+    /// Create the counters and register them with the given registry.
+    pub fn new(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        let events = prometheus::IntCounterVec::new(
+            prometheus::Opts::new("network_events_total", "Network events by kind and peer"),
+            &["event", "peer"],
+        )?;
+        let bytes = prometheus::IntCounterVec::new(
+            prometheus::Opts::new("network_bytes_total", "Message bytes by direction and peer"),
+            &["direction", "peer"],
+        )?;
+        registry.register(Box::new(events.clone()))?;
+        registry.register(Box::new(bytes.clone()))?;
+        Ok(Self { events, bytes })
+    }
+
+    fn event(&self, event: &str, peer: SocketAddr) {
+        self.events
+            .with_label_values(&[event, &peer.to_string()])
+            .inc();
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl ObserverSink for PrometheusSink {
+    fn message_sent(&self, peer: SocketAddr, bytes: usize) {
+        self.event("sent", peer);
+        self.bytes
+            .with_label_values(&["sent", &peer.to_string()])
+            .inc_by(bytes as u64);
+    }
+
+    fn message_received(&self, peer: SocketAddr, bytes: usize) {
+        self.event("received", peer);
+        self.bytes
+            .with_label_values(&["received", &peer.to_string()])
+            .inc_by(bytes as u64);
+    }
+
+    fn connected(&self, peer: SocketAddr) {
+        self.event("connected", peer);
+    }
+
+    fn ready(&self, peer: SocketAddr) {
+        self.event("ready", peer);
+    }
+
+    fn disconnected(&self, peer: SocketAddr) {
+        self.event("disconnected", peer);
+    }
+
+    fn retransmit(&self, peer: SocketAddr, _attempts: usize) {
+        self.event("retransmit", peer);
+    }
+
+    fn failed(&self, peer: SocketAddr) {
+        self.event("failed", peer);
+    }
+
+    fn peer_rejected(&self, peer: SocketAddr) {
+        self.event("peer_rejected", peer);
+    }
+
+    fn reset_mid_frame(&self, peer: SocketAddr) {
+        self.event("reset_mid_frame", peer);
+    }
+}
+
+/// Sends the events as StatsD counters over UDP. Metrics are named `<prefix>.<event>`, sending is
+/// best effort and errors are ignored.
+#[cfg(feature = "statsd")]
+#[derive(Debug)]
+pub struct StatsdSink {
+    socket: std::net::UdpSocket,
+    prefix: String,
+}
+
+#[cfg(feature = "statsd")]
+impl StatsdSink {
+    pub fn new(server: SocketAddr, prefix: &str) -> std::io::Result<Self> {
+        let socket = std::net::UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(server)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn count(&self, metric: &str, value: usize) {
+        let line = format!("{}.{}:{}|c", self.prefix, metric, value);
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+#[cfg(feature = "statsd")]
+impl ObserverSink for StatsdSink {
+    fn message_sent(&self, _peer: SocketAddr, bytes: usize) {
+        self.count("sent", 1);
+        self.count("sent_bytes", bytes);
+    }
+
+    fn message_received(&self, _peer: SocketAddr, bytes: usize) {
+        self.count("received", 1);
+        self.count("received_bytes", bytes);
+    }
+
+    fn connected(&self, _peer: SocketAddr) {
+        self.count("connected", 1);
+    }
+
+    fn ready(&self, _peer: SocketAddr) {
+        self.count("ready", 1);
+    }
+
+    fn disconnected(&self, _peer: SocketAddr) {
+        self.count("disconnected", 1);
+    }
+
+    fn retransmit(&self, _peer: SocketAddr, _attempts: usize) {
+        self.count("retransmit", 1);
+    }
+
+    fn failed(&self, _peer: SocketAddr) {
+        self.count("failed", 1);
+    }
+
+    fn peer_rejected(&self, _peer: SocketAddr) {
+        self.count("peer_rejected", 1);
+    }
+
+    fn reset_mid_frame(&self, _peer: SocketAddr) {
+        self.count("reset_mid_frame", 1);
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::message::{InboundMessage, NetworkMessage};
+use crate::network::Delivery;
+
+#[cfg(test)]
+#[path = "tests/ordering_tests.rs"]
+pub mod ordering_tests;
+
+/// Ordering guarantee of a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryOrder {
+    // Messages are delivered strictly in sequence, out of order arrivals are held back.
+    #[default]
+    Strict,
+    // Messages are delivered as soon as they arrive.
+    Unordered,
+}
+
+/// Puts items that arrive out of order back into sequence. Sequence numbers start at 0 and the
+/// amount of held back items is bounded: if the buffer is full, the oldest gap is skipped.
+pub struct ReorderBuffer<T> {
+    // Sequence number of the next item to deliver.
+    next: u64,
+
+    // Items that arrived before their predecessors.
+    pending: BTreeMap<u64, T>,
+
+    // Maximum number of held back items.
+    capacity: usize,
+
+    // Sequence numbers that were skipped because the buffer was full.
+    skipped: u64,
+
+    // Sequence numbers ahead of next that won't arrive, their sender said so.
+    gone: BTreeSet<u64>,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self::starting_at(0, capacity)
+    }
+
+    /// Buffer whose first item has the given sequence number.
+    pub fn starting_at(next: u64, capacity: usize) -> Self {
+        Self {
+            next,
+            pending: BTreeMap::new(),
+            capacity: capacity.max(1),
+            skipped: 0,
+            gone: BTreeSet::new(),
+        }
+    }
+
+    /// Add an item and return every item that can be delivered now, in order. Items with an already
+    /// delivered sequence number are dropped, and so are those that were given up on with skip.
+    pub fn push(&mut self, seq: u64, item: T) -> Vec<T> {
+        if seq < self.next || self.gone.contains(&seq) {
+            return Vec::new();
+        }
+        self.pending.insert(seq, item);
+
+        // Skip the gap if too many items are waiting for it.
+        if self.pending.len() > self.capacity {
+            if let Some(first) = self.pending.keys().next() {
+                self.skipped += first - self.next;
+                self.next = *first;
+                self.gone = self.gone.split_off(first);
+            }
+        }
+        self.release()
+    }
+
+    /// Stop waiting for the item with the given sequence number, because its sender won't send
+    /// it under this number. Returns every item that can be delivered now, in order. If the item
+    /// arrives anyway it is dropped. At most capacity numbers are given up on ahead of time.
+    pub fn skip(&mut self, seq: u64) -> Vec<T> {
+        let known = seq < self.next || self.pending.contains_key(&seq);
+        if !known && self.gone.len() < self.capacity {
+            self.gone.insert(seq);
+        }
+        self.release()
+    }
+
+    // Hand out the items that no gap comes before, in sequence.
+    fn release(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        loop {
+            if let Some(item) = self.pending.remove(&self.next) {
+                ready.push(item);
+            } else if !self.gone.remove(&self.next) {
+                break;
+            }
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Sequence number the buffer is waiting for.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// Number of held back items.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Number of sequence numbers that were never delivered because the buffer was full.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+/// Reorder buffers of the remote nodes of a NetworkReceiver, shared between its workers, so
+/// messages of a node are put back into sequence even if they arrive over different connections.
+#[derive(Clone)]
+pub(crate) struct PeerReorder {
+    buffers: Arc<Mutex<HashMap<SocketAddr, ReorderBuffer<InboundMessage>>>>,
+    capacity: usize,
+}
+
+impl PeerReorder {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            buffers: Arc::default(),
+            capacity,
+        }
+    }
+
+    // Add a message and return the messages of its sender that can be delivered now, in order,
+    // together with the number of sequence numbers that were skipped on the way because the
+    // buffer was full. Messages without a sequence number pass. The numbers the message says
+    // won't arrive aren't waited for, see SKIPPED. A sender numbers its messages from 1, so
+    // getting that number again starts the sequence over, e.g. after the sender restarted.
+    pub(crate) fn push(&self, inbound: InboundMessage) -> (Vec<InboundMessage>, u64) {
+        let seq = match inbound.message.sequence() {
+            Some(seq) => seq,
+            None => return (vec![inbound], 0),
+        };
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = buffers
+            .entry(inbound.message.sender)
+            .or_insert_with(|| ReorderBuffer::starting_at(1, self.capacity));
+        if seq == 1 && buffer.next() > 1 {
+            *buffer = ReorderBuffer::starting_at(1, self.capacity);
+        }
+        let skipped = buffer.skipped();
+        let mut ready = Vec::new();
+        for gone in inbound.message.skipped() {
+            ready.extend(buffer.skip(gone));
+        }
+        ready.extend(buffer.push(seq, inbound));
+        (ready, buffer.skipped() - skipped)
+    }
+}
+
+/// Ordering guarantees of topics, see TopicOrdering.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    // Ordering of the topics. Topics without an entry use the default.
+    orders: HashMap<String, DeliveryOrder>,
+    default: DeliveryOrder,
+
+    // Maximum number of held back items per strict topic.
+    capacity: usize,
+}
+
+impl Topics {
+    pub fn new(default: DeliveryOrder, capacity: usize) -> Self {
+        Self {
+            orders: HashMap::new(),
+            default,
+            capacity,
+        }
+    }
+
+    /// Declare the ordering guarantee of a topic.
+    pub fn declare(mut self, topic: &str, order: DeliveryOrder) -> Self {
+        self.orders.insert(topic.to_string(), order);
+        self
+    }
+
+    pub fn order(&self, topic: &str) -> DeliveryOrder {
+        self.orders.get(topic).copied().unwrap_or(self.default)
+    }
+}
+
+/// Applies the ordering guarantee of each topic. Every strict topic has its own sequence, so a gap
+/// in one topic never holds back messages of another.
+pub struct TopicOrdering<T> {
+    topics: Topics,
+
+    // Reorder buffer of every strict topic.
+    buffers: HashMap<String, ReorderBuffer<T>>,
+}
+
+impl<T> TopicOrdering<T> {
+    pub fn new(default: DeliveryOrder, capacity: usize) -> Self {
+        Self::with_topics(Topics::new(default, capacity))
+    }
+
+    pub fn with_topics(topics: Topics) -> Self {
+        Self {
+            topics,
+            buffers: HashMap::new(),
+        }
+    }
+
+    /// Declare the ordering guarantee of a topic.
+    pub fn declare(&mut self, topic: &str, order: DeliveryOrder) {
+        self.topics.orders.insert(topic.to_string(), order);
+    }
+
+    pub fn order(&self, topic: &str) -> DeliveryOrder {
+        self.topics.order(topic)
+    }
+
+    /// Add an item of a topic and return every item that can be delivered now.
+    pub fn push(&mut self, topic: &str, seq: u64, item: T) -> Vec<T> {
+        match self.order(topic) {
+            DeliveryOrder::Unordered => vec![item],
+            DeliveryOrder::Strict => {
+                let capacity = self.topics.capacity;
+                self.buffers
+                    .entry(topic.to_string())
+                    .or_insert_with(|| ReorderBuffer::new(capacity))
+                    .push(seq, item)
+            }
+        }
+    }
+
+    /// Start the sequence of the topic over, e.g. because its sender restarted. Items that were
+    /// held back are dropped.
+    pub fn restart(&mut self, topic: &str) {
+        self.buffers.remove(topic);
+    }
+
+    /// Sequence number the topic is waiting for.
+    pub fn next(&self, topic: &str) -> u64 {
+        self.buffers.get(topic).map_or(0, ReorderBuffer::next)
+    }
+}
+
+/// Topic orderings of the remote nodes of a NetworkReceiver, shared between its workers, so every
+/// node has a sequence per topic.
+#[derive(Clone)]
+pub(crate) struct PeerTopics {
+    orderings: Arc<Mutex<HashMap<SocketAddr, TopicOrdering<InboundMessage>>>>,
+    topics: Topics,
+}
+
+impl PeerTopics {
+    pub(crate) fn new(topics: Topics) -> Self {
+        Self {
+            orderings: Arc::default(),
+            topics,
+        }
+    }
+
+    // Add a message and return the messages of its sender that can be delivered now. Messages
+    // without a topic or topic sequence number pass. Getting number 0 of a topic again starts
+    // the topic over, e.g. after the sender restarted.
+    pub(crate) fn push(&self, inbound: InboundMessage) -> Vec<InboundMessage> {
+        let (topic, seq) = match (inbound.message.topic(), inbound.message.topic_sequence()) {
+            (Some(topic), Some(seq)) => (topic.to_string(), seq),
+            _ => return vec![inbound],
+        };
+        let mut orderings = self.orderings.lock().unwrap();
+        let ordering = orderings
+            .entry(inbound.message.sender)
+            .or_insert_with(|| TopicOrdering::with_topics(self.topics.clone()));
+        if seq == 0 && ordering.next(&topic) > 0 {
+            ordering.restart(&topic);
+        }
+        ordering.push(&topic, seq, inbound)
+    }
+}
+
+/// Sequence numbers per peer that were written to it but won't arrive under them, because their
+/// messages weren't acknowledged and are retransmitted, see SKIPPED. Shared between the workers
+/// of a NetworkSender.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SkippedSequences(Arc<Mutex<HashMap<SocketAddr, BTreeSet<u64>>>>);
+
+impl SkippedSequences {
+    // Remember the sequence number of the delivery, which is about to be retransmitted.
+    pub(crate) fn add(&self, delivery: &Delivery) {
+        if let Some(seq) = delivery.message.sequence() {
+            let mut peers = self.0.lock().unwrap();
+            peers.entry(delivery.address).or_default().insert(seq);
+        }
+    }
+
+    // Numbers to announce to the peer, those the message already announced included. A
+    // retransmitted message announces them again, its first copy may have been lost.
+    pub(crate) fn get(&self, peer: SocketAddr, message: &NetworkMessage) -> Vec<u64> {
+        let peers = self.0.lock().unwrap();
+        let mut skipped = peers.get(&peer).cloned().unwrap_or_default();
+        skipped.extend(message.skipped());
+        skipped.into_iter().collect()
+    }
+
+    // Forget the numbers that a written message announced.
+    pub(crate) fn announced(&self, peer: SocketAddr, skipped: &[u64]) {
+        if let Some(numbers) = self.0.lock().unwrap().get_mut(&peer) {
+            for seq in skipped {
+                numbers.remove(seq);
+            }
+        }
+    }
+}
+
+/// Numbers the messages of every topic per recipient, see TOPIC_SEQUENCE.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TopicSequences(Arc<Mutex<HashMap<(SocketAddr, String), u64>>>);
+
+impl TopicSequences {
+    // Give the message of the delivery the next number of its topic to the recipient, unless it
+    // has no topic or already has a number, like a retransmission. Returns whether it got one.
+    pub(crate) fn stamp(&self, delivery: &mut Delivery) -> bool {
+        let Some(topic) = delivery.message.topic() else {
+            return false;
+        };
+        if delivery.message.topic_sequence().is_some() {
+            return false;
+        }
+        let mut sequences = self.0.lock().unwrap();
+        let next = sequences
+            .entry((delivery.address, topic.to_string()))
+            .or_default();
+        delivery.message.set_topic_sequence(*next);
+        *next += 1;
+        true
+    }
+
+    // Hand the number stamp gave the delivery back, because it won't be written after all, so
+    // the next message of the topic takes it. Only a connection of a pool can have handed out a
+    // later number in between, that one stays a gap.
+    pub(crate) fn unstamp(&self, delivery: &Delivery) {
+        let (Some(topic), Some(seq)) =
+            (delivery.message.topic(), delivery.message.topic_sequence())
+        else {
+            return;
+        };
+        let mut sequences = self.0.lock().unwrap();
+        if let Some(next) = sequences.get_mut(&(delivery.address, topic.to_string())) {
+            if *next == seq + 1 {
+                *next = seq;
+            }
+        }
+    }
+}
+
+/// Keeps the messages to FIFO peers in order when some of them are retransmitted. Every message
+/// to such a peer gets a sequence number, and while an earlier message waits in the retransmitter
+/// the later ones are held back until it returns or is given up on. Shared between the
+/// NetworkSender, its workers and the NetworkRetransmitter.
+#[derive(Debug, Clone, Default)]
+pub struct RetransmitOrder(Arc<Mutex<HashMap<SocketAddr, PeerOrder>>>);
+
+#[derive(Debug, Default)]
+struct PeerOrder {
+    // Sequence number of the next new message.
+    next: u64,
+
+    // Messages currently handed to the retransmitter.
+    gaps: BTreeSet<u64>,
+
+    // Messages waiting for an earlier one to return from the retransmitter.
+    held: BTreeMap<u64, Delivery>,
+}
+
+impl PeerOrder {
+    // Hand out the held messages that no gap comes before, in sequence.
+    fn release(&mut self) -> Vec<Delivery> {
+        let gap = self.gaps.first().copied();
+        let mut ready = Vec::new();
+        while let Some(entry) = self.held.first_entry() {
+            if gap.is_some_and(|gap| *entry.key() > gap) {
+                break;
+            }
+            ready.push(entry.remove());
+        }
+        ready
+    }
+}
+
+impl RetransmitOrder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give a new message to the peer of the delivery the next sequence number.
+    pub fn stamp(&self, delivery: &mut Delivery) {
+        let mut peers = self.0.lock().unwrap();
+        let peer = peers.entry(delivery.address).or_default();
+        delivery.seq = Some(peer.next);
+        peer.next += 1;
+    }
+
+    /// Note that the delivery is handed to the retransmitter, later messages wait for it.
+    pub fn retransmitting(&self, delivery: &Delivery) {
+        if let Some(seq) = delivery.seq {
+            let mut peers = self.0.lock().unwrap();
+            peers.entry(delivery.address).or_default().gaps.insert(seq);
+        }
+    }
+
+    /// Returns the deliveries that can be sent now, in order: the given one and those it held
+    /// back, or none if it has to wait itself. Deliveries without a sequence number always pass.
+    pub fn admit(&self, delivery: Delivery) -> Vec<Delivery> {
+        let Some(seq) = delivery.seq else {
+            return vec![delivery];
+        };
+        let mut peers = self.0.lock().unwrap();
+        let peer = peers.entry(delivery.address).or_default();
+        peer.gaps.remove(&seq);
+        peer.held.insert(seq, delivery);
+        peer.release()
+    }
+
+    /// The retransmitter gave up on the delivery. Returns the deliveries that waited for it.
+    pub fn give_up(&self, delivery: &Delivery) -> Vec<Delivery> {
+        let Some(seq) = delivery.seq else {
+            return Vec::new();
+        };
+        let mut peers = self.0.lock().unwrap();
+        let peer = peers.entry(delivery.address).or_default();
+        peer.gaps.remove(&seq);
+        peer.release()
+    }
+}
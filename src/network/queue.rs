@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+#[cfg(test)]
+#[path = "tests/queue_tests.rs"]
+pub mod queue_tests;
+
+/// What happens to a message for a peer whose send queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // Wait until the worker made room. This holds up the messages to every other peer as well.
+    #[default]
+    Block,
+    // Drop the new message.
+    DropNewest,
+    // Drop the oldest queued message to make room for the new one.
+    DropOldest,
+    // Refuse the new message and report it as rejected.
+    Reject,
+}
+
+/// Result of pushing an item to a queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Push<T> {
+    Queued,
+    // The new item or, with DropOldest, the oldest item was dropped.
+    Dropped(T),
+    Rejected(T),
+    // The receiving side is gone, the item wasn't queued.
+    Closed(T),
+}
+
+/// Bounded queue with a single sending and a single receiving side, like a channel whose sender
+/// can drop queued items when it is full.
+pub fn bounded<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            sending: true,
+            receiving: true,
+        }),
+        capacity: capacity.max(1),
+        items: Notify::new(),
+        space: Notify::new(),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    // Notified when an item was queued or the sending side is gone.
+    items: Notify,
+    // Notified when an item was taken or the receiving side is gone.
+    space: Notify,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    items: VecDeque<T>,
+    sending: bool,
+    receiving: bool,
+}
+
+#[derive(Debug)]
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    /// Queue the item, applying the policy if the queue is full.
+    pub async fn push(&self, item: T, policy: OverflowPolicy) -> Push<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if !state.receiving {
+                    return Push::Closed(item);
+                }
+                if state.items.len() < self.shared.capacity {
+                    state.items.push_back(item);
+                    self.shared.items.notify_one();
+                    return Push::Queued;
+                }
+                match policy {
+                    OverflowPolicy::Block => (),
+                    OverflowPolicy::DropNewest => return Push::Dropped(item),
+                    OverflowPolicy::DropOldest => {
+                        let oldest = state.items.pop_front();
+                        state.items.push_back(item);
+                        return Push::Dropped(oldest.expect("the queue is full"));
+                    }
+                    OverflowPolicy::Reject => return Push::Rejected(item),
+                }
+            }
+            self.shared.space.notified().await;
+        }
+    }
+
+    /// Number of queued items.
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().sending = false;
+        self.shared.items.notify_one();
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Take the oldest item. Returns None once the queue is empty and the sending side is gone.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    self.shared.space.notify_one();
+                    return Some(item);
+                }
+                if !state.sending {
+                    return None;
+                }
+            }
+            self.shared.items.notified().await;
+        }
+    }
+
+    /// Take the oldest item if one is queued, without waiting for one.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let item = self.shared.state.lock().unwrap().items.pop_front()?;
+        self.shared.space.notify_one();
+        Some(item)
+    }
+
+    /// Stop receiving and return the items that are still queued. Further pushes fail.
+    pub fn close(self) -> Vec<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiving = false;
+        state.items.drain(..).collect()
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiving = false;
+        self.shared.space.notify_one();
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/quota_tests.rs"]
+pub mod quota_tests;
+
+/// What happens to a message that would exceed the quota of its peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotaPolicy {
+    // Hold the message back until the next window starts.
+    #[default]
+    Delay,
+    // Drop the message.
+    Reject,
+}
+
+/// Maximum number of bytes that may be sent to a peer per window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub bytes: u64,
+    pub window: Duration,
+    pub policy: QuotaPolicy,
+}
+
+/// Decision about a message that is about to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Send,
+    // The quota is exhausted, try again after the given time.
+    Wait(Duration),
+    Reject,
+}
+
+/// Bytes transferred with a peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteUsage {
+    pub total: u64,
+    // Bytes in the current window.
+    pub window: u64,
+    // Messages dropped because the quota was exhausted.
+    pub rejected: u64,
+}
+
+/// Counts the bytes sent to a peer in fixed windows and enforces an optional quota on them.
+#[derive(Debug, Clone, Copy)]
+pub struct ByteAccounter {
+    quota: Option<Quota>,
+    window_start: Instant,
+    usage: ByteUsage,
+}
+
+impl ByteAccounter {
+    pub fn new(quota: Option<Quota>, now: Instant) -> Self {
+        Self {
+            quota,
+            window_start: now,
+            usage: ByteUsage::default(),
+        }
+    }
+
+    /// Decide whether a message of the given size may be sent now, and count it if so. A message
+    /// that is larger than the whole quota is let through at the start of a window, otherwise it
+    /// could never be sent.
+    pub fn admit(&mut self, bytes: u64, now: Instant) -> Admission {
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None => {
+                self.record(bytes);
+                return Admission::Send;
+            }
+        };
+
+        if now.duration_since(self.window_start) >= quota.window {
+            self.window_start = now;
+            self.usage.window = 0;
+        }
+        if self.usage.window == 0 || self.usage.window + bytes <= quota.bytes {
+            self.record(bytes);
+            return Admission::Send;
+        }
+
+        match quota.policy {
+            QuotaPolicy::Delay => {
+                Admission::Wait(quota.window - now.duration_since(self.window_start))
+            }
+            QuotaPolicy::Reject => {
+                self.usage.rejected += 1;
+                Admission::Reject
+            }
+        }
+    }
+
+    /// Count bytes without applying the quota.
+    pub fn record(&mut self, bytes: u64) {
+        self.usage.total += bytes;
+        self.usage.window += bytes;
+    }
+
+    pub fn usage(&self) -> ByteUsage {
+        self.usage
+    }
+}
+
+/// Byte accounting per peer, shared between a component and its workers.
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    // Quota that applies to every peer.
+    quota: Option<Quota>,
+    peers: Arc<Mutex<HashMap<SocketAddr, ByteAccounter>>>,
+}
+
+impl Bandwidth {
+    pub fn new(quota: Option<Quota>) -> Self {
+        Self {
+            quota,
+            peers: Arc::default(),
+        }
+    }
+
+    pub fn admit(&self, peer: SocketAddr, bytes: u64) -> Admission {
+        let now = Instant::now();
+        self.peers
+            .lock()
+            .unwrap()
+            .entry(peer)
+            .or_insert_with(|| ByteAccounter::new(self.quota, now))
+            .admit(bytes, now)
+    }
+
+    pub fn record(&self, peer: SocketAddr, bytes: u64) {
+        let now = Instant::now();
+        self.peers
+            .lock()
+            .unwrap()
+            .entry(peer)
+            .or_insert_with(|| ByteAccounter::new(None, now))
+            .record(bytes);
+    }
+
+    /// Usage of a single peer.
+    pub fn get(&self, peer: &SocketAddr) -> Option<ByteUsage> {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(peer)
+            .map(|peer| peer.usage())
+    }
+
+    /// Usage of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, ByteUsage> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(address, peer)| (*address, peer.usage()))
+            .collect()
+    }
+}
+
+/// What a rate limit counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateUnit {
+    #[default]
+    Messages,
+    // Encoded bytes of the messages.
+    Bytes,
+}
+
+/// Token bucket limiting how fast messages are sent to a peer: it refills with per_second
+/// tokens every second and holds up to burst of them, a message takes one token per unit. Unlike
+/// a quota the limit never drops messages, the sender waits for the tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+    pub unit: RateUnit,
+}
+
+impl RateLimit {
+    pub fn messages(per_second: f64) -> Self {
+        Self {
+            per_second,
+            burst: 1.0,
+            unit: RateUnit::Messages,
+        }
+    }
+
+    pub fn bytes(per_second: f64) -> Self {
+        Self {
+            per_second,
+            burst: per_second,
+            unit: RateUnit::Bytes,
+        }
+    }
+
+    // Tokens a message of the given size takes.
+    fn cost(&self, bytes: usize) -> f64 {
+        match self.unit {
+            RateUnit::Messages => 1.0,
+            RateUnit::Bytes => bytes as f64,
+        }
+    }
+}
+
+/// Tokens of a rate limit, full at first.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            refilled: now,
+        }
+    }
+
+    /// Take the tokens of a message of the given size, or tell how long to wait until there are
+    /// enough. A message that costs more than the whole burst is let through once the bucket is
+    /// full and leaves it in debt, otherwise it could never be sent.
+    pub fn take(&mut self, bytes: usize, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.refilled = now;
+
+        let cost = self.limit.cost(bytes);
+        let needed = cost.min(self.limit.burst);
+        if self.tokens >= needed {
+            self.tokens -= cost;
+            return None;
+        }
+        let missing = needed - self.tokens;
+        Some(Duration::from_secs_f64(missing / self.limit.per_second))
+    }
+}
+
+/// Rate limits per peer, shared between the workers of a NetworkSender so the connections of a
+/// pool take from the same bucket.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits(Arc<Mutex<HashMap<SocketAddr, TokenBucket>>>);
+
+impl RateLimits {
+    /// Wait until a message of the given size may be sent to the peer.
+    pub async fn acquire(&self, peer: SocketAddr, limit: RateLimit, bytes: usize) {
+        loop {
+            let wait = self
+                .0
+                .lock()
+                .unwrap()
+                .entry(peer)
+                .or_insert_with(|| TokenBucket::new(limit, Instant::now()))
+                .take(bytes, Instant::now());
+            match wait {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return,
+            }
+        }
+    }
+}
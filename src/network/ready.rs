@@ -0,0 +1,70 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// Signals that a component, usually Core, is ready to process messages. Cloned handles share the
+/// same state and it can't be reset once set.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    ready: AtomicBool,
+    notify: Notify,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_ready(&self) {
+        self.inner.ready.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the component is ready, returns right away if it already is.
+    pub async fn wait(&self) {
+        loop {
+            // Register before checking, so a concurrent set_ready isn't missed.
+            let notified = self.inner.notify.notified();
+            if self.is_ready() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Tells long-running loops to stop, e.g. the accept loop of a NetworkReceiver. Cloned handles
+/// share the same state and it can't be reset once triggered.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Readiness);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.0.set_ready();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.is_ready()
+    }
+
+    /// Wait until the shutdown is triggered, returns right away if it already is.
+    pub async fn wait(&self) {
+        self.0.wait().await
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::{
+        mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::{timeout, Duration},
+};
+
+use crate::message::{InboundMessage, NetworkMessage, Payload};
+use crate::network::Transport;
+
+#[cfg(test)]
+#[path = "tests/request_tests.rs"]
+pub mod request_tests;
+
+/// Why a request got no reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    // No reply arrived within the timeout of the Requester.
+    Timeout,
+    // The Requests transport is gone, so the request can't be sent or its reply received.
+    Closed,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Timeout => write!(f, "request timed out"),
+            RequestError::Closed => write!(f, "transport closed"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+// Requests waiting for their replies, by id.
+#[derive(Debug, Default)]
+struct Pending {
+    next_id: u64,
+    waiting: HashMap<u64, oneshot::Sender<NetworkMessage>>,
+}
+
+/// Transport that lets Requesters send requests and wait for their replies. A reply to a pending
+/// request resolves it and isn't received, every other message is received as usual. Requests
+/// are sent while the transport waits to receive, so whoever owns it has to keep receiving, like
+/// Core does.
+#[derive(Debug)]
+pub struct Requests<T> {
+    transport: T,
+    pending: Arc<Mutex<Pending>>,
+    outgoing: UnboundedReceiver<NetworkMessage>,
+
+    // Handed to the Requesters, which keeps outgoing open while the transport exists.
+    requests: UnboundedSender<NetworkMessage>,
+}
+
+impl<T: Transport> Requests<T> {
+    pub fn new(transport: T) -> Self {
+        let (requests, outgoing) = unbounded_channel();
+        Self {
+            transport,
+            pending: Arc::default(),
+            outgoing,
+            requests,
+        }
+    }
+
+    /// Handle that sends requests from the node at name, which fail if no reply arrived within
+    /// timeout. Cloned handles share the pending requests.
+    pub fn requester(&self, name: SocketAddr, timeout: Duration) -> Requester {
+        Requester {
+            name,
+            timeout,
+            pending: self.pending.clone(),
+            requests: self.requests.clone(),
+        }
+    }
+
+    // Resolve the request the message replies to. Returns the message if it isn't a reply.
+    fn resolve(&self, inbound: InboundMessage) -> Option<InboundMessage> {
+        let id = match inbound.message.reply_to() {
+            Some(id) => id,
+            None => return Some(inbound),
+        };
+        match self.pending.lock().unwrap().waiting.remove(&id) {
+            // The requester may have given up in the meantime, then nobody waits for the reply.
+            Some(waiting) => {
+                let _ = waiting.send(inbound.message);
+            }
+            None => tracing::debug!(peer = %inbound.peer, id, "dropping reply to unknown request"),
+        }
+        None
+    }
+}
+
+impl<T: Transport> Transport for Requests<T> {
+    async fn send(&mut self, message: NetworkMessage) -> Result<(), SendError<NetworkMessage>> {
+        self.transport.send(message).await
+    }
+
+    async fn recv(&mut self) -> Option<InboundMessage> {
+        loop {
+            tokio::select! {
+                inbound = self.transport.recv() => {
+                    if let Some(inbound) = self.resolve(inbound?) {
+                        return Some(inbound);
+                    }
+                }
+                Some(request) = self.outgoing.recv() => {
+                    if let Err(SendError(request)) = self.transport.send(request).await {
+                        // Fails the request at once instead of letting it time out.
+                        if let Some(id) = request.request_id() {
+                            self.pending.lock().unwrap().waiting.remove(&id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<InboundMessage> {
+        loop {
+            let inbound = self.transport.try_recv()?;
+            if let Some(inbound) = self.resolve(inbound) {
+                return Some(inbound);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Requests<T> {
+    // No reply can arrive anymore, so pending requests fail right away.
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().waiting.clear();
+    }
+}
+
+/// Sends requests through a Requests transport and waits for their replies.
+#[derive(Debug, Clone)]
+pub struct Requester {
+    name: SocketAddr,
+    timeout: Duration,
+    pending: Arc<Mutex<Pending>>,
+    requests: UnboundedSender<NetworkMessage>,
+}
+
+impl Requester {
+    /// Send the message to the node at address and wait for its reply, see
+    /// NetworkMessage::reply.
+    pub async fn request(
+        &self,
+        address: SocketAddr,
+        message: impl Into<Payload>,
+    ) -> Result<NetworkMessage, RequestError> {
+        let (reply, waiting) = oneshot::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let id = pending.next_id;
+            pending.next_id += 1;
+            pending.waiting.insert(id, reply);
+            id
+        };
+        let _forget = Forget {
+            pending: &self.pending,
+            id,
+        };
+        let mut request = NetworkMessage::unicast(self.name, address, message);
+        request.set_request_id(id);
+        self.requests
+            .send(request)
+            .map_err(|_| RequestError::Closed)?;
+        match timeout(self.timeout, waiting).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(RequestError::Closed),
+            Err(_) => Err(RequestError::Timeout),
+        }
+    }
+
+    /// Number of requests waiting for their replies.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().waiting.len()
+    }
+}
+
+// Forgets a request once it got its reply, timed out or its future was dropped.
+struct Forget<'a> {
+    pending: &'a Mutex<Pending>,
+    id: u64,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().waiting.remove(&self.id);
+    }
+}
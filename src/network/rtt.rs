@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/rtt_tests.rs"]
+pub mod rtt_tests;
+
+/// Smoothed round trip time estimation of a peer, computed like TCP does it (RFC 6298). Every ACK
+/// yields a sample of the time between sending a message and receiving its ACK.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttEstimator {
+    // Smoothed round trip time in seconds.
+    srtt: f64,
+
+    // Round trip time variation in seconds.
+    rttvar: f64,
+
+    // Number of samples seen so far.
+    samples: u64,
+}
+
+// Weights of a new sample, as recommended by RFC 6298.
+const ALPHA: f64 = 1.0 / 8.0;
+const BETA: f64 = 1.0 / 4.0;
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the estimation with the round trip time of a single message.
+    pub fn update(&mut self, sample: Duration) {
+        let sample = sample.as_secs_f64();
+        if self.samples == 0 {
+            self.srtt = sample;
+            self.rttvar = sample / 2.0;
+        } else {
+            self.rttvar = (1.0 - BETA) * self.rttvar + BETA * (self.srtt - sample).abs();
+            self.srtt = (1.0 - ALPHA) * self.srtt + ALPHA * sample;
+        }
+        self.samples += 1;
+    }
+
+    /// Smoothed round trip time, None if there wasn't any sample yet.
+    pub fn srtt(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| Duration::from_secs_f64(self.srtt))
+    }
+
+    /// Round trip time variation, None if there wasn't any sample yet.
+    pub fn rttvar(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| Duration::from_secs_f64(self.rttvar))
+    }
+
+    /// Retransmission timeout: smoothed round trip time plus four times the variation.
+    pub fn rto(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| Duration::from_secs_f64(self.srtt + 4.0 * self.rttvar))
+    }
+}
+
+/// A timeout that is either fixed or derived from the estimated round trip time of a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timeout {
+    Fixed(Duration),
+    // Multiple of the retransmission timeout. The fallback is used until there is an estimation
+    // and the result never goes below the minimum.
+    Rtt {
+        multiple: f64,
+        min: Duration,
+        fallback: Duration,
+    },
+}
+
+impl Timeout {
+    /// Returns the timeout for a peer with the given estimation.
+    pub fn resolve(&self, rtt: &RttEstimator) -> Duration {
+        match *self {
+            Timeout::Fixed(duration) => duration,
+            Timeout::Rtt {
+                multiple,
+                min,
+                fallback,
+            } => match rtt.rto() {
+                Some(rto) => rto.mul_f64(multiple).max(min),
+                None => fallback,
+            },
+        }
+    }
+}
+
+/// Round trip time estimations per peer, fed by the acknowledgements a NetworkSender gets.
+#[derive(Debug, Clone, Default)]
+pub struct PeerRtts(Arc<Mutex<HashMap<SocketAddr, RttEstimator>>>);
+
+impl PeerRtts {
+    pub(crate) fn sample(&self, peer: SocketAddr, rtt: Duration) {
+        self.0.lock().unwrap().entry(peer).or_default().update(rtt);
+    }
+
+    // Resolve the timeout with the estimation of the peer, or its fallback if there is none yet.
+    pub(crate) fn resolve(&self, peer: &SocketAddr, timeout: &Timeout) -> Duration {
+        let estimators = self.0.lock().unwrap();
+        timeout.resolve(&estimators.get(peer).copied().unwrap_or_default())
+    }
+
+    /// Estimation of a single peer, None if none of its messages was acknowledged yet.
+    pub fn get(&self, peer: &SocketAddr) -> Option<RttEstimator> {
+        self.0.lock().unwrap().get(peer).copied()
+    }
+
+    /// Estimations of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, RttEstimator> {
+        self.0.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,258 @@
+use std::cmp::Ordering;
+use std::collections::{hash_map::DefaultHasher, BinaryHeap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/scheduler_tests.rs"]
+pub mod scheduler_tests;
+
+/// Limits the number of outgoing connection attempts that are in progress at the same time, so a
+/// healing partition doesn't turn into a reconnect storm. If no permit is available, waiting
+/// connection attempts are served by priority first and by arrival second.
+#[derive(Clone)]
+pub struct ConnectScheduler {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    // Number of permits that can be handed out right away.
+    available: usize,
+
+    // Connection attempts waiting for a permit.
+    waiting: BinaryHeap<Waiter>,
+
+    // Counter used to keep waiters with the same priority in arrival order.
+    next: u64,
+}
+
+struct Waiter {
+    priority: u8,
+    seq: u64,
+    tx: oneshot::Sender<()>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: highest priority first, lowest sequence number on a tie.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+/// Permission to perform one connection attempt. The permit is given back when dropped.
+pub struct ConnectPermit {
+    scheduler: ConnectScheduler,
+}
+
+impl Drop for ConnectPermit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+// Receiving end of a queued acquire. If the acquire is cancelled after a permit was already handed
+// to it, the permit is passed on instead of being lost.
+struct Pending {
+    rx: oneshot::Receiver<()>,
+    scheduler: ConnectScheduler,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.rx.close();
+        if self.rx.try_recv().is_ok() {
+            self.scheduler.release();
+        }
+    }
+}
+
+impl ConnectScheduler {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                available: permits,
+                waiting: BinaryHeap::new(),
+                next: 0,
+            })),
+        }
+    }
+
+    /// Wait until a connection attempt with the given priority is allowed.
+    pub async fn acquire(&self, priority: u8) -> ConnectPermit {
+        let mut pending = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.available > 0 {
+                inner.available -= 1;
+                return ConnectPermit {
+                    scheduler: self.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = inner.next;
+            inner.next += 1;
+            inner.waiting.push(Waiter { priority, seq, tx });
+            Pending {
+                rx,
+                scheduler: self.clone(),
+            }
+        };
+
+        // The sending half is only dropped after handing over a permit, so this can't fail.
+        let _ = (&mut pending.rx).await;
+        ConnectPermit {
+            scheduler: self.clone(),
+        }
+    }
+
+    // Hand a freed permit to the most important waiter, or put it back if nobody is waiting.
+    fn release(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some(waiter) = inner.waiting.pop() {
+            // Sending fails if the waiter was cancelled in the meantime.
+            if waiter.tx.send(()).is_ok() {
+                return;
+            }
+        }
+        inner.available += 1;
+    }
+}
+
+/// Paces events to a maximum rate, e.g. spawning workers for new peers while a large cluster
+/// bootstraps. Events are spread out evenly, there are no bursts.
+pub struct Pacer {
+    // Minimum time between two events.
+    interval: Duration,
+
+    // Earliest time of the next event.
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    // Allow the given number of events per second.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: None,
+        }
+    }
+
+    /// Wait until the next event may happen.
+    pub async fn wait(&mut self) {
+        let now = Instant::now();
+        let at = match self.next {
+            Some(next) if next > now => {
+                sleep_until(next).await;
+                next
+            }
+            _ => now,
+        };
+        self.next = Some(at + self.interval);
+    }
+}
+
+/// Spreads messages over parallel connections to the same peer by smooth weighted round-robin.
+/// The weight of a connection is the inverse of its average send time, which follows the recent
+/// sends, so faster connections get more messages while slower ones still get their share.
+#[derive(Debug, Clone)]
+pub struct WeightedRoundRobin {
+    connections: Vec<ConnectionState>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ConnectionState {
+    // Moving average of the send time in seconds, None until the first send completed.
+    send_time: Option<f64>,
+
+    // Credit of the smooth weighted round-robin.
+    current: f64,
+}
+
+// Weight of the newest send time in the moving average.
+const SMOOTHING: f64 = 0.2;
+
+impl WeightedRoundRobin {
+    pub fn new(connections: usize) -> Self {
+        Self {
+            connections: vec![ConnectionState::default(); connections.max(1)],
+        }
+    }
+
+    /// Index of the connection the next message goes to.
+    pub fn pick(&mut self) -> usize {
+        let weights = self.weights();
+        let total: f64 = weights.iter().sum();
+        let mut best = 0;
+        for (i, weight) in weights.into_iter().enumerate() {
+            self.connections[i].current += weight;
+            if self.connections[i].current > self.connections[best].current {
+                best = i;
+            }
+        }
+        self.connections[best].current -= total;
+        best
+    }
+
+    /// Index of the connection a message with the given affinity key goes to. Messages with the
+    /// same key always take the same connection, messages without a key are spread as by pick.
+    pub fn pick_for(&mut self, key: Option<&str>) -> usize {
+        match key {
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % self.connections.len() as u64) as usize
+            }
+            None => self.pick(),
+        }
+    }
+
+    /// Record how long a send on the connection took.
+    pub fn record(&mut self, connection: usize, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64().max(f64::EPSILON);
+        if let Some(state) = self.connections.get_mut(connection) {
+            state.send_time = Some(match state.send_time {
+                Some(average) => average + SMOOTHING * (elapsed - average),
+                None => elapsed,
+            });
+        }
+    }
+
+    /// Share of the messages each connection currently gets. Connections without a completed
+    /// send are weighted like the average connection.
+    pub fn weights(&self) -> Vec<f64> {
+        let known: Vec<f64> = self
+            .connections
+            .iter()
+            .filter_map(|state| state.send_time.map(|time| 1.0 / time))
+            .collect();
+        let default = match known.len() {
+            0 => 1.0,
+            n => known.iter().sum::<f64>() / n as f64,
+        };
+        let weights: Vec<f64> = self
+            .connections
+            .iter()
+            .map(|state| state.send_time.map_or(default, |time| 1.0 / time))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        weights.into_iter().map(|weight| weight / total).collect()
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::Semaphore;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/stats_tests.rs"]
+pub mod stats_tests;
+
+/// Minimum, maximum and average of a series of durations.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DelayStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl DelayStats {
+    pub fn record(&mut self, delay: Duration) {
+        if self.count == 0 || delay < self.min {
+            self.min = delay;
+        }
+        self.max = self.max.max(delay);
+        self.total += delay;
+        self.count += 1;
+    }
+
+    /// Average delay, None if nothing was recorded yet.
+    pub fn avg(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count as u32)
+    }
+}
+
+/// Delay statistics per peer, shared between the workers of a NetworkSender.
+#[derive(Debug, Clone, Default)]
+pub struct PeerDelays(Arc<Mutex<HashMap<SocketAddr, DelayStats>>>);
+
+impl PeerDelays {
+    pub fn record(&self, peer: SocketAddr, delay: Duration) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(peer)
+            .or_default()
+            .record(delay);
+    }
+
+    /// Statistics of a single peer.
+    pub fn get(&self, peer: &SocketAddr) -> Option<DelayStats> {
+        self.0.lock().unwrap().get(peer).copied()
+    }
+
+    /// Statistics of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, DelayStats> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Highest sequence number per peer, either written to it by a NetworkSender or read from it by
+/// a NetworkReceiver. Comparing the marks of both ends shows whether messages went missing.
+#[derive(Debug, Clone, Default)]
+pub struct HighWaterMarks(Arc<Mutex<HashMap<SocketAddr, u64>>>);
+
+impl HighWaterMarks {
+    /// Raise the mark of the peer to seq, a lower number leaves it unchanged.
+    pub fn record(&self, peer: SocketAddr, seq: u64) {
+        let mut marks = self.0.lock().unwrap();
+        let mark = marks.entry(peer).or_default();
+        *mark = (*mark).max(seq);
+    }
+
+    /// Mark of a single peer, None if nothing was recorded for it.
+    pub fn get(&self, peer: &SocketAddr) -> Option<u64> {
+        self.0.lock().unwrap().get(peer).copied()
+    }
+
+    /// Marks of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, u64> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Frames per inbound connection that were read but not delivered yet, shared between a
+/// NetworkReceiver and its workers.
+#[derive(Debug, Clone, Default)]
+pub struct OutstandingFrames(Arc<Mutex<HashMap<SocketAddr, Permits>>>);
+
+// Limit of a connection and the semaphore handing out its permits.
+type Permits = (usize, Arc<Semaphore>);
+
+impl OutstandingFrames {
+    // Track the connection from the given remote address, which can't have more than limit
+    // outstanding frames.
+    pub(crate) fn register(&self, peer: SocketAddr, limit: usize) -> Arc<Semaphore> {
+        let limit = limit.max(1);
+        let semaphore = Arc::new(Semaphore::new(limit));
+        self.0
+            .lock()
+            .unwrap()
+            .insert(peer, (limit, semaphore.clone()));
+        semaphore
+    }
+
+    pub(crate) fn unregister(&self, peer: &SocketAddr) {
+        self.0.lock().unwrap().remove(peer);
+    }
+
+    /// Number of open inbound connections.
+    pub fn connections(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Outstanding frames of the connection from the given remote address, None if there is no
+    /// such connection.
+    pub fn get(&self, peer: &SocketAddr) -> Option<usize> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(peer)
+            .map(|(limit, semaphore)| limit - semaphore.available_permits())
+    }
+}
+
+/// Connection history of a single peer.
+#[derive(Debug, Clone, Default)]
+pub struct LinkState {
+    // Start of the current connection, None while disconnected.
+    since: Option<Instant>,
+
+    // Times the peer connected or disconnected, oldest first.
+    transitions: VecDeque<Instant>,
+}
+
+impl LinkState {
+    pub fn connected(&mut self, now: Instant, window: Duration) {
+        self.since = Some(now);
+        self.transition(now, window);
+    }
+
+    pub fn disconnected(&mut self, now: Instant, window: Duration) {
+        self.since = None;
+        self.transition(now, window);
+    }
+
+    fn transition(&mut self, now: Instant, window: Duration) {
+        self.transitions.push_back(now);
+        self.expire(now, window);
+    }
+
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some(oldest) = self.transitions.front() {
+            if now.duration_since(*oldest) < window {
+                break;
+            }
+            self.transitions.pop_front();
+        }
+    }
+
+    /// How long the current connection lasted, None while disconnected.
+    pub fn uptime(&self, now: Instant) -> Option<Duration> {
+        self.since.map(|since| now.duration_since(since))
+    }
+
+    /// Connects and disconnects per minute during the window before now.
+    pub fn flap_rate(&mut self, now: Instant, window: Duration) -> f64 {
+        self.expire(now, window);
+        if window.is_zero() {
+            return 0.0;
+        }
+        self.transitions.len() as f64 * 60.0 / window.as_secs_f64()
+    }
+}
+
+/// Connection history per peer, shared between the workers of a NetworkSender. A peer that keeps
+/// losing its connection has a high flap rate, which usually points to an unstable link.
+#[derive(Debug, Clone)]
+pub struct PeerLinks {
+    links: Arc<Mutex<HashMap<SocketAddr, LinkState>>>,
+
+    // Transitions older than this don't count towards the flap rate.
+    window: Duration,
+}
+
+impl PeerLinks {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            links: Arc::default(),
+            window,
+        }
+    }
+
+    pub(crate) fn connected(&self, peer: SocketAddr) {
+        let mut links = self.links.lock().unwrap();
+        links
+            .entry(peer)
+            .or_default()
+            .connected(Instant::now(), self.window);
+    }
+
+    pub(crate) fn disconnected(&self, peer: SocketAddr) {
+        let mut links = self.links.lock().unwrap();
+        links
+            .entry(peer)
+            .or_default()
+            .disconnected(Instant::now(), self.window);
+    }
+
+    /// How long the current connection to the peer lasted, None if it isn't connected.
+    pub fn uptime(&self, peer: &SocketAddr) -> Option<Duration> {
+        let links = self.links.lock().unwrap();
+        links.get(peer)?.uptime(Instant::now())
+    }
+
+    /// Connects and disconnects of the peer per minute during the window.
+    pub fn flap_rate(&self, peer: &SocketAddr) -> f64 {
+        let mut links = self.links.lock().unwrap();
+        links
+            .get_mut(peer)
+            .map_or(0.0, |link| link.flap_rate(Instant::now(), self.window))
+    }
+}
+
+/// Counters of a NetworkSender, either in total or for a single peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SenderCounts {
+    pub messages_sent: u64,
+    pub frames_sent: u64,
+    pub send_failures: u64,
+    pub retransmits: u64,
+    pub bytes_sent: u64,
+    pub connections_opened: u64,
+    pub connections_dropped: u64,
+}
+
+/// What a NetworkSender did so far, in total and per peer. Messages count when their frame was
+/// written to the connection, retransmits when the retransmitter handed a message back to the
+/// sender. With PeerConfig::batching a frame may carry several messages.
+#[derive(Debug, Default)]
+pub struct NetworkStats {
+    messages_sent: AtomicU64,
+    frames_sent: AtomicU64,
+    send_failures: AtomicU64,
+    retransmits: AtomicU64,
+    bytes_sent: AtomicU64,
+    connections_opened: AtomicU64,
+    connections_dropped: AtomicU64,
+
+    // The same counters for every peer.
+    peers: Mutex<HashMap<SocketAddr, SenderCounts>>,
+}
+
+impl NetworkStats {
+    pub(crate) fn sent(&self, peer: SocketAddr, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.update(peer, |counts| {
+            counts.messages_sent += 1;
+            counts.bytes_sent += bytes as u64;
+        });
+    }
+
+    pub(crate) fn frame_sent(&self, peer: SocketAddr) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.update(peer, |counts| counts.frames_sent += 1);
+    }
+
+    pub(crate) fn send_failed(&self, peer: SocketAddr) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+        self.update(peer, |counts| counts.send_failures += 1);
+    }
+
+    pub(crate) fn retransmit(&self, peer: SocketAddr) {
+        self.retransmits.fetch_add(1, Ordering::Relaxed);
+        self.update(peer, |counts| counts.retransmits += 1);
+    }
+
+    pub(crate) fn opened(&self, peer: SocketAddr) {
+        self.connections_opened.fetch_add(1, Ordering::Relaxed);
+        self.update(peer, |counts| counts.connections_opened += 1);
+    }
+
+    pub(crate) fn dropped(&self, peer: SocketAddr) {
+        self.connections_dropped.fetch_add(1, Ordering::Relaxed);
+        self.update(peer, |counts| counts.connections_dropped += 1);
+    }
+
+    fn update(&self, peer: SocketAddr, f: impl FnOnce(&mut SenderCounts)) {
+        f(self.peers.lock().unwrap().entry(peer).or_default());
+    }
+
+    /// Counters of all peers together.
+    pub fn totals(&self) -> SenderCounts {
+        SenderCounts {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            frames_sent: self.frames_sent.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            retransmits: self.retransmits.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            connections_opened: self.connections_opened.load(Ordering::Relaxed),
+            connections_dropped: self.connections_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counters of a single peer.
+    pub fn get(&self, peer: &SocketAddr) -> Option<SenderCounts> {
+        self.peers.lock().unwrap().get(peer).copied()
+    }
+
+    /// Counters of all peers.
+    pub fn snapshot(&self) -> HashMap<SocketAddr, SenderCounts> {
+        self.peers.lock().unwrap().clone()
+    }
+}
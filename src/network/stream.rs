@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::time::{Duration, Instant};
+
+use crate::message::{Chunk, ChunkAck};
+use crate::network::CodecError;
+
+#[cfg(test)]
+#[path = "tests/stream_tests.rs"]
+pub mod stream_tests;
+
+/// First byte of a frame that carries a chunk of a streamed message, see PeerConfig::streaming. No
+/// format tag of a message uses it.
+pub const CHUNK: u8 = 0x43;
+
+// Bytes of the id, index and total in front of the data of a chunk.
+const HEADER: usize = 8 + 4 + 4;
+
+/// Encode a chunk into a frame: the chunk tag, the id of the stream, the index of the chunk and
+/// the number of chunks, followed by the data.
+pub fn encode_chunk(chunk: &Chunk) -> Bytes {
+    let mut frame = BytesMut::with_capacity(1 + HEADER + chunk.data.len());
+    frame.put_u8(CHUNK);
+    frame.put_u64(chunk.message_id);
+    frame.put_u32(chunk.index);
+    frame.put_u32(chunk.total);
+    frame.put_slice(&chunk.data);
+    frame.freeze()
+}
+
+/// Decode a frame of encode_chunk. Fails if it isn't one or its index is out of range.
+pub fn decode_chunk(mut frame: &[u8]) -> Result<Chunk, CodecError> {
+    if frame.first() != Some(&CHUNK) || frame.len() < 1 + HEADER {
+        return Err(CodecError::MalformedChunk);
+    }
+    frame.advance(1);
+    let message_id = frame.get_u64();
+    let index = frame.get_u32();
+    let total = frame.get_u32();
+    if index >= total {
+        return Err(CodecError::MalformedChunk);
+    }
+    Ok(Chunk {
+        message_id,
+        index,
+        total,
+        data: frame.to_vec(),
+    })
+}
+
+/// Sending side of a streamed message. At most `window` chunks are unacknowledged at any time, a
+/// chunk whose ACK doesn't arrive within `timeout` is sent again. Acknowledged chunks are freed
+/// right away.
+pub struct StreamSender {
+    message_id: u64,
+
+    // Chunks that haven't been acknowledged yet. Acknowledged chunks are set to None.
+    chunks: Vec<Option<Vec<u8>>>,
+
+    // Maximum number of unacknowledged chunks.
+    window: usize,
+
+    // Maximum time to wait for the ACK of a chunk.
+    timeout: Duration,
+
+    // Index of the next chunk that was never sent.
+    next: usize,
+
+    // Chunks that were sent but not acknowledged yet, mapped to the time they were sent.
+    in_flight: BTreeMap<usize, Instant>,
+}
+
+impl StreamSender {
+    pub fn new(
+        message_id: u64,
+        data: &[u8],
+        chunk_size: usize,
+        window: usize,
+        timeout: Duration,
+    ) -> Self {
+        let chunks = data
+            .chunks(chunk_size.max(1))
+            .map(|c| Some(c.to_vec()))
+            .collect();
+        Self {
+            message_id,
+            chunks,
+            window: window.max(1),
+            timeout,
+            next: 0,
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the chunks that should be sent now: chunks whose ACK timed out first, then new
+    /// chunks as long as the window isn't full.
+    pub fn poll(&mut self, now: Instant) -> Vec<Chunk> {
+        let mut indices = self
+            .in_flight
+            .iter()
+            .filter(|(_, sent)| now.duration_since(**sent) >= self.timeout)
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        // Timed out chunks are still in flight, so they keep their place in the window.
+        while self.in_flight.len() < self.window && self.next < self.chunks.len() {
+            indices.push(self.next);
+            self.in_flight.insert(self.next, now);
+            self.next += 1;
+        }
+
+        indices
+            .into_iter()
+            .map(|index| {
+                self.in_flight.insert(index, now);
+                self.chunk(index)
+            })
+            .collect()
+    }
+
+    /// Process the ACK for a chunk and free its data.
+    pub fn ack(&mut self, ack: ChunkAck) {
+        if ack.message_id != self.message_id {
+            return;
+        }
+        let index = ack.index as usize;
+        if self.in_flight.remove(&index).is_some() {
+            self.chunks[index] = None;
+        }
+    }
+
+    /// True if every chunk was acknowledged.
+    pub fn is_complete(&self) -> bool {
+        self.next == self.chunks.len() && self.in_flight.is_empty()
+    }
+
+    /// Time at which the oldest chunk in flight times out, None if no chunk is in flight.
+    pub fn deadline(&self) -> Option<Instant> {
+        let sent = self.in_flight.values().min()?;
+        Some(*sent + self.timeout)
+    }
+
+    fn chunk(&self, index: usize) -> Chunk {
+        Chunk {
+            message_id: self.message_id,
+            index: index as u32,
+            total: self.chunks.len() as u32,
+            data: self.chunks[index].clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Receiving side of a streamed message. Collects chunks in any order and acknowledges each one.
+pub struct StreamReceiver {
+    message_id: u64,
+
+    // Chunks received so far by index, only those take memory.
+    chunks: BTreeMap<u32, Vec<u8>>,
+    total: u32,
+}
+
+impl StreamReceiver {
+    pub fn new(message_id: u64, total: u32) -> Self {
+        Self {
+            message_id,
+            chunks: BTreeMap::new(),
+            total,
+        }
+    }
+
+    /// Store a chunk and return the ACK that has to be sent back. Chunks of other messages or with
+    /// an invalid index are ignored.
+    pub fn receive(&mut self, chunk: Chunk) -> Option<ChunkAck> {
+        if chunk.message_id != self.message_id || chunk.index >= self.total {
+            return None;
+        }
+        self.chunks.insert(chunk.index, chunk.data);
+        Some(ChunkAck {
+            message_id: self.message_id,
+            index: chunk.index,
+        })
+    }
+
+    /// Returns the reassembled message once every chunk was received.
+    pub fn assemble(&self) -> Option<Vec<u8>> {
+        if self.chunks.len() < self.total as usize {
+            return None;
+        }
+        Some(self.chunks.values().flatten().copied().collect())
+    }
+}
+
+// Streams of a connection that are complete, so retransmitted chunks of them are only
+// acknowledged. Chunks are retransmitted shortly after, so the last few are enough.
+const COMPLETED: usize = 16;
+
+/// Streamed messages arriving over a connection of a NetworkReceiver. Every chunk is
+/// acknowledged, complete messages are handed out as the frame they were split from.
+pub(crate) struct Streams {
+    receivers: HashMap<u64, StreamReceiver>,
+
+    // Bytes of the frames of the chunks that are kept so far.
+    len: usize,
+
+    // Limit of len. A stream carries a single frame, so twice its maximum length leaves room for
+    // the headers of its chunks.
+    max_length: usize,
+
+    completed: VecDeque<u64>,
+}
+
+impl Streams {
+    pub(crate) fn new(max_frame_length: usize) -> Self {
+        Self {
+            receivers: HashMap::new(),
+            len: 0,
+            max_length: max_frame_length.saturating_mul(2),
+            completed: VecDeque::new(),
+        }
+    }
+
+    // Keep the chunk of the frame and return its acknowledgement, together with the whole frame
+    // once it is the last missing chunk of its stream. Fails if the frame isn't a chunk or the
+    // streams of the connection would grow beyond their limit.
+    pub(crate) fn receive(
+        &mut self,
+        frame: &[u8],
+    ) -> Result<(ChunkAck, Option<BytesMut>), CodecError> {
+        let chunk = decode_chunk(frame)?;
+        let ack = ChunkAck {
+            message_id: chunk.message_id,
+            index: chunk.index,
+        };
+        if self.completed.contains(&chunk.message_id) {
+            return Ok((ack, None));
+        }
+        // Kept chunks count with their header, so many tiny chunks can't take much more memory
+        // than a frame either.
+        let len = frame.len();
+        if self.len + len > self.max_length {
+            return Err(CodecError::MalformedChunk);
+        }
+        let receiver = self
+            .receivers
+            .entry(chunk.message_id)
+            .or_insert_with(|| StreamReceiver::new(chunk.message_id, chunk.total));
+        // A chunk that was sent again is only acknowledged. One that differs from the first
+        // would change the size of the stream after it was counted.
+        if let Some(data) = receiver.chunks.get(&chunk.index) {
+            if *data != chunk.data {
+                return Err(CodecError::MalformedChunk);
+            }
+            return Ok((ack, None));
+        }
+        if receiver.receive(chunk).is_none() {
+            return Err(CodecError::MalformedChunk);
+        }
+        self.len += len;
+        let Some(assembled) = receiver.assemble() else {
+            return Ok((ack, None));
+        };
+        let receiver = self.receivers.remove(&ack.message_id).unwrap();
+        self.len = self
+            .len
+            .saturating_sub(assembled.len() + receiver.chunks.len() * (1 + HEADER));
+        if self.completed.len() == COMPLETED {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(ack.message_id);
+        Ok((ack, Some(BytesMut::from(&assembled[..]))))
+    }
+}
@@ -0,0 +1,34 @@
+use super::*;
+
+#[test]
+fn valid() {
+    let auth = FrameAuth::new(b"cluster secret");
+    let signed = auth.sign(b"frame");
+    assert_eq!(signed.len(), 5 + TAG_LENGTH);
+    let frame = auth.verify(BytesMut::from(&signed[..])).unwrap();
+    assert_eq!(&frame[..], b"frame");
+}
+
+#[test]
+fn tampered() {
+    let auth = FrameAuth::new(b"cluster secret");
+    let signed = auth.sign(b"frame");
+
+    // A changed payload, a changed tag, a different secret and a frame without a tag all fail.
+    let mut payload = BytesMut::from(&signed[..]);
+    payload[0] ^= 1;
+    let mut tag = BytesMut::from(&signed[..]);
+    tag[5] ^= 1;
+    let other = FrameAuth::new(b"other secret");
+    for (auth, frame) in [
+        (&auth, payload),
+        (&auth, tag),
+        (&other, BytesMut::from(&signed[..])),
+        (&auth, BytesMut::from(&b"frame"[..])),
+    ] {
+        assert!(matches!(
+            auth.verify(frame),
+            Err(CodecError::Unauthenticated)
+        ));
+    }
+}
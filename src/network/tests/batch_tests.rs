@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+
+use super::*;
+use crate::message::NetworkMessage;
+use crate::network::{encode_frame, BincodeCodec, Codecs};
+
+fn frames() -> Vec<Bytes> {
+    let a = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let b = "127.0.0.1:1235".parse::<SocketAddr>().unwrap();
+    ["one", "two", "three"]
+        .iter()
+        .map(|content| {
+            let message = NetworkMessage::unicast(a, b, *content);
+            encode_frame(&BincodeCodec::default(), &message).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn roundtrip() {
+    let frames = frames();
+    let batch = encode_batch(&frames);
+    let len = frames.iter().map(Bytes::len).sum();
+    assert_eq!(batch.len(), batch_len(frames.len(), len));
+    assert_eq!(batch[0], BATCH);
+
+    let unpacked = unbatch(BytesMut::from(&batch[..])).unwrap();
+    assert_eq!(unpacked, frames);
+    let codecs = Codecs::default();
+    let contents = unpacked
+        .iter()
+        .map(|frame| codecs.decode(frame).unwrap().message)
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["one", "two", "three"]);
+}
+
+#[test]
+fn single_frame() {
+    // A frame that isn't a batch carries a single message.
+    let frame = BytesMut::from(&frames()[0][..]);
+    assert_eq!(unbatch(frame.clone()).unwrap(), vec![frame]);
+}
+
+#[test]
+fn malformed() {
+    let batch = encode_batch(&frames());
+    let truncated = BytesMut::from(&batch[..batch.len() - 1]);
+    assert!(matches!(
+        unbatch(truncated),
+        Err(CodecError::MalformedBatch)
+    ));
+    let partial_length = BytesMut::from(&[BATCH, 0, 0][..]);
+    assert!(matches!(
+        unbatch(partial_length),
+        Err(CodecError::MalformedBatch)
+    ));
+    let empty = BytesMut::from(&[BATCH][..]);
+    assert!(matches!(unbatch(empty), Err(CodecError::MalformedBatch)));
+}
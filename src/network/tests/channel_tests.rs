@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::time::{timeout, Duration};
+
+use super::*;
+use crate::core::Core;
+use crate::network::{Inflight, Readiness};
+
+fn addresses() -> Vec<SocketAddr> {
+    (0..4)
+        .map(|i| format!("127.0.0.1:{}", 7000 + i).parse().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn broadcast() {
+    let nodes = addresses();
+    let mut transports = ChannelTransport::network(&nodes);
+    let message = NetworkMessage::new(nodes[0], nodes[1..].to_vec(), "Hello, World!");
+    transports[0].send(message.clone()).await.unwrap();
+
+    // Every recipient has the message right away, the sender isn't one of them.
+    assert!(transports[0].try_recv().is_none());
+    for transport in &mut transports[1..] {
+        let inbound = transport.try_recv().unwrap();
+        assert_eq!(inbound.message, message);
+        assert_eq!(inbound.peer, nodes[0]);
+    }
+}
+
+// Reports the peer of every message the wrapped transport received.
+struct Recording(ChannelTransport, UnboundedSender<(SocketAddr, SocketAddr)>);
+
+impl Transport for Recording {
+    async fn send(&mut self, message: NetworkMessage) -> Result<(), SendError<NetworkMessage>> {
+        self.0.send(message).await
+    }
+
+    async fn recv(&mut self) -> Option<InboundMessage> {
+        let inbound = self.0.recv().await?;
+        let _ = self.1.send((self.0.address(), inbound.peer));
+        Some(inbound)
+    }
+
+    fn try_recv(&mut self) -> Option<InboundMessage> {
+        self.0.try_recv()
+    }
+}
+
+#[tokio::test]
+async fn cores() {
+    let nodes = addresses();
+    let (tx, mut rx) = unbounded_channel();
+    let mut cores = Vec::new();
+    for (id, transport) in ChannelTransport::network(&nodes).into_iter().enumerate() {
+        let transport = Recording(transport, tx.clone());
+        let core = Core::spawn(
+            id,
+            nodes[id],
+            nodes.clone(),
+            transport,
+            unbounded_channel().1,
+            Readiness::new(),
+            Inflight::new(),
+        );
+        cores.push(core);
+    }
+
+    // The cores broadcast to each other without a single port being bound.
+    let mut missing = nodes[1..]
+        .iter()
+        .map(|node| (*node, nodes[0]))
+        .collect::<HashSet<_>>();
+    while !missing.is_empty() {
+        let received = timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+        missing.remove(&received.unwrap());
+    }
+    for core in cores {
+        core.shutdown(Duration::from_secs(1)).await.unwrap();
+    }
+}
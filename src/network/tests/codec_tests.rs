@@ -0,0 +1,180 @@
+use std::net::SocketAddr;
+
+use super::*;
+
+fn message() -> NetworkMessage {
+    let address = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    NetworkMessage::new(address, vec![address], "Hello, World!")
+}
+
+#[test]
+fn round_trip() {
+    // Frames of both formats are decoded by the codec their tag refers to.
+    let codecs = Codecs::default();
+    for codec in [&BincodeCodec::default() as &dyn Codec, &JsonCodec] {
+        let frame = encode_frame(codec, &message()).unwrap();
+        assert_eq!(frame[0], codec.tag());
+        assert_eq!(codecs.decode(&frame).unwrap(), message());
+    }
+}
+
+#[test]
+fn unknown_format() {
+    // A receiver that only knows bincode rejects json frames.
+    let codecs = Codecs::new(vec![Arc::new(BincodeCodec::default())]);
+    let frame = encode_frame(&JsonCodec, &message()).unwrap();
+    assert!(matches!(
+        codecs.decode(&frame),
+        Err(CodecError::UnknownFormat(1))
+    ));
+    assert!(matches!(codecs.decode(&[]), Err(CodecError::MissingTag)));
+}
+
+#[test]
+fn mismatched_payload() {
+    // A payload handed to the other codec, e.g. behind a wrong tag, fails to decode.
+    let bincode = BincodeCodec::default();
+    let bytes = bincode.encode(&message()).unwrap();
+    assert!(matches!(JsonCodec.decode(&bytes), Err(CodecError::Json(_))));
+    let bytes = JsonCodec.encode(&message()).unwrap();
+    assert!(matches!(
+        bincode.decode(&bytes),
+        Err(CodecError::Bincode(_))
+    ));
+}
+
+#[test]
+fn tolerant() {
+    // Frames of an unknown format are handed back with their payload, known ones are decoded.
+    let codecs = Codecs::default();
+    let frame = [0x7f, 1, 2, 3];
+    assert_eq!(
+        codecs.decode_tolerant(&frame).unwrap(),
+        Decoded::Unknown {
+            tag: 0x7f,
+            raw_bytes: Bytes::from_static(&[1, 2, 3]),
+        }
+    );
+    let frame = encode_frame(&JsonCodec, &message()).unwrap();
+    assert_eq!(
+        codecs.decode_tolerant(&frame).unwrap(),
+        Decoded::Message(message())
+    );
+    assert!(matches!(
+        codecs.decode_tolerant(&[0x7f]),
+        Err(CodecError::EmptyPayload)
+    ));
+}
+
+#[test]
+fn limit() {
+    // Encode a message and change the length prefix of its content, which is followed only by the
+    // length of the empty headers, to claim a terabyte of data.
+    let mut message = message();
+    message.message = "".into();
+    let mut frame = encode_frame(&BincodeCodec::default(), &message)
+        .unwrap()
+        .to_vec();
+    let len = frame.len();
+    frame[len - 16..len - 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+
+    // Decoding fails cleanly because of the limit instead of trying to read the content.
+    let codecs = Codecs::new(vec![Arc::new(BincodeCodec::with_limit(1024))]);
+    match codecs.decode(&frame) {
+        Err(CodecError::Bincode(e)) => assert!(matches!(*e, bincode::ErrorKind::SizeLimit)),
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression_threshold() {
+    let codec = BincodeCodec::default();
+    let codecs = Codecs::default();
+
+    // A small message stays uncompressed.
+    let small = message();
+    let frame = encode_frame_compressed(&codec, &small, Some(1024)).unwrap();
+    assert_eq!(frame[0] & COMPRESSED, 0);
+    assert_eq!(codecs.decode(&frame).unwrap(), small);
+
+    // A large one gets compressed and shrinks.
+    let mut large = message();
+    large.message = "a".repeat(64 * 1024).into();
+    let frame = encode_frame_compressed(&codec, &large, Some(1024)).unwrap();
+    assert_ne!(frame[0] & COMPRESSED, 0);
+    assert!(frame.len() < encode_frame(&codec, &large).unwrap().len());
+    assert_eq!(codecs.decode(&frame).unwrap(), large);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn decompression_bomb() {
+    // A compressed frame claiming a gigantic uncompressed size is rejected.
+    let mut frame = vec![BincodeCodec::default().tag() | COMPRESSED];
+    frame.extend_from_slice(&u32::MAX.to_le_bytes());
+    frame.extend_from_slice(&[0; 16]);
+    assert!(matches!(
+        Codecs::default().decode(&frame),
+        Err(CodecError::Compression(_))
+    ));
+}
+
+#[derive(Debug)]
+struct EmptyCodec;
+
+impl Codec for EmptyCodec {
+    fn tag(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self, _: &NetworkMessage) -> Result<Vec<u8>, CodecError> {
+        Ok(Vec::new())
+    }
+
+    fn decode(&self, _: &[u8]) -> Result<NetworkMessage, CodecError> {
+        Ok(message())
+    }
+}
+
+#[test]
+fn empty_payload() {
+    // Neither side accepts a frame without a payload, even if a codec would.
+    assert!(matches!(
+        encode_frame(&EmptyCodec, &message()),
+        Err(CodecError::EmptyPayload)
+    ));
+    let codecs = Codecs::new(vec![Arc::new(EmptyCodec)]);
+    assert!(matches!(codecs.decode(&[2]), Err(CodecError::EmptyPayload)));
+}
+
+#[test]
+fn headers() {
+    // Headers survive the round trip in both formats.
+    let mut message = message();
+    message
+        .headers
+        .insert("request-id".to_string(), "42".to_string());
+    message
+        .headers
+        .insert("tenant".to_string(), "a".to_string());
+    let codecs = Codecs::default();
+    for codec in [&BincodeCodec::default() as &dyn Codec, &JsonCodec] {
+        let frame = encode_frame(codec, &message).unwrap();
+        assert_eq!(codecs.decode(&frame).unwrap().headers, message.headers);
+    }
+
+    // Json without headers, e.g. from an older peer, decodes to empty headers.
+    let json = r#"{"sender":"127.0.0.1:1234","addresses":[],"message":""}"#;
+    assert!(JsonCodec
+        .decode(json.as_bytes())
+        .unwrap()
+        .headers
+        .is_empty());
+}
+
+#[test]
+fn dump() {
+    assert_eq!(hex_dump(&[0x00, 0x1f, 0xff], 8), "00 1f ff");
+    assert_eq!(hex_dump(&[0x00, 0x1f, 0xff], 2), "00 1f ..");
+}
@@ -0,0 +1,64 @@
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use super::*;
+
+const PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000));
+
+#[test]
+fn duplicates() {
+    let mut cache = DedupCache::new(Dedup::default());
+    let now = Instant::now();
+    assert!(cache.insert(PEER, 1, now));
+    assert!(cache.insert(PEER, 2, now));
+    assert!(!cache.insert(PEER, 1, now + Duration::from_secs(1)));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn capacity() {
+    // The oldest id is forgotten once the cache is full.
+    let mut cache = DedupCache::new(Dedup {
+        capacity: 2,
+        ..Dedup::default()
+    });
+    let now = Instant::now();
+    for id in 0..3 {
+        assert!(cache.insert(PEER, id, now));
+    }
+    assert_eq!(cache.len(), 2);
+    assert!(cache.insert(PEER, 0, now));
+    assert!(!cache.insert(PEER, 2, now));
+}
+
+#[test]
+fn ttl() {
+    let bounds = Dedup {
+        ttl: Duration::from_secs(10),
+        ..Dedup::default()
+    };
+    let mut cache = DedupCache::new(bounds);
+    let start = Instant::now();
+    assert!(cache.insert(PEER, 7, start));
+
+    // Within the TTL the id is a duplicate, even if it keeps coming.
+    assert!(!cache.insert(PEER, 7, start + Duration::from_secs(5)));
+    assert!(!cache.insert(PEER, 7, start + Duration::from_secs(9)));
+
+    // Past the TTL it is new again.
+    let later = start + Duration::from_secs(10);
+    assert!(cache.insert(PEER, 7, later));
+    assert!(!cache.insert(PEER, 7, later + Duration::from_secs(1)));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn senders() {
+    // Two senders may pick the same id.
+    let mut cache = DedupCache::new(Dedup::default());
+    let other = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
+    let now = Instant::now();
+    assert!(cache.insert(PEER, 1, now));
+    assert!(cache.insert(other, 1, now));
+    assert!(!cache.insert(other, 1, now));
+    assert_eq!(cache.len(), 2);
+}
@@ -0,0 +1,19 @@
+use super::*;
+
+#[test]
+fn roundtrip() {
+    let hello = Hello::new(3, "127.0.0.1:8003".parse().unwrap());
+    assert_eq!(Hello::decode(&hello.encode()), Some(hello));
+}
+
+#[test]
+fn malformed() {
+    let frame = Hello::new(3, "127.0.0.1:8003".parse().unwrap()).encode();
+    assert_eq!(Hello::decode(&[]), None);
+    assert_eq!(Hello::decode(&frame[..frame.len() - 1]), None);
+
+    // A message frame starts with the tag of its codec instead.
+    let mut message = frame.to_vec();
+    message[0] = 0;
+    assert_eq!(Hello::decode(&message), None);
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+#[test]
+fn percentiles() {
+    // Small values are recorded exactly.
+    let mut histogram = Histogram::new();
+    for value in 1..=20 {
+        histogram.record(value);
+    }
+    assert_eq!(
+        histogram.percentiles(),
+        Percentiles {
+            count: 20,
+            p50: 10,
+            p90: 18,
+            p99: 20,
+            max: 20,
+        }
+    );
+
+    // Larger values are off by less than 1/16.
+    let mut histogram = Histogram::new();
+    for value in 1..=1000 {
+        histogram.record(value * 1000);
+    }
+    for (quantile, expected) in [(0.5, 500_000), (0.9, 900_000), (0.99, 990_000)] {
+        let value = histogram.value_at_quantile(quantile);
+        assert!(value >= expected, "{} below {}", value, expected);
+        assert!(
+            value - expected < expected / 16,
+            "{} above {}",
+            value,
+            expected
+        );
+    }
+    assert_eq!(histogram.value_at_quantile(1.0), 1_000_000);
+
+    assert_eq!(Histogram::new().percentiles(), Percentiles::default());
+}
+
+#[test]
+fn buckets() {
+    // Every value falls into a bucket whose range holds it.
+    for value in (0..64).chain([1000, 1 << 40, u64::MAX - 1, u64::MAX]) {
+        let bucket = Histogram::bucket(value);
+        assert!(bucket < BUCKETS);
+        assert!(Histogram::highest(bucket) >= value);
+        if bucket > 0 {
+            assert!(Histogram::highest(bucket - 1) < value);
+        }
+    }
+}
+
+#[test]
+fn flows() {
+    let peer = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let flows = PeerFlows::default();
+    let start = Instant::now();
+    for i in 0..10 {
+        let enqueued = start + Duration::from_millis(i * 10);
+        flows.record(peer, 100, enqueued, Duration::from_micros(20));
+    }
+    // A retransmitted message doesn't count as arriving.
+    flows.record(peer, 100, start, Duration::from_micros(20));
+
+    let flow = flows.get(&peer).unwrap();
+    assert_eq!(flow.sizes.count, 11);
+    assert_eq!(flow.sizes.p99, 100);
+    assert_eq!(flow.send_times.p50, 20);
+    assert_eq!(flow.inter_arrival.count, 9);
+    let gap = flow.inter_arrival.p50;
+    assert!((10_000..10_000 + 10_000 / 16).contains(&gap), "{}", gap);
+    assert_eq!(flows.snapshot().len(), 1);
+}
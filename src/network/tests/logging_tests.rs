@@ -0,0 +1,67 @@
+use std::net::SocketAddr;
+
+use super::*;
+
+// Collects the output of a tracing subscriber.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Capture {
+    fn lines(&self) -> Vec<String> {
+        let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_string).collect()
+    }
+}
+
+#[test]
+fn coalesce() {
+    let capture = Capture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // A flood of failures only logs the first one.
+    let peer = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let limiter = LogLimiter::new(Some(Duration::from_millis(100)));
+    for i in 0..100 {
+        limiter.warn("connect failures", peer, format_args!("failure {}", i));
+    }
+    let lines = capture.lines();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("failure 0"));
+
+    // Other events and peers are counted on their own.
+    let other = "127.0.0.1:2".parse::<SocketAddr>().unwrap();
+    limiter.warn("connect failures", other, "other peer");
+    limiter.warn("send failures", peer, "other event");
+    assert_eq!(capture.lines().len(), 3);
+
+    // After the window the next failure is logged together with a summary of the others.
+    std::thread::sleep(Duration::from_millis(150));
+    limiter.warn("connect failures", peer, "failure 100");
+    let lines = capture.lines();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[3].contains("99 connect failures for 127.0.0.1:1 in the last"));
+    assert!(lines[4].contains("failure 100"));
+
+    // Without a window every failure is logged.
+    let limiter = LogLimiter::new(None);
+    for _ in 0..10 {
+        limiter.warn("connect failures", peer, "failure");
+    }
+    assert_eq!(capture.lines().len(), 15);
+}
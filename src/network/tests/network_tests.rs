@@ -1,29 +1,32 @@
 use futures::future::try_join_all;
+use tokio::sync::mpsc::channel;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio::time::Duration;
+use tokio_util::codec::LengthDelimitedCodec;
 
 use super::*;
+use crate::network::{
+    encode_frame, BincodeCodec, Codec, Dedup, Inflight, JsonCodec, LocalRegistry, Loopback,
+    NetworkEvent, SenderCounts, Shutdown, Timeout, ACKNOWLEDGE, HEARTBEAT,
+};
 
 #[tokio::test]
 async fn retransmit() {
     let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
     let (tx, rx) = channel(10);
-    let mut sender = NetworkSender::new(rx, tx_retransmit);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
     tokio::spawn(async move {
         sender.run().await;
     });
 
     // Run the retransmitter
-    NetworkRetransmitter::run(rx_retransmit, tx.clone());
+    NetworkRetransmitter::run(rx_retransmit, tx_retry);
 
     // Send a message via the network sender.
-    let address = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
-    let message = NetworkMessage {
-        sender: address,
-        addresses: vec![address],
-        message: "Hello, World!".to_string(),
-    };
+    let address = "127.0.0.1:8081".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
     let _ = tx.send(message).await;
 
     sleep(Duration::from_millis(50)).await;
@@ -34,12 +37,95 @@ async fn retransmit() {
     assert!(handle.await.is_ok());
 }
 
+#[tokio::test]
+async fn delivery_failed() {
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Run the retransmitter, it gives up after 3 attempts.
+    let (tx_failed, mut rx_failed) = unbounded_channel();
+    let policy = RetransmitPolicy {
+        max_attempts: Some(3),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, Some(tx_failed));
+
+    // Send a message to an address nobody listens on.
+    let address = "127.0.0.1:9003".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let _ = tx.send(message.clone()).await;
+
+    // The failure is reported once the retries are exhausted.
+    let failed = tokio::time::timeout(Duration::from_secs(1), rx_failed.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let NetworkEvent::DeliveryFailed(failed) = failed else {
+        panic!("Unexpected event {:?}", failed);
+    };
+    assert_eq!(failed.message, message);
+    assert_eq!(failed.peer, address);
+}
+
+#[tokio::test]
+async fn max_attempts() {
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Count every failed try on its way from the sender to the retransmitter.
+    let (tx_counted, rx_counted) = channel(10);
+    let tries = Arc::new(AtomicUsize::new(0));
+    let counter = tries.clone();
+    tokio::spawn(async move {
+        while let Some(delivery) = rx_retransmit.recv().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if tx_counted.send(delivery).await.is_err() {
+                break;
+            }
+        }
+    });
+    let (tx_failed, mut rx_failed) = unbounded_channel();
+    let policy = RetransmitPolicy {
+        max_attempts: Some(3),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_counted, tx_retry, policy, Some(tx_failed));
+
+    // Nothing ever listens on port 1.
+    let address = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+
+    // The message is given up on after exactly three tries and not tried again.
+    let failed = tokio::time::timeout(Duration::from_secs(1), rx_failed.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let NetworkEvent::DeliveryFailed(failed) = failed else {
+        panic!("Unexpected event {:?}", failed);
+    };
+    assert_eq!(failed.message, message);
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(tries.load(Ordering::SeqCst), 3);
+}
+
 #[tokio::test]
 async fn send() {
     // Create a network sender and run it.
     let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
     let (tx, rx) = channel(10);
-    let mut sender = NetworkSender::new(rx, tx_retransmit);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
     tokio::spawn(async move {
         sender.run().await;
     });
@@ -49,11 +135,7 @@ async fn send() {
     let handle = listener(address);
 
     // Send a message via the network sender.
-    let message = NetworkMessage {
-        sender: address,
-        addresses: vec![address],
-        message: "Hello, World!".to_string(),
-    };
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
     let _ = tx.send(message).await;
 
     // Use the handle to check if the sender successfully transmitted the data over the TCP
@@ -65,8 +147,9 @@ async fn send() {
 async fn broadcast() {
     // Create a network sender and run it.
     let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
     let (tx, rx) = channel(10);
-    let mut sender = NetworkSender::new(rx, tx_retransmit);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
     tokio::spawn(async move {
         sender.run().await;
     });
@@ -84,11 +167,7 @@ async fn broadcast() {
         .unzip();
 
     // Broadcast a message via the network sender.
-    let message = NetworkMessage {
-        sender: addresses[0],
-        addresses,
-        message: "Hello, World!".to_string(),
-    };
+    let message = NetworkMessage::new(addresses[0], addresses, "Hello, World!");
     let _ = tx.send(message).await;
 
     // Use the handle to check if the sender successfully transmitted the data over the TCP
@@ -103,15 +182,15 @@ async fn receive() {
     let (tx, mut rx) = channel(10);
     let receiver = NetworkReceiver::new(address.clone(), tx);
     tokio::spawn(async move {
-        receiver.run().await;
+        receiver.run().await.unwrap();
     });
 
     // Sleep to make sure the receiver is ready.
     sleep(Duration::from_millis(50)).await;
 
     // Create a message and serialize it.
-    let message = NetworkMessage{sender: address, addresses: vec![address], message: "Hello, World!".to_string()};
-    let bytes = Bytes::from(bincode::serialize(&message).unwrap());
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
 
     // Connect to the address of the receiver.
     let stream = TcpStream::connect(address).await.unwrap();
@@ -122,7 +201,7 @@ async fn receive() {
 
     // Make sure the message receives the receiver and gets passed into the channel.
     match rx.recv().await {
-        Some(val) => assert_eq!(val, message),
+        Some(val) => assert_eq!(val.message, message),
         _ => assert!(false),
     }
 }
@@ -145,3 +224,4077 @@ pub fn listener(address: SocketAddr) -> JoinHandle<()> {
         }
     })
 }
+
+// Helper function that connects to the given address and sends a message with the given sender
+// and content.
+async fn connect_and_send(
+    address: SocketAddr,
+    sender: SocketAddr,
+    content: &str,
+) -> Framed<TcpStream, LengthDelimitedCodec> {
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let message = NetworkMessage::new(sender, vec![address], content);
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(bytes).await.unwrap();
+    transport
+}
+
+// Helper function that opens two connections from the same node to a receiver with the given
+// duplicate policy. Returns the delivered contents and which of the connections were closed by
+// the receiver.
+async fn duplicate_connections(port: u16, policy: DuplicatePolicy) -> (Vec<String>, bool, bool) {
+    let address = format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        duplicate_policy: policy,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Both connections identify as the same node.
+    let identity = "127.0.0.1:9999".parse::<SocketAddr>().unwrap();
+    let mut first = connect_and_send(address, identity, "first").await;
+    sleep(Duration::from_millis(50)).await;
+    let mut second = connect_and_send(address, identity, "second").await;
+    sleep(Duration::from_millis(50)).await;
+
+    let mut delivered = Vec::new();
+    while let Ok(inbound) = rx.try_recv() {
+        delivered.push(inbound.message.message.into());
+    }
+
+    // A closed connection yields None, an open one times out.
+    let timeout = Duration::from_millis(50);
+    let first_closed = matches!(tokio::time::timeout(timeout, first.next()).await, Ok(None));
+    let second_closed = matches!(tokio::time::timeout(timeout, second.next()).await, Ok(None));
+    (delivered, first_closed, second_closed)
+}
+
+#[tokio::test]
+async fn duplicate_keep_newest() {
+    let (delivered, first_closed, second_closed) =
+        duplicate_connections(9000, DuplicatePolicy::KeepNewest).await;
+    assert_eq!(delivered, vec!["first", "second"]);
+    assert!(first_closed);
+    assert!(!second_closed);
+}
+
+#[tokio::test]
+async fn duplicate_keep_oldest() {
+    let (delivered, first_closed, second_closed) =
+        duplicate_connections(9001, DuplicatePolicy::KeepOldest).await;
+    assert_eq!(delivered, vec!["first"]);
+    assert!(!first_closed);
+    assert!(second_closed);
+}
+
+#[tokio::test]
+async fn duplicate_allow_both() {
+    let (delivered, first_closed, second_closed) =
+        duplicate_connections(9002, DuplicatePolicy::AllowBoth).await;
+    assert_eq!(delivered, vec!["first", "second"]);
+    assert!(!first_closed);
+    assert!(!second_closed);
+}
+
+// Helper function that accepts a single TCP connection and returns the first message sent over it.
+pub fn receive_one(address: SocketAddr) -> JoinHandle<NetworkMessage> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let frame = transport.next().await.unwrap().unwrap();
+        Codecs::default().decode(&frame).unwrap()
+    })
+}
+
+#[tokio::test]
+async fn send_many() {
+    // Create a network sender and run it.
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Run 3 dummy TCP servers.
+    let addresses = (9004..9007)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let handles = addresses
+        .iter()
+        .map(|address| receive_one(*address))
+        .collect::<Vec<_>>();
+
+    // Send a different payload to every server.
+    let handle = SenderHandle::new(addresses[0], tx);
+    let messages = addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| (*address, format!("share {}", i)))
+        .collect();
+    let results = handle.send_many(messages).await;
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    // Every server received exactly its own payload.
+    for (i, handle) in handles.into_iter().enumerate() {
+        let message = handle.await.unwrap();
+        assert_eq!(message.message, format!("share {}", i));
+        assert_eq!(message.addresses, vec![addresses[i]]);
+    }
+}
+
+#[tokio::test]
+async fn send_many_above_capacity() {
+    // A batch larger than the channel can't be enqueued as a unit, none of it is.
+    let (tx, mut rx) = channel(2);
+    let address = "127.0.0.1:9225".parse::<SocketAddr>().unwrap();
+    let handle = SenderHandle::new(address, tx);
+    let messages = (0..5).map(|i| (address, format!("share {}", i))).collect();
+    let results = handle.send_many(messages).await;
+    assert_eq!(results.len(), 5);
+    assert!(results.iter().all(|(_, result)| result.is_err()));
+    assert!(rx.try_recv().is_err());
+
+    // A batch that fits the channel is enqueued.
+    let results = handle.send_many(vec![(address, "a"), (address, "b")]).await;
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert_eq!(rx.recv().await.unwrap().message, "a");
+    assert_eq!(rx.recv().await.unwrap().message, "b");
+}
+
+#[tokio::test]
+async fn send_many_closed() {
+    // Nothing is enqueued if the sender is gone, every peer reports the error.
+    let (tx, rx) = channel(10);
+    drop(rx);
+    let address = "127.0.0.1:9007".parse::<SocketAddr>().unwrap();
+    let handle = SenderHandle::new(address, tx);
+    let results = handle.send_many(vec![(address, "a"), (address, "b")]).await;
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|(_, result)| result.is_err()));
+}
+
+#[tokio::test]
+async fn handle_broadcast() {
+    // The message goes to every peer but our own node.
+    let (tx, mut rx) = channel(10);
+    let addresses = (9008..9011)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let handle = SenderHandle::new(addresses[1], tx);
+    handle.broadcast(&addresses, "everyone").await.unwrap();
+    let message = rx.recv().await.unwrap();
+    assert_eq!(message.sender, addresses[1]);
+    assert_eq!(message.addresses, vec![addresses[0], addresses[2]]);
+    assert_eq!(message.message, "everyone");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn inherited_listener() {
+    use std::os::unix::io::IntoRawFd;
+
+    // Bind a listener like a parent process would and only keep its file descriptor.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let fd = listener.into_raw_fd();
+
+    // A peer can connect before the receiver runs, the connection waits in the backlog.
+    let stream = TcpStream::connect(address).await.unwrap();
+
+    // Create a receiver from the file descriptor and run it.
+    let (tx, mut rx) = channel(10);
+    let receiver = unsafe { NetworkReceiver::from_raw_fd(fd, tx, ReceiverConfig::default()) };
+    let receiver = receiver.unwrap();
+    assert_eq!(receiver.listener_fd(), Some(fd));
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+
+    // The message sent over the early connection gets delivered.
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message, message);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn bound_listener() {
+    use std::os::unix::io::{BorrowedFd, IntoRawFd};
+
+    // The receiver binds the address itself and exposes the listener once it runs.
+    let address = "127.0.0.1:9242".parse::<SocketAddr>().unwrap();
+    let shutdown = Shutdown::new();
+    let (tx, _rx) = channel(10);
+    let config = ReceiverConfig {
+        shutdown: shutdown.clone(),
+        ..ReceiverConfig::default()
+    };
+    let receiver = Arc::new(NetworkReceiver::with_config(address, tx, config));
+    assert_eq!(receiver.listener_fd(), None);
+    let handle = tokio::spawn({
+        let receiver = receiver.clone();
+        async move { receiver.run().await.unwrap() }
+    });
+    sleep(Duration::from_millis(50)).await;
+    let fd = receiver.listener_fd().unwrap();
+
+    // After the shutdown the listener is still open, a peer connecting waits in the backlog.
+    shutdown.trigger();
+    handle.await.unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+
+    // Hand a duplicate of the file descriptor to a successor, like across an exec, and drop the
+    // old receiver.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) }
+        .try_clone_to_owned()
+        .unwrap()
+        .into_raw_fd();
+    drop(receiver);
+    let (tx, mut rx) = channel(10);
+    let receiver = unsafe { NetworkReceiver::from_raw_fd(fd, tx, ReceiverConfig::default()) };
+    let receiver = receiver.unwrap();
+    assert_eq!(receiver.listener_fd(), Some(fd));
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+
+    // The successor accepts the waiting connection.
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message, message);
+}
+
+#[tokio::test]
+async fn peer_codecs() {
+    // Run two receivers.
+    let addresses = (9008..9010)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let mut receivers = Vec::new();
+    for address in &addresses {
+        let (tx, rx) = channel(10);
+        let receiver = NetworkReceiver::new(*address, tx);
+        tokio::spawn(async move {
+            receiver.run().await.unwrap();
+        });
+        receivers.push(rx);
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a network sender that uses bincode for the first and json for the second peer.
+    let mut config = SenderConfig::default();
+    let codecs: [Arc<dyn Codec>; 2] = [Arc::new(BincodeCodec::default()), Arc::new(JsonCodec)];
+    for (address, codec) in addresses.iter().zip(codecs) {
+        let peer = PeerConfig {
+            codec,
+            ..PeerConfig::default()
+        };
+        config.peers.insert(*address, peer);
+    }
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Broadcast a message, both peers decode it correctly.
+    let message = NetworkMessage::new(addresses[0], addresses.clone(), "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+    for rx in &mut receivers {
+        assert_eq!(rx.recv().await.unwrap().message, message);
+    }
+}
+
+#[tokio::test]
+async fn startup_grace() {
+    // Create a network sender with a startup grace period and run it.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let config = SenderConfig {
+        startup_grace: Duration::from_secs(2),
+        ..SenderConfig::default()
+    };
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Run the retransmitter, it gives up after 3 attempts.
+    let (tx_failed, mut rx_failed) = unbounded_channel();
+    let policy = RetransmitPolicy {
+        max_attempts: Some(3),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, Some(tx_failed));
+
+    // Send a message to a peer that only comes up after way more than 3 attempts.
+    let address = "127.0.0.1:9010".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let _ = tx.send(message.clone()).await;
+    sleep(Duration::from_millis(300)).await;
+    let handle = receive_one(address);
+
+    // The message still arrives and wasn't given up on.
+    assert_eq!(handle.await.unwrap(), message);
+    assert!(rx_failed.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn queue_delay() {
+    // Create a network sender with a retransmitter and run it.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    let queue_delays = sender.queue_delays();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    NetworkRetransmitter::run(rx_retransmit, tx_retry);
+
+    // Send a message to a peer that only comes up after 200ms, so the message has to wait.
+    let address = "127.0.0.1:9011".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let _ = tx.send(message).await;
+    sleep(Duration::from_millis(200)).await;
+    receive_one(address).await.unwrap();
+
+    // The measured delay reflects the wait.
+    let stats = queue_delays.get(&address).unwrap();
+    assert_eq!(stats.count, 1);
+    assert!(stats.min >= Duration::from_millis(200), "{:?}", stats);
+    assert_eq!(stats.avg(), Some(stats.min));
+}
+
+#[tokio::test]
+async fn time_to_first_byte() {
+    // Submit a message through a handle while the network sender isn't running yet.
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    let queue_delays = sender.queue_delays();
+    let first_bytes = sender.time_to_first_byte();
+    #[cfg(feature = "histograms")]
+    let flows = sender.flows();
+    let address = "127.0.0.1:9228".parse::<SocketAddr>().unwrap();
+    let handle = receive_one(address);
+    let results = SenderHandle::new(address, tx)
+        .send_many(vec![(address, "Hello, World!")])
+        .await;
+    assert!(results[0].1.is_ok());
+
+    // Only pick it up after 200ms.
+    sleep(Duration::from_millis(200)).await;
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    handle.await.unwrap();
+
+    // The time to first byte reflects the wait since the submission, the queue delay since the
+    // pickup doesn't.
+    let first_byte = first_bytes.get(&address).unwrap();
+    assert_eq!(first_byte.count, 1);
+    assert!(
+        first_byte.min >= Duration::from_millis(200),
+        "{:?}",
+        first_byte
+    );
+    #[cfg(feature = "histograms")]
+    {
+        let flow = flows.get(&address).unwrap();
+        assert!(flow.time_to_first_byte.p50 >= 200_000, "{:?}", flow);
+    }
+    let delays = queue_delays.get(&address).unwrap();
+    assert!(delays.max < Duration::from_millis(200), "{:?}", delays);
+}
+
+#[tokio::test]
+async fn fallback() {
+    // The primary address of the peer is down, only its fallback address is listening.
+    let primary = "127.0.0.1:9012".parse::<SocketAddr>().unwrap();
+    let fallback = "127.0.0.1:9013".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&fallback).await.unwrap();
+
+    // Create a network sender that knows the fallback address and run it.
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        fallback: Some(fallback),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(primary, peer);
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Send two messages to the primary address.
+    for content in ["first", "second"] {
+        let message = NetworkMessage::new(primary, vec![primary], content);
+        tx.send(message).await.unwrap();
+    }
+
+    // Both messages arrive at the fallback address over a single connection.
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    for content in ["first", "second"] {
+        let frame = transport.next().await.unwrap().unwrap();
+        let message = Codecs::default().decode(&frame).unwrap();
+        assert_eq!(message.message, content);
+    }
+}
+
+#[tokio::test]
+async fn text_mode() {
+    use tokio::io::AsyncWriteExt;
+
+    // Create a network receiver in text mode and run it.
+    let address = "127.0.0.1:9014".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        text_mode: true,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect like netcat would and type a line.
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let peer = stream.local_addr().unwrap();
+    stream.write_all(b"Hello, World!\n").await.unwrap();
+
+    // The line is delivered as a message from the client.
+    let inbound = rx.recv().await.unwrap();
+    assert_eq!(inbound.message.message, "Hello, World!");
+    assert_eq!(inbound.message.sender, peer);
+    assert_eq!(inbound.message.addresses, vec![address]);
+    assert_eq!(inbound.peer, peer);
+}
+
+#[tokio::test]
+async fn interceptors() {
+    // Create a network receiver whose interceptor records and strips the tenant tag.
+    let address = "127.0.0.1:9015".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let mut receiver = NetworkReceiver::new(address, tx_deliver);
+    let tenants = Arc::new(Mutex::new(Vec::new()));
+    let seen = tenants.clone();
+    receiver.add_interceptor(move |message| {
+        if let Some((tenant, content)) = message.message.split_once(": ") {
+            seen.lock().unwrap().push(tenant.to_string());
+            message.message = content.into();
+        }
+    });
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a network sender whose interceptor stamps the tenant onto every message.
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    sender.add_interceptor(|message| {
+        message.message = format!("tenant-a: {}", message.message).into()
+    });
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+
+    // The tag made it over the wire and was removed again before delivery.
+    assert_eq!(rx_deliver.recv().await.unwrap().message, message);
+    assert_eq!(*tenants.lock().unwrap(), vec!["tenant-a".to_string()]);
+}
+
+#[tokio::test]
+async fn quota() {
+    use crate::network::{Quota, QuotaPolicy};
+
+    let address = "127.0.0.1:9016".parse::<SocketAddr>().unwrap();
+    let message = |content: &str| NetworkMessage::new(address, vec![address], content);
+
+    // Create a network sender that may send two messages per window and drops the rest.
+    let size = encode_frame(&BincodeCodec::default(), &message("msg 1"))
+        .unwrap()
+        .len() as u64;
+    let config = SenderConfig {
+        quota: Some(Quota {
+            bytes: 2 * size,
+            window: Duration::from_millis(300),
+            policy: QuotaPolicy::Reject,
+        }),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let bandwidth = sender.bandwidth();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The third message exceeds the quota.
+    let listener = TcpListener::bind(address).await.unwrap();
+    for content in ["msg 1", "msg 2", "msg 3"] {
+        tx.send(message(content)).await.unwrap();
+    }
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    for content in ["msg 1", "msg 2"] {
+        let frame = transport.next().await.unwrap().unwrap();
+        assert_eq!(Codecs::default().decode(&frame).unwrap().message, content);
+    }
+    sleep(Duration::from_millis(50)).await;
+    let usage = bandwidth.get(&address).unwrap();
+    assert_eq!(usage.total, 2 * size);
+    assert_eq!(usage.rejected, 1);
+
+    // In the next window messages go through again.
+    sleep(Duration::from_millis(300)).await;
+    tx.send(message("msg 4")).await.unwrap();
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "msg 4");
+}
+
+#[tokio::test]
+async fn empty_frame() {
+    // Create a network receiver and run it.
+    let address = "127.0.0.1:9017".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A zero-length frame is a protocol error, the receiver closes the connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(bytes::Bytes::new()).await.unwrap();
+    assert!(transport.next().await.is_none());
+    assert!(rx.try_recv().is_err());
+
+    // The receiver keeps serving other connections.
+    let _transport = connect_and_send(address, address, "Hello, World!").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "Hello, World!");
+}
+
+#[tokio::test]
+async fn retransmit_backlog() {
+    let path = std::env::temp_dir().join(format!("retransmit-backlog-{}", std::process::id()));
+    let policy = RetransmitPolicy {
+        backlog: Some(path.clone()),
+        ..RetransmitPolicy::default()
+    };
+    let address = "127.0.0.1:9018".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+
+    // Queue a retransmission and shut the retransmitter down before it is due.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, _rx_retry) = channel(10);
+    let handle =
+        NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy.clone(), None);
+    tx_retransmit
+        .send(Delivery::new(message.clone(), address))
+        .await
+        .unwrap();
+    drop(tx_retransmit);
+    handle.await.unwrap();
+    assert!(path.exists());
+
+    // After the restart the message is retransmitted with its attempts so far.
+    let (_tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, mut rx_retry) = channel(10);
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+    let delivery = rx_retry.recv().await.unwrap();
+    assert_eq!(delivery.message, message);
+    assert_eq!(delivery.address, address);
+    assert_eq!(delivery.attempts, 1);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn max_pending() {
+    let path = std::env::temp_dir().join(format!("retransmit-pending-{}", std::process::id()));
+    let (tx_receipts, mut rx_receipts) = channel(100);
+    let policy = RetransmitPolicy {
+        max_pending: Some(5),
+        backoff: Backoff {
+            base_delay: Duration::from_secs(3600),
+            ..Backoff::default()
+        },
+        backlog: Some(path.clone()),
+        receipts: Some(tx_receipts),
+        ..RetransmitPolicy::default()
+    };
+
+    // Flood the retransmitter with messages to a peer that is down.
+    let address = "127.0.0.1:9019".parse::<SocketAddr>().unwrap();
+    let (tx_retransmit, rx_retransmit) = channel(100);
+    let (tx_retry, _rx_retry) = channel(10);
+    let handle = NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+    for i in 0..100 {
+        let message = NetworkMessage::unicast(address, address, format!("msg {}", i));
+        tx_retransmit
+            .send(Delivery::new(message, address))
+            .await
+            .unwrap();
+    }
+    drop(tx_retransmit);
+    handle.await.unwrap();
+
+    // Only the newest messages are still pending, the others were dropped.
+    let pending = NetworkRetransmitter::load(&path);
+    let contents = pending
+        .iter()
+        .map(|delivery| delivery.message.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["msg 95", "msg 96", "msg 97", "msg 98", "msg 99"]);
+    for _ in 0..95 {
+        let receipt = rx_receipts.recv().await.unwrap();
+        assert_eq!(receipt.outcome, DeliveryOutcome::Dropped);
+    }
+    assert!(rx_receipts.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn max_outstanding() {
+    // Create a network receiver whose deliver channel fills up immediately.
+    let address = "127.0.0.1:9019".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let config = ReceiverConfig {
+        max_outstanding: 4,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let outstanding = receiver.outstanding();
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Flood a single connection.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let peer = stream.local_addr().unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    tokio::spawn(async move {
+        for i in 0..100 {
+            let message = NetworkMessage::new(peer, vec![address], i.to_string());
+            let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+            transport.send(bytes).await.unwrap();
+        }
+    });
+
+    // The receiver stops reading once the limit is reached.
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(outstanding.get(&peer), Some(4));
+
+    // Nothing is lost, every message gets delivered in order once Core catches up.
+    for i in 0..100 {
+        assert_eq!(rx.recv().await.unwrap().message.message, i.to_string());
+        assert!(outstanding.get(&peer).unwrap_or(0) <= 4);
+    }
+}
+
+#[tokio::test]
+async fn peer_unreachable() {
+    // Create a network sender that gives up on a peer after three failed connection attempts.
+    let config = SenderConfig {
+        max_connect_failures: Some(3),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, mut rx_retransmit) = channel::<Delivery>(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let (tx_unreachable, mut rx_unreachable) = channel(10);
+    sender.report_unreachable(tx_unreachable);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Retransmit right away and count the retransmissions.
+    let retransmits = Arc::new(Mutex::new(0));
+    let counter = retransmits.clone();
+    tokio::spawn(async move {
+        while let Some(delivery) = rx_retransmit.recv().await {
+            *counter.lock().unwrap() += 1;
+            tx_retry.send(delivery).await.unwrap();
+        }
+    });
+
+    // Send a message to a peer that is permanently down.
+    let address = "127.0.0.1:9020".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+
+    // The peer is reported as unreachable after the third failure.
+    let event = tokio::time::timeout(Duration::from_secs(1), rx_unreachable.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        event,
+        PeerUnreachable {
+            peer: address,
+            failures: 3,
+        }
+    );
+
+    // The sender stops trying, also for new messages.
+    tx.send(message).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(*retransmits.lock().unwrap(), 2);
+    assert!(rx_unreachable.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn spawn_rate() {
+    // Create a network sender that spawns 20 workers per second at most.
+    let config = SenderConfig {
+        spawn_rate: Some(20.0),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Broadcast a message to five new peers at once and note when each one is connected.
+    let addresses = (9021..9026)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let mut accepts = Vec::new();
+    for address in &addresses {
+        let listener = TcpListener::bind(address).await.unwrap();
+        accepts.push(tokio::spawn(async move {
+            listener.accept().await.unwrap();
+            Instant::now()
+        }));
+    }
+    let message = NetworkMessage::new(addresses[0], addresses.clone(), "Hello, World!");
+    tx.send(message).await.unwrap();
+
+    // The connections are spread out over at least four intervals of 50ms.
+    let times = try_join_all(accepts).await.unwrap();
+    let first = times.iter().min().unwrap();
+    let last = times.iter().max().unwrap();
+    assert!(*last - *first >= Duration::from_millis(190));
+}
+
+// Collects the output of a tracing subscriber.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn frame_dump() {
+    // Capture trace output. The test runtime runs every task on this thread, so the workers log
+    // to the capture too.
+    let capture = Capture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // Create a network receiver and sender that dump the first 4 bytes of every frame.
+    let address = "127.0.0.1:9026".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        frame_dump: Some(4),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    let config = SenderConfig {
+        frame_dump: Some(4),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+    rx_deliver.recv().await.unwrap();
+
+    // Both sides logged the frame: its length, the bincode tag and the start of the sender
+    // address.
+    let len = encode_frame(&BincodeCodec::default(), &message)
+        .unwrap()
+        .len();
+    let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+    for event in ["sent frame", "received frame"] {
+        let line = output.lines().find(|line| line.contains(event)).unwrap();
+        assert!(line.contains(&format!("len={}", len)), "{}", line);
+        assert!(line.contains("dump=00 00 00 00 .."), "{}", line);
+    }
+}
+
+// Helper function that runs a receiver waiting for the returned readiness with the given policy.
+async fn early_receiver(
+    address: SocketAddr,
+    policy: EarlyPolicy,
+) -> (Readiness, Receiver<InboundMessage>) {
+    let (tx, rx) = channel(10);
+    let config = ReceiverConfig {
+        early_policy: policy,
+        ..ReceiverConfig::default()
+    };
+    let mut receiver = NetworkReceiver::with_config(address, tx, config);
+    let ready = Readiness::new();
+    receiver.wait_for(ready.clone());
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    (ready, rx)
+}
+
+#[tokio::test]
+async fn early_buffer() {
+    let address = "127.0.0.1:9027".parse::<SocketAddr>().unwrap();
+    let (ready, mut rx) = early_receiver(address, EarlyPolicy::Buffer(2)).await;
+
+    // Only two of the messages sent before Core is ready are kept.
+    let mut transports = Vec::new();
+    for content in ["early 1", "early 2", "early 3"] {
+        transports.push(connect_and_send(address, address, content).await);
+        sleep(Duration::from_millis(20)).await;
+    }
+    ready.set_ready();
+    transports.push(connect_and_send(address, address, "ready").await);
+
+    let mut delivered = Vec::new();
+    for _ in 0..3 {
+        delivered.push(rx.recv().await.unwrap().message.message);
+    }
+    assert_eq!(delivered, vec!["early 1", "early 2", "ready"]);
+}
+
+#[tokio::test]
+async fn early_hold() {
+    let address = "127.0.0.1:9028".parse::<SocketAddr>().unwrap();
+    let (ready, mut rx) = early_receiver(address, EarlyPolicy::Hold).await;
+
+    // The connection isn't served before Core is ready.
+    let _transport = connect_and_send(address, address, "Hello, World!").await;
+    let early = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await;
+    assert!(early.is_err());
+
+    // Afterwards the message is delivered.
+    ready.set_ready();
+    assert_eq!(rx.recv().await.unwrap().message.message, "Hello, World!");
+}
+
+#[tokio::test]
+async fn stream_compression() {
+    // Create a network receiver and run it.
+    let address = "127.0.0.1:9031".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a network sender that compresses the connection to the receiver.
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        stream_compression: true,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The receiver detects the compression on its own.
+    for i in 0..5 {
+        let message = NetworkMessage::new(address, vec![address], format!("message {}", i));
+        tx.send(message.clone()).await.unwrap();
+        assert_eq!(rx_deliver.recv().await.unwrap().message, message);
+    }
+}
+
+#[tokio::test]
+async fn max_peers() {
+    // Create a network sender that tracks two peers at most.
+    let config = SenderConfig {
+        max_peers: Some(2),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Send two messages to three peers.
+    let addresses = (9034..9037)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let mut listeners = Vec::new();
+    for address in &addresses {
+        listeners.push(TcpListener::bind(address).await.unwrap());
+    }
+    for content in ["first", "second"] {
+        let message = NetworkMessage::new(addresses[0], addresses.clone(), content);
+        tx.send(message).await.unwrap();
+    }
+
+    // The first two peers get both messages.
+    let third = listeners.pop().unwrap();
+    for listener in listeners {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        for content in ["first", "second"] {
+            let frame = transport.next().await.unwrap().unwrap();
+            assert_eq!(Codecs::default().decode(&frame).unwrap().message, content);
+        }
+    }
+
+    // The third peer is never connected to.
+    let accepted = tokio::time::timeout(Duration::from_millis(100), third.accept()).await;
+    assert!(accepted.is_err());
+}
+
+#[tokio::test]
+async fn message_ids() {
+    use crate::message::SequenceIds;
+
+    // Create a network sender that numbers messages deterministically.
+    let config = SenderConfig {
+        ids: Some(Arc::new(SequenceIds::new(vec![7, 8]))),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Send four messages, one of them already has an id.
+    let address = "127.0.0.1:9037".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    for i in 0..4 {
+        let mut message = NetworkMessage::new(address, vec![address], "Hello, World!");
+        if i == 2 {
+            message
+                .headers
+                .insert(MESSAGE_ID.to_string(), "1000".to_string());
+        }
+        tx.send(message).await.unwrap();
+    }
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let mut ids = Vec::new();
+    for _ in 0..4 {
+        let frame = transport.next().await.unwrap().unwrap();
+        ids.push(Codecs::default().decode(&frame).unwrap().id());
+    }
+    assert_eq!(ids, vec![Some(7), Some(8), Some(1000), Some(9)]);
+}
+
+#[tokio::test]
+async fn flow_control() {
+    // Run a receiver that gives every connection two credits.
+    let address = "127.0.0.1:9041".parse::<SocketAddr>().unwrap();
+    let config = ReceiverConfig {
+        credits: Some(2),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    let credits = receiver.credits();
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a network sender that uses flow control towards the receiver.
+    let peer = PeerConfig {
+        flow_control: true,
+        ..PeerConfig::default()
+    };
+    let config = SenderConfig {
+        peers: HashMap::from([(address, peer)]),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let node = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    for i in 0..5 {
+        let message = NetworkMessage::new(node, vec![address], i.to_string());
+        tx.send(message).await.unwrap();
+    }
+
+    // The sender pauses once the two credits are used up.
+    for i in 0..2 {
+        assert_eq!(
+            rx_deliver.recv().await.unwrap().message.message,
+            i.to_string()
+        );
+    }
+    let paused = tokio::time::timeout(Duration::from_millis(200), rx_deliver.recv()).await;
+    assert!(paused.is_err());
+
+    // And resumes once the receiver grants more.
+    assert!(credits.grant(&node, 3));
+    for i in 2..5 {
+        assert_eq!(
+            rx_deliver.recv().await.unwrap().message.message,
+            i.to_string()
+        );
+    }
+    assert!(!credits.grant(&"127.0.0.1:4321".parse().unwrap(), 1));
+}
+
+#[tokio::test]
+async fn stale_epochs() {
+    // Run a receiver in epoch 5 that still accepts messages from the last epoch.
+    let address = "127.0.0.1:9042".parse::<SocketAddr>().unwrap();
+    let epochs = EpochFilter::new(5, 1);
+    let config = ReceiverConfig {
+        epochs: Some(epochs.clone()),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send messages of several epochs, and one without epoch.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let send = |epoch: Option<u64>| {
+        let mut message = NetworkMessage::new(address, vec![address], format!("{:?}", epoch));
+        if let Some(epoch) = epoch {
+            message.set_epoch(epoch);
+        }
+        encode_frame(&BincodeCodec::default(), &message).unwrap()
+    };
+    for epoch in [Some(3), Some(4), Some(5), None, Some(6)] {
+        transport.send(send(epoch)).await.unwrap();
+    }
+
+    let mut delivered = Vec::new();
+    for _ in 0..4 {
+        delivered.push(rx_deliver.recv().await.unwrap().message.epoch());
+    }
+    assert_eq!(delivered, vec![Some(4), Some(5), None, Some(6)]);
+    assert_eq!(epochs.dropped(), 1);
+
+    // Once Core moves on, the older epochs are dropped as well.
+    epochs.advance(7);
+    epochs.advance(2);
+    assert_eq!(epochs.current(), 7);
+    for epoch in [Some(5), Some(6)] {
+        transport.send(send(epoch)).await.unwrap();
+    }
+    assert_eq!(rx_deliver.recv().await.unwrap().message.epoch(), Some(6));
+    assert_eq!(epochs.dropped(), 2);
+}
+
+#[tokio::test]
+async fn local_delivery() {
+    // Register a node of the same process, nothing listens on its address.
+    let address = "127.0.0.1:9045".parse::<SocketAddr>().unwrap();
+    let local = LocalRegistry::new();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    local.register(address, tx_deliver, Inflight::default());
+
+    let config = SenderConfig {
+        local: Some(local.clone()),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let bandwidth = sender.bandwidth();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The message arrives without a connection.
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message.clone()).await.unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message, message);
+    assert_eq!(bandwidth.get(&address), None);
+
+    // Once the node is gone the sender falls back to connecting, which fails.
+    drop(rx_deliver);
+    tx.send(message).await.unwrap();
+    assert_eq!(rx_retransmit.recv().await.unwrap().address, address);
+    assert!(!local.contains(&address));
+}
+
+#[tokio::test]
+async fn dedup() {
+    // Run a receiver that drops duplicates.
+    let address = "127.0.0.1:9046".parse::<SocketAddr>().unwrap();
+    let config = ReceiverConfig {
+        dedup: Some(Dedup::default()),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // The same id arrives twice on different connections, next to a message without id. Another
+    // sender uses the same id, its message isn't a duplicate.
+    let other = "127.0.0.1:9222".parse::<SocketAddr>().unwrap();
+    let frame = |sender: SocketAddr, id: Option<u64>| {
+        let mut message = NetworkMessage::new(sender, vec![address], format!("{:?}", id));
+        if let Some(id) = id {
+            message
+                .headers
+                .insert(MESSAGE_ID.to_string(), id.to_string());
+        }
+        encode_frame(&BincodeCodec::default(), &message).unwrap()
+    };
+    let mut transports = Vec::new();
+    for (sender, id) in [
+        (address, Some(1)),
+        (address, Some(1)),
+        (address, None),
+        (address, Some(2)),
+        (other, Some(1)),
+    ] {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        transport.send(frame(sender, id)).await.unwrap();
+        transports.push(transport);
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let mut delivered = Vec::new();
+    for _ in 0..4 {
+        let message = rx_deliver.recv().await.unwrap().message;
+        delivered.push((message.sender, message.id()));
+    }
+    assert_eq!(
+        delivered,
+        vec![
+            (address, Some(1)),
+            (address, None),
+            (address, Some(2)),
+            (other, Some(1))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn receipts() {
+    use crate::message::{DeliveryOutcome, DeliveryReceipt, SequenceIds};
+
+    // Run a receiver, the other peers are down.
+    let address = "127.0.0.1:9049".parse::<SocketAddr>().unwrap();
+    let down = "127.0.0.1:9050".parse::<SocketAddr>().unwrap();
+    let rejected = "127.0.0.1:9051".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a sender that tracks two peers and a retransmitter that gives up after two
+    // attempts, both reporting to the same receipts channel.
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let config = SenderConfig {
+        max_peers: Some(2),
+        ids: Some(Arc::new(SequenceIds::new(vec![1]))),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts.clone());
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let policy = RetransmitPolicy {
+        max_attempts: Some(2),
+        receipts: Some(tx_receipts),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+
+    for peer in [address, down, rejected] {
+        let message = NetworkMessage::new(address, vec![peer], "Hello, World!");
+        tx.send(message).await.unwrap();
+    }
+    rx_deliver.recv().await.unwrap();
+
+    // Every message gets exactly one receipt.
+    let mut receipts = Vec::new();
+    for _ in 0..3 {
+        receipts.push(rx_receipts.recv().await.unwrap());
+    }
+    receipts.sort_by_key(|receipt| receipt.message_id);
+    let receipt = |id, peer, outcome| DeliveryReceipt {
+        message_id: Some(id),
+        peer,
+        outcome,
+    };
+    assert_eq!(
+        receipts,
+        vec![
+            receipt(1, address, DeliveryOutcome::Sent),
+            receipt(2, down, DeliveryOutcome::Dropped),
+            receipt(3, rejected, DeliveryOutcome::Dropped),
+        ]
+    );
+    let more = tokio::time::timeout(Duration::from_millis(200), rx_receipts.recv()).await;
+    assert!(more.is_err());
+}
+
+#[tokio::test]
+async fn queue_overflow() {
+    use crate::message::DeliveryOutcome;
+
+    // Run a receiver that never grants credits, so its queue fills up, and one that keeps up.
+    let stuck = "127.0.0.1:9055".parse::<SocketAddr>().unwrap();
+    let healthy = "127.0.0.1:9056".parse::<SocketAddr>().unwrap();
+    let config = ReceiverConfig {
+        credits: Some(0),
+        ..ReceiverConfig::default()
+    };
+    let (tx_stuck, _rx_stuck) = channel(10);
+    let receiver = NetworkReceiver::with_config(stuck, tx_stuck, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(healthy, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Only the stuck peer has a small queue that rejects messages once it is full.
+    let peer = PeerConfig {
+        flow_control: true,
+        queue_capacity: 2,
+        overflow: OverflowPolicy::Reject,
+        ..PeerConfig::default()
+    };
+    let config = SenderConfig {
+        peers: HashMap::from([(stuck, peer)]),
+        ..SenderConfig::default()
+    };
+    let (tx_receipts, mut rx_receipts) = channel(100);
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    for i in 0..10 {
+        let message = NetworkMessage::new(healthy, vec![stuck, healthy], i.to_string());
+        tx.send(message).await.unwrap();
+    }
+
+    // The healthy peer gets every message although the queue of the other one is full.
+    for i in 0..10 {
+        assert_eq!(
+            rx_deliver.recv().await.unwrap().message.message,
+            i.to_string()
+        );
+    }
+
+    // The worker of the stuck peer waits for credits with one message, two more are queued and
+    // the rest is rejected.
+    let mut rejected = 0;
+    let mut sent = 0;
+    while let Ok(Some(receipt)) =
+        tokio::time::timeout(Duration::from_millis(200), rx_receipts.recv()).await
+    {
+        match receipt.outcome {
+            DeliveryOutcome::Rejected => {
+                assert_eq!(receipt.peer, stuck);
+                rejected += 1;
+            }
+            DeliveryOutcome::Sent => {
+                assert_eq!(receipt.peer, healthy);
+                sent += 1;
+            }
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+    assert_eq!(sent, 10);
+    assert!((7..=8).contains(&rejected), "{} rejected", rejected);
+}
+
+#[tokio::test]
+async fn inflight() {
+    // Run a receiver that gives every connection a single credit. It shares the count with the
+    // sender, like the components of a node.
+    let inflight = Inflight::new();
+    let address = "127.0.0.1:9057".parse::<SocketAddr>().unwrap();
+    let config = ReceiverConfig {
+        credits: Some(1),
+        inflight: inflight.clone(),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    let credits = receiver.credits();
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let peer = PeerConfig {
+        flow_control: true,
+        ..PeerConfig::default()
+    };
+    let config = SenderConfig {
+        peers: HashMap::from([(address, peer)]),
+        inflight: inflight.clone(),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Whoever enqueues messages counts them.
+    let node = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    inflight.add(5);
+    for i in 0..5 {
+        let message = NetworkMessage::new(node, vec![address], i.to_string());
+        tx.send(message).await.unwrap();
+    }
+
+    // One message waits in the deliver channel, one for a credit and the rest in the queue of
+    // the worker. They stay in flight until they are read.
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(inflight.count(), 5);
+    rx_deliver.recv().await.unwrap();
+    inflight.remove(1);
+    assert_eq!(inflight.count(), 4);
+
+    // Once the receiver grants the credits for the rest, they drain.
+    assert!(credits.grant(&node, 4));
+    for _ in 0..4 {
+        rx_deliver.recv().await.unwrap();
+        inflight.remove(1);
+    }
+    let drained = async {
+        while inflight.count() > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(1), drained)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn fifo() {
+    // Create a sender that keeps the order of the messages to a peer that isn't up yet. The test
+    // plays the retransmitter.
+    let address = "127.0.0.1:9058".parse::<SocketAddr>().unwrap();
+    let peer = PeerConfig {
+        fifo: true,
+        ..PeerConfig::default()
+    };
+    let config = SenderConfig {
+        peers: HashMap::from([(address, peer)]),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let message = |content: &str| NetworkMessage::new(address, vec![address], content);
+
+    // The first message can't be sent and goes to the retransmitter.
+    tx.send(message("first")).await.unwrap();
+    let retransmitted = rx_retransmit.recv().await.unwrap();
+    assert_eq!(retransmitted.message.message, "first");
+
+    // Once the peer is up a new message waits for the retransmitted one.
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    tx.send(message("second")).await.unwrap();
+    let early = tokio::time::timeout(Duration::from_millis(200), rx_deliver.recv()).await;
+    assert!(early.is_err());
+
+    tx_retry.send(retransmitted).await.unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "first");
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "second");
+}
+
+#[tokio::test]
+async fn backoff() {
+    let policy = RetransmitPolicy {
+        backoff: Backoff {
+            base_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(80),
+            multiplier: 2.0,
+        },
+        jitter: 0.0,
+        ..RetransmitPolicy::default()
+    };
+    let expected = [20, 40, 80, 80].map(Duration::from_millis);
+    for (attempts, delay) in expected.iter().enumerate() {
+        assert_eq!(policy.delay(attempts + 1), *delay);
+    }
+
+    // Keep handing the retransmitter the same message, as a sender whose peer is down would.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, mut rx_retry) = channel(10);
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+    let address = "127.0.0.1:9059".parse::<SocketAddr>().unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let mut delivery = Delivery::new(message, address);
+    let mut delays = Vec::new();
+    for _ in 0..expected.len() {
+        let start = Instant::now();
+        tx_retransmit.send(delivery).await.unwrap();
+        delivery = rx_retry.recv().await.unwrap();
+        delays.push(start.elapsed());
+    }
+
+    // The delays grow with every attempt until they reach the maximum.
+    assert_eq!(delivery.attempts, expected.len());
+    for (delay, expected) in delays.iter().zip(expected) {
+        assert!(*delay >= expected, "{:?}", delays);
+    }
+    assert!(
+        delays[0] < delays[1] && delays[1] < delays[2],
+        "{:?}",
+        delays
+    );
+}
+
+#[test]
+fn jitter() {
+    let policy = RetransmitPolicy {
+        backoff: Backoff {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(100),
+            multiplier: 1.0,
+        },
+        jitter: 0.3,
+        ..RetransmitPolicy::default()
+    };
+
+    // The delays spread over the whole range around the backoff delay.
+    let delays = (0..1000).map(|_| policy.delay(1)).collect::<Vec<_>>();
+    let (min, max) = (delays.iter().min().unwrap(), delays.iter().max().unwrap());
+    assert!(*min >= Duration::from_millis(70), "{:?}", min);
+    assert!(*max <= Duration::from_millis(130), "{:?}", max);
+    assert!(*min < Duration::from_millis(85), "{:?}", min);
+    assert!(*max > Duration::from_millis(115), "{:?}", max);
+    assert!(delays.iter().collect::<HashSet<_>>().len() > 100);
+}
+
+// Counts the connections accepted by receivers.
+#[derive(Debug, Default)]
+struct AcceptCounter(AtomicUsize);
+
+impl ObserverSink for AcceptCounter {
+    fn connected(&self, _peer: SocketAddr) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn dialing() {
+    // Run 100 receivers that count the connections they accept.
+    let accepted = Arc::new(AcceptCounter::default());
+    let addresses = (9060..9160)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    for address in &addresses {
+        let config = ReceiverConfig {
+            observer: accepted.clone(),
+            ..ReceiverConfig::default()
+        };
+        let (tx_deliver, _) = channel(10);
+        let receiver = NetworkReceiver::with_config(*address, tx_deliver, config);
+        tokio::spawn(async move {
+            receiver.run().await.unwrap();
+        });
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // A sender doesn't connect to anyone until it sends a message.
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(accepted.0.load(Ordering::SeqCst), 0);
+
+    // Then it only connects to the recipient.
+    let message = NetworkMessage::new(addresses[0], vec![addresses[1]], "Hello, World!");
+    tx.send(message).await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(accepted.0.load(Ordering::SeqCst), 1);
+
+    // A sender that dials eagerly connects to every peer right away.
+    let config = SenderConfig {
+        dialing: Dialing::Eager(addresses.clone()),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (_tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(accepted.0.load(Ordering::SeqCst), 101);
+}
+
+#[tokio::test]
+async fn connect_timeout() {
+    use tokio::net::TcpSocket;
+
+    // A listener that never accepts and whose backlog is full drops further handshakes, like an
+    // address that swallows packets.
+    let address = "127.0.0.1:9160".parse::<SocketAddr>().unwrap();
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(address).unwrap();
+    let _listener = socket.listen(0).unwrap();
+    let mut backlog = Vec::new();
+    for _ in 0..4 {
+        let connect = TcpStream::connect(address);
+        if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), connect).await {
+            backlog.push(stream);
+        }
+    }
+
+    let config = SenderConfig {
+        connect_timeout: Duration::from_millis(300),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (_, rx) = channel(10);
+    let sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+
+    // The worker reports the failed connection once the timeout passed instead of hanging.
+    let start = Instant::now();
+    let (tx_ok, rx_ok) = oneshot::channel();
+    let peer = PeerConfig::default();
+    let slot = Pool::new(1).slot(0);
+    let _ = NetworkSender::spawn_worker(address, peer, sender.shared.clone(), slot, tx_ok).await;
+    let ok = tokio::time::timeout(Duration::from_secs(2), rx_ok).await;
+    assert!(!ok.unwrap().unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn unknown_format() {
+    let address = "127.0.0.1:9163".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A frame of a format from the future is skipped, the connection stays open for the next one.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport
+        .send(bytes::Bytes::from_static(&[0x7f, 1, 2, 3]))
+        .await
+        .unwrap();
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message, message);
+
+    // A receiver that disconnects on unknown formats closes the connection instead.
+    let address = "127.0.0.1:9164".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        unknown: UnknownPolicy::Disconnect,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport
+        .send(bytes::Bytes::from_static(&[0x7f, 1, 2, 3]))
+        .await
+        .unwrap();
+    assert!(transport.next().await.is_none());
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let address = "127.0.0.1:9165".parse::<SocketAddr>().unwrap();
+    let message = |content: &str| NetworkMessage::new(address, vec![address], content);
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let listener = TcpListener::bind(address).await.unwrap();
+    tx.send(message("first")).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "first");
+
+    // Drop the connection. The first write after it may still succeed, a later one fails and
+    // makes the worker reconnect and send it again.
+    drop(transport);
+    for content in ["second", "third"] {
+        sleep(Duration::from_millis(50)).await;
+        tx.send(message(content)).await.unwrap();
+    }
+    let (socket, _) = tokio::time::timeout(Duration::from_secs(2), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(2), transport.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        if Codecs::default().decode(&frame).unwrap().message == "third" {
+            break;
+        }
+    }
+
+    // The worker survived the blip, nothing went through the retransmitter.
+    assert!(rx_retransmit.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn dead_workers() {
+    let address = "127.0.0.1:9166".parse::<SocketAddr>().unwrap();
+    let message = |content: &str| NetworkMessage::new(address, vec![address], content);
+    let config = SenderConfig {
+        reconnects: 0,
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+
+    let peer = async {
+        // The peer takes a single message and goes away, so the worker dies on a later send.
+        let listener = TcpListener::bind(address).await.unwrap();
+        tx.send(message("first")).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        transport.next().await.unwrap().unwrap();
+        drop((transport, listener));
+        while rx_retransmit.try_recv().is_err() {
+            tx.send(message("more")).await.unwrap();
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        // The next message finds the queue of the dead worker closed and can't reach the peer
+        // with a new one either.
+        tx.send(message("last")).await.unwrap();
+        while rx_retransmit.recv().await.unwrap().message.message != "last" {}
+    };
+    let run = tokio::time::timeout(Duration::from_secs(2), sender.run());
+    let (_, ()) = tokio::join!(run, peer);
+
+    // The dead worker doesn't keep its entry.
+    assert!(sender.senders.is_empty());
+}
+
+#[tokio::test]
+async fn max_frame_length() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let address = "127.0.0.1:9167".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        max_frame_length: 1024,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let receiver = tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A length prefix above the limit closes the connection before the frame is read.
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(&(1u32 << 20).to_be_bytes()).await.unwrap();
+    stream.write_all(&[0; 64]).await.unwrap();
+    let mut buf = [0; 1];
+    assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+    assert!(rx.try_recv().is_err());
+
+    // The receiver keeps serving other connections.
+    let _transport = connect_and_send(address, address, "Hello, World!").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "Hello, World!");
+    assert!(!receiver.is_finished());
+}
+
+#[tokio::test]
+async fn large_frames() {
+    // A receiver that allows frames above the default of 8 MiB decodes messages that large.
+    let address = "127.0.0.1:9223".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        max_frame_length: 16 * 1024 * 1024,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let content = "x".repeat(9 * 1024 * 1024);
+    let stream = TcpStream::connect(address).await.unwrap();
+    let codec = LengthDelimitedCodec::builder()
+        .max_frame_length(16 * 1024 * 1024)
+        .new_codec();
+    let mut transport = Framed::new(stream, codec);
+    let message = NetworkMessage::unicast(address, address, content.as_str());
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, content);
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn compressed_above_max_frame_length() {
+    use crate::network::encode_frame_compressed;
+
+    let address = "127.0.0.1:9224".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        max_frame_length: 16 * 1024,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A frame within the limit that decompresses to far more than it is skipped.
+    let bomb = NetworkMessage::unicast(address, address, "x".repeat(1024 * 1024));
+    let frame = encode_frame_compressed(&BincodeCodec::default(), &bomb, Some(0)).unwrap();
+    assert!(frame.len() < 16 * 1024);
+    let mut transport = connect_and_send(address, address, "first").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "first");
+    transport.send(frame).await.unwrap();
+    let message = NetworkMessage::unicast(address, address, "second");
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, "second");
+}
+
+#[tokio::test]
+async fn malformed() {
+    let address = "127.0.0.1:9168".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        max_decode_failures: 2,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let receiver = tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Garbage tagged as bincode is skipped and the connection still delivers the next message.
+    let garbage = bytes::Bytes::from_static(&[0, 0xde, 0xad, 0xbe, 0xef]);
+    let mut transport = connect_and_send(address, address, "first").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "first");
+    transport.send(garbage.clone()).await.unwrap();
+    let message = NetworkMessage::new(address, vec![address], "second");
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, "second");
+
+    // More failures in a row than allowed close the connection.
+    for _ in 0..3 {
+        transport.send(garbage.clone()).await.unwrap();
+    }
+    assert!(transport.next().await.is_none());
+    assert!(!receiver.is_finished());
+}
+
+#[tokio::test]
+async fn stop() {
+    // The receiver stops accepting and closes its listener.
+    let address = "127.0.0.1:9169".parse::<SocketAddr>().unwrap();
+    let shutdown = Shutdown::new();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        shutdown: shutdown.clone(),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let receiver = tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    let mut transport = connect_and_send(address, address, "before").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "before");
+
+    // The sender and the retransmitter stop although their channels are still open.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (_tx, rx_transmit) = channel::<NetworkMessage>(10);
+    let config = SenderConfig {
+        shutdown: shutdown.clone(),
+        ..SenderConfig::default()
+    };
+    let mut sender = NetworkSender::with_config(rx_transmit, tx_retransmit, rx_retry, config);
+    let sender = tokio::spawn(async move {
+        sender.run().await;
+    });
+    let policy = RetransmitPolicy {
+        shutdown: shutdown.clone(),
+        ..RetransmitPolicy::default()
+    };
+    let retransmitter =
+        NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+
+    shutdown.trigger();
+    for handle in [receiver, sender, retransmitter] {
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    assert!(TcpStream::connect(address).await.is_err());
+
+    // A connection that was open before is still read.
+    let message = NetworkMessage::new(address, vec![address], "after");
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, "after");
+}
+
+#[tokio::test]
+async fn nodelay() {
+    let address = "127.0.0.1:9170".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let _outbound = TcpStream::connect(address).await.unwrap();
+    let (inbound, peer) = listener.accept().await.unwrap();
+    assert!(!inbound.nodelay().unwrap());
+
+    // Without the flag the option stays at the system default.
+    configure_socket(&inbound, peer, false, None);
+    assert!(!inbound.nodelay().unwrap());
+
+    configure_socket(&inbound, peer, true, None);
+    assert!(inbound.nodelay().unwrap());
+}
+
+#[tokio::test]
+async fn handshake() {
+    let address = "127.0.0.1:9171".parse::<SocketAddr>().unwrap();
+    let hello = Hello::new(7, "127.0.0.1:9172".parse().unwrap());
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        handshake: true,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let node_ids = receiver.node_ids();
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A connection that starts right with a message is closed without delivering it.
+    let mut transport = connect_and_send(address, hello.address, "anonymous").await;
+    assert!(matches!(transport.next().await, None | Some(Err(_))));
+    assert!(rx.try_recv().is_err());
+
+    // The identity is known before the first message is read.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(hello.encode()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(node_ids.get(&hello.address), Some(7));
+    let message = NetworkMessage::new(hello.address, vec![address], "identified");
+    let frame = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(frame).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, "identified");
+
+    // A sender configured with a hello sends it ahead of the messages.
+    let peer = "127.0.0.1:9173".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(peer).await.unwrap();
+    let config = SenderConfig {
+        hello: Some(hello),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    tx.send(NetworkMessage {
+        addresses: vec![peer],
+        ..message
+    })
+    .await
+    .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Hello::decode(&frame), Some(hello));
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(
+        Codecs::default().decode(&frame).unwrap().message,
+        "identified"
+    );
+}
+
+#[tokio::test]
+async fn peer_address() {
+    let address = "127.0.0.1:9174".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // The message claims to come from another node, the peer is where it really came from.
+    let claimed = "127.0.0.1:9999".parse::<SocketAddr>().unwrap();
+    let transport = connect_and_send(address, claimed, "spoofed").await;
+    let inbound = rx.recv().await.unwrap();
+    assert_eq!(inbound.message.sender, claimed);
+    assert_eq!(inbound.peer, transport.get_ref().local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn loopback() {
+    // Nothing listens on our own address, a connection to it would fail.
+    let address = "127.0.0.1:9175".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let connections = Arc::new(AcceptCounter::default());
+    let inflight = Inflight::new();
+    let config = SenderConfig {
+        loopback: Some(Loopback::new(address, tx_deliver, inflight.clone())),
+        observer: connections.clone(),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The message arrives like one from a connection of our own node.
+    let message = NetworkMessage::unicast(address, address, "to myself");
+    tx.send(message.clone()).await.unwrap();
+    let inbound = rx_deliver.recv().await.unwrap();
+    assert_eq!(inbound.message, message);
+    assert_eq!(inbound.peer, address);
+    assert_eq!(inflight.count(), 1);
+    assert_eq!(connections.0.load(Ordering::SeqCst), 0);
+    assert!(rx_retransmit.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn membership() {
+    let address = "127.0.0.1:9178".parse::<SocketAddr>().unwrap();
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    sender.report_receipts(tx_receipts);
+    let membership = sender.membership();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let listener = TcpListener::bind(address).await.unwrap();
+    let message = |content: &str| NetworkMessage::unicast(address, address, content);
+    tx.send(message("first")).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "first");
+    assert_eq!(
+        rx_receipts.recv().await.unwrap().outcome,
+        DeliveryOutcome::Sent
+    );
+
+    // Once the peer is removed its connection is closed and its messages are dropped.
+    membership.remove_peer(address);
+    assert!(!membership.is_member(&address));
+    tx.send(message("second")).await.unwrap();
+    assert_eq!(
+        rx_receipts.recv().await.unwrap().outcome,
+        DeliveryOutcome::Dropped
+    );
+    assert!(transport.next().await.is_none());
+
+    // Added again, it gets a new connection.
+    membership.add_peer(address);
+    tx.send(message("third")).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "third");
+}
+
+#[tokio::test]
+async fn stats() {
+    let address = "127.0.0.1:9179".parse::<SocketAddr>().unwrap();
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    sender.report_receipts(tx_receipts);
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let listener = TcpListener::bind(address).await.unwrap();
+    let message = |content: &str| NetworkMessage::unicast(address, address, content);
+    for content in ["one", "two", "three"] {
+        tx.send(message(content)).await.unwrap();
+    }
+    // A message handed back by the retransmitter counts as a retransmit.
+    tx_retry
+        .send(Delivery::new(message("four"), address))
+        .await
+        .unwrap();
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let mut bytes = 0;
+    for _ in 0..4 {
+        bytes += transport.next().await.unwrap().unwrap().len() as u64;
+        assert_eq!(
+            rx_receipts.recv().await.unwrap().outcome,
+            DeliveryOutcome::Sent
+        );
+    }
+
+    let counts = SenderCounts {
+        messages_sent: 4,
+        frames_sent: 4,
+        send_failures: 0,
+        retransmits: 1,
+        bytes_sent: bytes,
+        connections_opened: 1,
+        connections_dropped: 0,
+    };
+    assert_eq!(stats.totals(), counts);
+    assert_eq!(stats.get(&address), Some(counts));
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn compressed_payload() {
+    use crate::network::COMPRESSED;
+
+    let address = "127.0.0.1:9180".parse::<SocketAddr>().unwrap();
+    let config = SenderConfig {
+        compression_threshold: Some(1024),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // A highly compressible 1 MiB payload goes over the wire as a much smaller frame.
+    let listener = TcpListener::bind(address).await.unwrap();
+    let message = NetworkMessage::unicast(address, address, "a".repeat(1024 * 1024));
+    tx.send(message.clone()).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_ne!(frame[0] & COMPRESSED, 0);
+    assert!(frame.len() < 64 * 1024);
+    assert_eq!(Codecs::default().decode(&frame).unwrap(), message);
+}
+
+#[tokio::test]
+async fn warm_up() {
+    let addresses = (9181..9184)
+        .map(|port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    let mut listeners = Vec::new();
+    for address in &addresses {
+        listeners.push(TcpListener::bind(address).await.unwrap());
+    }
+    let config = SenderConfig {
+        dialing: Dialing::Eager(addresses.clone()),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let warmed_up = sender.warmed_up();
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Every connection is open before the first message is sent.
+    warmed_up.wait().await;
+    for address in &addresses {
+        assert_eq!(stats.get(address).unwrap().connections_opened, 1);
+    }
+    let message = NetworkMessage::broadcast(addresses[0], &addresses, "first");
+    tx.send(message).await.unwrap();
+    for listener in listeners.iter().skip(1) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let frame = transport.next().await.unwrap().unwrap();
+        assert_eq!(Codecs::default().decode(&frame).unwrap().message, "first");
+    }
+    assert_eq!(stats.totals().connections_opened, 3);
+}
+
+#[tokio::test]
+async fn reorder() {
+    let address = "127.0.0.1:9184".parse::<SocketAddr>().unwrap();
+    let sender = "127.0.0.1:9185".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        reorder: Some(10),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Messages written out of order are delivered in sequence.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    for seq in [2, 4, 1, 3] {
+        let mut message = NetworkMessage::unicast(sender, address, format!("message {}", seq));
+        message.set_sequence(seq);
+        let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+        transport.send(bytes).await.unwrap();
+    }
+    for seq in 1..=4 {
+        let inbound = rx.recv().await.unwrap();
+        assert_eq!(inbound.message.sequence(), Some(seq));
+    }
+
+    // A message that stays missing holds back the later ones.
+    let mut message = NetworkMessage::unicast(sender, address, "message 6");
+    message.set_sequence(6);
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    transport.send(bytes).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn topics() {
+    use crate::network::{DeliveryOrder, Topics};
+
+    let address = "127.0.0.1:9230".parse::<SocketAddr>().unwrap();
+    let sender = "127.0.0.1:9185".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        reorder: Some(10),
+        topics: Some(
+            Topics::new(DeliveryOrder::Strict, 10).declare("telemetry", DeliveryOrder::Unordered),
+        ),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Control message 0 is missing, telemetry arrives out of order in between.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let frame = |topic: &'static str, seq: u64| {
+        let mut message = NetworkMessage::unicast(sender, address, format!("{} {}", topic, seq));
+        message.set_topic(topic);
+        message.set_topic_sequence(seq);
+        encode_frame(&BincodeCodec::default(), &message).unwrap()
+    };
+    let frames = [
+        frame("control", 1),
+        frame("telemetry", 2),
+        frame("control", 2),
+        frame("telemetry", 0),
+    ];
+    for bytes in frames {
+        transport.send(bytes).await.unwrap();
+    }
+
+    // Telemetry isn't blocked by the gap in control.
+    for content in ["telemetry 2", "telemetry 0"] {
+        assert_eq!(rx.recv().await.unwrap().message.message, content);
+    }
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx.try_recv().is_err());
+
+    // Once the gap is filled the control messages follow in order.
+    transport.send(frame("control", 0)).await.unwrap();
+    for content in ["control 0", "control 1", "control 2"] {
+        assert_eq!(rx.recv().await.unwrap().message.message, content);
+    }
+}
+
+#[tokio::test]
+async fn topic_sequences() {
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let config = SenderConfig {
+        max_frame_length: 1024,
+        ..SenderConfig::default()
+    };
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The sender numbers the messages of every topic on their own. Messages that are dropped,
+    // here one that expired and one that is too long, don't take a number.
+    let address = "127.0.0.1:9231".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let mut expired = NetworkMessage::unicast(address, address, "expired");
+    expired.set_topic("control");
+    expired.set_ttl(Duration::ZERO);
+    let mut long = NetworkMessage::unicast(address, address, "x".repeat(4096));
+    long.set_topic("control");
+    tx.send(expired).await.unwrap();
+    tx.send(long).await.unwrap();
+    for topic in ["control", "telemetry", "control", ""] {
+        let mut message = NetworkMessage::unicast(address, address, format!("{} message", topic));
+        if !topic.is_empty() {
+            message.set_topic(topic);
+        }
+        tx.send(message).await.unwrap();
+    }
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let mut sequences = Vec::new();
+    for _ in 0..4 {
+        let frame = transport.next().await.unwrap().unwrap();
+        let message = Codecs::default().decode(&frame).unwrap();
+        sequences.push((
+            message.topic().map(str::to_string),
+            message.topic_sequence(),
+        ));
+    }
+    assert_eq!(
+        sequences,
+        vec![
+            (Some("control".to_string()), Some(0)),
+            (Some("telemetry".to_string()), Some(0)),
+            (Some("control".to_string()), Some(1)),
+            (None, None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn acknowledged() {
+    let address = "127.0.0.1:9186".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Create a network sender that waits for the acknowledgements of the receiver.
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        ack_timeout: Some(Timeout::Fixed(Duration::from_secs(5))),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    let unacked = sender.unacked();
+    let rtts = sender.rtts();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The message only counts as sent once the acknowledgement came back.
+    tx.send(NetworkMessage::unicast(address, address, "acknowledged"))
+        .await
+        .unwrap();
+    assert_eq!(
+        rx_deliver.recv().await.unwrap().message.message,
+        "acknowledged"
+    );
+    assert_eq!(
+        rx_receipts.recv().await.unwrap().outcome,
+        DeliveryOutcome::Sent
+    );
+    assert_eq!(unacked.get(&address), 0);
+
+    // Its round trip is the first sample of the estimation, the variation starts at half of it.
+    let rtt = rtts.get(&address).unwrap();
+    let srtt = rtt.srtt().unwrap();
+    assert!(srtt < Duration::from_secs(1), "{:?}", rtt);
+    let rttvar = rtt.rttvar().unwrap().as_secs_f64();
+    assert!(
+        (rttvar - srtt.as_secs_f64() / 2.0).abs() < 1e-6,
+        "{:?}",
+        rtt
+    );
+}
+
+#[tokio::test]
+async fn rtt_ack_timeout() {
+    use tokio::io::AsyncReadExt;
+    use tokio_util::codec::FramedRead;
+
+    let address = "127.0.0.1:9229".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        ack_timeout: Some(Timeout::Rtt {
+            multiple: 2.0,
+            min: Duration::from_millis(10),
+            fallback: Duration::from_secs(5),
+        }),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    let rtts = sender.rtts();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // A peer that acknowledges every message 50ms after reading it.
+    for i in 0..5 {
+        let message = NetworkMessage::unicast(address, address, format!("message {}", i));
+        tx.send(message).await.unwrap();
+    }
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut request = [0];
+    socket.read_exact(&mut request).await.unwrap();
+    assert_eq!(request[0], ACKNOWLEDGE);
+    let (read, write) = socket.into_split();
+    let mut transport = FramedRead::new(read, LengthDelimitedCodec::new());
+    let acks = spawn_credit_writer(write, None, true, false, false)
+        .acks
+        .unwrap();
+    for _ in 0..5 {
+        let frame = transport.next().await.unwrap().unwrap();
+        let seq = Codecs::default()
+            .decode(&frame)
+            .unwrap()
+            .sequence()
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        acks.send(seq).unwrap();
+        assert_eq!(
+            rx_receipts.recv().await.unwrap().outcome,
+            DeliveryOutcome::Sent
+        );
+    }
+
+    // The estimation follows the round trips.
+    let rtt = rtts.get(&address).unwrap();
+    let srtt = rtt.srtt().unwrap();
+    assert!(srtt >= Duration::from_millis(40), "{:?}", rtt);
+    assert!(srtt < Duration::from_millis(200), "{:?}", rtt);
+
+    // A message that isn't acknowledged is retransmitted after twice the retransmission timeout
+    // of the estimation, long before the fallback.
+    let started = Instant::now();
+    tx.send(NetworkMessage::unicast(address, address, "lost"))
+        .await
+        .unwrap();
+    transport.next().await.unwrap().unwrap();
+    let delivery = rx_retransmit.recv().await.unwrap();
+    assert_eq!(delivery.message.message, "lost");
+    let rto = rtt.rto().unwrap() * 2;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= rto, "{:?} {:?}", elapsed, rto);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn streaming() {
+    use crate::network::{decode_chunk, Streaming, CHUNK, STREAMING};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    let address = "127.0.0.1:9233".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+
+    // A proxy in front of the receiver reports every chunk and loses the first copy of chunk 5.
+    let proxy = "127.0.0.1:9232".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(proxy).await.unwrap();
+    let (tx_chunks, mut rx_chunks) = unbounded_channel();
+    tokio::spawn(async move {
+        let (mut inbound, _) = listener.accept().await.unwrap();
+        let mut outbound = TcpStream::connect(address).await.unwrap();
+        let mut request = [0];
+        inbound.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[0], STREAMING);
+        outbound.write_all(&request).await.unwrap();
+        let (read, mut write) = inbound.into_split();
+        let (mut acks, forward) = outbound.into_split();
+        tokio::spawn(async move { tokio::io::copy(&mut acks, &mut write).await });
+        let mut frames = FramedRead::new(read, LengthDelimitedCodec::new());
+        let mut forward = FramedWrite::new(forward, LengthDelimitedCodec::new());
+        let mut lost = false;
+        while let Some(Ok(frame)) = frames.next().await {
+            if frame.first() == Some(&CHUNK) {
+                let chunk = decode_chunk(&frame).unwrap();
+                tx_chunks.send(chunk.index).unwrap();
+                if chunk.index == 5 && !lost {
+                    lost = true;
+                    continue;
+                }
+            }
+            forward.send(frame.freeze()).await.unwrap();
+        }
+    });
+
+    // Stream frames of at least 1 KiB in chunks of 1 KiB to the proxy.
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        streaming: Some(Streaming {
+            threshold: 1024,
+            chunk_size: 1024,
+            window: 4,
+            timeout: Duration::from_millis(100),
+        }),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(proxy, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let large = "x".repeat(20 * 1024);
+    for payload in [large.as_str(), "small"] {
+        tx.send(NetworkMessage::unicast(proxy, proxy, payload))
+            .await
+            .unwrap();
+    }
+
+    // The large message arrives whole, followed by the small one, which isn't streamed.
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, large);
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "small");
+    for _ in 0..2 {
+        assert_eq!(
+            rx_receipts.recv().await.unwrap().outcome,
+            DeliveryOutcome::Sent
+        );
+    }
+
+    // Only the lost chunk was sent twice.
+    let mut sent = HashMap::<u32, usize>::new();
+    while let Ok(index) = rx_chunks.try_recv() {
+        *sent.entry(index).or_default() += 1;
+    }
+    assert_eq!(sent.len(), 21);
+    let retransmitted = sent
+        .iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(index, count)| (*index, *count))
+        .collect::<Vec<_>>();
+    assert_eq!(retransmitted, vec![(5, 2)]);
+}
+
+#[tokio::test]
+async fn lost_ack() {
+    use tokio::io::AsyncReadExt;
+
+    let address = "127.0.0.1:9187".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        ack_timeout: Some(Timeout::Fixed(Duration::from_millis(100))),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    let unacked = sender.unacked();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // A peer that reads the message but never acknowledges it.
+    tx.send(NetworkMessage::unicast(address, address, "lost"))
+        .await
+        .unwrap();
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut request = [0];
+    socket.read_exact(&mut request).await.unwrap();
+    assert_eq!(request[0], ACKNOWLEDGE);
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "lost");
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(unacked.get(&address), 1);
+
+    // Once the timeout passed the message is retransmitted.
+    let delivery = rx_retransmit.recv().await.unwrap();
+    assert_eq!(delivery.message.message, "lost");
+    assert_eq!(unacked.get(&address), 0);
+    assert!(rx_receipts.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn max_connections() {
+    use tokio::io::AsyncReadExt;
+
+    let address = "127.0.0.1:9188".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        max_connections: Some(2),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let first_node = "127.0.0.1:9997".parse::<SocketAddr>().unwrap();
+    let second_node = "127.0.0.1:9998".parse::<SocketAddr>().unwrap();
+    let first = connect_and_send(address, first_node, "first").await;
+    let mut second = connect_and_send(address, second_node, "second").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "first");
+    assert_eq!(rx.recv().await.unwrap().message.message, "second");
+
+    // The connection beyond the limit is reset right after it was accepted.
+    let mut extra = TcpStream::connect(address).await.unwrap();
+    let mut buffer = [0; 1];
+    let read = tokio::time::timeout(Duration::from_secs(1), extra.read(&mut buffer))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    // The connections within the limit keep working.
+    let message = NetworkMessage::unicast(second_node, address, "again");
+    let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+    second.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().message.message, "again");
+
+    // Closing one of them makes room for a new connection.
+    drop(first);
+    sleep(Duration::from_millis(50)).await;
+    let _third = connect_and_send(address, first_node, "third").await;
+    assert_eq!(rx.recv().await.unwrap().message.message, "third");
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+    use tokio::io::AsyncReadExt;
+
+    let address = "127.0.0.1:9189".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    let config = ReceiverConfig {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A connection that never sends anything is closed once the idle period is over.
+    let mut silent = TcpStream::connect(address).await.unwrap();
+    let opened = tokio::time::Instant::now();
+    let mut buffer = [0; 1];
+    let read = tokio::time::timeout(Duration::from_secs(1), silent.read(&mut buffer))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0)));
+    assert!(opened.elapsed() >= Duration::from_millis(200));
+
+    // One that keeps sending stays open for much longer.
+    let node = "127.0.0.1:9996".parse::<SocketAddr>().unwrap();
+    let mut busy = connect_and_send(address, node, "0").await;
+    for i in 1..5 {
+        sleep(Duration::from_millis(100)).await;
+        let message = NetworkMessage::unicast(node, address, i.to_string());
+        let bytes = encode_frame(&BincodeCodec::default(), &message).unwrap();
+        busy.send(bytes).await.unwrap();
+    }
+    for i in 0..5 {
+        assert_eq!(rx.recv().await.unwrap().message.message, i.to_string());
+    }
+}
+
+#[tokio::test]
+async fn heartbeat() {
+    let address = "127.0.0.1:9190".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    // Without the heartbeats the receiver would close the connection as idle.
+    let config = ReceiverConfig {
+        idle_timeout: Some(Duration::from_millis(150)),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        heartbeat_interval: Some(Duration::from_millis(50)),
+        missed_heartbeats: 2,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    tx.send(NetworkMessage::unicast(address, address, "data"))
+        .await
+        .unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "data");
+
+    // The heartbeats are answered, so the connection stays up, but they aren't delivered.
+    sleep(Duration::from_millis(500)).await;
+    assert!(rx_deliver.try_recv().is_err());
+    let totals = stats.totals();
+    assert_eq!(totals.connections_opened, 1);
+    assert_eq!(totals.connections_dropped, 0);
+}
+
+#[tokio::test]
+async fn dead_peer() {
+    use tokio::io::AsyncReadExt;
+
+    let address = "127.0.0.1:9191".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        heartbeat_interval: Some(Duration::from_millis(50)),
+        missed_heartbeats: 2,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // A peer that reads everything but never answers a heartbeat.
+    tx.send(NetworkMessage::unicast(address, address, "data"))
+        .await
+        .unwrap();
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut request = [0];
+    socket.read_exact(&mut request).await.unwrap();
+    assert_eq!(request[0], HEARTBEAT);
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = transport.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "data");
+    let frame = transport.next().await.unwrap().unwrap();
+    let message = Codecs::default().decode(&frame).unwrap();
+    assert_eq!(message.kind(), MessageKind::Heartbeat);
+
+    // The sender gives up on the connection without a message failing and reconnects.
+    let reconnected = tokio::time::timeout(Duration::from_secs(1), listener.accept()).await;
+    assert!(reconnected.is_ok());
+}
+
+#[tokio::test]
+async fn retransmitter_gone() {
+    let address = "127.0.0.1:9192".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        ack_timeout: Some(Timeout::Fixed(Duration::from_millis(50))),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    let handle = tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Nobody takes the message that isn't acknowledged in time, like during shutdown.
+    drop(rx_retransmit);
+    tx.send(NetworkMessage::unicast(address, address, "lost"))
+        .await
+        .unwrap();
+    let (_socket, _) = listener.accept().await.unwrap();
+    let receipt = rx_receipts.recv().await.unwrap();
+    assert_eq!(receipt.outcome, DeliveryOutcome::Failed);
+
+    // The sender shuts down cleanly instead of panicking.
+    drop(tx);
+    let result = tokio::time::timeout(Duration::from_secs(1), handle).await;
+    assert!(result.unwrap().is_ok());
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn tls() {
+    use crate::network::tls::tls_tests::configs;
+    use crate::network::TlsPolicy;
+
+    let address = "127.0.0.1:9193".parse::<SocketAddr>().unwrap();
+    let (client, server) = configs();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        tls: ServerTls {
+            policy: TlsPolicy::Require,
+            ..server
+        },
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Both sides require TLS, so the message only arrives over an encrypted connection.
+    let config = SenderConfig {
+        tls: client,
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    tx.send(NetworkMessage::unicast(address, address, "encrypted"))
+        .await
+        .unwrap();
+    let inbound = rx_deliver.recv().await.unwrap();
+    assert_eq!(inbound.message.message, "encrypted");
+}
+
+#[tokio::test]
+async fn abort_worker() {
+    let address = "127.0.0.1:9196".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    let workers = sender.workers();
+    let handle = sender.spawn();
+
+    tx.send(NetworkMessage::unicast(address, address, "first"))
+        .await
+        .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut first = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = first.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "first");
+    assert_eq!(workers.snapshot(), vec![address]);
+
+    // Aborting the worker closes its connection.
+    assert!(workers.abort(&address));
+    assert!(first.next().await.is_none());
+    assert!(!workers.is_running(&address));
+    assert!(!workers.abort(&address));
+
+    // The sender notices and spawns a new worker for the next message.
+    tx.send(NetworkMessage::unicast(address, address, "second"))
+        .await
+        .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut second = Framed::new(socket, LengthDelimitedCodec::new());
+    let frame = second.next().await.unwrap().unwrap();
+    assert_eq!(Codecs::default().decode(&frame).unwrap().message, "second");
+    assert!(workers.is_running(&address));
+
+    // The sender itself keeps running until its transmit channel is closed.
+    assert!(!handle.is_finished());
+    drop(tx);
+    tokio::time::timeout(Duration::from_secs(1), handle)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn batching() {
+    let address = "127.0.0.1:9197".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(1000);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    let received = receiver.received_sequences();
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        batching: Some(Batching {
+            max_messages: 32,
+            max_bytes: 4096,
+            max_delay: Duration::from_millis(5),
+        }),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(1000);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let count = 500;
+    for i in 0..count {
+        tx.send(NetworkMessage::unicast(address, address, i.to_string()))
+            .await
+            .unwrap();
+    }
+
+    // Every message arrives on its own and in order, though they share frames.
+    for i in 0..count {
+        let inbound = rx_deliver.recv().await.unwrap();
+        assert_eq!(inbound.message.message, i.to_string());
+        assert_eq!(inbound.message.sequence(), Some(i + 1));
+    }
+    assert_eq!(received.get(&address), Some(count));
+    let totals = stats.totals();
+    assert_eq!(totals.messages_sent, count);
+    assert!(totals.frames_sent < count, "{} frames", totals.frames_sent);
+
+    // A message on its own is sent after the delay without waiting for a full batch.
+    tx.send(NetworkMessage::unicast(address, address, "alone"))
+        .await
+        .unwrap();
+    let inbound = timeout(Duration::from_millis(500), rx_deliver.recv()).await;
+    assert_eq!(inbound.unwrap().unwrap().message.message, "alone");
+}
+
+#[tokio::test]
+async fn pool() {
+    let address = "127.0.0.1:9198".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(100);
+    // The connections of a pool would replace each other otherwise.
+    let config = ReceiverConfig {
+        duplicate_policy: DuplicatePolicy::AllowBoth,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        pool_size: 3,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(100);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let stats = sender.stats();
+    let workers = sender.workers();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    for i in 0..30 {
+        tx.send(NetworkMessage::unicast(address, address, i.to_string()))
+            .await
+            .unwrap();
+    }
+    let mut contents = HashSet::new();
+    let mut connections = HashMap::<SocketAddr, usize>::new();
+    for _ in 0..30 {
+        let inbound = rx_deliver.recv().await.unwrap();
+        // The connections of a pool don't number their messages.
+        assert_eq!(inbound.message.sequence(), None);
+        contents.insert(inbound.message.message);
+        *connections.entry(inbound.peer).or_default() += 1;
+    }
+
+    // Every message arrived once, every connection got some of them.
+    assert_eq!(contents.len(), 30);
+    assert_eq!(connections.len(), 3);
+    assert!(
+        connections.values().all(|count| *count > 0),
+        "{:?}",
+        connections
+    );
+    assert_eq!(stats.totals().connections_opened, 3);
+    assert!(workers.is_running(&address));
+    assert!(workers.abort(&address));
+    assert!(!workers.is_running(&address));
+}
+
+#[tokio::test]
+async fn pool_affinity() {
+    let address = "127.0.0.1:9227".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(100);
+    let config = ReceiverConfig {
+        duplicate_policy: DuplicatePolicy::AllowBoth,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        pool_size: 3,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(100);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // Every other message has one of five keys.
+    for i in 0..60 {
+        let mut message = NetworkMessage::unicast(address, address, i.to_string());
+        if i % 2 == 0 {
+            message.set_affinity(&format!("stream-{}", i % 10));
+        }
+        tx.send(message).await.unwrap();
+    }
+    let mut keys = HashMap::new();
+    let mut last = HashMap::new();
+    let mut connections = HashSet::new();
+    for _ in 0..60 {
+        let InboundMessage { message, peer } = rx_deliver.recv().await.unwrap();
+        connections.insert(peer);
+        let key = match message.affinity() {
+            Some(key) => key.to_string(),
+            None => continue,
+        };
+        // All messages of a key took the same connection and arrived in order.
+        assert_eq!(*keys.entry(key.clone()).or_insert(peer), peer);
+        let index = message.message.parse::<u32>().unwrap();
+        assert!(last.insert(key, index).is_none_or(|last| last < index));
+    }
+    assert_eq!(keys.len(), 5);
+    assert!(
+        keys.values().collect::<HashSet<_>>().len() > 1,
+        "{:?}",
+        keys
+    );
+    assert_eq!(connections.len(), 3);
+}
+
+#[tokio::test]
+async fn pool_weights() {
+    use tokio::net::TcpSocket;
+
+    // A peer that reads one of two connections slowly. The small receive buffer makes writes to
+    // it wait for the reader.
+    let address = "127.0.0.1:9226".parse::<SocketAddr>().unwrap();
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_recv_buffer_size(4096).unwrap();
+    socket.bind(address).unwrap();
+    let listener = socket.listen(16).unwrap();
+    let (tx_counts, mut rx_counts) = channel(2);
+    tokio::spawn(async move {
+        for delay in [Duration::from_millis(10), Duration::ZERO] {
+            let (socket, _) = listener.accept().await.unwrap();
+            let tx_counts = tx_counts.clone();
+            tokio::spawn(async move {
+                let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+                let mut count = 0;
+                while let Some(Ok(_)) = transport.next().await {
+                    count += 1;
+                    sleep(delay).await;
+                }
+                let _ = tx_counts.send((delay, count)).await;
+            });
+        }
+    });
+
+    let mut config = SenderConfig {
+        dialing: Dialing::Eager(vec![address]),
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        pool_size: 2,
+        queue_capacity: 4,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    let sender = tokio::spawn(async move {
+        sender.run().await;
+    });
+    let payload = "x".repeat(256 * 1024);
+    for _ in 0..200 {
+        tx.send(NetworkMessage::unicast(address, address, payload.as_str()))
+            .await
+            .unwrap();
+    }
+    drop(tx);
+    sender.await.unwrap();
+
+    // The fast connection took most of the messages, the slow one still some.
+    let mut counts = HashMap::new();
+    for _ in 0..2 {
+        let (delay, count) = rx_counts.recv().await.unwrap();
+        counts.insert(delay, count);
+    }
+    let (slow, fast) = (counts[&Duration::from_millis(10)], counts[&Duration::ZERO]);
+    assert_eq!(slow + fast, 200);
+    assert!(slow > 0 && fast > 2 * slow, "slow {}, fast {}", slow, fast);
+}
+
+#[tokio::test]
+async fn tracked() {
+    use crate::message::DeliveryOutcome;
+    use tokio::sync::oneshot;
+
+    // Run a receiver, the other peer is down.
+    let address = "127.0.0.1:9199".parse::<SocketAddr>().unwrap();
+    let down = "127.0.0.1:9200".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // The retransmitter gives up after two attempts.
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let (tx_tracked, rx_tracked) = channel(10);
+    let mut sender =
+        NetworkSender::with_config(rx, tx_retransmit, rx_retry, SenderConfig::default());
+    sender.submit_tracked(rx_tracked);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let policy = RetransmitPolicy {
+        max_attempts: Some(2),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+    let handle = SenderHandle::new(address, tx.clone()).with_tracking(tx_tracked);
+
+    let mut outcomes = Vec::new();
+    for peers in [vec![address], vec![down], vec![address, down]] {
+        let (notify, outcome) = oneshot::channel();
+        let message = NetworkMessage::new(address, peers, "tracked");
+        handle.send_tracked(message, notify).await.unwrap();
+        outcomes.push(outcome);
+    }
+    let [sent, failed, mixed] = <[_; 3]>::try_from(outcomes).unwrap();
+    assert_eq!(sent.await.unwrap(), DeliveryOutcome::Sent);
+    assert_eq!(failed.await.unwrap(), DeliveryOutcome::Dropped);
+    // One of the recipients didn't get the message, so the message as a whole failed.
+    assert_eq!(mixed.await.unwrap(), DeliveryOutcome::Dropped);
+    for _ in 0..2 {
+        assert_eq!(rx_deliver.recv().await.unwrap().message.message, "tracked");
+    }
+
+    // Fire-and-forget messages are sent alongside.
+    tx.send(NetworkMessage::unicast(address, address, "untracked"))
+        .await
+        .unwrap();
+    assert_eq!(
+        rx_deliver.recv().await.unwrap().message.message,
+        "untracked"
+    );
+
+    // A handle without the channel for tracked messages can't submit them.
+    let (notify, _outcome) = oneshot::channel();
+    let untracked = SenderHandle::new(address, tx);
+    let message = NetworkMessage::unicast(address, address, "lost");
+    assert!(untracked.send_tracked(message, notify).await.is_err());
+}
+
+#[tokio::test]
+async fn tracked_dropped() {
+    use crate::message::DeliveryOutcome;
+    use tokio::sync::oneshot;
+
+    // The peer is given up on after its second failed connection attempt, the first one is
+    // retransmitted.
+    let down = "127.0.0.1:9201".parse::<SocketAddr>().unwrap();
+    let config = SenderConfig {
+        max_connect_failures: Some(2),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (_tx, rx) = channel(10);
+    let (tx_tracked, rx_tracked) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.submit_tracked(rx_tracked);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    NetworkRetransmitter::run(rx_retransmit, tx_retry);
+
+    let (notify, outcome) = oneshot::channel();
+    let message = NetworkMessage::unicast(down, down, "tracked");
+    tx_tracked
+        .send(Tracked {
+            message,
+            outcome: notify,
+        })
+        .await
+        .unwrap();
+    let outcome = tokio::time::timeout(Duration::from_secs(2), outcome).await;
+    assert_eq!(outcome.unwrap().unwrap(), DeliveryOutcome::Dropped);
+}
+
+#[tokio::test]
+async fn expired() {
+    use crate::message::{DeliveryOutcome, TTL};
+
+    // The peer is down and the retransmitter would retry forever.
+    let down = "127.0.0.1:9202".parse::<SocketAddr>().unwrap();
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender =
+        NetworkSender::with_config(rx, tx_retransmit, rx_retry, SenderConfig::default());
+    sender.report_receipts(tx_receipts.clone());
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let policy = RetransmitPolicy {
+        receipts: Some(tx_receipts),
+        ..RetransmitPolicy::default()
+    };
+    let (tx_failed, mut rx_failed) = unbounded_channel();
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, Some(tx_failed));
+
+    let mut message = NetworkMessage::unicast(down, down, "vote");
+    message.set_ttl(Duration::from_millis(100));
+    tx.send(message).await.unwrap();
+
+    // The message is given up on once its time to live passed instead of being retried.
+    let receipt = tokio::time::timeout(Duration::from_secs(2), rx_receipts.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receipt.outcome, DeliveryOutcome::Expired);
+    assert_eq!(receipt.peer, down);
+    let Some(NetworkEvent::DeliveryFailed(failed)) = rx_failed.recv().await else {
+        panic!("No failure reported");
+    };
+    assert_eq!(failed.message.headers[TTL], "100");
+    let retransmits = stats.totals().retransmits;
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(stats.totals().retransmits, retransmits);
+    assert!(rx_receipts.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn shared_payload() {
+    use crate::message::Payload;
+
+    // Nodes of the same process get the copies of the message that the sender made for them.
+    let local = LocalRegistry::new();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let peers = (0..8)
+        .map(|i| {
+            format!("127.0.0.1:{}", 9210 + i)
+                .parse::<SocketAddr>()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for peer in &peers {
+        local.register(*peer, tx_deliver.clone(), Inflight::default());
+    }
+    let config = SenderConfig {
+        local: Some(local),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let payload = Payload::from("x".repeat(1024 * 1024));
+    let buffer = payload.bytes().as_ptr();
+    let message = NetworkMessage::new(peers[0], peers.clone(), payload);
+    tx.send(message).await.unwrap();
+
+    // Every copy shares the buffer of the payload instead of duplicating it.
+    for _ in &peers {
+        let inbound = rx_deliver.recv().await.unwrap();
+        assert_eq!(inbound.message.message.len(), 1024 * 1024);
+        assert_eq!(inbound.message.message.bytes().as_ptr(), buffer);
+    }
+}
+
+#[tokio::test]
+async fn serialize_failure() {
+    use crate::message::DeliveryOutcome;
+
+    // Bincode, except for messages it can't serialize.
+    #[derive(Debug)]
+    struct Picky;
+
+    impl Codec for Picky {
+        fn tag(&self) -> u8 {
+            BincodeCodec::default().tag()
+        }
+
+        fn encode(&self, message: &NetworkMessage) -> Result<Vec<u8>, CodecError> {
+            match message.message == "poison" {
+                true => Err(CodecError::Bincode(Box::new(bincode::ErrorKind::Custom(
+                    "unserializable".to_string(),
+                )))),
+                false => BincodeCodec::default().encode(message),
+            }
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<NetworkMessage, CodecError> {
+            BincodeCodec::default().decode(bytes)
+        }
+    }
+
+    let address = "127.0.0.1:9203".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        codec: Arc::new(Picky),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_receipts, mut rx_receipts) = channel(10);
+    let (tx_retransmit, mut rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.report_receipts(tx_receipts);
+    let stats = sender.stats();
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    for content in ["first", "poison", "valid"] {
+        tx.send(NetworkMessage::unicast(address, address, content))
+            .await
+            .unwrap();
+    }
+
+    // Only the message that can't be serialized is dropped, the worker keeps its connection.
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "first");
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "valid");
+    let outcomes = [
+        rx_receipts.recv().await.unwrap().outcome,
+        rx_receipts.recv().await.unwrap().outcome,
+        rx_receipts.recv().await.unwrap().outcome,
+    ];
+    assert_eq!(
+        outcomes,
+        [
+            DeliveryOutcome::Sent,
+            DeliveryOutcome::Dropped,
+            DeliveryOutcome::Sent
+        ]
+    );
+    assert!(rx_retransmit.try_recv().is_err());
+    assert_eq!(stats.totals().connections_opened, 1);
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    use crate::network::RateLimit;
+
+    let slow = "127.0.0.1:9204".parse::<SocketAddr>().unwrap();
+    let fast = "127.0.0.1:9205".parse::<SocketAddr>().unwrap();
+    let mut delivered = Vec::new();
+    for address in [slow, fast] {
+        let (tx_deliver, rx_deliver) = channel(100);
+        let receiver = NetworkReceiver::new(address, tx_deliver);
+        tokio::spawn(async move {
+            receiver.run().await.unwrap();
+        });
+        delivered.push(rx_deliver);
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // Only the slow peer is limited, to 50 messages per second.
+    let mut config = SenderConfig::default();
+    let peer = PeerConfig {
+        rate_limit: Some(RateLimit::messages(50.0)),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(slow, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(100);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let start = Instant::now();
+    for i in 0..11 {
+        let message = NetworkMessage::new(fast, vec![slow, fast], i.to_string());
+        tx.send(message).await.unwrap();
+    }
+    let [rx_slow, rx_fast] = &mut delivered[..] else {
+        unreachable!()
+    };
+    for _ in 0..11 {
+        rx_fast.recv().await.unwrap();
+    }
+    let fast_done = start.elapsed();
+    for _ in 0..11 {
+        rx_slow.recv().await.unwrap();
+    }
+    let slow_done = start.elapsed();
+
+    // The first message uses the burst, the other ten wait 20ms each.
+    assert!(fast_done < Duration::from_millis(150), "{:?}", fast_done);
+    assert!(slow_done >= Duration::from_millis(190), "{:?}", slow_done);
+    assert!(slow_done < Duration::from_millis(600), "{:?}", slow_done);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket() {
+    // Two nodes on the same host, each listening on a Unix socket instead of its port.
+    let nodes = [
+        "127.0.0.1:9206".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:9207".parse::<SocketAddr>().unwrap(),
+    ];
+    let path = |node: SocketAddr| {
+        std::env::temp_dir().join(format!(
+            "tcp-test-{}-{}.sock",
+            std::process::id(),
+            node.port()
+        ))
+    };
+    let mut delivered = Vec::new();
+    for node in nodes {
+        let config = ReceiverConfig {
+            unix_socket: Some(path(node)),
+            ..ReceiverConfig::default()
+        };
+        let (tx_deliver, rx_deliver) = channel(10);
+        let receiver = NetworkReceiver::with_config(node, tx_deliver, config);
+        tokio::spawn(async move {
+            receiver.run().await.unwrap();
+        });
+        delivered.push(rx_deliver);
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    let mut senders = Vec::new();
+    for node in nodes {
+        let mut config = SenderConfig::default();
+        for peer in nodes.iter().filter(|peer| **peer != node) {
+            let peer_config = PeerConfig {
+                unix_socket: Some(path(*peer)),
+                ..PeerConfig::default()
+            };
+            config.peers.insert(*peer, peer_config);
+        }
+        let (tx_retransmit, _rx_retransmit) = channel(10);
+        let (tx_retry, rx_retry) = channel(10);
+        let (tx, rx) = channel(10);
+        let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+        let stats = sender.stats();
+        tokio::spawn(async move {
+            sender.run().await;
+        });
+        senders.push((tx, stats, tx_retry));
+    }
+
+    // Every node gets the message of the other one, although none of the ports is bound.
+    for (i, (tx, _, _)) in senders.iter().enumerate() {
+        let message = NetworkMessage::unicast(nodes[i], nodes[1 - i], format!("from {}", i));
+        tx.send(message).await.unwrap();
+    }
+    for (i, rx) in delivered.iter_mut().enumerate() {
+        let inbound = rx.recv().await.unwrap();
+        assert_eq!(inbound.message.sender, nodes[1 - i]);
+        assert_eq!(inbound.message.message, format!("from {}", 1 - i));
+    }
+    for (_, stats, _) in &senders {
+        assert_eq!(stats.totals().connections_opened, 1);
+    }
+    for node in nodes {
+        assert!(TcpStream::connect(node).await.is_err());
+        let _ = std::fs::remove_file(path(node));
+    }
+}
+
+#[tokio::test]
+async fn peer_events() {
+    use crate::network::PeerEvent;
+
+    let address = "127.0.0.1:9208".parse::<SocketAddr>().unwrap();
+    let name = "127.0.0.1:9209".parse::<SocketAddr>().unwrap();
+    let start = |events| {
+        let stop = Shutdown::new();
+        let config = ReceiverConfig {
+            shutdown: stop.clone(),
+            ..ReceiverConfig::default()
+        };
+        let (tx_deliver, rx_deliver) = channel(10);
+        let mut receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+        receiver.peer_events(events);
+        let workers = receiver.workers();
+        tokio::spawn(async move {
+            receiver.run().await.unwrap();
+        });
+        (rx_deliver, stop, workers)
+    };
+    let (tx_inbound, mut rx_inbound) = unbounded_channel();
+    let (mut rx_deliver, stop, workers) = start(tx_inbound.clone());
+    sleep(Duration::from_millis(50)).await;
+
+    // A peer that doesn't answer two heartbeats in a row counts as gone.
+    let mut config = SenderConfig {
+        reconnects: 0,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        heartbeat_interval: Some(Duration::from_millis(20)),
+        missed_heartbeats: 2,
+        ..PeerConfig::default()
+    };
+    config.peers.insert(address, peer);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx_outbound, mut rx_outbound) = unbounded_channel();
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.peer_events(tx_outbound);
+    let running = tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    // The peer comes up.
+    tx.send(NetworkMessage::unicast(name, address, "up"))
+        .await
+        .unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "up");
+    assert_eq!(
+        rx_outbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Connected(address))
+    );
+    assert_eq!(
+        rx_inbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Connected(name))
+    );
+
+    // The peer goes down, its connection is gone without being closed.
+    stop.trigger();
+    workers.abort_all();
+    assert_eq!(
+        rx_outbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Disconnected(address))
+    );
+
+    // The peer comes back, and the next message connects to it again.
+    sleep(Duration::from_millis(50)).await;
+    let (mut rx_deliver, _stop, _workers) = start(tx_inbound);
+    sleep(Duration::from_millis(50)).await;
+    tx.send(NetworkMessage::unicast(name, address, "again"))
+        .await
+        .unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "again");
+    assert_eq!(
+        rx_outbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Connected(address))
+    );
+    assert_eq!(
+        rx_inbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Connected(name))
+    );
+
+    // Once the sender stops its worker closes the connection, which both ends report.
+    drop(tx);
+    running.await.unwrap();
+    assert_eq!(
+        rx_outbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Disconnected(address))
+    );
+    assert_eq!(
+        rx_inbound.recv().await.unwrap(),
+        NetworkEvent::Peer(PeerEvent::Disconnected(name))
+    );
+    assert!(rx_outbound.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn rebind() {
+    use tokio::io::AsyncReadExt;
+
+    let address = "127.0.0.1:9218".parse::<SocketAddr>().unwrap();
+    let stop = Shutdown::new();
+    let config = ReceiverConfig {
+        shutdown: stop.clone(),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, _rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    let workers = receiver.workers();
+    let running = tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // The receiver closes a connection first, which leaves it in TIME_WAIT, and stops.
+    let mut client = TcpStream::connect(address).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    stop.trigger();
+    running.await.unwrap();
+    workers.abort_all();
+    assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+    drop(client);
+    sleep(Duration::from_millis(50)).await;
+
+    // Without SO_REUSEADDR the address can't be bound again yet.
+    #[cfg(target_os = "linux")]
+    {
+        let result = bind(address, false);
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::AddrInUse)
+        );
+    }
+
+    // A restarted receiver binds it right away.
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::new(address, tx_deliver);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    tx.send(NetworkMessage::unicast(address, address, "restarted"))
+        .await
+        .unwrap();
+    assert_eq!(
+        rx_deliver.recv().await.unwrap().message.message,
+        "restarted"
+    );
+}
+
+#[tokio::test]
+async fn bind_retry() {
+    // Another process holds the port while the receiver starts.
+    let address = "127.0.0.1:9219".parse::<SocketAddr>().unwrap();
+    let blocker = TcpListener::bind(address).await.unwrap();
+    let config = ReceiverConfig {
+        bind_retries: 10,
+        bind_backoff: Backoff {
+            base_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(50),
+            multiplier: 2.0,
+        },
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config).spawn();
+    sleep(Duration::from_millis(60)).await;
+    assert!(!receiver.is_finished());
+
+    // Once the port is free the receiver binds it and serves.
+    drop(blocker);
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::new(rx, tx_retransmit, rx_retry);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    sleep(Duration::from_millis(100)).await;
+    tx.send(NetworkMessage::unicast(address, address, "bound"))
+        .await
+        .unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "bound");
+}
+
+#[tokio::test]
+async fn bind_failure() {
+    // The port stays taken, so the receiver gives up after its retries instead of panicking.
+    let address = "127.0.0.1:9220".parse::<SocketAddr>().unwrap();
+    let _blocker = TcpListener::bind(address).await.unwrap();
+    let config = ReceiverConfig {
+        bind_retries: 2,
+        bind_backoff: Backoff {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            multiplier: 1.0,
+        },
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, _rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    let result = receiver.run().await;
+    assert_eq!(
+        result.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::AddrInUse)
+    );
+}
+
+#[cfg(feature = "hmac")]
+#[tokio::test]
+async fn authenticated() {
+    use crate::network::FrameAuth;
+    use futures::SinkExt;
+
+    let address = "127.0.0.1:9221".parse::<SocketAddr>().unwrap();
+    let auth = FrameAuth::new(b"cluster secret");
+    let config = ReceiverConfig {
+        auth: Some(auth.clone()),
+        ..ReceiverConfig::default()
+    };
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // A sender with the secret gets its messages through.
+    let config = SenderConfig {
+        auth: Some(auth.clone()),
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _rx_retransmit) = channel(10);
+    let (_tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    tx.send(NetworkMessage::unicast(address, address, "signed"))
+        .await
+        .unwrap();
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "signed");
+
+    // A changed payload, a frame signed with another secret and an unsigned frame are dropped,
+    // the connection stays and takes the valid frame after them.
+    let signed = |auth: &FrameAuth, content: &str| {
+        let message = NetworkMessage::unicast(address, address, content);
+        auth.sign(&encode_frame(&BincodeCodec::default(), &message).unwrap())
+    };
+    let mut tampered = BytesMut::from(&signed(&auth, "forged")[..]);
+    let at = tampered.len() - TAG_LENGTH - 1;
+    tampered[at] ^= 1;
+    let message = NetworkMessage::unicast(address, address, "unsigned");
+    let frames = [
+        tampered.freeze(),
+        signed(&FrameAuth::new(b"other secret"), "forged"),
+        encode_frame(&BincodeCodec::default(), &message).unwrap(),
+        signed(&auth, "valid"),
+    ];
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    for frame in frames {
+        transport.send(frame).await.unwrap();
+    }
+    assert_eq!(rx_deliver.recv().await.unwrap().message.message, "valid");
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx_deliver.try_recv().is_err());
+}
+
+// Starts a receiver that records the node ids of its peers and a sender that dials them in
+// pairs, for the node with the given id.
+fn paired_node(
+    id: u64,
+    addresses: &[SocketAddr],
+    accept_timeout: Duration,
+) -> (NetworkSender, NodeIds, Sender<NetworkMessage>) {
+    let address = addresses[id as usize];
+    let (tx, rx) = channel(10);
+    let config = ReceiverConfig {
+        handshake: true,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx, config);
+    let node_ids = receiver.node_ids();
+    tokio::spawn(async move {
+        let _rx = rx;
+        receiver.run().await.unwrap();
+    });
+
+    let peers = (0..addresses.len() as u64)
+        .filter(|peer| *peer != id)
+        .map(|peer| (addresses[peer as usize], peer))
+        .collect();
+    let config = SenderConfig {
+        hello: Some(Hello::new(id, address)),
+        dialing: Dialing::Paired {
+            id,
+            peers,
+            accept_timeout,
+        },
+        ..SenderConfig::default()
+    };
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    sender.accepting(node_ids.clone());
+    (sender, node_ids, tx)
+}
+
+#[tokio::test]
+async fn paired_dialing() {
+    let addresses = ["127.0.0.1:9234", "127.0.0.1:9235", "127.0.0.1:9236"]
+        .map(|address| address.parse::<SocketAddr>().unwrap());
+    let nodes = (0..3)
+        .map(|id| paired_node(id, &addresses, Duration::from_secs(5)))
+        .collect::<Vec<_>>();
+    sleep(Duration::from_millis(50)).await;
+
+    // The nodes start together, every one only dials the nodes with a higher id. Wait until
+    // every node learned of the nodes with a lower id, well before the accept timeout.
+    let mut stats = Vec::new();
+    let mut ids = Vec::new();
+    let mut keep = Vec::new();
+    for (mut sender, node_ids, tx) in nodes {
+        stats.push(sender.stats());
+        ids.push(node_ids);
+        keep.push(tx);
+        tokio::spawn(async move {
+            sender.run().await;
+        });
+    }
+    for (id, node_ids) in ids.iter().enumerate() {
+        while node_ids.snapshot().len() < id {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // A single connection was opened per pair, by the node with the lower id.
+    for (from, stats) in stats.iter().enumerate() {
+        for (to, address) in addresses.iter().enumerate().filter(|(to, _)| *to != from) {
+            let opened = stats
+                .get(address)
+                .map_or(0, |counts| counts.connections_opened);
+            assert_eq!(opened, u64::from(from < to), "{from} to {to}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn paired_fallback() {
+    let addresses =
+        ["127.0.0.1:9237", "127.0.0.1:9238"].map(|address| address.parse::<SocketAddr>().unwrap());
+    let accept_timeout = Duration::from_millis(500);
+
+    // The node with the lower id never dials, only its receiver runs.
+    let (_lower, lower_ids, _tx_lower) = paired_node(0, &addresses, accept_timeout);
+    let (mut higher, _, tx) = paired_node(1, &addresses, accept_timeout);
+    let other = "127.0.0.1:9239".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(other).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    // The node with the higher id doesn't wait for it before it sends to other peers.
+    let stats = higher.stats();
+    let start = tokio::time::Instant::now();
+    tokio::spawn(async move {
+        higher.run().await;
+    });
+    tx.send(NetworkMessage::unicast(addresses[1], other, "hello"))
+        .await
+        .unwrap();
+    let _ = listener.accept().await.unwrap();
+    assert!(start.elapsed() < accept_timeout);
+    assert!(stats.get(&addresses[0]).is_none());
+
+    // Once the accept timeout passed it connects on its own.
+    while stats.get(&addresses[0]).is_none() && start.elapsed() < Duration::from_secs(2) {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert!(start.elapsed() >= accept_timeout);
+    assert_eq!(stats.get(&addresses[0]).unwrap().connections_opened, 1);
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(lower_ids.get(&addresses[1]), Some(1));
+}
+
+#[tokio::test]
+async fn skipped_sequence() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    let address = "127.0.0.1:9240".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        reorder: Some(16),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+
+    // A proxy in front of the receiver loses the first message.
+    let proxy = "127.0.0.1:9241".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(proxy).await.unwrap();
+    tokio::spawn(async move {
+        let (mut inbound, _) = listener.accept().await.unwrap();
+        let mut outbound = TcpStream::connect(address).await.unwrap();
+        let mut request = [0];
+        inbound.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[0], ACKNOWLEDGE);
+        outbound.write_all(&request).await.unwrap();
+        let (read, mut write) = inbound.into_split();
+        let (mut acks, forward) = outbound.into_split();
+        tokio::spawn(async move { tokio::io::copy(&mut acks, &mut write).await });
+        let mut frames = FramedRead::new(read, LengthDelimitedCodec::new()).skip(1);
+        let mut forward = FramedWrite::new(forward, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = frames.next().await {
+            forward.send(frame.freeze()).await.unwrap();
+        }
+    });
+
+    // The sender retransmits the lost message once its ack timeout passed, but its time to live
+    // passes first, so it is dropped and its number never arrives.
+    let mut config = SenderConfig {
+        sequence_numbers: true,
+        ..SenderConfig::default()
+    };
+    let peer = PeerConfig {
+        ack_timeout: Some(Timeout::Fixed(Duration::from_millis(200))),
+        ..PeerConfig::default()
+    };
+    config.peers.insert(proxy, peer);
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    NetworkRetransmitter::run(rx_retransmit, tx_retry);
+    let mut first = NetworkMessage::unicast(proxy, proxy, "first");
+    first.set_ttl(Duration::from_millis(100));
+    tx.send(first).await.unwrap();
+    sleep(Duration::from_millis(500)).await;
+
+    // The next message tells the receiver not to wait for it.
+    tx.send(NetworkMessage::unicast(proxy, proxy, "second"))
+        .await
+        .unwrap();
+    let inbound = tokio::time::timeout(Duration::from_secs(1), rx_deliver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(inbound.message.message, "second");
+    assert_eq!(inbound.message.sequence(), Some(2));
+    assert_eq!(inbound.message.skipped(), vec![1]);
+}
@@ -0,0 +1,239 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+use super::*;
+use crate::message::NetworkMessage;
+use crate::network::{
+    NetworkReceiver, NetworkRetransmitter, NetworkSender, ReceiverConfig, RetransmitPolicy,
+    SenderConfig,
+};
+
+// Records every event as a string.
+#[derive(Debug, Default)]
+struct RecordingSink(Mutex<Vec<String>>);
+
+impl RecordingSink {
+    fn record(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl ObserverSink for RecordingSink {
+    fn message_sent(&self, peer: SocketAddr, _bytes: usize) {
+        self.record(format!("sent {}", peer));
+    }
+
+    fn message_received(&self, peer: SocketAddr, _bytes: usize) {
+        self.record(format!("received {}", peer));
+    }
+
+    fn connected(&self, peer: SocketAddr) {
+        self.record(format!("connected {}", peer));
+    }
+
+    fn ready(&self, peer: SocketAddr) {
+        self.record(format!("ready {}", peer));
+    }
+
+    fn retransmit(&self, peer: SocketAddr, attempts: usize) {
+        self.record(format!("retransmit {} {}", peer, attempts));
+    }
+
+    fn failed(&self, peer: SocketAddr) {
+        self.record(format!("failed {}", peer));
+    }
+
+    fn reset_mid_frame(&self, peer: SocketAddr) {
+        self.record(format!("reset mid-frame {}", peer));
+    }
+}
+
+#[tokio::test]
+async fn events() {
+    let sink = Arc::new(RecordingSink::default());
+
+    // Create a network receiver, a sender and a retransmitter that give up after two attempts,
+    // all reporting to the sink.
+    let address = "127.0.0.1:9032".parse::<SocketAddr>().unwrap();
+    let down = "127.0.0.1:9033".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        observer: sink.clone(),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let (tx_retransmit, rx_retransmit) = channel(10);
+    let (tx_retry, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let config = SenderConfig {
+        observer: sink.clone(),
+        ..SenderConfig::default()
+    };
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+    let policy = RetransmitPolicy {
+        max_attempts: Some(2),
+        observer: sink.clone(),
+        ..RetransmitPolicy::default()
+    };
+    NetworkRetransmitter::run_with_policy(rx_retransmit, tx_retry, policy, None);
+
+    // Send a message to the receiver and one to a peer that is down.
+    for peer in [address, down] {
+        let message = NetworkMessage::new(address, vec![peer], "Hello, World!");
+        tx.send(message).await.unwrap();
+    }
+    rx_deliver.recv().await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+
+    let events = sink.events();
+    for event in [
+        format!("connected {}", address),
+        format!("sent {}", address),
+        format!("received {}", address),
+        format!("retransmit {} 1", down),
+        format!("failed {}", down),
+    ] {
+        assert!(events.contains(&event), "{} missing in {:?}", event, events);
+    }
+    assert!(!events.contains(&format!("connected {}", down)));
+}
+
+#[tokio::test]
+async fn reset_mid_frame() {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    let sink = Arc::new(RecordingSink::default());
+    let address = "127.0.0.1:9047".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        observer: sink.clone(),
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Close connections within the length prefix, right after it and within the payload.
+    let mut peers = Vec::new();
+    for bytes in [&[0, 0][..], &[0, 0, 0, 9], &[0, 0, 0, 9, 0, 0]] {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        peers.push(stream.local_addr().unwrap());
+        stream.write_all(bytes).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    // A connection that closes between frames ends cleanly.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let clean = stream.local_addr().unwrap();
+    drop(stream);
+    sleep(Duration::from_millis(100)).await;
+
+    // None of the partial frames is delivered.
+    assert!(rx_deliver.try_recv().is_err());
+    let events = sink.events();
+    for peer in peers {
+        let event = format!("reset mid-frame {}", peer);
+        assert!(events.contains(&event), "{} missing in {:?}", event, events);
+    }
+    assert!(!events.contains(&format!("reset mid-frame {}", clean)));
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn warmup() {
+    use crate::network::tls::tls_tests::configs;
+
+    // Run a receiver and a sender that both use TLS.
+    let (client, server) = configs();
+    let address = "127.0.0.1:9048".parse::<SocketAddr>().unwrap();
+    let (tx_deliver, mut rx_deliver) = channel(10);
+    let config = ReceiverConfig {
+        tls: server,
+        ..ReceiverConfig::default()
+    };
+    let receiver = NetworkReceiver::with_config(address, tx_deliver, config);
+    tokio::spawn(async move {
+        receiver.run().await.unwrap();
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    let sink = Arc::new(RecordingSink::default());
+    let (tx_retransmit, _) = channel(10);
+    let (_, rx_retry) = channel(10);
+    let (tx, rx) = channel(10);
+    let config = SenderConfig {
+        observer: sink.clone(),
+        tls: client,
+        ..SenderConfig::default()
+    };
+    let mut sender = NetworkSender::with_config(rx, tx_retransmit, rx_retry, config);
+    tokio::spawn(async move {
+        sender.run().await;
+    });
+
+    let message = NetworkMessage::new(address, vec![address], "Hello, World!");
+    tx.send(message).await.unwrap();
+    rx_deliver.recv().await.unwrap();
+
+    // The message was sent once the handshake was done, not right after connecting.
+    let events = sink.events();
+    let expected = ["connected", "ready", "sent"].map(|event| format!("{} {}", event, address));
+    assert_eq!(events, expected, "{:?}", events);
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus() {
+    let registry = prometheus::Registry::new();
+    let sink = PrometheusSink::new(&registry).unwrap();
+    let peer = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    sink.message_sent(peer, 10);
+    sink.message_sent(peer, 5);
+    sink.failed(peer);
+
+    let families = registry.gather();
+    let value = |name: &str, label: &str| {
+        families
+            .iter()
+            .find(|family| family.get_name() == name)
+            .unwrap()
+            .get_metric()
+            .iter()
+            .find(|metric| metric.get_label().iter().any(|l| l.get_value() == label))
+            .unwrap()
+            .get_counter()
+            .get_value()
+    };
+    assert_eq!(value("network_events_total", "sent"), 2.0);
+    assert_eq!(value("network_events_total", "failed"), 1.0);
+    assert_eq!(value("network_bytes_total", "sent"), 15.0);
+}
+
+#[cfg(feature = "statsd")]
+#[test]
+fn statsd() {
+    let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sink = StatsdSink::new(server.local_addr().unwrap(), "node").unwrap();
+    sink.failed("127.0.0.1:1234".parse().unwrap());
+
+    let mut buf = [0; 64];
+    let len = server.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"node.failed:1|c");
+}
@@ -0,0 +1,155 @@
+use super::*;
+
+#[test]
+fn reorder() {
+    // Items arriving out of order are delivered in sequence.
+    let mut buffer = ReorderBuffer::new(10);
+    assert_eq!(buffer.push(1, "b"), Vec::<&str>::new());
+    assert_eq!(buffer.push(2, "c"), Vec::<&str>::new());
+    assert_eq!(buffer.push(0, "a"), vec!["a", "b", "c"]);
+
+    // Duplicates of delivered items are dropped.
+    assert!(buffer.push(1, "b").is_empty());
+    assert_eq!(buffer.next(), 3);
+}
+
+#[test]
+fn bounded() {
+    // If the gap isn't filled before the buffer is full, it is skipped.
+    let mut buffer = ReorderBuffer::new(2);
+    assert!(buffer.push(1, 1).is_empty());
+    assert!(buffer.push(2, 2).is_empty());
+    assert_eq!(buffer.push(3, 3), vec![1, 2, 3]);
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.skipped(), 1);
+}
+
+#[test]
+fn skip() {
+    // Items that won't arrive aren't waited for, the items behind them are delivered.
+    let mut buffer = ReorderBuffer::new(10);
+    assert!(buffer.push(2, "c").is_empty());
+    assert!(buffer.skip(3).is_empty());
+    assert_eq!(buffer.push(4, "e"), Vec::<&str>::new());
+    assert_eq!(buffer.push(0, "a"), vec!["a"]);
+    assert_eq!(buffer.skip(1), vec!["c", "e"]);
+    assert_eq!(buffer.next(), 5);
+
+    // An item that was given up on is dropped if it arrives after all, and so is the skip of a
+    // delivered one.
+    assert!(buffer.skip(6).is_empty());
+    assert!(buffer.push(6, "g").is_empty());
+    assert!(buffer.skip(0).is_empty());
+    assert_eq!(buffer.push(5, "f"), vec!["f"]);
+    assert_eq!(buffer.next(), 7);
+    assert_eq!(buffer.skipped(), 0);
+}
+
+#[test]
+fn topics() {
+    let mut ordering = TopicOrdering::new(DeliveryOrder::Strict, 100);
+    ordering.declare("telemetry", DeliveryOrder::Unordered);
+
+    // Control message 0 is missing, so the later control messages are held back.
+    let mut delivered = Vec::new();
+    delivered.extend(ordering.push("control", 1, "control 1"));
+    delivered.extend(ordering.push("telemetry", 5, "telemetry 5"));
+    delivered.extend(ordering.push("control", 2, "control 2"));
+    delivered.extend(ordering.push("telemetry", 3, "telemetry 3"));
+
+    // Telemetry isn't blocked by the gap.
+    assert_eq!(delivered, vec!["telemetry 5", "telemetry 3"]);
+
+    // Once the gap is filled the control messages are delivered in order.
+    let delivered = ordering.push("control", 0, "control 0");
+    assert_eq!(delivered, vec!["control 0", "control 1", "control 2"]);
+}
+
+#[test]
+fn retransmit_order() {
+    let peer = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let order = RetransmitOrder::new();
+    let delivery = |content: &str, ordered: bool| {
+        let message = crate::message::NetworkMessage::new(peer, vec![peer], content);
+        let mut delivery = Delivery::new(message, peer);
+        if ordered {
+            order.stamp(&mut delivery);
+        }
+        delivery
+    };
+    let contents = |deliveries: Vec<Delivery>| {
+        deliveries
+            .into_iter()
+            .map(|delivery| delivery.message.message)
+            .collect::<Vec<_>>()
+    };
+
+    // While a and b are retransmitted the later messages wait, each returning gap releases what
+    // it can.
+    let (a, b, c) = (
+        delivery("a", true),
+        delivery("b", true),
+        delivery("c", true),
+    );
+    order.retransmitting(&a);
+    order.retransmitting(&b);
+    assert!(order.admit(c).is_empty());
+    assert!(order.admit(b).is_empty());
+    assert_eq!(contents(order.admit(a)), vec!["a", "b", "c"]);
+
+    // A message that is given up on releases the ones waiting for it.
+    let (d, e) = (delivery("d", true), delivery("e", true));
+    order.retransmitting(&d);
+    assert!(order.admit(e).is_empty());
+    assert_eq!(contents(order.give_up(&d)), vec!["e"]);
+
+    // Messages without a sequence number aren't ordered.
+    order.retransmitting(&delivery("f", true));
+    assert_eq!(contents(order.admit(delivery("g", false))), vec!["g"]);
+}
+
+#[test]
+fn peer_topics() {
+    let address = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let topics = PeerTopics::new(Topics::new(DeliveryOrder::Strict, 10));
+    let inbound = |sender: SocketAddr, topic: Option<&str>, seq: u64| {
+        let mut message = crate::message::NetworkMessage::unicast(sender, address, "");
+        if let Some(topic) = topic {
+            message.set_topic(topic);
+        }
+        message.set_topic_sequence(seq);
+        InboundMessage {
+            message,
+            peer: sender,
+        }
+    };
+    let seqs = |ready: Vec<InboundMessage>| {
+        ready
+            .iter()
+            .map(|inbound| inbound.message.topic_sequence().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Every sender has a sequence of its own, messages without a topic pass.
+    let other = "127.0.0.1:1235".parse::<SocketAddr>().unwrap();
+    assert!(topics.push(inbound(address, Some("control"), 1)).is_empty());
+    assert_eq!(
+        seqs(topics.push(inbound(other, Some("control"), 0))),
+        vec![0]
+    );
+    assert_eq!(seqs(topics.push(inbound(address, None, 5))), vec![5]);
+    assert_eq!(
+        seqs(topics.push(inbound(address, Some("control"), 0))),
+        vec![0, 1]
+    );
+
+    // Number 0 again starts the topic over, like after a restart of the sender.
+    assert_eq!(
+        seqs(topics.push(inbound(address, Some("control"), 0))),
+        vec![0]
+    );
+    assert_eq!(
+        seqs(topics.push(inbound(address, Some("control"), 1))),
+        vec![1]
+    );
+}
@@ -0,0 +1,45 @@
+use tokio::time::{sleep, timeout, Duration};
+
+use super::*;
+
+#[tokio::test]
+async fn policies() {
+    for (policy, expected, kept) in [
+        (OverflowPolicy::DropNewest, Push::Dropped(3), vec![1, 2]),
+        (OverflowPolicy::DropOldest, Push::Dropped(1), vec![2, 3]),
+        (OverflowPolicy::Reject, Push::Rejected(3), vec![1, 2]),
+    ] {
+        let (tx, mut rx) = bounded(2);
+        assert_eq!(tx.push(1, policy).await, Push::Queued);
+        assert_eq!(tx.push(2, policy).await, Push::Queued);
+        assert_eq!(tx.push(3, policy).await, expected);
+        drop(tx);
+        let mut items = Vec::new();
+        while let Some(item) = rx.recv().await {
+            items.push(item);
+        }
+        assert_eq!(items, kept, "{:?}", policy);
+    }
+}
+
+#[tokio::test]
+async fn block() {
+    let (tx, mut rx) = bounded(1);
+    assert_eq!(tx.push(1, OverflowPolicy::Block).await, Push::Queued);
+
+    // A full queue holds the push up until the receiver takes an item.
+    let blocked = timeout(Duration::from_millis(50), tx.push(2, OverflowPolicy::Block)).await;
+    assert!(blocked.is_err());
+    let receiver = tokio::spawn(async move {
+        sleep(Duration::from_millis(50)).await;
+        let first = rx.recv().await;
+        (first, rx)
+    });
+    assert_eq!(tx.push(2, OverflowPolicy::Block).await, Push::Queued);
+    let (first, rx) = receiver.await.unwrap();
+    assert_eq!(first, Some(1));
+
+    // Without a receiver nothing is queued anymore.
+    drop(rx);
+    assert_eq!(tx.push(3, OverflowPolicy::Block).await, Push::Closed(3));
+}
@@ -0,0 +1,97 @@
+use super::*;
+
+fn quota(policy: QuotaPolicy) -> Quota {
+    Quota {
+        bytes: 100,
+        window: Duration::from_secs(1),
+        policy,
+    }
+}
+
+#[test]
+fn delay() {
+    let start = Instant::now();
+    let mut accounter = ByteAccounter::new(Some(quota(QuotaPolicy::Delay)), start);
+    assert_eq!(accounter.admit(60, start), Admission::Send);
+
+    // The second message doesn't fit anymore and has to wait for the next window.
+    let now = start + Duration::from_millis(400);
+    assert_eq!(
+        accounter.admit(60, now),
+        Admission::Wait(Duration::from_millis(600))
+    );
+
+    // Once the window rolled over it is sent.
+    let now = start + Duration::from_secs(1);
+    assert_eq!(accounter.admit(60, now), Admission::Send);
+    assert_eq!(
+        accounter.usage(),
+        ByteUsage {
+            total: 120,
+            window: 60,
+            rejected: 0,
+        }
+    );
+}
+
+#[test]
+fn reject() {
+    let start = Instant::now();
+    let mut accounter = ByteAccounter::new(Some(quota(QuotaPolicy::Reject)), start);
+    assert_eq!(accounter.admit(100, start), Admission::Send);
+    assert_eq!(accounter.admit(1, start), Admission::Reject);
+    assert_eq!(accounter.usage().rejected, 1);
+
+    // The quota resets in the next window.
+    assert_eq!(
+        accounter.admit(1, start + Duration::from_secs(1)),
+        Admission::Send
+    );
+}
+
+#[test]
+fn oversized() {
+    // A message larger than the quota still goes out at the start of a window.
+    let start = Instant::now();
+    let mut accounter = ByteAccounter::new(Some(quota(QuotaPolicy::Delay)), start);
+    assert_eq!(accounter.admit(500, start), Admission::Send);
+    assert_eq!(accounter.usage().window, 500);
+}
+
+#[test]
+fn token_bucket() {
+    let start = Instant::now();
+    let limit = RateLimit {
+        per_second: 10.0,
+        burst: 2.0,
+        unit: RateUnit::Messages,
+    };
+    let mut bucket = TokenBucket::new(limit, start);
+
+    // The burst goes out at once, the next message waits for its token.
+    assert_eq!(bucket.take(1, start), None);
+    assert_eq!(bucket.take(1, start), None);
+    assert_eq!(bucket.take(1, start), Some(Duration::from_millis(100)));
+    assert_eq!(bucket.take(1, start + Duration::from_millis(100)), None);
+
+    // Idle time refills at most the burst.
+    let later = start + Duration::from_secs(10);
+    assert_eq!(bucket.take(1, later), None);
+    assert_eq!(bucket.take(1, later), None);
+    assert!(bucket.take(1, later).is_some());
+}
+
+#[test]
+fn token_bucket_bytes() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(RateLimit::bytes(100.0), start);
+    assert_eq!(bucket.take(60, start), None);
+    assert_eq!(bucket.take(60, start), Some(Duration::from_millis(200)));
+
+    // A message above the burst waits for a full bucket and leaves it in debt.
+    let full = start + Duration::from_millis(600);
+    assert_eq!(bucket.take(500, full), None);
+    assert!(bucket
+        .take(1, full)
+        .is_some_and(|wait| wait > Duration::from_secs(4)));
+}
@@ -0,0 +1,91 @@
+use tokio::sync::mpsc::unbounded_channel;
+
+use super::*;
+use crate::network::ChannelTransport;
+
+fn addresses() -> Vec<SocketAddr> {
+    (0..2)
+        .map(|i| format!("127.0.0.1:{}", 7100 + i).parse().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn reply() {
+    let nodes = addresses();
+    let mut transports = ChannelTransport::network(&nodes);
+    let mut responder = transports.pop().unwrap();
+    let mut requests = Requests::new(transports.pop().unwrap());
+    let requester = requests.requester(nodes[0], Duration::from_secs(5));
+
+    // The requesting node keeps receiving, which sends its requests and takes their replies.
+    let (received, mut rx) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(inbound) = requests.recv().await {
+            let _ = received.send(inbound);
+        }
+    });
+    // The other node answers every request and also sends a message of its own.
+    let (requesting, answering) = (nodes[0], nodes[1]);
+    tokio::spawn(async move {
+        while let Some(inbound) = responder.recv().await {
+            let answer = format!("re: {}", inbound.message.message);
+            let reply = inbound.message.reply(answering, answer).unwrap();
+            responder.send(reply).await.unwrap();
+            let other = NetworkMessage::unicast(answering, requesting, "unrelated");
+            responder.send(other).await.unwrap();
+        }
+    });
+
+    let reply = requester.request(nodes[1], "ping").await.unwrap();
+    assert_eq!(reply.message, "re: ping");
+    assert_eq!(reply.sender, nodes[1]);
+    let reply = requester.request(nodes[1], "pong").await.unwrap();
+    assert_eq!(reply.message, "re: pong");
+    assert_eq!(requester.pending(), 0);
+
+    // Only the messages that aren't replies are received.
+    for _ in 0..2 {
+        assert_eq!(rx.recv().await.unwrap().message.message, "unrelated");
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn timeout() {
+    let nodes = addresses();
+    let mut transports = ChannelTransport::network(&nodes);
+    let _silent = transports.pop().unwrap();
+    let mut requests = Requests::new(transports.pop().unwrap());
+    let requester = requests.requester(nodes[0], Duration::from_millis(100));
+    let requester_clone = requester.clone();
+    let address = nodes[1];
+    let request = tokio::spawn(async move { requester_clone.request(address, "ping").await });
+
+    // The node never answers, so the request times out and is forgotten.
+    let receiving = tokio::time::timeout(Duration::from_millis(500), requests.recv()).await;
+    assert!(receiving.is_err());
+    assert_eq!(request.await.unwrap().unwrap_err(), RequestError::Timeout);
+    assert_eq!(requester.pending(), 0);
+}
+
+#[tokio::test]
+async fn closed() {
+    let nodes = addresses();
+    let mut transports = ChannelTransport::network(&nodes);
+    let requests = Requests::new(transports.remove(0));
+    let requester = requests.requester(nodes[0], Duration::from_secs(5));
+    let requester_clone = requester.clone();
+    let address = nodes[1];
+    let request = tokio::spawn(async move { requester_clone.request(address, "ping").await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(requester.pending(), 1);
+
+    // Without the transport the request fails at once instead of waiting for its timeout.
+    drop(requests);
+    assert_eq!(request.await.unwrap().unwrap_err(), RequestError::Closed);
+    assert_eq!(requester.pending(), 0);
+    assert_eq!(
+        requester.request(nodes[1], "ping").await.unwrap_err(),
+        RequestError::Closed
+    );
+}
@@ -0,0 +1,79 @@
+use super::*;
+
+#[test]
+fn converges() {
+    let mut rtt = RttEstimator::new();
+    assert!(rtt.srtt().is_none());
+
+    // Feed samples that jitter around 100ms.
+    for i in 0..200 {
+        let sample = if i % 2 == 0 { 90 } else { 110 };
+        rtt.update(Duration::from_millis(sample));
+    }
+
+    // The smoothed value converges to the mean, the variation to the jitter.
+    let srtt = rtt.srtt().unwrap().as_secs_f64() * 1000.0;
+    let rttvar = rtt.rttvar().unwrap().as_secs_f64() * 1000.0;
+    assert!((srtt - 100.0).abs() < 2.0, "srtt {}", srtt);
+    assert!((rttvar - 10.0).abs() < 2.0, "rttvar {}", rttvar);
+    assert!(rtt.rto().unwrap() > rtt.srtt().unwrap());
+}
+
+#[test]
+fn adapts() {
+    // After a change of the network conditions the estimation follows the new round trip time.
+    let mut rtt = RttEstimator::new();
+    for _ in 0..50 {
+        rtt.update(Duration::from_millis(10));
+    }
+    for _ in 0..100 {
+        rtt.update(Duration::from_millis(200));
+    }
+    let srtt = rtt.srtt().unwrap().as_secs_f64() * 1000.0;
+    assert!((srtt - 200.0).abs() < 1.0, "srtt {}", srtt);
+}
+
+#[test]
+fn timeout() {
+    let fixed = Timeout::Fixed(Duration::from_millis(30));
+    let scaled = Timeout::Rtt {
+        multiple: 2.0,
+        min: Duration::from_millis(5),
+        fallback: Duration::from_millis(500),
+    };
+
+    // Without samples the fallback is used.
+    let mut rtt = RttEstimator::new();
+    assert_eq!(fixed.resolve(&rtt), Duration::from_millis(30));
+    assert_eq!(scaled.resolve(&rtt), Duration::from_millis(500));
+
+    // A constant round trip time has no variation, so the timeout is twice the round trip time.
+    for _ in 0..100 {
+        rtt.update(Duration::from_millis(50));
+    }
+    let timeout = scaled.resolve(&rtt).as_secs_f64() * 1000.0;
+    assert!((timeout - 100.0).abs() < 1.0, "timeout {}", timeout);
+}
+
+#[test]
+fn peers() {
+    let rtts = PeerRtts::default();
+    let first = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let second = "127.0.0.1:1235".parse::<SocketAddr>().unwrap();
+    let timeout = Timeout::Rtt {
+        multiple: 1.0,
+        min: Duration::from_millis(5),
+        fallback: Duration::from_millis(500),
+    };
+    rtts.sample(first, Duration::from_millis(20));
+
+    // Every peer has an estimation of its own, a peer without one falls back.
+    assert_eq!(
+        rtts.get(&first).unwrap().srtt(),
+        Some(Duration::from_millis(20))
+    );
+    assert!(rtts.get(&second).is_none());
+    assert_eq!(rtts.resolve(&first, &timeout), Duration::from_millis(60));
+    assert_eq!(rtts.resolve(&second, &timeout), Duration::from_millis(500));
+    assert_eq!(rtts.snapshot().len(), 1);
+}
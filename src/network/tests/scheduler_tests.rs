@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+use tokio::time::{sleep, Duration};
+
+use super::*;
+
+#[tokio::test]
+async fn priority() {
+    // Create a scheduler with a single permit and hold it.
+    let scheduler = ConnectScheduler::new(1);
+    let permit = scheduler.acquire(0).await;
+
+    // Queue three connection attempts while no permit is free. The low priority ones are queued
+    // first.
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut handles = Vec::new();
+    for (name, priority) in [("low", 0), ("low2", 0), ("high", 10)] {
+        let scheduler = scheduler.clone();
+        let order = order.clone();
+        handles.push(tokio::spawn(async move {
+            let _permit = scheduler.acquire(priority).await;
+            order.lock().unwrap().push(name);
+        }));
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Give the permit back and wait for all attempts to finish.
+    drop(permit);
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    // The high priority attempt gets the permit first, equal priorities keep their order.
+    assert_eq!(*order.lock().unwrap(), vec!["high", "low", "low2"]);
+}
+
+#[tokio::test]
+async fn cancelled() {
+    // Create a scheduler with a single permit and hold it.
+    let scheduler = ConnectScheduler::new(1);
+    let permit = scheduler.acquire(0).await;
+
+    // Queue an attempt and cancel it before the permit is freed.
+    let waiting = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            scheduler.acquire(10).await;
+        })
+    };
+    sleep(Duration::from_millis(10)).await;
+    waiting.abort();
+    let _ = waiting.await;
+
+    // The permit must not get lost on the cancelled attempt.
+    drop(permit);
+    let acquired = tokio::time::timeout(Duration::from_millis(100), scheduler.acquire(0)).await;
+    assert!(acquired.is_ok());
+}
+
+#[tokio::test]
+async fn pacer() {
+    // Five events at a rate of 50 per second take at least 80ms, the first one is immediate.
+    let mut pacer = Pacer::new(50.0);
+    let start = Instant::now();
+    pacer.wait().await;
+    assert!(start.elapsed() < Duration::from_millis(10));
+    for _ in 0..4 {
+        pacer.wait().await;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(80));
+}
+
+#[test]
+fn weighted_round_robin() {
+    // Without measurements the connections take turns.
+    let mut scheduler = WeightedRoundRobin::new(2);
+    let picks: Vec<usize> = (0..4).map(|_| scheduler.pick()).collect();
+    assert_eq!(picks, vec![0, 1, 0, 1]);
+
+    // Connection 0 sends a message in 1ms, connection 1 needs 4ms.
+    let speeds = [Duration::from_millis(1), Duration::from_millis(4)];
+    let mut counts = [0, 0];
+    for _ in 0..1000 {
+        let connection = scheduler.pick();
+        counts[connection] += 1;
+        scheduler.record(connection, speeds[connection]);
+    }
+
+    // The faster connection gets about four times the messages, the slower one still some.
+    let ratio = counts[0] as f64 / counts[1] as f64;
+    assert!((3.5..4.5).contains(&ratio), "{:?}", counts);
+    let weights = scheduler.weights();
+    assert!((weights[0] - 0.8).abs() < 0.01, "{:?}", weights);
+
+    // Weights follow when the speeds change.
+    for _ in 0..100 {
+        scheduler.record(0, Duration::from_millis(4));
+        scheduler.record(1, Duration::from_millis(1));
+    }
+    assert!(scheduler.weights()[1] > 0.79);
+}
+
+#[test]
+fn affinity() {
+    use crate::message::NetworkMessage;
+    use std::collections::HashMap;
+
+    // Spread messages with a few keys and some without over four connections.
+    let mut scheduler = WeightedRoundRobin::new(4);
+    let mut connections = vec![Vec::new(); 4];
+    let sender = "127.0.0.1:1234".parse().unwrap();
+    for i in 0..100 {
+        let mut message = NetworkMessage::new(sender, vec![sender], i.to_string());
+        if i % 2 == 0 {
+            message.set_affinity(&format!("stream-{}", i % 10));
+        }
+        let connection = scheduler.pick_for(message.affinity());
+        connections[connection].push(message);
+    }
+
+    // The messages of a key all took one connection, in the order they were sent.
+    let mut used = HashMap::new();
+    for (connection, messages) in connections.iter().enumerate() {
+        let mut last = HashMap::new();
+        for message in messages {
+            if let Some(key) = message.affinity() {
+                assert_eq!(*used.entry(key).or_insert(connection), connection);
+                let index = message.message.parse::<u32>().unwrap();
+                assert!(last.insert(key, index).is_none_or(|last| last < index));
+            }
+        }
+    }
+    assert_eq!(used.len(), 5);
+    let mut spread = used.values().collect::<Vec<_>>();
+    spread.sort();
+    spread.dedup();
+    assert!(spread.len() > 1, "{:?}", used);
+
+    // Different keys and the messages without a key use the other connections as well.
+    assert!(connections.iter().all(|messages| !messages.is_empty()));
+}
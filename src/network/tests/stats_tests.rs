@@ -0,0 +1,87 @@
+use super::*;
+
+#[test]
+fn flap_rate() {
+    let window = Duration::from_secs(60);
+    let start = Instant::now();
+    let mut link = LinkState::default();
+    assert_eq!(link.uptime(start), None);
+
+    // A stable connection flaps once, when it is established.
+    link.connected(start, window);
+    let later = start + Duration::from_secs(30);
+    assert_eq!(link.uptime(later), Some(Duration::from_secs(30)));
+    assert_eq!(link.flap_rate(later, window), 1.0);
+
+    // A peer that keeps losing its connection flaps every time.
+    for i in 0..10 {
+        let at = later + Duration::from_secs(i);
+        link.disconnected(at, window);
+        link.connected(at + Duration::from_millis(500), window);
+    }
+    let now = later + Duration::from_secs(10);
+    assert_eq!(link.flap_rate(now, window), 21.0);
+    assert_eq!(link.uptime(now), Some(Duration::from_millis(500)));
+
+    // Once the link is stable again the flaps leave the window.
+    let quiet = now + window;
+    assert_eq!(link.flap_rate(quiet, window), 0.0);
+    assert_eq!(
+        link.uptime(quiet),
+        Some(window + Duration::from_millis(500))
+    );
+
+    link.disconnected(quiet, window);
+    assert_eq!(link.uptime(quiet), None);
+}
+
+#[test]
+fn links() {
+    let peer = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let links = PeerLinks::new(Duration::from_secs(60));
+    assert_eq!(links.flap_rate(&peer), 0.0);
+    for _ in 0..5 {
+        links.connected(peer);
+        links.disconnected(peer);
+    }
+    assert_eq!(links.flap_rate(&peer), 10.0);
+    assert_eq!(links.uptime(&peer), None);
+    links.connected(peer);
+    assert!(links.uptime(&peer).is_some());
+}
+
+#[test]
+fn network_stats() {
+    let a = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    let b = "127.0.0.1:1235".parse::<SocketAddr>().unwrap();
+    let stats = NetworkStats::default();
+    assert_eq!(stats.totals(), SenderCounts::default());
+    assert_eq!(stats.get(&a), None);
+
+    stats.opened(a);
+    stats.sent(a, 10);
+    stats.sent(a, 20);
+    // Both messages went in one frame.
+    stats.frame_sent(a);
+    stats.send_failed(a);
+    stats.dropped(a);
+    stats.retransmit(a);
+    stats.opened(b);
+    stats.sent(b, 5);
+
+    let counts = SenderCounts {
+        messages_sent: 2,
+        frames_sent: 1,
+        send_failures: 1,
+        retransmits: 1,
+        bytes_sent: 30,
+        connections_opened: 1,
+        connections_dropped: 1,
+    };
+    assert_eq!(stats.get(&a), Some(counts));
+    assert_eq!(stats.snapshot().len(), 2);
+    let totals = stats.totals();
+    assert_eq!(totals.messages_sent, 3);
+    assert_eq!(totals.bytes_sent, 35);
+    assert_eq!(totals.connections_opened, 2);
+}
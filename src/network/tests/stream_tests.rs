@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use super::*;
+
+#[test]
+fn lost_chunk() {
+    // Stream a 1000 byte message in chunks of 10 bytes with a window of 4 chunks.
+    let data = (0..1000).map(|x| x as u8).collect::<Vec<_>>();
+    let timeout = Duration::from_millis(100);
+    let mut sender = StreamSender::new(7, &data, 10, 4, timeout);
+    let mut receiver = StreamReceiver::new(7, 100);
+
+    // Count how often each chunk was sent. The first transmission of chunk 42 gets lost.
+    let mut sent = HashMap::<u32, usize>::new();
+    let mut now = Instant::now();
+    while !sender.is_complete() {
+        let chunks = sender.poll(now);
+        assert!(chunks.len() <= 4);
+        for chunk in chunks {
+            let count = sent.entry(chunk.index).or_default();
+            *count += 1;
+            if chunk.index == 42 && *count == 1 {
+                continue;
+            }
+            sender.ack(receiver.receive(chunk).unwrap());
+        }
+        now += Duration::from_millis(10);
+    }
+
+    // Only the lost chunk was sent twice and the message was reassembled correctly.
+    let retransmitted = sent
+        .iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(index, _)| *index)
+        .collect::<Vec<_>>();
+    assert_eq!(retransmitted, vec![42]);
+    assert_eq!(sent.len(), 100);
+    assert_eq!(receiver.assemble(), Some(data));
+}
+
+#[test]
+fn window() {
+    // Without any ACKs not more than the window is sent, no matter how often we poll.
+    let mut sender = StreamSender::new(1, &[0; 100], 10, 3, Duration::from_secs(1));
+    let now = Instant::now();
+    assert_eq!(sender.poll(now).len(), 3);
+    assert!(sender.poll(now).is_empty());
+
+    // Every ACK frees exactly one slot.
+    sender.ack(ChunkAck {
+        message_id: 1,
+        index: 0,
+    });
+    let chunks = sender.poll(now);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].index, 3);
+}
+
+#[test]
+fn streams() {
+    let chunk = |message_id: u64, index: u32, total: u32, data: &[u8]| {
+        encode_chunk(&Chunk {
+            message_id,
+            index,
+            total,
+            data: data.to_vec(),
+        })
+    };
+    let mut streams = Streams::new(32);
+
+    // The chunks of two streams interleave, each frame is handed out once it is complete.
+    let (ack, frame) = streams.receive(&chunk(1, 1, 2, b"world")).unwrap();
+    assert_eq!((ack.message_id, ack.index, frame), (1, 1, None));
+    assert!(streams.receive(&chunk(2, 0, 1, b"ab")).unwrap().1.is_some());
+    let (_, frame) = streams.receive(&chunk(1, 0, 2, b"hello ")).unwrap();
+    assert_eq!(frame.unwrap(), &b"hello world"[..]);
+
+    // A chunk of a complete stream that was sent again is only acknowledged.
+    let (ack, frame) = streams.receive(&chunk(1, 0, 2, b"hello ")).unwrap();
+    assert_eq!((ack.message_id, ack.index, frame), (1, 0, None));
+
+    // A chunk that arrives twice is only kept once. One that changed in between, here to a
+    // different size, is rejected and the stream still completes with the first one.
+    assert!(streams
+        .receive(&chunk(4, 0, 2, b"abc"))
+        .unwrap()
+        .1
+        .is_none());
+    assert!(streams
+        .receive(&chunk(4, 0, 2, b"abc"))
+        .unwrap()
+        .1
+        .is_none());
+    assert!(streams.receive(&chunk(4, 0, 2, b"a")).is_err());
+    assert!(streams.receive(&chunk(4, 0, 2, b"abcdefgh")).is_err());
+    let (_, frame) = streams.receive(&chunk(4, 1, 2, b"def")).unwrap();
+    assert_eq!(frame.unwrap(), &b"abcdef"[..]);
+    assert_eq!(streams.len, 0);
+
+    // Chunks beyond the limit, out of range or truncated are rejected.
+    assert!(streams.receive(&chunk(3, 0, 2, &[0; 64])).is_err());
+    assert!(streams.receive(&chunk(3, 2, 2, b"a")).is_err());
+    assert!(streams.receive(&chunk(3, 0, 2, b"a")[..10]).is_err());
+}
@@ -0,0 +1,168 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use super::*;
+use crate::network::{frame_reader, frame_writer};
+
+// Accept a single connection, negotiate TLS and return the first frame, or why there is none.
+async fn serve(address: SocketAddr, tls: ServerTls) -> JoinHandle<std::io::Result<Bytes>> {
+    let listener = TcpListener::bind(address).await.unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let socket = server_upgrade(socket, &tls).await?;
+        let mut reader = frame_reader(socket).await?;
+        let frame = reader.next().await.ok_or(ErrorKind::UnexpectedEof)??;
+        Ok(frame.freeze())
+    })
+}
+
+// Configs with a self-signed certificate for 127.0.0.1, also used by tests of other modules.
+#[cfg(feature = "tls")]
+pub fn configs() -> (ClientTls, ServerTls) {
+    use std::sync::Arc;
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+    let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let server = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certified.cert.der().clone()],
+            PrivateKeyDer::Pkcs8(key),
+        )
+        .unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let client = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    (
+        ClientTls {
+            policy: TlsPolicy::Require,
+            config: Some(Arc::new(client)),
+        },
+        ServerTls {
+            policy: TlsPolicy::Prefer,
+            config: Some(Arc::new(server)),
+        },
+    )
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn upgrade() {
+    let address = "127.0.0.1:9038".parse::<SocketAddr>().unwrap();
+    let (client, server) = configs();
+    let server = serve(address, server).await;
+
+    // Both sides support TLS, so the connection is upgraded and carries frames as usual.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let socket = client_upgrade(stream, &client).await.unwrap();
+    let mut writer = frame_writer(socket, false).await.unwrap();
+    writer.send(Bytes::from("encrypted")).await.unwrap();
+    assert_eq!(server.await.unwrap().unwrap(), Bytes::from("encrypted"));
+}
+
+#[tokio::test]
+async fn require_unsupported() {
+    let address = "127.0.0.1:9039".parse::<SocketAddr>().unwrap();
+    let server = serve(address, ServerTls::default()).await;
+
+    // The receiver refuses TLS, so a sender that requires it gives up on the connection.
+    #[cfg(feature = "tls")]
+    let client = configs().0;
+    #[cfg(not(feature = "tls"))]
+    let client = ClientTls {
+        policy: TlsPolicy::Require,
+    };
+    let stream = TcpStream::connect(address).await.unwrap();
+    let error = client_upgrade(stream, &client).await.err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert!(server.await.unwrap().is_err());
+
+    // A receiver that requires TLS closes plaintext connections.
+    let address = "127.0.0.1:9040".parse::<SocketAddr>().unwrap();
+    let server = serve(
+        address,
+        ServerTls {
+            policy: TlsPolicy::Require,
+            #[cfg(feature = "tls")]
+            config: None,
+        },
+    )
+    .await;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let socket = client_upgrade(stream, &ClientTls::default()).await.unwrap();
+    let mut writer = frame_writer(socket, false).await.unwrap();
+    writer.send(Bytes::from("plaintext")).await.unwrap();
+    let error = server.await.unwrap().err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+// Write a self-signed certificate for 127.0.0.1 and its key to PEM files, returns their paths.
+#[cfg(feature = "tls")]
+fn pem_files(name: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+    let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let dir = std::env::temp_dir();
+    let cert = dir.join(format!("{}-{}.crt", name, std::process::id()));
+    let key = dir.join(format!("{}-{}.key", name, std::process::id()));
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+    (cert, key)
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn mutual() {
+    let (server_cert, server_key) = pem_files("tls-server");
+    let (client_cert, client_key) = pem_files("tls-client");
+    let server = ServerTls::from_files(
+        TlsPolicy::Require,
+        &server_cert,
+        &server_key,
+        Some(&client_cert),
+    )
+    .unwrap();
+
+    // A sender with a certificate the receiver trusts gets through.
+    let address = "127.0.0.1:9194".parse::<SocketAddr>().unwrap();
+    let served = serve(address, server.clone()).await;
+    let identity = Some((client_cert.as_path(), client_key.as_path()));
+    let client = ClientTls::from_files(TlsPolicy::Require, &server_cert, identity).unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let socket = client_upgrade(stream, &client).await.unwrap();
+    let mut writer = frame_writer(socket, false).await.unwrap();
+    writer.send(Bytes::from("mutual")).await.unwrap();
+    assert_eq!(served.await.unwrap().unwrap(), Bytes::from("mutual"));
+
+    // One without a certificate is turned away. With TLS 1.3 the sender may only notice once it
+    // wrote to the connection.
+    let address = "127.0.0.1:9195".parse::<SocketAddr>().unwrap();
+    let served = serve(address, server).await;
+    let anonymous = ClientTls::from_files(TlsPolicy::Require, &server_cert, None).unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+    if let Ok(socket) = client_upgrade(stream, &anonymous).await {
+        let mut writer = frame_writer(socket, false).await.unwrap();
+        let _ = writer.send(Bytes::from("anonymous")).await;
+    }
+    assert!(served.await.unwrap().is_err());
+
+    // Files that don't exist or don't hold what they should are rejected right away.
+    let missing = std::env::temp_dir().join("tls-missing.crt");
+    let error = ServerTls::from_files(TlsPolicy::Require, &missing, &server_key, None);
+    assert_eq!(error.err().unwrap().kind(), ErrorKind::InvalidInput);
+    let error = ServerTls::from_files(TlsPolicy::Require, &server_key, &server_cert, None);
+    assert!(error.is_err());
+
+    for path in [server_cert, server_key, client_cert, client_key] {
+        let _ = std::fs::remove_file(path);
+    }
+}
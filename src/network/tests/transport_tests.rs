@@ -0,0 +1,123 @@
+use std::net::SocketAddr;
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::duplex;
+
+use super::*;
+use crate::message::NetworkMessage;
+use crate::network::{encode_frame_compressed, BincodeCodec};
+
+// Similar messages, like the ones of a consensus protocol.
+fn messages() -> Vec<NetworkMessage> {
+    let address = "127.0.0.1:1234".parse::<SocketAddr>().unwrap();
+    (0..100)
+        .map(|i| {
+            NetworkMessage::new(
+                address,
+                vec![address],
+                format!("vote for block {} in round {}", i * 7, i),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn uncompressed() {
+    // Without stream compression the connection carries plain frames.
+    let (client, server) = duplex(64 * 1024);
+    let mut writer = frame_writer(client, false).await.unwrap();
+    let frame = encode_frame_compressed(&BincodeCodec::default(), &messages()[0], None).unwrap();
+    writer.send(frame.clone()).await.unwrap();
+    let mut reader = frame_reader(server).await.unwrap();
+    assert_eq!(reader.next().await.unwrap().unwrap(), frame);
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn compressed() {
+    use crate::network::Codecs;
+    use tokio::io::AsyncReadExt;
+
+    // Capture what goes over the wire.
+    let (client, mut server) = duplex(1024 * 1024);
+    let wire = tokio::spawn(async move {
+        let mut wire = Vec::new();
+        server.read_to_end(&mut wire).await.unwrap();
+        wire
+    });
+
+    // Send many messages over a compressed stream, flushing after every one like a worker does.
+    let mut writer = frame_writer(client, true).await.unwrap();
+    let codec = BincodeCodec::default();
+    let mut per_message = 0;
+    for message in messages() {
+        writer
+            .send(encode_frame_compressed(&codec, &message, None).unwrap())
+            .await
+            .unwrap();
+        per_message += encode_frame_compressed(&codec, &message, Some(0))
+            .unwrap()
+            .len();
+    }
+    drop(writer);
+    let wire = wire.await.unwrap();
+    assert_eq!(wire[0], STREAM_COMPRESSION);
+
+    // The stream is much smaller than the messages compressed one by one.
+    assert!(
+        wire.len() * 2 < per_message,
+        "{} {}",
+        wire.len(),
+        per_message
+    );
+
+    // Every message comes out again.
+    let mut reader = frame_reader(std::io::Cursor::new(wire)).await.unwrap();
+    for message in messages() {
+        let frame = reader.next().await.unwrap().unwrap();
+        assert_eq!(Codecs::default().decode(&frame).unwrap(), message);
+    }
+}
+
+#[test]
+fn mid_frame() {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    // Between frames the end of the stream is clean.
+    let mut codec = FrameCodec::default();
+    let mut buffer = BytesMut::from(&[0, 0, 0, 1, 7][..]);
+    assert_eq!(&codec.decode_eof(&mut buffer).unwrap().unwrap()[..], &[7]);
+    assert!(!codec.mid_frame());
+    assert!(codec.decode_eof(&mut buffer).unwrap().is_none());
+
+    // Within a length prefix, right after it and within the payload it isn't.
+    for bytes in [&[0, 0][..], &[0, 0, 0, 3], &[0, 0, 0, 3, 1]] {
+        let mut codec = FrameCodec::default();
+        let mut buffer = BytesMut::from(bytes);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        assert!(codec.mid_frame());
+        let error = codec.decode_eof(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn user_timeout() {
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:9052").await.unwrap();
+    let outbound = TcpStream::connect("127.0.0.1:9052").await.unwrap();
+    let (inbound, _) = listener.accept().await.unwrap();
+
+    for stream in [&outbound, &inbound] {
+        let socket = socket2::SockRef::from(stream);
+        assert_eq!(socket.tcp_user_timeout().unwrap(), None);
+        set_user_timeout(stream, Duration::from_secs(7)).unwrap();
+        assert_eq!(
+            socket.tcp_user_timeout().unwrap(),
+            Some(Duration::from_secs(7))
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "tls")]
+use std::path::Path;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+#[cfg(test)]
+#[path = "tests/tls_tests.rs"]
+pub mod tls_tests;
+
+/// First byte of a connection whose sender offers to upgrade it to TLS. Like the marker of stream
+/// compression it can't be confused with the length prefix of a frame.
+pub const STARTTLS: u8 = 0x54;
+
+// Answers of the receiver to STARTTLS.
+const ACCEPT: u8 = 1;
+const REFUSE: u8 = 0;
+
+/// Whether a connection is upgraded to TLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsPolicy {
+    // Never upgrade. Senders don't offer TLS and receivers refuse it.
+    #[default]
+    Disable,
+    // Upgrade if the other side supports it, otherwise stay plaintext.
+    Prefer,
+    // Upgrade or close the connection.
+    Require,
+}
+
+/// TLS settings of the NetworkSender. Without a client config TLS is never offered.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTls {
+    pub policy: TlsPolicy,
+    #[cfg(feature = "tls")]
+    pub config: Option<std::sync::Arc<tokio_rustls::rustls::ClientConfig>>,
+}
+
+/// TLS settings of the NetworkReceiver. Without a server config TLS is always refused.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTls {
+    pub policy: TlsPolicy,
+    #[cfg(feature = "tls")]
+    pub config: Option<std::sync::Arc<tokio_rustls::rustls::ServerConfig>>,
+}
+
+impl ClientTls {
+    fn available(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.config.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+}
+
+impl ServerTls {
+    fn available(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.config.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+}
+
+#[cfg(feature = "tls")]
+impl ClientTls {
+    /// TLS that trusts the receivers whose certificates are signed by one of the CAs in the PEM
+    /// file at ca. With an identity, the paths of a PEM certificate chain and its private key,
+    /// the sender also authenticates itself to receivers that demand client certificates.
+    pub fn from_files(
+        policy: TlsPolicy,
+        ca: &Path,
+        identity: Option<(&Path, &Path)>,
+    ) -> std::io::Result<Self> {
+        use tokio_rustls::rustls::ClientConfig;
+
+        let builder = ClientConfig::builder().with_root_certificates(roots(ca)?);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(certs(cert)?, private_key(key)?)
+                .map_err(invalid)?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Self {
+            policy,
+            config: Some(std::sync::Arc::new(config)),
+        })
+    }
+}
+
+#[cfg(feature = "tls")]
+impl ServerTls {
+    /// TLS with the PEM certificate chain at cert and its private key at key. With a client CA
+    /// only senders that present a certificate signed by one of its CAs are accepted, which
+    /// authenticates them like the certificate of the receiver authenticates it (mutual TLS).
+    pub fn from_files(
+        policy: TlsPolicy,
+        cert: &Path,
+        key: &Path,
+        client_ca: Option<&Path>,
+    ) -> std::io::Result<Self> {
+        use tokio_rustls::rustls::server::WebPkiClientVerifier;
+        use tokio_rustls::rustls::ServerConfig;
+
+        let builder = match client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder(std::sync::Arc::new(roots(ca)?))
+                    .build()
+                    .map_err(invalid)?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certs(cert)?, private_key(key)?)
+            .map_err(invalid)?;
+        Ok(Self {
+            policy,
+            config: Some(std::sync::Arc::new(config)),
+        })
+    }
+}
+
+#[cfg(feature = "tls")]
+fn certs(
+    path: &Path,
+) -> std::io::Result<Vec<tokio_rustls::rustls::pki_types::CertificateDer<'static>>> {
+    use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer};
+
+    CertificateDer::pem_file_iter(path)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)
+}
+
+#[cfg(feature = "tls")]
+fn private_key(
+    path: &Path,
+) -> std::io::Result<tokio_rustls::rustls::pki_types::PrivateKeyDer<'static>> {
+    use tokio_rustls::rustls::pki_types::{pem::PemObject, PrivateKeyDer};
+
+    PrivateKeyDer::from_pem_file(path).map_err(invalid)
+}
+
+#[cfg(feature = "tls")]
+fn roots(path: &Path) -> std::io::Result<tokio_rustls::rustls::RootCertStore> {
+    let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+    for cert in certs(path)? {
+        roots.add(cert).map_err(invalid)?;
+    }
+    Ok(roots)
+}
+
+// Certificates and keys that can't be read or used are invalid input.
+#[cfg(feature = "tls")]
+fn invalid(error: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidInput, error.to_string())
+}
+
+/// A connection that is either plaintext or encrypted.
+pub trait Socket: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Socket for T {}
+
+/// A connection before TLS was negotiated on it.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    // Connections over a Unix socket never leave the host, so TLS isn't negotiated on them. A
+    // policy that requires TLS refuses them.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub async fn client_upgrade(self, tls: &ClientTls) -> std::io::Result<Box<dyn Socket>> {
+        match self {
+            Self::Tcp(stream) => client_upgrade(stream, tls).await,
+            #[cfg(unix)]
+            Self::Unix(stream) => local(stream, tls.policy),
+        }
+    }
+
+    pub async fn server_upgrade(self, tls: &ServerTls) -> std::io::Result<Box<dyn Socket>> {
+        match self {
+            Self::Tcp(stream) => server_upgrade(stream, tls).await,
+            #[cfg(unix)]
+            Self::Unix(stream) => local(stream, tls.policy),
+        }
+    }
+
+    /// The connection as it is, without negotiating anything.
+    pub fn plain(self) -> Box<dyn Socket> {
+        match self {
+            Self::Tcp(stream) => Box::new(stream),
+            #[cfg(unix)]
+            Self::Unix(stream) => Box::new(stream),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local(stream: UnixStream, policy: TlsPolicy) -> std::io::Result<Box<dyn Socket>> {
+    match policy {
+        TlsPolicy::Require => Err(required()),
+        _ => Ok(Box::new(stream)),
+    }
+}
+
+fn required() -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        "TLS required but not supported",
+    )
+}
+
+/// Negotiate TLS on an outgoing connection before anything else is sent on it. The receiver
+/// is authenticated by the IP address of the peer. Fails if the policy requires TLS and
+/// either side doesn't support it.
+pub async fn client_upgrade(
+    mut stream: TcpStream,
+    tls: &ClientTls,
+) -> std::io::Result<Box<dyn Socket>> {
+    if tls.policy == TlsPolicy::Disable {
+        return Ok(Box::new(stream));
+    }
+    if !tls.available() {
+        return match tls.policy {
+            TlsPolicy::Require => Err(required()),
+            _ => Ok(Box::new(stream)),
+        };
+    }
+
+    stream.write_all(&[STARTTLS]).await?;
+    match stream.read_u8().await? {
+        #[cfg(feature = "tls")]
+        ACCEPT => {
+            use tokio_rustls::rustls::pki_types::ServerName;
+            let config = tls.config.clone().expect("checked by available");
+            let name = ServerName::IpAddress(stream.peer_addr()?.ip().into());
+            let stream = tokio_rustls::TlsConnector::from(config)
+                .connect(name, stream)
+                .await?;
+            Ok(Box::new(stream))
+        }
+        REFUSE if tls.policy == TlsPolicy::Prefer => Ok(Box::new(stream)),
+        REFUSE => Err(required()),
+        answer => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected answer to STARTTLS: {}", answer),
+        )),
+    }
+}
+
+/// Negotiate TLS on an incoming connection. A sender that offers TLS is answered, a sender that
+/// doesn't is only accepted if the policy doesn't require TLS.
+pub async fn server_upgrade(
+    mut stream: TcpStream,
+    tls: &ServerTls,
+) -> std::io::Result<Box<dyn Socket>> {
+    let mut first = [0];
+    let offered = stream.peek(&mut first).await? == 1 && first[0] == STARTTLS;
+    if !offered {
+        return match tls.policy {
+            TlsPolicy::Require => Err(required()),
+            _ => Ok(Box::new(stream)),
+        };
+    }
+
+    stream.read_u8().await?;
+    if tls.policy == TlsPolicy::Disable || !tls.available() {
+        // The sender decides whether to go on without TLS.
+        stream.write_u8(REFUSE).await?;
+        return Ok(Box::new(stream));
+    }
+    stream.write_u8(ACCEPT).await?;
+
+    #[cfg(feature = "tls")]
+    {
+        let config = tls.config.clone().expect("checked by available");
+        let stream = tokio_rustls::TlsAcceptor::from(config)
+            .accept(stream)
+            .await?;
+        Ok(Box::new(stream))
+    }
+    #[cfg(not(feature = "tls"))]
+    unreachable!("TLS is never available without the tls feature")
+}
@@ -0,0 +1,162 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::Duration;
+use tokio_util::codec::{Decoder, FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::network::MAX_FRAME_LENGTH;
+
+#[cfg(test)]
+#[path = "tests/transport_tests.rs"]
+pub mod transport_tests;
+
+/// First byte of a connection whose whole byte stream is zstd compressed. A frame can't start
+/// with it, the length prefix of a frame below 16 MiB always starts with 0.
+pub const STREAM_COMPRESSION: u8 = 0x5a;
+
+pub type FrameWriter = FramedWrite<Box<dyn AsyncWrite + Send + Unpin>, LengthDelimitedCodec>;
+pub type FrameReader = FramedRead<Box<dyn AsyncRead + Send + Unpin>, FrameCodec>;
+
+/// Decodes length delimited frames and knows whether it is in the middle of one. The inner codec
+/// consumes the length prefix before the payload arrived, so a connection that ends right after a
+/// prefix would otherwise look like a clean close.
+#[derive(Debug, Default)]
+pub struct FrameCodec {
+    inner: LengthDelimitedCodec,
+    partial: bool,
+}
+
+impl FrameCodec {
+    /// Codec that fails on frames longer than max_frame_length instead of buffering them.
+    pub fn with_max_length(max_frame_length: usize) -> Self {
+        Self {
+            inner: length_delimited(max_frame_length),
+            partial: false,
+        }
+    }
+
+    /// Whether part of a frame was read but not the whole frame yet.
+    pub fn mid_frame(&self) -> bool {
+        self.partial
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<BytesMut>> {
+        let before = src.len();
+        let frame = self.inner.decode(src)?;
+        match frame {
+            Some(_) => self.partial = false,
+            None => self.partial |= src.len() < before || !src.is_empty(),
+        }
+        Ok(frame)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> std::io::Result<Option<BytesMut>> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if self.partial => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed mid-frame",
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Frame the sending side of a connection. With stream compression the connection is announced
+/// as compressed and its bytes go through a zstd stream, which keeps its context from message to
+/// message and so compresses similar messages much better than per-message compression.
+/// Without the compression feature the connection stays uncompressed.
+pub async fn frame_writer<W>(writer: W, compressed: bool) -> std::io::Result<FrameWriter>
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    frame_writer_with_limit(writer, compressed, MAX_FRAME_LENGTH).await
+}
+
+/// Frame the sending side of a connection like frame_writer, refusing to send frames longer than
+/// max_frame_length.
+pub async fn frame_writer_with_limit<W>(
+    writer: W,
+    compressed: bool,
+    max_frame_length: usize,
+) -> std::io::Result<FrameWriter>
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let writer: Box<dyn AsyncWrite + Send + Unpin> = match compressed {
+        #[cfg(feature = "compression")]
+        true => {
+            use tokio::io::AsyncWriteExt;
+            let mut writer = writer;
+            writer.write_all(&[STREAM_COMPRESSION]).await?;
+            Box::new(async_compression::tokio::write::ZstdEncoder::new(writer))
+        }
+        _ => Box::new(writer),
+    };
+    Ok(FramedWrite::new(writer, length_delimited(max_frame_length)))
+}
+
+/// Frame the receiving side of a connection, decompressing it if the peer announced stream
+/// compression.
+pub async fn frame_reader<R>(reader: R) -> std::io::Result<FrameReader>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    frame_reader_with_limit(reader, MAX_FRAME_LENGTH).await
+}
+
+/// Frame the receiving side of a connection like frame_reader. A frame whose length prefix
+/// claims more than max_frame_length bytes fails to decode before anything is buffered for it.
+pub async fn frame_reader_with_limit<R>(
+    reader: R,
+    max_frame_length: usize,
+) -> std::io::Result<FrameReader>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let mut reader = BufReader::new(reader);
+    let compressed = reader.fill_buf().await?.first() == Some(&STREAM_COMPRESSION);
+    let reader: Box<dyn AsyncRead + Send + Unpin> = match compressed {
+        #[cfg(feature = "compression")]
+        true => {
+            reader.consume(1);
+            Box::new(async_compression::tokio::bufread::ZstdDecoder::new(reader))
+        }
+        #[cfg(not(feature = "compression"))]
+        true => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "stream compression not supported",
+            ))
+        }
+        false => Box::new(reader),
+    };
+    Ok(FramedRead::new(
+        reader,
+        FrameCodec::with_max_length(max_frame_length),
+    ))
+}
+
+fn length_delimited(max_frame_length: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_length)
+        .new_codec()
+}
+
+/// Limit how long sent data may stay unacknowledged before the kernel closes the connection,
+/// which detects a peer that vanished in the middle of a transfer much sooner than keepalive.
+/// Only Linux supports it, elsewhere the timeout is ignored.
+pub fn set_user_timeout(stream: &TcpStream, timeout: Duration) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return socket2::SockRef::from(stream).set_tcp_user_timeout(Some(timeout));
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (stream, timeout);
+        Ok(())
+    }
+}
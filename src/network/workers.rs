@@ -0,0 +1,71 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::task::{AbortHandle, JoinHandle};
+
+/// The tasks of the workers of a NetworkSender or a NetworkReceiver, by the address of the peer
+/// they handle: a sender has a worker per connection to a peer it sends to, see
+/// PeerConfig::pool_size, a receiver one per open connection. Lets a supervisor see which
+/// workers still run and abort one that hangs. Cloned handles refer to the same workers.
+#[derive(Debug, Clone, Default)]
+pub struct Workers(Arc<Mutex<HashMap<SocketAddr, Vec<AbortHandle>>>>);
+
+impl Workers {
+    // Workers that ended are forgotten.
+    pub(crate) fn register(&self, peer: SocketAddr, worker: &JoinHandle<()>) {
+        let mut workers = self.0.lock().unwrap();
+        workers.retain(|_, workers| {
+            workers.retain(|worker| !worker.is_finished());
+            !workers.is_empty()
+        });
+        workers.entry(peer).or_default().push(worker.abort_handle());
+    }
+
+    /// Whether a worker of the peer still runs.
+    pub fn is_running(&self, peer: &SocketAddr) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(peer)
+            .is_some_and(|workers| workers.iter().any(|worker| !worker.is_finished()))
+    }
+
+    /// Abort the workers of the peer, which closes their connections. Messages they had queued
+    /// are lost. Returns false if no worker of the peer runs.
+    pub fn abort(&self, peer: &SocketAddr) -> bool {
+        let workers = self.0.lock().unwrap().remove(peer).unwrap_or_default();
+        let mut aborted = false;
+        for worker in workers.iter().filter(|worker| !worker.is_finished()) {
+            worker.abort();
+            aborted = true;
+        }
+        aborted
+    }
+
+    /// Abort every worker that still runs.
+    pub fn abort_all(&self) {
+        for worker in self
+            .0
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, workers)| workers)
+        {
+            worker.abort();
+        }
+    }
+
+    /// Peers whose workers still run.
+    pub fn snapshot(&self) -> Vec<SocketAddr> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, workers)| workers.iter().any(|worker| !worker.is_finished()))
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+}
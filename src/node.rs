@@ -1,35 +1,548 @@
-use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+};
 
-use tokio::sync::mpsc::channel;
-use tokio::time::{sleep, Duration};
+use tokio::sync::mpsc::{channel, unbounded_channel};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{sleep, Duration, Instant};
 
-use crate::{core::Core, network::*};
+use crate::core::{Core, CoreHandle};
+use crate::{message::AtomicIds, network::*};
 
-pub struct Node;
+#[cfg(test)]
+#[path = "tests/node_tests.rs"]
+pub mod node_tests;
+
+/// Settings of a node. Check them with validate before starting a cluster, which neither binds
+/// ports nor connects to anyone.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    // Index of the node in nodes.
+    pub id: usize,
+
+    // Addresses of all nodes, as given by the operator.
+    pub nodes: Vec<String>,
+
+    pub sender: SenderConfig,
+    pub receiver: ReceiverConfig,
+
+    // Messages the channels between the components of the node buffer: received messages for
+    // the core, messages of the core for the sender, and messages on their way through the
+    // retransmitter.
+    pub deliver_capacity: usize,
+    pub send_capacity: usize,
+    pub retransmit_capacity: usize,
+
+    // Messages queued for the worker of a peer that has no PeerConfig of its own.
+    pub worker_capacity: usize,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            nodes: Vec::new(),
+            sender: SenderConfig::default(),
+            receiver: ReceiverConfig::default(),
+            deliver_capacity: 10_000,
+            send_capacity: 10_000,
+            retransmit_capacity: 10_000,
+            worker_capacity: 10_000,
+        }
+    }
+}
+
+/// A problem with a NodeConfig.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    // The address of a node isn't a socket address.
+    InvalidAddress(String),
+    // Two nodes have the same address, so their ids can't be told apart.
+    DuplicateAddress(SocketAddr),
+    // The id doesn't refer to one of the nodes.
+    UnknownId(usize),
+    // TLS is turned on but there is no certificate or the tls feature is disabled.
+    Tls(&'static str),
+    // A setting needs a feature that isn't compiled in.
+    MissingFeature(&'static str, &'static str),
+    // A setting only works together with a value of another one.
+    Needs(&'static str, &'static str),
+    // Two settings can't be used together.
+    Conflicts(&'static str, &'static str),
+    // A limit or buffer is too small to let any message through.
+    Zero(&'static str),
+    // The port of a node would be above 65535.
+    PortOutOfRange(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidAddress(address) => write!(f, "invalid address {:?}", address),
+            ConfigError::DuplicateAddress(address) => write!(f, "duplicate address {}", address),
+            ConfigError::UnknownId(id) => write!(f, "no node with id {}", id),
+            ConfigError::Tls(side) => write!(f, "{} TLS is enabled without a config", side),
+            ConfigError::MissingFeature(setting, feature) => {
+                write!(f, "{} needs the {} feature", setting, feature)
+            }
+            ConfigError::Needs(setting, other) => write!(f, "{} needs {}", setting, other),
+            ConfigError::Conflicts(setting, other) => {
+                write!(f, "{} can't be used with {}", setting, other)
+            }
+            ConfigError::Zero(setting) => write!(f, "{} must be greater than zero", setting),
+            ConfigError::PortOutOfRange(port) => write!(f, "port {} is out of range", port),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl NodeConfig {
+    /// Check the config without starting anything. Returns every problem, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        let mut seen = HashSet::new();
+        for address in &self.nodes {
+            match address.parse::<SocketAddr>() {
+                Ok(address) if !seen.insert(address) => {
+                    errors.push(ConfigError::DuplicateAddress(address))
+                }
+                Ok(_) => (),
+                Err(_) => errors.push(ConfigError::InvalidAddress(address.clone())),
+            }
+        }
+        if self.id >= self.nodes.len() {
+            errors.push(ConfigError::UnknownId(self.id));
+        }
+
+        // TLS needs a certificate, which only the tls feature can load.
+        #[cfg(feature = "tls")]
+        let (client, server) = (
+            self.sender.tls.config.is_some(),
+            self.receiver.tls.config.is_some(),
+        );
+        #[cfg(not(feature = "tls"))]
+        let (client, server) = (false, false);
+        if self.sender.tls.policy != TlsPolicy::Disable && !client {
+            errors.push(ConfigError::Tls("client"));
+        }
+        if self.receiver.tls.policy != TlsPolicy::Disable && !server {
+            errors.push(ConfigError::Tls("server"));
+        }
+
+        if !cfg!(feature = "compression") {
+            if self.sender.compression_threshold.is_some() {
+                errors.push(ConfigError::MissingFeature(
+                    "compression_threshold",
+                    "compression",
+                ));
+            }
+            if self
+                .sender
+                .peers
+                .values()
+                .any(|peer| peer.stream_compression)
+            {
+                errors.push(ConfigError::MissingFeature(
+                    "stream_compression",
+                    "compression",
+                ));
+            }
+        }
+
+        // The receivers of the other nodes, configured like ours, would close all but one of the
+        // parallel connections to them.
+        let pools = self
+            .sender
+            .peers
+            .values()
+            .chain([&self.sender.default_peer])
+            .any(|peer| peer.pool_size > 1);
+        if pools && self.receiver.duplicate_policy != DuplicatePolicy::AllowBoth {
+            errors.push(ConfigError::Needs(
+                "pool_size above 1",
+                "duplicate_policy AllowBoth",
+            ));
+        }
+
+        // The messages to a pool aren't acknowledged, their ack timeout would never apply.
+        let acked_pools = self
+            .sender
+            .peers
+            .values()
+            .chain([&self.sender.default_peer])
+            .any(|peer| peer.pool_size > 1 && peer.ack_timeout.is_some());
+        if acked_pools {
+            errors.push(ConfigError::Conflicts("pool_size above 1", "ack_timeout"));
+        }
+
+        let zeros = [
+            ("connect_permits", self.sender.connect_permits == 0),
+            (
+                "queue_capacity",
+                self.sender
+                    .peers
+                    .values()
+                    .chain([&self.sender.default_peer])
+                    .any(|peer| peer.queue_capacity == 0),
+            ),
+            (
+                "quota",
+                self.sender
+                    .quota
+                    .is_some_and(|quota| quota.bytes == 0 || quota.window.is_zero()),
+            ),
+            ("max_peers", self.sender.max_peers == Some(0)),
+            (
+                "rate_limit",
+                self.sender
+                    .peers
+                    .values()
+                    .chain([&self.sender.default_peer])
+                    .filter_map(|peer| peer.rate_limit)
+                    .any(|limit| limit.per_second <= 0.0 || limit.burst <= 0.0),
+            ),
+            (
+                "spawn_rate",
+                self.sender.spawn_rate.is_some_and(|rate| rate <= 0.0),
+            ),
+            ("max_outstanding", self.receiver.max_outstanding == 0),
+            (
+                "max_frame_length",
+                self.sender.max_frame_length == 0 || self.receiver.max_frame_length == 0,
+            ),
+            ("credits", self.receiver.credits == Some(0)),
+            ("deliver_capacity", self.deliver_capacity == 0),
+            ("send_capacity", self.send_capacity == 0),
+            ("retransmit_capacity", self.retransmit_capacity == 0),
+            ("worker_capacity", self.worker_capacity == 0),
+        ];
+        for (setting, zero) in zeros {
+            if zero {
+                errors.push(ConfigError::Zero(setting));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+/// Addresses of n nodes on the local host, listening on consecutive ports from base_port on.
+pub fn local_addresses(n: usize, base_port: u16) -> Result<Vec<SocketAddr>, ConfigError> {
+    (0..n)
+        .map(|i| {
+            let port = base_port as usize + i;
+            let port = u16::try_from(port).map_err(|_| ConfigError::PortOutOfRange(port))?;
+            Ok(SocketAddr::from(([127, 0, 0, 1], port)))
+        })
+        .collect()
+}
+
+/// Why a node didn't run cleanly, returned by Node::shutdown.
+#[derive(Debug)]
+pub enum NodeError {
+    // A component panicked.
+    Panicked(JoinError),
+    // The receiver couldn't bind its address, so the node never received anything.
+    Bind(std::io::Error),
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::Panicked(e) => write!(f, "component panicked: {}", e),
+            NodeError::Bind(e) => write!(f, "failed to bind the receiver: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+impl From<JoinError> for NodeError {
+    fn from(e: JoinError) -> Self {
+        NodeError::Panicked(e)
+    }
+}
+
+/// Sequence high-water marks of a peer when the node stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerReport {
+    // Highest sequence number written to the peer, None if nothing was sent to it.
+    pub sent: Option<u64>,
+
+    // Highest sequence number read from the peer, None if nothing was received from it.
+    pub received: Option<u64>,
+}
+
+/// What a node exchanged with each peer over the network, returned by Node::shutdown. After a
+/// clean run the messages one node sent to another match what the other one received, so a
+/// sent mark above the received mark of the other end means that messages were lost.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub peers: HashMap<SocketAddr, PeerReport>,
+}
+
+/// A running node and the tasks of its components.
+pub struct Node {
+    receiver: JoinHandle<std::io::Result<()>>,
+    sender: JoinHandle<()>,
+    retransmitter: JoinHandle<()>,
+    core: CoreHandle,
+
+    // Messages buffered anywhere in the node, shared by all of its components.
+    inflight: Inflight,
+
+    // Sequence high-water marks of the sender and the receiver, and the open inbound
+    // connections, which are drained before the marks are reported.
+    sent: HighWaterMarks,
+    received: HighWaterMarks,
+    inbound: OutstandingFrames,
+
+    // Workers of the inbound connections, the ones that are still open after the drain are
+    // closed.
+    connections: Workers,
+
+    // Stops the receiver.
+    stop: Shutdown,
+
+    // Adds and removes the peers of the sender.
+    membership: Membership,
+
+    // Counters of the sender.
+    stats: Arc<NetworkStats>,
+}
 
 impl Node {
-    pub async fn new(id: usize, nodes: Vec<SocketAddr>) {
+    pub async fn new(id: usize, nodes: Vec<SocketAddr>) -> Self {
+        Self::with_observer(id, nodes, Arc::new(NoopSink)).await
+    }
+
+    /// Create a node from a config, which is validated first. The sender and receiver configs
+    /// are the base of the ones the node runs with, the observer of the sender config gets the
+    /// events of every component.
+    pub async fn with_config(config: NodeConfig) -> Result<Self, Vec<ConfigError>> {
+        config.validate()?;
+        let nodes = config
+            .nodes
+            .iter()
+            .map(|address| address.parse().expect("validated"))
+            .collect();
+        let observer = config.sender.observer.clone();
+        Ok(Self::start(config.id, nodes, observer, None, config).await)
+    }
+
+    /// Create a node whose network components report their events to the given sink.
+    pub async fn with_observer(
+        id: usize,
+        nodes: Vec<SocketAddr>,
+        observer: Arc<dyn ObserverSink>,
+    ) -> Self {
+        Self::start(id, nodes, observer, None, NodeConfig::default()).await
+    }
+
+    /// Create a node that exchanges messages with the other nodes of the registry directly,
+    /// which are usually nodes of the same process.
+    pub async fn in_process(
+        id: usize,
+        nodes: Vec<SocketAddr>,
+        observer: Arc<dyn ObserverSink>,
+        local: LocalRegistry,
+    ) -> Self {
+        Self::start(id, nodes, observer, Some(local), NodeConfig::default()).await
+    }
+
+    async fn start(
+        id: usize,
+        nodes: Vec<SocketAddr>,
+        observer: Arc<dyn ObserverSink>,
+        local: Option<LocalRegistry>,
+        settings: NodeConfig,
+    ) -> Self {
         // Create channels for the networking.
-        let (tx_rec, rx_rec) = channel(10_000);
-        let (tx_send, rx_send) = channel(10_000);
-        let (tx_retransmit, rx_retransmit) = channel(10_000);
+        let (tx_rec, rx_rec) = channel(settings.deliver_capacity);
+        let (tx_send, rx_send) = channel(settings.send_capacity);
+        let (tx_retransmit, rx_retransmit) = channel(settings.retransmit_capacity);
+        let (tx_retry, rx_retry) = channel(settings.retransmit_capacity);
+        let inflight = Inflight::new();
+
+        // Core learns about failed deliveries and the connections of both the sender and the
+        // receiver through one channel.
+        let (tx_events, rx_events) = unbounded_channel();
+
+        // Run the retransmitter. Messages that can't be delivered after 100 attempts are reported
+        // to the core.
+        let policy = RetransmitPolicy {
+            max_attempts: Some(100),
+            observer: observer.clone(),
+            inflight: inflight.clone(),
+            ..RetransmitPolicy::default()
+        };
+        let retransmitter = NetworkRetransmitter::run_with_policy(
+            rx_retransmit,
+            tx_retry,
+            policy,
+            Some(tx_events.clone()),
+        );
+
+        // Create a network receiver and sender. Messages arriving before the core runs are
+        // buffered in the deliver channel. The sender gives every message an id, so a message
+        // that is retransmitted after it actually arrived reaches the core only once.
+        let ready = Readiness::new();
+        let stop = Shutdown::new();
+        let config = ReceiverConfig {
+            observer: observer.clone(),
+            local: local.clone(),
+            inflight: inflight.clone(),
+            shutdown: stop.clone(),
+            handshake: true,
+            dedup: Some(settings.receiver.dedup.unwrap_or_default()),
+            ..settings.receiver
+        };
+        let mut network_receiver = NetworkReceiver::with_config(nodes[id], tx_rec.clone(), config);
+        network_receiver.wait_for(ready.clone());
+        let received = network_receiver.received_sequences();
+        let inbound = network_receiver.outstanding();
+        let connections = network_receiver.workers();
+
+        // Nodes are started at roughly the same time, so give peers a few seconds to come up. The
+        // messages are numbered per peer for the shutdown report, and every connection starts
+        // with a handshake naming the node. Messages to the node itself skip the network, the
+        // other nodes are connected to before the core sends anything.
+        let dialing = match local {
+            // Nodes of the registry are reached without a connection.
+            Some(_) => Dialing::Lazy,
+            None => Dialing::Eager(
+                nodes
+                    .iter()
+                    .copied()
+                    .filter(|address| *address != nodes[id])
+                    .collect(),
+            ),
+        };
+        let config = SenderConfig {
+            startup_grace: Duration::from_secs(5),
+            ids: Some(Arc::new(AtomicIds::new(id as u16))),
+            observer,
+            local,
+            inflight: inflight.clone(),
+            sequence_numbers: true,
+            hello: Some(Hello::new(id as u64, nodes[id])),
+            loopback: Some(Loopback::new(nodes[id], tx_rec, inflight.clone())),
+            dialing,
+            default_peer: PeerConfig {
+                queue_capacity: settings.worker_capacity,
+                ..settings.sender.default_peer
+            },
+            ..settings.sender
+        };
+        let mut network_sender =
+            NetworkSender::with_config(rx_send, tx_retransmit, rx_retry, config);
+        let sent = network_sender.sent_sequences().unwrap_or_default();
+        let membership = network_sender.membership();
+        let warmed_up = network_sender.warmed_up();
+        let stats = network_sender.stats();
+
+        network_receiver.peer_events(tx_events.clone());
+        network_sender.peer_events(tx_events);
+
+        let receiver = network_receiver.spawn();
+        let sender = network_sender.spawn();
+
+        warmed_up.wait().await;
+
+        let core = Core::spawn(
+            id,
+            nodes[id],
+            nodes,
+            NetworkTransport {
+                transmit: tx_send,
+                deliver: rx_rec,
+            },
+            rx_events,
+            ready,
+            inflight.clone(),
+        );
+
+        Self {
+            receiver,
+            sender,
+            retransmitter,
+            core,
+            inflight,
+            sent,
+            received,
+            inbound,
+            connections,
+            stop,
+            membership,
+            stats,
+        }
+    }
+
+    /// Number of messages currently buffered anywhere in the node, waiting to be sent or to be
+    /// read by the core. A message to several peers counts once per peer.
+    pub fn inflight_count(&self) -> usize {
+        self.inflight.count()
+    }
+
+    /// Handle to add and remove the peers the node sends to while it runs. The core keeps
+    /// broadcasting to every node, messages to removed peers are dropped.
+    pub fn membership(&self) -> Membership {
+        self.membership.clone()
+    }
+
+    /// Messages, bytes, retransmits and connections of the sender, in total and per peer.
+    pub fn stats(&self) -> Arc<NetworkStats> {
+        self.stats.clone()
+    }
+
+    /// Add a hook that runs when the core of the node is stopped.
+    pub fn on_shutdown(&self, hook: impl FnOnce() + Send + 'static) {
+        self.core.on_shutdown(hook);
+    }
 
-        // Run the retransmitter
-        NetworkRetransmitter::run(rx_retransmit, tx_send.clone());
+    /// Stop the node. The order matters: the receiver is stopped first and closes its listener,
+    /// so no new connections arrive. Then the core handles what was already delivered and runs
+    /// its shutdown hooks, given at most a few seconds. Then the sender is given the time to hand
+    /// its queued messages to the network. The retransmitter stops last, once neither the sender
+    /// nor its workers can hand it messages anymore. Connections that are still open keep
+    /// reading what their peers flush, for at most a second, before they are closed and the
+    /// sequence marks are reported. Returns an error if one of the components panicked, or if
+    /// the receiver never bound its address.
+    pub async fn shutdown(self) -> Result<ShutdownReport, NodeError> {
+        self.stop.trigger();
+        let bound = self.receiver.await?;
+        self.core.shutdown(Duration::from_secs(5)).await?;
 
-        // Create a network receiver and sender.
-        let network_receiver = NetworkReceiver::new(nodes[id], tx_rec);
-        let mut network_sender = NetworkSender::new(rx_send, tx_retransmit);
+        // Dropping the core closed the transmit channel of the sender.
+        self.sender.await?;
 
-        tokio::spawn(async move {
-            network_receiver.run().await;
-        });
-        tokio::spawn(async move {
-            network_sender.run().await;
-        });
+        // With the sender gone the retransmit channel is closed.
+        self.retransmitter.await?;
 
-        sleep(Duration::from_millis(50)).await;
+        // Peers that are stopped at the same time flush their messages and close their
+        // connections, their last messages are only counted once they were read.
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while self.inbound.connections() > 0 && Instant::now() < deadline {
+            sleep(Duration::from_millis(10)).await;
+        }
+        self.connections.abort_all();
 
-        Core::spawn(id, nodes[id], nodes, tx_send.clone(), rx_rec);
+        let mut report = ShutdownReport::default();
+        for (peer, seq) in self.sent.snapshot() {
+            report.peers.entry(peer).or_default().sent = Some(seq);
+        }
+        for (peer, seq) in self.received.snapshot() {
+            report.peers.entry(peer).or_default().received = Some(seq);
+        }
+        bound.map_err(NodeError::Bind)?;
+        Ok(report)
     }
 }
@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+
+use tokio::time::timeout;
+
+use super::*;
+
+#[tokio::test]
+async fn shutdown() {
+    // Run a node whose only peer is down, so the sender and the retransmitter are busy.
+    let nodes = vec![
+        "127.0.0.1:9029".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:9030".parse::<SocketAddr>().unwrap(),
+    ];
+    let node = Node::new(0, nodes.clone()).await;
+    sleep(Duration::from_millis(600)).await;
+
+    // Every component stops without panicking, and the listener is closed.
+    let result = timeout(Duration::from_secs(2), node.shutdown()).await;
+    assert!(result.unwrap().is_ok());
+    assert!(tokio::net::TcpStream::connect(nodes[0]).await.is_err());
+}
+
+// Counts the connections of a node.
+#[derive(Debug, Default)]
+struct ConnectionCounter(std::sync::atomic::AtomicUsize);
+
+impl ObserverSink for ConnectionCounter {
+    fn connected(&self, _peer: SocketAddr) {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn in_process() {
+    // Run two nodes in the same registry.
+    let nodes = vec![
+        "127.0.0.1:9043".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:9044".parse::<SocketAddr>().unwrap(),
+    ];
+    let local = LocalRegistry::new();
+    let counter = Arc::new(ConnectionCounter::default());
+    let mut running = Vec::new();
+    for id in 0..2 {
+        let node = Node::in_process(id, nodes.clone(), counter.clone(), local.clone()).await;
+        running.push(node);
+    }
+    assert!(nodes.iter().all(|node| local.contains(node)));
+
+    // The nodes broadcast for a while without ever connecting to each other.
+    sleep(Duration::from_millis(600)).await;
+    assert_eq!(counter.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+    for node in running {
+        assert!(node.shutdown().await.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn shutdown_hook() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let nodes = vec![
+        "127.0.0.1:9053".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:9054".parse::<SocketAddr>().unwrap(),
+    ];
+    let node = Node::new(0, nodes).await;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    node.on_shutdown(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    // The hook ran exactly once by the time the node is shut down.
+    node.shutdown().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn validate() {
+    let valid = NodeConfig {
+        id: 1,
+        nodes: vec!["127.0.0.1:8000".to_string(), "127.0.0.1:8001".to_string()],
+        ..NodeConfig::default()
+    };
+    assert_eq!(valid.validate(), Ok(()));
+
+    let peer = PeerConfig {
+        queue_capacity: 0,
+        pool_size: 2,
+        ack_timeout: Some(Timeout::Fixed(Duration::from_secs(1))),
+        ..PeerConfig::default()
+    };
+    let broken = NodeConfig {
+        id: 3,
+        nodes: vec![
+            "127.0.0.1:8000".to_string(),
+            "localhost".to_string(),
+            "127.0.0.1:8000".to_string(),
+        ],
+        sender: SenderConfig {
+            peers: HashMap::from([("127.0.0.1:8000".parse().unwrap(), peer)]),
+            compression_threshold: Some(1024),
+            quota: Some(Quota {
+                bytes: 0,
+                window: Duration::from_secs(1),
+                policy: QuotaPolicy::Delay,
+            }),
+            tls: ClientTls {
+                policy: TlsPolicy::Require,
+                #[cfg(feature = "tls")]
+                config: None,
+            },
+            ..SenderConfig::default()
+        },
+        receiver: ReceiverConfig {
+            max_outstanding: 0,
+            ..ReceiverConfig::default()
+        },
+        worker_capacity: 0,
+        ..NodeConfig::default()
+    };
+
+    // Every problem is reported at once.
+    let mut expected = vec![
+        ConfigError::InvalidAddress("localhost".to_string()),
+        ConfigError::DuplicateAddress("127.0.0.1:8000".parse().unwrap()),
+        ConfigError::UnknownId(3),
+        ConfigError::Tls("client"),
+    ];
+    #[cfg(not(feature = "compression"))]
+    expected.push(ConfigError::MissingFeature(
+        "compression_threshold",
+        "compression",
+    ));
+    expected.extend([
+        ConfigError::Needs("pool_size above 1", "duplicate_policy AllowBoth"),
+        ConfigError::Conflicts("pool_size above 1", "ack_timeout"),
+        ConfigError::Zero("queue_capacity"),
+        ConfigError::Zero("quota"),
+        ConfigError::Zero("max_outstanding"),
+        ConfigError::Zero("worker_capacity"),
+    ]);
+    assert_eq!(broken.validate(), Err(expected));
+}
+
+#[test]
+fn addresses() {
+    let addresses = local_addresses(12, 8000).unwrap();
+    assert_eq!(addresses.len(), 12);
+    for (i, address) in addresses.iter().enumerate() {
+        assert_eq!(address.ip().to_string(), "127.0.0.1");
+        assert_eq!(address.port() as usize, 8000 + i);
+    }
+    assert_eq!(addresses.iter().collect::<HashSet<_>>().len(), 12);
+
+    // The last node may take the highest port, but not one beyond it.
+    let last = local_addresses(12, 65524).unwrap();
+    assert_eq!(last[11].port(), 65535);
+    assert_eq!(
+        local_addresses(12, 65525),
+        Err(ConfigError::PortOutOfRange(65536))
+    );
+    assert_eq!(local_addresses(0, 8000), Ok(Vec::new()));
+}
+
+// Counts the messages a node sent, per peer.
+#[derive(Debug, Default)]
+struct SentCounter(std::sync::Mutex<HashMap<SocketAddr, u64>>);
+
+impl ObserverSink for SentCounter {
+    fn message_sent(&self, peer: SocketAddr, _bytes: usize) {
+        *self.0.lock().unwrap().entry(peer).or_default() += 1;
+    }
+}
+
+#[tokio::test]
+async fn shutdown_report() {
+    let nodes = vec![
+        "127.0.0.1:9161".parse::<SocketAddr>().unwrap(),
+        "127.0.0.1:9162".parse::<SocketAddr>().unwrap(),
+    ];
+    let counters = [
+        Arc::new(SentCounter::default()),
+        Arc::new(SentCounter::default()),
+    ];
+    let a = Node::with_observer(0, nodes.clone(), counters[0].clone()).await;
+    let b = Node::with_observer(1, nodes.clone(), counters[1].clone()).await;
+    sleep(Duration::from_millis(1500)).await;
+
+    // Both nodes stop together, so each one flushes what it has left to the other.
+    let (a, b) = tokio::join!(a.shutdown(), b.shutdown());
+    let reports = [a.unwrap(), b.unwrap()];
+
+    // The sent mark is the number of messages written to the peer, and the peer read all of
+    // them.
+    for (from, to) in [(0, 1), (1, 0)] {
+        let sent = counters[from].0.lock().unwrap()[&nodes[to]];
+        assert!(sent > 0);
+        assert_eq!(reports[from].peers[&nodes[to]].sent, Some(sent));
+        assert_eq!(reports[to].peers[&nodes[from]].received, Some(sent));
+    }
+}
+
+#[tokio::test]
+async fn tiny_capacities() {
+    let nodes = ["127.0.0.1:9176", "127.0.0.1:9177"];
+    let config = |id| NodeConfig {
+        id,
+        nodes: nodes.map(String::from).to_vec(),
+        deliver_capacity: 1,
+        send_capacity: 1,
+        retransmit_capacity: 1,
+        worker_capacity: 1,
+        ..NodeConfig::default()
+    };
+    let a = Node::with_config(config(0)).await.unwrap();
+    let b = Node::with_config(config(1)).await.unwrap();
+    sleep(Duration::from_millis(1500)).await;
+
+    // Every channel holds a single message, the nodes still exchange theirs.
+    let (a, b) = tokio::join!(a.shutdown(), b.shutdown());
+    let (a, b) = (a.unwrap(), b.unwrap());
+    let (first, second) = (nodes[0].parse().unwrap(), nodes[1].parse().unwrap());
+    assert!(a.peers[&second].sent.is_some_and(|sent| sent > 0));
+    assert_eq!(b.peers[&first].received, a.peers[&second].sent);
+}